@@ -1,9 +1,15 @@
 #!/bin/sh
 dd if=/dev/urandom of=src/drivers/disk/test_drive.bin bs=1024 count=64
 dd if=/dev/zero of=fs.bin bs=1024 count=1024
-mkfs.fat fs.bin
+mkfs.fat -n YACURI fs.bin
 
 mkdir -p /tmp/fatfs
 sudo mount fs.bin /tmp/fatfs -o loop,uid=$(id -u)
-cp -r install_fs/* /tmp/fatfs/
+cp -r install_fs/. /tmp/fatfs/
+# Pre-compile the system library, so the kernel does not have to compile it on every run.
+# It needs the options the kernel compiles it with: no dead code elimination and the kernel flag
+cargo run --manifest-path ../lang/Cargo.toml --example snapshot -- --library --flag kernel /tmp/fatfs/system/yacuri.snap install_fs/system/yacuri
+# Symbol table used to resolve backtraces, needs to be regenerated whenever the kernel changes
+cargo kbuild
+nm -n -C ../target/x86_64-yacuri/debug/yacuri > /tmp/fatfs/system/kernel.sym
 sudo umount /tmp/fatfs
\ No newline at end of file
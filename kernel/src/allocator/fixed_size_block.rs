@@ -1,4 +1,4 @@
-use crate::allocator::Lock;
+use crate::allocator::{HeapStats, Lock};
 use core::{
     alloc::{GlobalAlloc, Layout},
     mem, ptr,
@@ -29,6 +29,10 @@ struct ListNode {
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    /// Bytes in blocks that are currently in one of the lists.
+    cached: usize,
+    /// The amount of live allocations.
+    allocations: usize,
 }
 
 impl FixedSizeBlockAllocator {
@@ -38,6 +42,8 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            cached: 0,
+            allocations: 0,
         }
     }
 
@@ -51,10 +57,23 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.fallback_allocator.size(),
+            used: self.fallback_allocator.used() - self.cached,
+            free: self.fallback_allocator.free() + self.cached,
+            fragmented: self.cached,
+            allocations: self.allocations,
+        }
+    }
+
     /// Allocates using the fallback allocator.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
         match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
+            Ok(ptr) => {
+                self.allocations += 1;
+                ptr.as_ptr()
+            }
             Err(_) => ptr::null_mut(),
         }
     }
@@ -68,6 +87,8 @@ unsafe impl GlobalAlloc for Lock<FixedSizeBlockAllocator> {
                 match allocator.list_heads[index].take() {
                     Some(node) => {
                         allocator.list_heads[index] = node.next.take();
+                        allocator.cached -= BLOCK_SIZES[index];
+                        allocator.allocations += 1;
                         node as *mut ListNode as *mut u8
                     }
                     None => {
@@ -86,8 +107,10 @@ unsafe impl GlobalAlloc for Lock<FixedSizeBlockAllocator> {
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.allocations -= 1;
         match list_index(&layout) {
             Some(index) => {
+                allocator.cached += BLOCK_SIZES[index];
                 let new_node = ListNode {
                     next: allocator.list_heads[index].take(),
                 };
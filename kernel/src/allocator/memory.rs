@@ -1,10 +1,19 @@
 use bootloader::boot_info::{MemoryRegionKind, MemoryRegions};
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::{
     registers::control::Cr3,
-    structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB},
+    structures::paging::{
+        FrameAllocator, OffsetPageTable, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    },
     PhysAddr, VirtAddr,
 };
 
+/// Frames below this address are never handed out, since they
+/// are needed for things like the trampoline starting other CPUs.
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryRegions,
@@ -31,7 +40,7 @@ impl BootInfoFrameAllocator {
         let regions = self.memory_map.iter();
         let usable_regions = regions.filter(|r| r.kind == MemoryRegionKind::Usable);
         // map each region to its address range
-        let addr_ranges = usable_regions.map(|r| r.start..r.end);
+        let addr_ranges = usable_regions.map(|r| r.start.max(LOW_MEMORY_END)..r.end);
         // transform to an iterator of frame start addresses
         let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
         // create `PhysFrame` types from the start addresses
@@ -54,10 +63,47 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
 /// the passed `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+/// Returns the virtual address the given physical address is mapped to.
+/// Only valid after `init`.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) + addr.as_u64())
+}
+
+/// Whether the given address is mapped in the active page tables.
+/// Always false before `init`, since the page tables cannot be read then.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    if PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) == 0 {
+        return false;
+    }
+
+    let (level_4_table_frame, _) = Cr3::read();
+    let indices = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut table = level_4_table_frame.start_address();
+    for (level, index) in indices.iter().enumerate() {
+        let table_ptr: *const PageTable = phys_to_virt(table).as_ptr();
+        let entry = unsafe { &(*table_ptr)[*index] };
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return false;
+        }
+        // Huge pages end the walk early, but do not exist on level 4
+        if level > 0 && entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return true;
+        }
+        table = entry.addr();
+    }
+    true
+}
+
 /// Returns a mutable reference to the active level 4 table.
 ///
 /// # Safety
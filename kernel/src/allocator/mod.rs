@@ -28,6 +28,24 @@ pub fn init_heap(
     Ok(())
 }
 
+/// Usage statistics of a heap, in bytes.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Part of the free memory that can only be reused
+    /// for allocations of a specific size.
+    pub fragmented: usize,
+    /// The amount of live allocations.
+    pub allocations: usize,
+}
+
+/// Returns usage statistics of the kernel heap.
+pub fn stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
 pub fn prepare_pages(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
@@ -0,0 +1,166 @@
+//! Walking the kernel stack using frame pointers, which the kernel is
+//! always compiled with (see `.cargo/config.toml`).
+//! Functions push `rbp` right below their return address,
+//! so the saved values form a linked list through the stack.
+//!
+//! Addresses are resolved to function names with the kernel's symbol table,
+//! which `init.sh` writes to `SYMBOL_FILE` as the output of `nm`.
+
+use crate::{allocator::memory::is_mapped, drivers::disk::FileSystem, vm, warn};
+use alloc::{string::String, vec::Vec};
+use conquer_once::spin::OnceCell;
+use core::fmt::{self, Write};
+use x86_64::VirtAddr;
+
+/// The maximum amount of frames walked, in case the stack is corrupted.
+const MAX_FRAMES: usize = 32;
+/// Where the symbol table of the kernel is found.
+const SYMBOL_FILE: &str = "/system/kernel.sym";
+
+/// Kernel functions sorted by their address.
+static SYMBOLS: OnceCell<Vec<(usize, String)>> = OnceCell::uninit();
+
+/// Load the kernel's symbol table, used to resolve addresses in backtraces.
+/// Without it, backtraces only contain addresses.
+pub fn load_symbols() {
+    match FileSystem::new().and_then(|fs| fs.read_to_string(SYMBOL_FILE)) {
+        Some(file) => SYMBOLS.init_once(|| parse_symbols(&file)),
+        None => warn!(
+            "no symbol table at {}, backtraces will not be resolved",
+            SYMBOL_FILE
+        ),
+    }
+}
+
+/// Parse the output of `nm`, keeping only functions.
+fn parse_symbols(file: &str) -> Vec<(usize, String)> {
+    let mut symbols = file
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, ' ');
+            let address = usize::from_str_radix(parts.next()?, 16).ok()?;
+            let kind = parts.next()?;
+            let name = parts.next()?;
+            matches!(kind, "T" | "t" | "W" | "w").then(|| (address, name.into()))
+        })
+        .collect::<Vec<_>>();
+    symbols.sort_by_key(|(address, _)| *address);
+    symbols
+}
+
+/// Returns the name of the kernel function containing the given address,
+/// together with the offset of the address into it.
+pub fn symbolize(address: usize) -> Option<(&'static str, usize)> {
+    let symbols = SYMBOLS.get()?;
+    let index = match symbols.binary_search_by_key(&address, |(start, _)| *start) {
+        Ok(index) => index,
+        Err(0) => return None,
+        Err(index) => index - 1,
+    };
+    let (start, name) = &symbols[index];
+    Some((name.as_str(), address - start))
+}
+
+/// Write a backtrace of the given addresses, one per line,
+/// resolving them to kernel functions or program functions where possible.
+pub fn write(out: &mut impl Write, addresses: impl Iterator<Item = usize>) -> fmt::Result {
+    for (index, address) in addresses.enumerate() {
+        write!(out, "{:>2}: {:016x}", index, address)?;
+        if let Some((name, offset)) = symbolize(address) {
+            write!(out, "  {}+{:#x}", name, offset)?;
+        } else if let Some(location) = vm::registry::describe(address) {
+            write!(out, "  {}", location)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Iterator over the return addresses on the stack, innermost first.
+/// Stops at the first frame that does not look valid.
+pub struct Frames {
+    rbp: u64,
+    count: usize,
+}
+
+impl Frames {
+    /// Start walking at the frame of the calling function.
+    #[inline(always)]
+    pub fn current() -> Self {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp) };
+        Self::starting_at(rbp)
+    }
+
+    /// Start walking at the code interrupted by the current interrupt.
+    /// Must be called from the interrupt handler itself, whose saved
+    /// frame pointer is the one of the interrupted function.
+    #[inline(always)]
+    pub fn interrupted() -> Self {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp) };
+        let saved = VirtAddr::try_new(rbp)
+            .ok()
+            .filter(|frame| is_mapped(*frame))
+            .map_or(0, |frame| unsafe { *frame.as_ptr::<u64>() });
+        Self::starting_at(saved)
+    }
+
+    /// Start walking at the frame with the given frame pointer.
+    pub fn starting_at(rbp: u64) -> Self {
+        Self { rbp, count: 0 }
+    }
+}
+
+impl Iterator for Frames {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.count == MAX_FRAMES || self.rbp == 0 || self.rbp % 8 != 0 {
+            return None;
+        }
+        let frame = VirtAddr::try_new(self.rbp).ok()?;
+        if !is_mapped(frame) || !is_mapped(frame + 8u64) {
+            return None;
+        }
+
+        let (next, return_address) = unsafe {
+            let frame = frame.as_ptr::<u64>();
+            (*frame, *frame.add(1))
+        };
+        if return_address == 0 {
+            return None;
+        }
+        // The stack grows down, so the frames of callers are always above
+        self.rbp = if next > self.rbp { next } else { 0 };
+        self.count += 1;
+        Some(return_address as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_symbols, Frames};
+    use alloc::vec;
+
+    #[test_case]
+    fn symbols() {
+        let symbols = parse_symbols(
+            "ffff800000001000 T kernel_main\n\
+             ffff800000000000 t yacuri::init\n\
+             0000000000000010 B yacuri::DATA\n",
+        );
+        assert_eq!(
+            symbols,
+            vec![
+                (0xffff800000000000, "yacuri::init".into()),
+                (0xffff800000001000, "kernel_main".into())
+            ]
+        );
+    }
+
+    #[test_case]
+    fn walks_stack() {
+        assert!(Frames::current().count() > 1);
+    }
+}
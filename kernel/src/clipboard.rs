@@ -0,0 +1,39 @@
+//! The clipboard, shared by all consoles.
+//! Shells save it to `CLIPBOARD_FILE` whenever they copy something,
+//! which is loaded again on boot.
+
+use crate::drivers::disk::{fat::FatDir, FileSystem};
+use alloc::string::String;
+use fatfs::Write;
+use spin::Mutex;
+
+/// Where the clipboard is saved to survive a reboot.
+pub const CLIPBOARD_FILE: &str = "/system/clipboard.txt";
+
+static CLIPBOARD: Mutex<String> = Mutex::new(String::new());
+
+pub fn copy(text: &str) {
+    let mut clipboard = CLIPBOARD.lock();
+    clipboard.clear();
+    clipboard.push_str(text);
+}
+
+pub fn paste() -> String {
+    CLIPBOARD.lock().clone()
+}
+
+/// Restore the clipboard from the last time it was saved, if it was.
+pub fn load() {
+    if let Some(text) = FileSystem::new().and_then(|fs| fs.read_to_string(CLIPBOARD_FILE)) {
+        copy(&text);
+    }
+}
+
+/// Write the clipboard to `CLIPBOARD_FILE` inside of the given root directory.
+pub fn save(root: &FatDir) -> bool {
+    let text = paste();
+    match root.create_file(CLIPBOARD_FILE.trim_start_matches('/')) {
+        Ok(mut file) => file.write_all(text.as_bytes()).is_ok() && file.truncate().is_ok(),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,116 @@
+//! Minimal parsing of the ACPI tables, only as far as needed
+//! to find the processors and interrupt controllers of the system.
+
+use crate::allocator::memory::phys_to_virt;
+use alloc::vec::Vec;
+use core::{ptr, slice};
+use x86_64::PhysAddr;
+
+/// Size of the header shared by all system description tables.
+const HEADER_SIZE: usize = 36;
+
+/// The processors and interrupt controllers described by the MADT.
+#[derive(Debug, Default)]
+pub struct Madt {
+    /// Physical address of the local APIC of every processor.
+    pub local_apic: u64,
+    /// Local APIC IDs of all usable processors.
+    pub processors: Vec<u8>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct IoApic {
+    pub id: u8,
+    /// Physical address of its registers.
+    pub address: u64,
+    /// The first global system interrupt it handles.
+    pub gsi_base: u32,
+}
+
+/// An ISA interrupt that is not connected to the GSI of the same number.
+#[derive(Debug, Copy, Clone)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    /// MPS INTI flags, containing polarity and trigger mode.
+    pub flags: u16,
+}
+
+/// Find and parse the MADT, given the physical address of the RSDP.
+///
+/// # Safety
+/// The address must point to a valid RSDP, and physical memory
+/// must be mapped (see `allocator::memory::init`).
+pub unsafe fn madt(rsdp: u64) -> Option<Madt> {
+    let rsdp = phys_to_virt(PhysAddr::new(rsdp)).as_ptr::<u8>();
+    if slice::from_raw_parts(rsdp, 8) != b"RSD PTR " {
+        return None;
+    }
+
+    // Revision 2 and up have the XSDT with 64-bit pointers
+    let (table, entry_size) = if read::<u8>(rsdp, 15) >= 2 {
+        (read::<u64>(rsdp, 24), 8)
+    } else {
+        (read::<u32>(rsdp, 16) as u64, 4)
+    };
+    let table = table_ptr(table);
+    let entries = (read::<u32>(table, 4) as usize - HEADER_SIZE) / entry_size;
+
+    (0..entries)
+        .map(|i| {
+            let offset = HEADER_SIZE + i * entry_size;
+            if entry_size == 8 {
+                read::<u64>(table, offset)
+            } else {
+                read::<u32>(table, offset) as u64
+            }
+        })
+        .map(table_ptr)
+        .find(|table| slice::from_raw_parts(*table, 4) == b"APIC")
+        .map(|table| parse_madt(table))
+}
+
+unsafe fn parse_madt(table: *const u8) -> Madt {
+    let length = read::<u32>(table, 4) as usize;
+    let mut madt = Madt {
+        local_apic: read::<u32>(table, 36) as u64,
+        ..Madt::default()
+    };
+
+    let mut offset = HEADER_SIZE + 8;
+    while offset + 2 <= length {
+        let entry = table.add(offset);
+        match read::<u8>(entry, 0) {
+            0 => {
+                // Enabled or online capable
+                if read::<u32>(entry, 4) & 0b11 != 0 {
+                    madt.processors.push(read(entry, 3))
+                }
+            }
+            1 => madt.io_apics.push(IoApic {
+                id: read(entry, 2),
+                address: read::<u32>(entry, 4) as u64,
+                gsi_base: read(entry, 8),
+            }),
+            2 => madt.overrides.push(InterruptOverride {
+                irq: read(entry, 3),
+                gsi: read(entry, 4),
+                flags: read(entry, 8),
+            }),
+            5 => madt.local_apic = read(entry, 4),
+            _ => (),
+        }
+        offset += read::<u8>(entry, 1).max(2) as usize;
+    }
+    madt
+}
+
+fn table_ptr(address: u64) -> *const u8 {
+    phys_to_virt(PhysAddr::new(address)).as_ptr()
+}
+
+unsafe fn read<T: Copy>(base: *const u8, offset: usize) -> T {
+    ptr::read_unaligned(base.add(offset) as *const T)
+}
@@ -0,0 +1,243 @@
+//! The local APIC of each CPU and the IOAPICs, which replace the legacy PICs
+//! once the ACPI tables were read. The local APIC provides the timer and
+//! interrupts between CPUs, while IOAPICs route device interrupts to a CPU.
+
+use crate::drivers::{
+    acpi::{InterruptOverride, IoApic, Madt},
+    interrupts::interrupts::InterruptIndex,
+    timer,
+};
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::{
+    instructions::port::Port,
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// Where the registers of the local APIC are mapped.
+/// Every CPU sees its own APIC at the same address.
+pub const LAPIC_START: usize = 0x_5555_5555_0000;
+/// Where the registers of the IOAPICs are mapped, one page each.
+pub const IOAPIC_START: usize = LAPIC_START + 0x1000;
+/// The vector the APIC delivers spurious interrupts to.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const ID: usize = 0x20;
+const EOI: usize = 0xB0;
+const SPURIOUS: usize = 0xF0;
+const ICR_LOW: usize = 0x300;
+const ICR_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const TIMER_INITIAL: usize = 0x380;
+const TIMER_CURRENT: usize = 0x390;
+const TIMER_DIVIDE: usize = 0x3E0;
+
+/// ICR delivery modes.
+const FIXED: u32 = 0 << 8;
+const INIT: u32 = 5 << 8;
+const STARTUP: u32 = 6 << 8;
+/// Set in the ICR while an interrupt is still being sent.
+const DELIVERY_PENDING: u32 = 1 << 12;
+const LEVEL_ASSERT: u32 = 1 << 14;
+
+const TIMER_PERIODIC: u32 = 1 << 17;
+const TIMER_DIVIDE_BY_16: u32 = 0b11;
+/// Amount of PIT ticks to measure the APIC timer over when calibrating.
+const CALIBRATION_TICKS: u64 = 10;
+
+/// Redirection entry flags of the IOAPIC.
+const ACTIVE_LOW: u32 = 1 << 13;
+const LEVEL_TRIGGERED: u32 = 1 << 15;
+
+/// ISA interrupts routed through the IOAPIC, with the vector they are delivered to.
+const ROUTED_IRQS: [(u8, InterruptIndex); 2] = [
+    (1, InterruptIndex::Keyboard),
+    (14, InterruptIndex::PrimaryAta),
+];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Switch from the legacy PICs to the APICs described by the given MADT:
+/// Map their registers, calibrate the local APIC timer against the PIT
+/// and route device interrupts to the current CPU.
+/// Needs the PIT timer interrupt to be running.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    madt: &Madt,
+) -> Result<(), MapToError<Size4KiB>> {
+    map(mapper, frame_allocator, LAPIC_START, madt.local_apic)?;
+    for (index, io_apic) in madt.io_apics.iter().enumerate() {
+        map(
+            mapper,
+            frame_allocator,
+            io_apic_base(index),
+            io_apic.address,
+        )?;
+    }
+    enable();
+
+    let ticks_per_interrupt = calibrate_timer();
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        disable_pics();
+        for (irq, vector) in ROUTED_IRQS.iter() {
+            route(madt, *irq, *vector as u8, id());
+        }
+        unsafe {
+            write(LVT_TIMER, TIMER_PERIODIC | InterruptIndex::Timer as u32);
+            write(TIMER_INITIAL, ticks_per_interrupt);
+        }
+        ENABLED.store(true, Ordering::Release);
+    });
+    Ok(())
+}
+
+/// Whether interrupts are handled by the APIC instead of the legacy PICs,
+/// which is not the case when the system has no ACPI tables.
+pub fn available() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+/// Enable the local APIC of the current CPU.
+pub fn enable() {
+    unsafe { write(SPURIOUS, 0x100 | SPURIOUS_VECTOR as u32) }
+}
+
+/// Returns the ID of the current CPU's local APIC.
+pub fn id() -> u8 {
+    unsafe { (read(ID) >> 24) as u8 }
+}
+
+/// Signal the end of the interrupt currently being handled.
+pub fn eoi() {
+    unsafe { write(EOI, 0) }
+}
+
+/// Send the given interrupt vector to the CPU with the given APIC ID.
+pub fn send_ipi(apic_id: u8, vector: u8) {
+    send(apic_id, FIXED | LEVEL_ASSERT | vector as u32)
+}
+
+/// Start a CPU, which begins executing in real mode
+/// at the given page (the address divided by 4096).
+/// Needs the timer interrupt to be running.
+pub fn start_cpu(apic_id: u8, page: u8) {
+    send(apic_id, INIT | LEVEL_ASSERT);
+    timer::sleep_ms(10);
+    // Intel recommends sending the startup IPI twice
+    for _ in 0..2 {
+        send(apic_id, STARTUP | LEVEL_ASSERT | page as u32);
+        timer::sleep_ms(1);
+    }
+}
+
+/// Stop a CPU, putting it back into the state it waits for `start_cpu` in.
+pub fn stop_cpu(apic_id: u8) {
+    send(apic_id, INIT | LEVEL_ASSERT);
+}
+
+fn send(apic_id: u8, command: u32) {
+    unsafe {
+        write(ICR_HIGH, (apic_id as u32) << 24);
+        write(ICR_LOW, command);
+        while read(ICR_LOW) & DELIVERY_PENDING != 0 {}
+    }
+}
+
+/// Measure how many APIC timer ticks pass between two PIT interrupts.
+fn calibrate_timer() -> u32 {
+    unsafe {
+        write(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        let start = timer::ticks();
+        while timer::ticks() == start {
+            x86_64::instructions::hlt();
+        }
+        write(TIMER_INITIAL, u32::MAX);
+        timer::sleep_ticks(CALIBRATION_TICKS);
+        let elapsed = u32::MAX - read(TIMER_CURRENT);
+        write(TIMER_INITIAL, 0);
+        elapsed / CALIBRATION_TICKS as u32
+    }
+}
+
+/// Mask all interrupts of the legacy PICs, which stay remapped
+/// so that spurious interrupts cannot be mistaken for exceptions.
+fn disable_pics() {
+    unsafe {
+        Port::<u8>::new(0x21).write(0xFF);
+        Port::<u8>::new(0xA1).write(0xFF);
+    }
+}
+
+/// Route the given ISA interrupt to the given vector on the CPU with the given APIC ID.
+fn route(madt: &Madt, irq: u8, vector: u8, apic_id: u8) {
+    let (gsi, flags) = match madt.overrides.iter().find(|o| o.irq == irq) {
+        Some(InterruptOverride { gsi, flags, .. }) => (*gsi, override_flags(*flags)),
+        // ISA interrupts are edge triggered and active high by default
+        None => (irq as u32, 0),
+    };
+
+    let io_apic = madt
+        .io_apics
+        .iter()
+        .enumerate()
+        .filter(|(_, io_apic)| io_apic.gsi_base <= gsi)
+        .max_by_key(|(_, io_apic)| io_apic.gsi_base);
+    if let Some((index, IoApic { gsi_base, .. })) = io_apic {
+        let entry = 0x10 + 2 * (gsi - gsi_base);
+        unsafe {
+            io_write(index, entry + 1, (apic_id as u32) << 24);
+            io_write(index, entry, flags | vector as u32);
+        }
+    }
+}
+
+/// Convert the MPS INTI flags of an interrupt override into redirection entry flags.
+fn override_flags(flags: u16) -> u32 {
+    let mut entry = 0;
+    if flags & 0b11 == 0b11 {
+        entry |= ACTIVE_LOW;
+    }
+    if (flags >> 2) & 0b11 == 0b11 {
+        entry |= LEVEL_TRIGGERED;
+    }
+    entry
+}
+
+fn map(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    virt: usize,
+    phys: u64,
+) -> Result<(), MapToError<Size4KiB>> {
+    let page = Page::containing_address(VirtAddr::new(virt as u64));
+    let frame = PhysFrame::containing_address(PhysAddr::new(phys));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    Ok(())
+}
+
+fn io_apic_base(index: usize) -> usize {
+    IOAPIC_START + index * 0x1000
+}
+
+unsafe fn read(register: usize) -> u32 {
+    ptr::read_volatile((LAPIC_START + register) as *const u32)
+}
+
+unsafe fn write(register: usize, value: u32) {
+    ptr::write_volatile((LAPIC_START + register) as *mut u32, value)
+}
+
+/// Write to a register of the IOAPIC with the given index,
+/// which are accessed indirectly through a select and a window register.
+unsafe fn io_write(index: usize, register: u32, value: u32) {
+    let base = io_apic_base(index);
+    ptr::write_volatile(base as *mut u32, register);
+    ptr::write_volatile((base + 0x10) as *mut u32, value);
+}
@@ -0,0 +1,69 @@
+//! Virtual consoles, each with its own screen and shell session.
+//! Only the active console is shown and receives keyboard input,
+//! Alt+F1 to Alt+F4 switch between them.
+//! Printed text goes to the active console, since shells only
+//! run commands while their console is active.
+
+use crate::drivers::vga_buffer::Writer;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{
+    fmt,
+    fmt::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+pub const CONSOLE_COUNT: usize = 4;
+
+static CONSOLES: OnceCell<Vec<Mutex<Writer>>> = OnceCell::uninit();
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Create the consoles, showing the first one.
+/// Text printed before is only written to serial.
+pub fn init() {
+    CONSOLES.init_once(|| {
+        (0..CONSOLE_COUNT)
+            .map(|index| Mutex::new(Writer::new(index == 0)))
+            .collect()
+    });
+}
+
+/// Returns the index of the active console.
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// Show the given console, which receives keyboard input from now on.
+pub fn switch_to(index: usize) {
+    let consoles = match CONSOLES.get() {
+        Some(consoles) if index < CONSOLE_COUNT => consoles,
+        _ => return,
+    };
+    interrupts::without_interrupts(|| {
+        let previous = ACTIVE.swap(index, Ordering::AcqRel);
+        if previous != index {
+            consoles[previous].lock().hide();
+            consoles[index].lock().show();
+        }
+    });
+}
+
+/// Run `f` with the writer of the given console,
+/// unless the consoles are not initialized yet.
+pub fn with_console<T>(index: usize, f: impl FnOnce(&mut Writer) -> T) -> Option<T> {
+    let consoles = CONSOLES.get()?;
+    Some(interrupts::without_interrupts(|| {
+        f(&mut consoles[index].lock())
+    }))
+}
+
+pub fn with_active<T>(f: impl FnOnce(&mut Writer) -> T) -> Option<T> {
+    with_console(active(), f)
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    with_active(|writer| writer.write_fmt(args).ok());
+}
@@ -297,9 +297,10 @@ impl Seek for AtaDrive {
 #[cfg(test)]
 mod tests {
     use super::AtaDrive;
-    use fatfs::{Read, Seek, SeekFrom, Write};
+    use alloc::{vec, vec::Vec};
+    use fatfs::{IoBase, Read, Seek, SeekFrom, Write};
     use lazy_static::lazy_static;
-    use rand::{rngs::SmallRng, RngCore, SeedableRng};
+    use rand::{rngs::SmallRng, Rng, RngCore, SeedableRng};
     use spin::{Mutex, MutexGuard};
 
     // 64KiB drive read from disk, this is what AtaBus should return.
@@ -459,6 +460,116 @@ mod tests {
         }
     }
 
+    /// An in-memory drive, which the driver needs to behave like.
+    struct ModelDrive {
+        data: Vec<u8>,
+        position: usize,
+    }
+
+    impl IoBase for ModelDrive {
+        type Error = ();
+    }
+
+    impl Read for ModelDrive {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+            let end = self.position + buf.len();
+            buf.copy_from_slice(&self.data[self.position..end]);
+            self.position = end;
+            Ok(buf.len())
+        }
+    }
+
+    impl Write for ModelDrive {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+            let end = self.position + buf.len();
+            self.data[self.position..end].copy_from_slice(buf);
+            self.position = end;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for ModelDrive {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, ()> {
+            let position = match pos {
+                SeekFrom::Start(pos) => pos as i64,
+                SeekFrom::Current(by) => self.position as i64 + by,
+                SeekFrom::End(_) => return Err(()),
+            };
+            if position < 0 {
+                return Err(());
+            }
+            self.position = position as usize;
+            Ok(position as u64)
+        }
+    }
+
+    #[test_case]
+    fn model_sequences() {
+        for seed in [1, 7, 512, 9001] {
+            model_sequence(seed, 100)
+        }
+    }
+
+    /// Run a random sequence of seeks, reads and writes on both the driver
+    /// and a model of the drive, which need to behave identically.
+    /// Sizes and positions are mostly unaligned to exercise partial sectors.
+    fn model_sequence(seed: u64, operations: usize) {
+        let mut bus = init();
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut data = vec![0; ACTUAL.len()];
+        bus.read(&mut data);
+        bus.seek(SeekFrom::Start(0));
+        let mut model = ModelDrive { data, position: 0 };
+
+        for _ in 0..operations {
+            // Operations stay inside the drive, which the model does not extend past
+            let len = rng.gen_range(1..1500);
+            let max_start = ACTUAL.len() - len;
+            match rng.gen_range(0..4) {
+                0 => {
+                    let pos = SeekFrom::Start(rng.gen_range(0..=max_start) as u64);
+                    assert_eq!(bus.seek(pos), model.seek(pos));
+                }
+                1 => {
+                    let by = rng.gen_range(-1024..1024);
+                    let target = model.position as i64 + by;
+                    if target <= max_start as i64 {
+                        assert_eq!(
+                            bus.seek(SeekFrom::Current(by)),
+                            model.seek(SeekFrom::Current(by))
+                        );
+                    }
+                }
+                2 if model.position <= max_start => {
+                    let mut bus_buf = vec![0; len];
+                    let mut model_buf = vec![0; len];
+                    assert_eq!(bus.read(&mut bus_buf), model.read(&mut model_buf));
+                    assert_eq!(bus_buf, model_buf);
+                }
+                3 if model.position <= max_start => {
+                    let mut buf = vec![0; len];
+                    rng.fill_bytes(&mut buf);
+                    assert_eq!(bus.write(&buf), model.write(&buf));
+                }
+                _ => (),
+            }
+            assert_eq!(bus.position, model.position);
+        }
+
+        let mut data = vec![0; ACTUAL.len()];
+        bus.seek(SeekFrom::Start(0));
+        bus.read(&mut data);
+        assert!(
+            data == model.data,
+            "drive differs from the model (seed {})",
+            seed
+        );
+    }
+
     fn init() -> MutexGuard<'static, AtaDrive> {
         let mut bus: MutexGuard<AtaDrive> = BUS.lock();
         bus.seek(SeekFrom::Start(0));
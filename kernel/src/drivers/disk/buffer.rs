@@ -0,0 +1,111 @@
+//! Reusable buffers for reading from disk. Buffers are aligned to sectors,
+//! so they can be handed to DMA transfers, and returned to a pool
+//! when dropped to avoid allocating for every file read.
+
+use crate::drivers::disk::fat::{FatError, FatFile};
+use alloc::vec::Vec;
+use core::{
+    mem,
+    ops::{Deref, DerefMut},
+    slice,
+};
+use fatfs::{Read, Seek, SeekFrom};
+use spin::Mutex;
+
+const SECTOR_SIZE: usize = 512;
+/// How many buffers are kept for reuse at most.
+const POOL_SIZE: usize = 8;
+/// Buffers larger than this are freed instead of being kept for reuse.
+const MAX_POOLED_SIZE: usize = 1024 * 1024;
+
+static POOL: Mutex<Vec<Vec<Sector>>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy)]
+#[repr(C, align(512))]
+struct Sector([u8; SECTOR_SIZE]);
+
+/// A zeroed, sector-aligned buffer of bytes taken from the pool.
+pub struct IoBuffer {
+    sectors: Vec<Sector>,
+    len: usize,
+}
+
+impl IoBuffer {
+    /// Take a buffer of `len` zeroed bytes, reusing a pooled allocation if one is large enough.
+    pub fn new(len: usize) -> Self {
+        let count = (len + SECTOR_SIZE - 1) / SECTOR_SIZE;
+        let mut sectors = {
+            let mut pool = POOL.lock();
+            match pool.iter().rposition(|buf| buf.capacity() >= count) {
+                Some(index) => pool.swap_remove(index),
+                None => Vec::with_capacity(count),
+            }
+        };
+        sectors.clear();
+        sectors.resize(count, Sector([0; SECTOR_SIZE]));
+        Self { sectors, len }
+    }
+
+    /// Read all of `file` into a buffer.
+    pub fn read_file(file: &mut FatFile) -> Result<Self, FatError> {
+        let size = file.seek(SeekFrom::End(0))?;
+        let mut buf = Self::new(size as usize);
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl Deref for IoBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // Safety: sectors are plain, initialized bytes and `len` never exceeds them
+        unsafe { slice::from_raw_parts(self.sectors.as_ptr() as *const u8, self.len) }
+    }
+}
+
+impl DerefMut for IoBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // Safety: see `deref`
+        unsafe { slice::from_raw_parts_mut(self.sectors.as_mut_ptr() as *mut u8, self.len) }
+    }
+}
+
+impl Drop for IoBuffer {
+    fn drop(&mut self) {
+        if self.sectors.capacity() * SECTOR_SIZE > MAX_POOLED_SIZE {
+            return;
+        }
+        let mut pool = POOL.lock();
+        if pool.len() < POOL_SIZE {
+            pool.push(mem::take(&mut self.sectors));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IoBuffer, SECTOR_SIZE};
+
+    #[test_case]
+    fn aligned() {
+        for len in [0, 1, 511, 512, 513, 4000] {
+            let buf = IoBuffer::new(len);
+            assert_eq!(buf.len(), len);
+            assert_eq!(buf.as_ptr() as usize % SECTOR_SIZE, 0);
+        }
+    }
+
+    #[test_case]
+    fn reused_zeroed() {
+        let mut buf = IoBuffer::new(1000);
+        buf.fill(0xAB);
+        let ptr = buf.as_ptr();
+        drop(buf);
+
+        let buf = IoBuffer::new(700);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert!(buf.iter().all(|b| *b == 0));
+    }
+}
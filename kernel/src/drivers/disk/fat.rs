@@ -1,10 +1,15 @@
 use crate::drivers::disk::ata_pio::AtaDrive;
-use fatfs::{DefaultTimeProvider, Dir, DirEntry, File, FileSystem, LossyOemCpConverter};
+use alloc::string::String;
+use fatfs::{
+    Date, DateTime, DefaultTimeProvider, Dir, DirEntry, FatType, File, FileAttributes, FileSystem,
+    LossyOemCpConverter,
+};
 
 pub type FatFs = FileSystem<AtaDrive, DefaultTimeProvider, LossyOemCpConverter>;
 pub type FatDir<'d> = Dir<'d, AtaDrive, DefaultTimeProvider, LossyOemCpConverter>;
 pub type FatFile<'d> = File<'d, AtaDrive, DefaultTimeProvider, LossyOemCpConverter>;
 pub type FatEntry<'d> = DirEntry<'d, AtaDrive, DefaultTimeProvider, LossyOemCpConverter>;
+pub type FatError = fatfs::Error<()>;
 
 /// Treat a given block device as a FAT filesystem.
 ///
@@ -20,3 +25,67 @@ pub fn fat_from_secondary() -> FatFs {
     let secondary = unsafe { AtaDrive::new(0x1F0, 0x3F6) };
     fat_from_ata(secondary)
 }
+
+/// Information about a mounted volume, shown by the `volinfo` shell command.
+#[derive(Debug)]
+pub struct VolumeInfo {
+    pub label: String,
+    pub id: u32,
+    pub fat_type: FatType,
+    /// Size of a cluster in bytes, the unit space is allocated in.
+    pub cluster_size: u32,
+    pub total_clusters: u32,
+    pub free_clusters: u32,
+}
+
+impl VolumeInfo {
+    /// Read the information of the given volume.
+    /// Counting free clusters reads the entire FAT unless the volume has an FSInfo sector.
+    pub fn read(fs: &FatFs) -> Result<VolumeInfo, FatError> {
+        let stats = fs.stats()?;
+        Ok(VolumeInfo {
+            label: fs.volume_label().trim_end().into(),
+            id: fs.volume_id(),
+            fat_type: fs.fat_type(),
+            cluster_size: stats.cluster_size(),
+            total_clusters: stats.total_clusters(),
+            free_clusters: stats.free_clusters(),
+        })
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_clusters as u64 * self.cluster_size as u64
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.free_clusters as u64 * self.cluster_size as u64
+    }
+}
+
+/// Information about a file or directory, shown by the `ls` and `stat` shell commands.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    pub name: String,
+    pub is_dir: bool,
+    /// Size in bytes, which is 0 for directories.
+    pub len: u64,
+    pub created: DateTime,
+    pub modified: DateTime,
+    /// FAT only stores the date of the last access, without a time.
+    pub accessed: Date,
+    pub attributes: FileAttributes,
+}
+
+impl Metadata {
+    pub fn of(entry: &FatEntry) -> Metadata {
+        Metadata {
+            name: entry.file_name(),
+            is_dir: entry.is_dir(),
+            len: entry.len(),
+            created: entry.created(),
+            modified: entry.modified(),
+            accessed: entry.accessed(),
+            attributes: entry.attributes(),
+        }
+    }
+}
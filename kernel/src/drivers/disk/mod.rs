@@ -1,72 +1,255 @@
 use crate::{
-    drivers::disk::fat::{FatDir, FatFile},
-    kprintln,
+    drivers::disk::{
+        buffer::IoBuffer,
+        fat::{FatDir, FatEntry, FatError, FatFile, FatFs, Metadata},
+    },
+    kprintln, warn,
 };
-use alloc::{string::String, vec::Vec};
-use fatfs::{Read, Seek, SeekFrom};
+use alloc::{format, string::String, vec, vec::Vec};
+use core::{
+    str,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use fatfs::Write;
 use spin::{RwLock, RwLockReadGuard};
 use yacari::{
-    filesystem::{File, Filesystem},
+    filesystem::{File, FileError, FileErrorKind, Filesystem},
     SmolStr,
 };
 
 pub mod ata_pio;
+pub mod buffer;
 pub mod fat;
+pub mod path;
+
+/// How deeply nested directories `walk_dir` enters.
+pub const MAX_DEPTH: usize = 16;
 
+/// Held for reading while the volume is mounted by a `FileSystem`,
+/// and for writing while it is unmounted by `sync` or `unmount`.
 static FS_LOCK: RwLock<()> = RwLock::new(());
+/// Set once the volume was unmounted for shutdown, after which it is not mounted again.
+static UNMOUNTED: AtomicBool = AtomicBool::new(false);
+/// Counts the files written, see `writes`.
+static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the volume can be mounted, which is the case until `unmount` is called.
+pub fn is_mounted() -> bool {
+    !UNMOUNTED.load(Ordering::Acquire)
+}
+
+/// Returns how many files were written through a `FileSystem` so far.
+/// Without a clock, FAT modification times do not change when a file is written,
+/// so this tells whether files that were not resized could have changed.
+pub fn writes() -> usize {
+    WRITES.load(Ordering::Acquire)
+}
+
+/// Mount the volume, unless it was unmounted for shutdown.
+pub fn mount() -> Option<FatFs> {
+    let _lock = FS_LOCK.read();
+    is_mounted().then(fat::fat_from_secondary)
+}
+
+/// Write everything `fs` keeps in memory to disk by unmounting it
+/// and mounting the volume again. Waits until no `FileSystem` is in use.
+pub fn sync(fs: FatFs) -> Result<FatFs, FatError> {
+    let _lock = FS_LOCK.write();
+    fs.unmount()?;
+    Ok(fat::fat_from_secondary())
+}
+
+/// Unmount the volume for shutdown. Creating a `FileSystem`
+/// and `mount` fail from now on.
+pub fn unmount(fs: FatFs) -> Result<(), FatError> {
+    let _lock = FS_LOCK.write();
+    UNMOUNTED.store(true, Ordering::Release);
+    fs.unmount()
+}
 
 pub struct FileSystem<'fs> {
     fs: fat::FatFs,
+    /// Normalized, see `path`.
+    working_dir: String,
     lock: RwLockReadGuard<'fs, ()>,
 }
 
 impl<'fs> FileSystem<'fs> {
-    pub fn new() -> Self {
-        FileSystem {
+    pub fn new() -> Option<Self> {
+        Self::with_working_dir(None)
+    }
+
+    /// Create a filesystem that resolves relative paths against `working_dir`
+    /// instead of the disk root. Returns `None` once the volume was unmounted.
+    pub fn with_working_dir(working_dir: Option<&str>) -> Option<Self> {
+        let lock = FS_LOCK.read();
+        if !is_mounted() {
+            return None;
+        }
+        Some(FileSystem {
             fs: fat::fat_from_secondary(),
-            lock: FS_LOCK.read(),
+            working_dir: path::resolve("", working_dir.unwrap_or("")),
+            lock,
+        })
+    }
+
+    /// Read the file at `path` into a string, if it exists and is valid UTF-8.
+    pub fn read_to_string(&self, path: &str) -> Option<String> {
+        let (root, path) = self.resolve(path);
+        root.open_file(&path).ok().and_then(read_file)
+    }
+
+    /// Read the file at `path`, if it exists.
+    pub fn read(&self, path: &str) -> Option<IoBuffer> {
+        let (root, path) = self.resolve(path);
+        root.open_file(&path)
+            .ok()
+            .and_then(|mut file| IoBuffer::read_file(&mut file).ok())
+    }
+
+    /// Replace the contents of the file at `path`, creating it if it does not exist.
+    /// Changes are written to disk once this filesystem is dropped.
+    pub fn write(&self, path: &str, data: &[u8]) -> Result<(), FatError> {
+        let (root, path) = self.resolve(path);
+        let mut file = root.create_file(&path)?;
+        WRITES.fetch_add(1, Ordering::AcqRel);
+        file.write_all(data)?;
+        file.truncate()
+    }
+
+    /// Returns the metadata of the files `walk_directory` reads at `path` without
+    /// reading them, named by their path relative to `path` instead of their name.
+    pub fn source_metadata(&self, path: &str) -> Vec<Metadata> {
+        let (root, path) = self.resolve(path);
+        let mut sources = Vec::new();
+        if let Some(dir) = path::open_dir(root, &path) {
+            walk_dir(dir, &mut |rel_path, entry| {
+                if is_source(rel_path, entry) {
+                    sources.push(Metadata {
+                        name: rel_path.join("/"),
+                        ..Metadata::of(entry)
+                    });
+                }
+            })
+        } else {
+            let (parent, name) = path::split(&path);
+            if let Some(dir) = path::open_dir(self.fs.root_dir(), parent) {
+                sources.extend(
+                    list_dir(&dir)
+                        .into_iter()
+                        .filter(|file| !file.is_dir && file.name.eq_ignore_ascii_case(name)),
+                );
+            }
         }
+        sources
+    }
+
+    /// Returns the root directory and `path` normalized relative to it.
+    fn resolve(&self, path: &str) -> (FatDir, String) {
+        (self.fs.root_dir(), path::resolve(&self.working_dir, path))
     }
 }
 
 impl<'fs> Filesystem for FileSystem<'fs> {
-    fn walk_directory<T: FnMut(File)>(&self, path: &str, mut cls: T) {
-        let dir = self.fs.root_dir().open_dir(path).unwrap();
-        walk_dir(dir, &mut Vec::new(), &mut cls)
+    fn walk_directory<'a, T: FnMut(Result<File<'a>, FileError>)>(&'a self, path: &str, mut cls: T) {
+        let (root, path) = self.resolve(path);
+        let (_, name) = path::split(&path);
+        if let Some(dir) = path::open_dir(self.fs.root_dir(), &path) {
+            walk_dir(dir, &mut |rel_path, entry| {
+                if !is_source(rel_path, entry) {
+                    return;
+                }
+                let (file_name, dirs) = rel_path.split_last().unwrap();
+                let file = read_source(entry.to_file(), || {
+                    format!("/{}", path::resolve(&path, &rel_path.join("/")))
+                });
+                cls(file.map(|contents| {
+                    // Module paths start with the name of the walked directory, like on the host
+                    let mut path = vec![SmolStr::new(name)];
+                    path.extend(dirs.iter().map(SmolStr::new));
+                    path.push(SmolStr::new(stem(file_name)));
+                    File {
+                        path,
+                        contents: contents.into(),
+                    }
+                }));
+            })
+        } else if let Ok(file) = root.open_file(&path) {
+            let file = read_source(file, || format!("/{}", path));
+            cls(file.map(|contents| File {
+                path: vec![SmolStr::new(stem(name))],
+                contents: contents.into(),
+            }));
+        }
     }
 }
 
-fn walk_dir<T: FnMut(File)>(entry: FatDir, path_buf: &mut Vec<SmolStr>, cls: &mut T) {
-    for sub in entry.iter().skip(2) { // Skip '.' and '..'
-        match sub {
-            Ok(entry) if entry.is_dir() => {
-                path_buf.push(SmolStr::new(entry.file_name()));
-                walk_dir(entry.to_dir(), path_buf, cls);
-                path_buf.pop();
-            }
+/// Returns the metadata of every entry in `dir`, sorted by name.
+pub fn list_dir(dir: &FatDir) -> Vec<Metadata> {
+    entries(dir).iter().map(Metadata::of).collect()
+}
 
-            Ok(entry) if entry.is_file() => {
-                read_file(entry.to_file()).map(|contents| {
-                    cls(File {
-                        path: path_buf.clone(),
-                        contents,
-                    })
-                });
-            }
+/// Walk the given directory recursively, calling `visit` with the path of every
+/// entry relative to `dir`, in sorted order and directories before their contents.
+/// Directories nested deeper than `MAX_DEPTH` are not entered,
+/// which also stops cycles in corrupted filesystems.
+pub fn walk_dir(dir: FatDir, visit: &mut impl FnMut(&[String], &FatEntry)) {
+    walk(dir, &mut Vec::new(), visit)
+}
 
-            _ => (),
+fn walk(dir: FatDir, path: &mut Vec<String>, visit: &mut impl FnMut(&[String], &FatEntry)) {
+    if path.len() == MAX_DEPTH {
+        warn!(
+            "not walking {} deeper, directories nested too deeply",
+            path.join("/")
+        );
+        return;
+    }
+
+    for entry in entries(&dir) {
+        path.push(entry.file_name());
+        visit(path, &entry);
+        if entry.is_dir() {
+            walk(entry.to_dir(), path, visit);
         }
+        path.pop();
     }
 }
 
+/// Returns the entries of `dir` without `.` and `..`, sorted by name
+/// to list them in the same order regardless of the layout on disk.
+fn entries<'d>(dir: &FatDir<'d>) -> Vec<FatEntry<'d>> {
+    let mut entries = dir
+        .iter()
+        .filter_map(Result::ok)
+        .filter(|entry| !matches!(&entry.file_name()[..], "." | ".."))
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.file_name());
+    entries
+}
+
+/// Whether the entry at `rel_path` is compiled when walking a directory.
+fn is_source(rel_path: &[String], entry: &FatEntry) -> bool {
+    entry.is_file() && rel_path.last().unwrap().ends_with(".yacari")
+}
+
+fn stem(name: &str) -> &str {
+    name.split('.').next().unwrap()
+}
+
 fn read_file(mut file: FatFile) -> Option<String> {
-    let size = file.seek(SeekFrom::End(0)).unwrap();
-    let mut buf = Vec::with_capacity(size as usize);
-    unsafe {
-        buf.set_len(size as usize);
-    }
+    let buf = IoBuffer::read_file(&mut file).ok()?;
+    str::from_utf8(&buf).ok().map(String::from)
+}
 
-    file.seek(SeekFrom::Start(0)).unwrap();
-    file.read(&mut buf).unwrap();
-    String::from_utf8(buf).ok()
+/// Read a source file for the compiler, naming it with `path` if it cannot be read.
+fn read_source(mut file: FatFile, path: impl FnOnce() -> String) -> Result<String, FileError> {
+    let kind = match IoBuffer::read_file(&mut file) {
+        Ok(buf) => match str::from_utf8(&buf) {
+            Ok(contents) => return Ok(String::from(contents)),
+            Err(_) => FileErrorKind::InvalidUtf8,
+        },
+        Err(_) => FileErrorKind::Unreadable,
+    };
+    Err(FileError { path: path(), kind })
 }
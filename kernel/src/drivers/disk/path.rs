@@ -0,0 +1,66 @@
+//! Paths on the FAT volume. Normalized paths are relative to the root
+//! directory and contain no `.`, `..` or empty components,
+//! which makes the root directory itself the empty path.
+
+use crate::drivers::disk::fat::FatDir;
+use alloc::{string::String, vec::Vec};
+
+/// Resolve `path` against the normalized `working_dir`, unless it is absolute.
+/// Like on Unix, `..` in the root directory stays in the root directory.
+pub fn resolve(working_dir: &str, path: &str) -> String {
+    let mut components = Vec::new();
+    if !path.starts_with('/') {
+        components.extend(working_dir.split('/').filter(|c| !c.is_empty()));
+    }
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+    components.join("/")
+}
+
+/// Split a normalized path into the path of its parent directory and its name.
+pub fn split(path: &str) -> (&str, &str) {
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// Open the directory at the given normalized path.
+pub fn open_dir<'d>(root: FatDir<'d>, path: &str) -> Option<FatDir<'d>> {
+    if path.is_empty() {
+        Some(root)
+    } else {
+        root.open_dir(path).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resolve, split};
+
+    #[test_case]
+    fn resolves() {
+        assert_eq!(resolve("", "system/lib"), "system/lib");
+        assert_eq!(resolve("system", "lib/"), "system/lib");
+        assert_eq!(resolve("system/lib", ".."), "system");
+        assert_eq!(resolve("system/lib", "../../.."), "");
+        assert_eq!(
+            resolve("system/lib", "/bin//./hello.yacari"),
+            "bin/hello.yacari"
+        );
+        assert_eq!(
+            resolve("system", "./lib/../kernel.sym"),
+            "system/kernel.sym"
+        );
+    }
+
+    #[test_case]
+    fn splits() {
+        assert_eq!(split("system/lib/io.yacari"), ("system/lib", "io.yacari"));
+        assert_eq!(split("hello.yacari"), ("", "hello.yacari"));
+    }
+}
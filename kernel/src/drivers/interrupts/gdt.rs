@@ -1,6 +1,11 @@
+use alloc::{boxed::Box, vec};
 use lazy_static::lazy_static;
 use x86_64::{
-    instructions::{segmentation::set_cs, tables::load_tss},
+    instructions::{
+        segmentation::{load_ds, load_es, load_ss, set_cs},
+        tables::load_tss,
+    },
+    registers::model_specific::{GsBase, KernelGsBase},
     structures::{
         gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
         tss::TaskStateSegment,
@@ -9,42 +14,137 @@ use x86_64::{
 };
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+/// Size of every stack set up in the TSS.
+const STACK_SIZE: usize = 4096 * 5;
 
 lazy_static! {
+    /// The TSS of the boot CPU. Its stacks are static since
+    /// it is set up before the heap is available.
     static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            stack_start + STACK_SIZE // stack end
-        };
-        tss
-    };
-    static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (
-            gdt,
-            Selectors {
-                code_selector,
-                tss_selector,
-            },
-        )
+        static mut DOUBLE_FAULT_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        static mut PRIVILEGE_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+        unsafe {
+            new_tss(
+                VirtAddr::from_ptr(&DOUBLE_FAULT_STACK) + STACK_SIZE,
+                VirtAddr::from_ptr(&PRIVILEGE_STACK) + STACK_SIZE,
+            )
+        }
     };
+    static ref GDT: (GlobalDescriptorTable, Selectors) = new_gdt(&TSS);
+}
+
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub tss: SegmentSelector,
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+/// Data private to a CPU, found through the GS base.
+#[repr(C)]
+pub struct PerCpu {
+    /// The index of the CPU, the boot CPU is 0.
+    pub id: u32,
+    /// The stack switched to when entering the kernel from ring 3,
+    /// same as the TSS privilege stack.
+    pub kernel_stack: VirtAddr,
+    /// Scratch space for the user stack pointer while switching stacks.
+    pub user_stack: u64,
 }
 
+static mut BOOT_CPU: PerCpu = PerCpu {
+    id: 0,
+    kernel_stack: VirtAddr::zero(),
+    user_stack: 0,
+};
+
+/// Set up the boot CPU.
 pub fn init() {
-    GDT.0.load();
-    unsafe {
-        set_cs(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
-    }
+    unsafe { load(&GDT, &TSS, &mut BOOT_CPU) }
+}
+
+/// Set up a CPU started after boot, with the given index.
+/// Its tables and stacks are leaked, since CPUs are never stopped.
+pub fn init_ap(id: u32) {
+    let tss: &'static TaskStateSegment = Box::leak(Box::new(new_tss(stack(), stack())));
+    let gdt: &'static _ = Box::leak(Box::new(new_gdt(tss)));
+    let cpu = Box::leak(Box::new(PerCpu {
+        id,
+        kernel_stack: VirtAddr::zero(),
+        user_stack: 0,
+    }));
+    unsafe { load(gdt, tss, cpu) }
+}
+
+/// Returns the selectors, which are the same on every CPU.
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
+}
+
+/// Returns the data of the CPU this runs on.
+pub fn per_cpu() -> &'static PerCpu {
+    unsafe { &*GsBase::read().as_ptr() }
+}
+
+fn new_tss(double_fault_stack: VirtAddr, privilege_stack: VirtAddr) -> TaskStateSegment {
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack;
+    // Used by the CPU when an interrupt arrives while in ring 3
+    tss.privilege_stack_table[0] = privilege_stack;
+    tss
+}
+
+fn new_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
+    let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+    let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+    // `sysret` requires user data to directly precede user code
+    let user_data = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code = gdt.add_entry(Descriptor::user_code_segment());
+    let tss = gdt.add_entry(Descriptor::tss_segment(tss));
+    (
+        gdt,
+        Selectors {
+            kernel_code,
+            kernel_data,
+            user_code,
+            user_data,
+            tss,
+        },
+    )
+}
+
+/// Allocate a stack, returning its end.
+fn stack() -> VirtAddr {
+    let stack = Box::leak(vec![0u8; STACK_SIZE].into_boxed_slice());
+    VirtAddr::from_ptr(stack.as_ptr()) + STACK_SIZE
+}
+
+/// Load the given tables on the current CPU and point the GS base to its data.
+/// The kernel GS base is set as well, so that it survives `swapgs` when entering from ring 3.
+unsafe fn load(
+    gdt: &'static (GlobalDescriptorTable, Selectors),
+    tss: &TaskStateSegment,
+    cpu: &'static mut PerCpu,
+) {
+    let (gdt, selectors) = gdt;
+    gdt.load();
+    set_cs(selectors.kernel_code);
+    load_ss(selectors.kernel_data);
+    load_ds(selectors.kernel_data);
+    load_es(selectors.kernel_data);
+    load_tss(selectors.tss);
+
+    cpu.kernel_stack = tss.privilege_stack_table[0];
+    let address = VirtAddr::from_ptr(cpu);
+    GsBase::write(address);
+    KernelGsBase::write(address);
+}
+
+#[test_case]
+fn per_cpu_data() {
+    let cpu = per_cpu();
+    assert_eq!(cpu.id, 0);
+    assert_eq!(cpu.kernel_stack, TSS.privilege_stack_table[0]);
 }
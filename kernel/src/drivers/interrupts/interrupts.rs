@@ -1,6 +1,8 @@
 use crate::{
-    drivers::{interrupts::gdt, keyboard},
-    hlt_loop, kprintln,
+    backtrace,
+    backtrace::Frames,
+    drivers::{apic, interrupts::gdt, keyboard, serial::SERIAL1, timer},
+    hlt_loop, kprintln, vm,
 };
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
@@ -30,6 +32,9 @@ lazy_static! {
 
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::PrimaryAta.as_usize()].set_handler_fn(ata_interrupt_handler);
+        idt[InterruptIndex::Wakeup.as_usize()].set_handler_fn(wakeup_interrupt_handler);
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(spurious_interrupt_handler);
 
         idt.breakpoint.set_handler_fn(generic_fault::<"BREAKPOINT">);
         idt.divide_error
@@ -70,6 +75,9 @@ lazy_static! {
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    PrimaryAta = PIC_1_OFFSET + 14,
+    /// Sent between CPUs to wake up a halted executor.
+    Wakeup = 0xF0,
 }
 
 impl InterruptIndex {
@@ -90,12 +98,16 @@ extern "x86-interrupt" fn generic_fault<const NAME: &'static str>(
     stack_frame: InterruptStackFrame,
 ) {
     kprintln!("EXCEPTION: {}\n{:#?}", NAME, stack_frame);
+    report_location(&stack_frame);
+    report_backtrace(&stack_frame, Frames::interrupted());
 }
 extern "x86-interrupt" fn generic_fault_code<const NAME: &'static str>(
     stack_frame: InterruptStackFrame,
     code: u64,
 ) {
     kprintln!("EXCEPTION: {}\n{:#?}\nCODE: {}", NAME, stack_frame, code);
+    report_location(&stack_frame);
+    report_backtrace(&stack_frame, Frames::interrupted());
 }
 
 extern "x86-interrupt" fn page_fault_handler(
@@ -108,6 +120,8 @@ extern "x86-interrupt" fn page_fault_handler(
     kprintln!("Accessed Address: {:?}", Cr2::read());
     kprintln!("Error Code: {:?}", error_code);
     kprintln!("{:#?}", stack_frame);
+    report_location(&stack_frame);
+    report_backtrace(&stack_frame, Frames::interrupted());
     hlt_loop();
 }
 
@@ -118,7 +132,9 @@ extern "x86-interrupt" fn double_fault_handler(
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: InterruptStackFrame) {
+    timer::tick();
+    vm::profiler::sample(stack_frame.instruction_pointer.as_u64() as usize);
     end_interrupt(InterruptIndex::Timer)
 }
 
@@ -129,9 +145,44 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     end_interrupt(InterruptIndex::Keyboard)
 }
 
+/// Print the program function the fault happened in, if any.
+fn report_location(stack_frame: &InterruptStackFrame) {
+    let ip = stack_frame.instruction_pointer.as_u64() as usize;
+    if let Some(location) = vm::registry::describe(ip) {
+        kprintln!("In program: {}", location);
+    }
+}
+
+/// Print a backtrace of the interrupted code, starting with the faulting instruction.
+fn report_backtrace(stack_frame: &InterruptStackFrame, frames: Frames) {
+    let ip = stack_frame.instruction_pointer.as_u64() as usize;
+    kprintln!("Backtrace:");
+    backtrace::write(&mut *SERIAL1.lock(), core::iter::once(ip).chain(frames)).ok();
+}
+
+extern "x86-interrupt" fn ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // The driver polls, reading the status only acknowledges the interrupt
+    let mut status = Port::<u8>::new(0x1F7);
+    unsafe { status.read() };
+    end_interrupt(InterruptIndex::PrimaryAta)
+}
+
+extern "x86-interrupt" fn wakeup_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // Nothing to do, the executor checks for new tasks after `hlt` returns
+    end_interrupt(InterruptIndex::Wakeup)
+}
+
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+/// Signal the end of an interrupt to the controller that delivered it,
+/// which is the APIC once it was initialized.
 fn end_interrupt(id: InterruptIndex) {
-    unsafe {
-        PICS.lock().notify_end_of_interrupt(id.as_u8());
+    if apic::available() {
+        apic::eoi();
+    } else {
+        unsafe {
+            PICS.lock().notify_end_of_interrupt(id.as_u8());
+        }
     }
 }
 
@@ -1,40 +1,119 @@
-use crate::{drivers::disk::fat::fat_from_secondary, kprintln, shell::Shell};
+use crate::{
+    clipboard,
+    drivers::{
+        console,
+        console::CONSOLE_COUNT,
+        disk,
+        disk::fat::FatFs,
+        keymap::{register_shortcut, Keymap, Modifiers, Shortcut},
+    },
+    graphics::compositor,
+    shell::Shell,
+    vm, warn,
+};
+use alloc::vec::Vec;
 use conquer_once::spin::OnceCell;
 use core::{
     pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
     task::{Context, Poll},
 };
 use crossbeam_queue::ArrayQueue;
 use futures_util::{task::AtomicWaker, Stream, StreamExt};
-use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
+use pc_keyboard::{DecodedKey, KeyCode};
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
+/// How many lines Shift+PageUp/PageDown scroll the console.
+const SCROLL_LINES: usize = 12;
+/// Scancodes of Ctrl (either one, the right one is prefixed) and C, for killing programs.
+const CTRL: u8 = 0x1D;
+const CTRL_RELEASED: u8 = CTRL | 0x80;
+const C: u8 = 0x2E;
+/// Whether Ctrl is held, tracked separately from the `Keymap` since
+/// the keyboard task does not run while the shell waits for a program.
+static CTRL_HELD: AtomicBool = AtomicBool::new(false);
 
 pub async fn process_keypresses() {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
-    let mut shell = Shell::new(fat_from_secondary());
+    let mut keymap = Keymap::new();
+    // Shells are started when their console is first switched to
+    let mut shells: Vec<Option<Shell>> = (0..CONSOLE_COUNT).map(|_| None).collect();
+    console::init();
+    clipboard::load();
+    shells[0] = Some(Shell::new(0, mount()));
+
+    register_shortcut(
+        Shortcut::new(Modifiers::SHIFT, DecodedKey::RawKey(KeyCode::PageUp)),
+        || {
+            console::with_active(|w| w.scroll_up(SCROLL_LINES));
+        },
+    );
+    register_shortcut(
+        Shortcut::new(Modifiers::SHIFT, DecodedKey::RawKey(KeyCode::PageDown)),
+        || {
+            console::with_active(|w| w.scroll_down(SCROLL_LINES));
+        },
+    );
+    register_shortcut(
+        Shortcut::new(Modifiers::ALT, DecodedKey::Unicode('\t')),
+        compositor::cycle_focus,
+    );
+    let switch_console: [fn(); CONSOLE_COUNT] = [
+        || console::switch_to(0),
+        || console::switch_to(1),
+        || console::switch_to(2),
+        || console::switch_to(3),
+    ];
+    let function_keys = [KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4];
+    for (key, action) in function_keys.iter().zip(switch_console.iter()) {
+        register_shortcut(
+            Shortcut::new(Modifiers::ALT, DecodedKey::RawKey(*key)),
+            *action,
+        );
+    }
 
     while let Some(scancode) = scancodes.next().await {
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                shell.key_pressed(key)
+        let press = keymap.add_scancode(scancode);
+        let active = console::active();
+        let shell = shells[active].get_or_insert_with(|| Shell::new(active, mount()));
+        if let Some(press) = press {
+            // Text typed while a window is focused goes to its program instead
+            if press.is_command() || compositor::focused().is_none() {
+                shell.key_pressed(press);
+            } else if let DecodedKey::Unicode(c) = press.key {
+                compositor::key_pressed(c);
             }
         }
     }
 }
 
+fn mount() -> FatFs {
+    disk::mount().expect("filesystem was unmounted")
+}
+
 /// Called by the keyboard interrupt handler, must not block or allocate.
 pub(crate) fn add_scancode(scancode: u8) {
+    check_kill(scancode);
     if let Ok(queue) = SCANCODE_QUEUE.try_get() {
         if queue.push(scancode).is_ok() {
             WAKER.wake();
         } else {
-            kprintln!("WARNING: scancode queue full; dropping keyboard input");
+            warn!("scancode queue full; dropping keyboard input");
         }
     } else {
-        kprintln!("WARNING: scancode queue uninitialized");
+        warn!("scancode queue uninitialized");
+    }
+}
+
+/// Kill the program the shell is waiting for when Ctrl+C is pressed.
+/// The key press is still queued, but the shell ignores it.
+fn check_kill(scancode: u8) {
+    match scancode {
+        CTRL => CTRL_HELD.store(true, Ordering::Relaxed),
+        CTRL_RELEASED => CTRL_HELD.store(false, Ordering::Relaxed),
+        C if CTRL_HELD.load(Ordering::Relaxed) => vm::jobs::kill_foreground(),
+        _ => (),
     }
 }
 
@@ -0,0 +1,194 @@
+//! The layer between decoding scancodes and whoever consumes key presses.
+//! It tracks which modifiers are held, delivers key presses together with
+//! them, and triggers global shortcuts, which are never seen by the consumer.
+
+use alloc::vec::Vec;
+use pc_keyboard::{
+    layouts, DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard, ScancodeSet1,
+};
+use spin::Mutex;
+
+static SHORTCUTS: Mutex<Vec<(Shortcut, fn())>> = Mutex::new(Vec::new());
+
+/// The modifier keys held down, without distinguishing left and right.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers {
+        shift: false,
+        ctrl: false,
+        alt: false,
+    };
+    pub const SHIFT: Modifiers = Modifiers {
+        shift: true,
+        ..Modifiers::NONE
+    };
+    pub const CTRL: Modifiers = Modifiers {
+        ctrl: true,
+        ..Modifiers::NONE
+    };
+    pub const ALT: Modifiers = Modifiers {
+        alt: true,
+        ..Modifiers::NONE
+    };
+
+    /// Update the state with a key event, returning if it was a modifier key.
+    fn update(&mut self, event: &KeyEvent) -> bool {
+        let held = event.state == KeyState::Down;
+        match event.code {
+            KeyCode::ShiftLeft | KeyCode::ShiftRight => self.shift = held,
+            KeyCode::ControlLeft | KeyCode::ControlRight => self.ctrl = held,
+            KeyCode::AltLeft | KeyCode::AltRight => self.alt = held,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A decoded key together with the modifiers held while it was pressed.
+/// Letters are always decoded as themselves, so Ctrl+C is `'c'` with `ctrl` set.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyPress {
+    pub key: DecodedKey,
+    pub modifiers: Modifiers,
+}
+
+impl KeyPress {
+    /// Whether Ctrl or Alt are held, meaning the key is not meant as text input.
+    pub fn is_command(&self) -> bool {
+        self.modifiers.ctrl || self.modifiers.alt
+    }
+}
+
+/// A key combination triggering an action when pressed.
+/// The modifiers must match exactly, Ctrl+C does not trigger on Ctrl+Alt+C.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Shortcut {
+    pub modifiers: Modifiers,
+    pub key: DecodedKey,
+}
+
+impl Shortcut {
+    pub const fn new(modifiers: Modifiers, key: DecodedKey) -> Self {
+        Self { modifiers, key }
+    }
+}
+
+/// Register a global shortcut, replacing any previous action for it.
+/// Actions run in the keyboard task, not in the interrupt handler.
+pub fn register_shortcut(shortcut: Shortcut, action: fn()) {
+    let mut shortcuts = SHORTCUTS.lock();
+    shortcuts.retain(|(existing, _)| *existing != shortcut);
+    shortcuts.push((shortcut, action));
+}
+
+pub fn unregister_shortcut(shortcut: Shortcut) {
+    SHORTCUTS
+        .lock()
+        .retain(|(existing, _)| *existing != shortcut);
+}
+
+fn shortcut_action(press: &KeyPress) -> Option<fn()> {
+    let shortcut = Shortcut::new(press.modifiers, press.key);
+    SHORTCUTS
+        .lock()
+        .iter()
+        .find(|(existing, _)| *existing == shortcut)
+        .map(|(_, action)| *action)
+}
+
+/// Decodes scancodes into key presses.
+pub struct Keymap {
+    keyboard: Keyboard<layouts::Us104Key, ScancodeSet1>,
+    modifiers: Modifiers,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Self {
+            keyboard: Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore),
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    /// Feed a scancode, returning the key press it completes.
+    /// Returns `None` for incomplete scancodes, releases, modifiers on their own
+    /// and presses that triggered a shortcut, whose action is run before returning.
+    pub fn add_scancode(&mut self, scancode: u8) -> Option<KeyPress> {
+        let event = self.keyboard.add_byte(scancode).ok()??;
+        let is_modifier = self.modifiers.update(&event);
+        let key = self.keyboard.process_keyevent(event)?;
+        if is_modifier {
+            return None;
+        }
+
+        let press = KeyPress {
+            key,
+            modifiers: self.modifiers,
+        };
+        match shortcut_action(&press) {
+            Some(action) => {
+                action();
+                None
+            }
+            None => Some(press),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{register_shortcut, unregister_shortcut, KeyPress, Keymap, Modifiers, Shortcut};
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use pc_keyboard::DecodedKey;
+
+    const LEFT_CTRL: u8 = 0x1D;
+    const LEFT_SHIFT: u8 = 0x2A;
+    const C: u8 = 0x2E;
+    const RELEASED: u8 = 0x80;
+
+    #[test_case]
+    fn modifiers() {
+        let mut keymap = Keymap::new();
+        assert_eq!(keymap.add_scancode(LEFT_SHIFT), None);
+        assert_eq!(
+            keymap.add_scancode(C),
+            Some(KeyPress {
+                key: DecodedKey::Unicode('C'),
+                modifiers: Modifiers::SHIFT
+            })
+        );
+        assert_eq!(keymap.add_scancode(C | RELEASED), None);
+        assert_eq!(keymap.add_scancode(LEFT_SHIFT | RELEASED), None);
+        assert_eq!(keymap.modifiers(), Modifiers::NONE);
+
+        keymap.add_scancode(LEFT_CTRL);
+        let press = keymap.add_scancode(C).unwrap();
+        assert_eq!(press.key, DecodedKey::Unicode('c'));
+        assert!(press.is_command());
+    }
+
+    #[test_case]
+    fn shortcuts() {
+        static TRIGGERED: AtomicBool = AtomicBool::new(false);
+        let shortcut = Shortcut::new(Modifiers::CTRL, DecodedKey::Unicode('c'));
+        register_shortcut(shortcut, || TRIGGERED.store(true, Ordering::SeqCst));
+
+        let mut keymap = Keymap::new();
+        assert!(keymap.add_scancode(C).is_some());
+        assert!(!TRIGGERED.load(Ordering::SeqCst));
+        keymap.add_scancode(LEFT_CTRL);
+        assert_eq!(keymap.add_scancode(C), None);
+        assert!(TRIGGERED.load(Ordering::SeqCst));
+        unregister_shortcut(shortcut);
+    }
+}
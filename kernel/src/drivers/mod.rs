@@ -1,5 +1,11 @@
+pub mod acpi;
+pub mod apic;
+pub mod console;
 pub mod disk;
 pub mod interrupts;
 pub mod keyboard;
+pub mod keymap;
 pub mod serial;
+pub mod speaker;
+pub mod timer;
 pub mod vga_buffer;
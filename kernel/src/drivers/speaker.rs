@@ -0,0 +1,55 @@
+//! The PC speaker, which plays a square wave generated by channel 2 of the PIT.
+
+use crate::drivers::{interrupts::gdt, timer, timer::PIT_FREQUENCY};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// Port whose lowest two bits connect channel 2 to the speaker.
+const SPEAKER_CONTROL: u16 = 0x61;
+
+/// Frequencies outside of this range are clamped to it.
+pub const MIN_FREQUENCY: u32 = 20;
+pub const MAX_FREQUENCY: u32 = 20_000;
+
+/// Held while a beep is playing, so that beeps from different CPUs play after each other.
+static SPEAKER: Mutex<()> = Mutex::new(());
+
+/// Play a tone of the given frequency in Hz for the given amount of milliseconds.
+/// Waits until the tone finished playing.
+pub fn beep(frequency: u32, ms: u64) {
+    let _speaker = SPEAKER.lock();
+    play(frequency);
+    // Only the boot CPU receives the timer interrupt, others measure time with the TSC
+    if gdt::per_cpu().id == 0 {
+        timer::sleep_ms(ms);
+    } else {
+        timer::spin_ms(ms);
+    }
+    silence();
+}
+
+/// Start playing a tone, until `silence` is called.
+fn play(frequency: u32) {
+    let divisor = PIT_FREQUENCY / frequency.clamp(MIN_FREQUENCY, MAX_FREQUENCY);
+    unsafe {
+        // Channel 2, lobyte/hibyte, square wave generator
+        Port::<u8>::new(PIT_COMMAND).write(0xB6);
+        let mut data = Port::<u8>::new(PIT_CHANNEL_2);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+
+        let mut control = Port::<u8>::new(SPEAKER_CONTROL);
+        let value = control.read();
+        control.write(value | 0b11);
+    }
+}
+
+fn silence() {
+    unsafe {
+        let mut control = Port::<u8>::new(SPEAKER_CONTROL);
+        let value = control.read();
+        control.write(value & !0b11);
+    }
+}
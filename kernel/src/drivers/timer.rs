@@ -0,0 +1,100 @@
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use x86_64::instructions::{hlt, port::Port};
+
+/// Frequency of the PIT's input clock.
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+/// Frequency of the timer interrupt after `init`, which the
+/// APIC timer keeps once it is calibrated against the PIT.
+pub const TICKS_PER_SECOND: u32 = 1000;
+/// Amount of ticks to measure the TSC over when calibrating.
+const CALIBRATION_TICKS: u64 = 10;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static TSC_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Make the PIT fire the timer interrupt at `TICKS_PER_SECOND`.
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TICKS_PER_SECOND) as u16;
+    unsafe {
+        // Channel 0, lobyte/hibyte, rate generator
+        Port::<u8>::new(0x43).write(0x34);
+        let mut data = Port::<u8>::new(0x40);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+    }
+}
+
+/// Measure the frequency of the TSC against the timer interrupt,
+/// which is required for `nanos`. Interrupts need to be enabled.
+pub fn calibrate() {
+    wait_for_tick();
+    let start = tsc();
+    for _ in 0..CALIBRATION_TICKS {
+        wait_for_tick();
+    }
+    let per_tick = (tsc() - start) / CALIBRATION_TICKS;
+    TSC_PER_MS.store(per_tick * 1000 / TICKS_PER_SECOND as u64, Ordering::Relaxed);
+}
+
+/// Called by the timer interrupt handler.
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The amount of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Nanoseconds since boot, with the precision of the TSC.
+/// Returns 0 before `calibrate` was called.
+pub fn nanos() -> u64 {
+    let per_ms = TSC_PER_MS.load(Ordering::Relaxed);
+    if per_ms == 0 {
+        return 0;
+    }
+    (tsc() as u128 * 1_000_000 / per_ms as u128) as u64
+}
+
+/// Wait for at least the given amount of milliseconds.
+/// Only works on the boot CPU, which receives the timer interrupt.
+pub fn sleep_ms(ms: u64) {
+    sleep_ticks(ms * TICKS_PER_SECOND as u64 / 1000 + 1)
+}
+
+/// Wait for at least the given amount of milliseconds by polling the TSC,
+/// which works on every CPU but keeps it busy. Returns immediately
+/// before `calibrate` was called.
+pub fn spin_ms(ms: u64) {
+    if TSC_PER_MS.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let end = nanos() + ms * 1_000_000;
+    while nanos() < end {
+        spin_loop();
+    }
+}
+
+/// Wait until the given amount of timer interrupts happened.
+/// Only works on the boot CPU, which receives the timer interrupt.
+pub fn sleep_ticks(amount: u64) {
+    let end = ticks() + amount;
+    while ticks() < end {
+        hlt();
+    }
+}
+
+fn wait_for_tick() {
+    let start = ticks();
+    while ticks() == start {
+        hlt();
+    }
+}
+
+/// Reads the time stamp counter of the current CPU.
+pub fn tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
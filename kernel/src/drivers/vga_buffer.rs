@@ -1,8 +1,8 @@
-use core::{fmt, fmt::Write};
-use lazy_static::lazy_static;
-use spin::Mutex;
+use crate::drivers::console;
+use alloc::{boxed::Box, collections::VecDeque};
+use core::{cmp::min, fmt};
 use volatile::Volatile;
-use x86_64::instructions::{interrupts, port::Port};
+use x86_64::instructions::port::Port;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -26,6 +26,76 @@ pub enum Color {
     White = 15,
 }
 
+impl Color {
+    pub const ALL: [Color; 16] = [
+        Color::Black,
+        Color::Blue,
+        Color::Green,
+        Color::Cyan,
+        Color::Red,
+        Color::Magenta,
+        Color::Brown,
+        Color::LightGray,
+        Color::DarkGray,
+        Color::LightBlue,
+        Color::LightGreen,
+        Color::LightCyan,
+        Color::LightRed,
+        Color::Pink,
+        Color::Yellow,
+        Color::White,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Color> {
+        Color::ALL
+            .iter()
+            .copied()
+            .find(|color| color.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Color::Black => "black",
+            Color::Blue => "blue",
+            Color::Green => "green",
+            Color::Cyan => "cyan",
+            Color::Red => "red",
+            Color::Magenta => "magenta",
+            Color::Brown => "brown",
+            Color::LightGray => "light_gray",
+            Color::DarkGray => "dark_gray",
+            Color::LightBlue => "light_blue",
+            Color::LightGreen => "light_green",
+            Color::LightCyan => "light_cyan",
+            Color::LightRed => "light_red",
+            Color::Pink => "pink",
+            Color::Yellow => "yellow",
+            Color::White => "white",
+        }
+    }
+}
+
+/// The colors a console uses for its text, the shell prompt,
+/// the commands the shell echoes before running them and directories listed by `ls`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorScheme {
+    pub text: Color,
+    pub prompt: Color,
+    pub command: Color,
+    pub directory: Color,
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self {
+            text: Color::Magenta,
+            prompt: Color::Blue,
+            command: Color::Yellow,
+            directory: Color::LightBlue,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 struct ColorCode(u8);
@@ -43,42 +113,110 @@ struct ScreenChar {
     color_code: ColorCode,
 }
 
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: ColorCode(0),
+};
+
 const BUFFER_HEIGHT: usize = 25;
-const TEXT_HEIGHT: usize = BUFFER_HEIGHT - 1;
-const SHELL_ROW: usize = BUFFER_HEIGHT - 1;
-const BUFFER_WIDTH: usize = 80;
+/// Rows available for text, above the shell line.
+pub const TEXT_HEIGHT: usize = BUFFER_HEIGHT - 1;
+pub const SHELL_ROW: usize = BUFFER_HEIGHT - 1;
+pub const BUFFER_WIDTH: usize = 80;
+/// How many lines that scrolled off the screen are kept.
+const SCROLLBACK_LINES: usize = 500;
+
+type Line = [ScreenChar; BUFFER_WIDTH];
 
 #[repr(transparent)]
 struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+/// The VGA text buffer. Only the visible writer may use it.
+fn hardware() -> &'static mut Buffer {
+    unsafe { &mut *(0xb8000 as *mut Buffer) }
+}
+
 struct Cursor {
     port1: Port<u8>,
     port2: Port<u8>,
 }
 
+/// The screen of a console. Text is kept in memory and
+/// additionally written to the VGA buffer while the writer is visible.
 pub struct Writer {
     row_position: usize,
     column_position: usize,
     color_code: ColorCode,
-    buffer: &'static mut Buffer,
+    scheme: ColorScheme,
+    screen: Box<[Line; BUFFER_HEIGHT]>,
+    visible: bool,
     cursor: Cursor,
+    cursor_x: usize,
+    /// Lines that scrolled off the top of the screen, oldest first.
+    scrollback: VecDeque<Line>,
+    /// How many lines the view is scrolled back.
+    scroll: usize,
+    /// The text on screen while scrolled back, restored when scrolling to the bottom.
+    saved_screen: Option<Box<[Line; TEXT_HEIGHT]>>,
+    /// The screen and position to return to after leaving the alternate screen.
+    alternate: Option<(Box<[Line; BUFFER_HEIGHT]>, usize, usize)>,
 }
 
 impl Writer {
-    pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // not part of printable ASCII range
-                _ => self.write_byte(0xfe),
+    pub fn new(visible: bool) -> Self {
+        let mut writer = Writer {
+            row_position: TEXT_HEIGHT - 1,
+            column_position: 0,
+            color_code: ColorCode::new(ColorScheme::default().text, Color::Black),
+            scheme: ColorScheme::default(),
+            screen: Box::new([[BLANK; BUFFER_WIDTH]; BUFFER_HEIGHT]),
+            visible: false,
+            cursor: Cursor {
+                port1: Port::new(0x3D4),
+                port2: Port::new(0x3D5),
+            },
+            cursor_x: 0,
+            scrollback: VecDeque::new(),
+            scroll: 0,
+            saved_screen: None,
+            alternate: None,
+        };
+        if visible {
+            writer.show();
+        }
+        writer
+    }
+
+    /// Make this writer the one shown on the VGA buffer.
+    /// The previously visible writer must be hidden first.
+    pub fn show(&mut self) {
+        self.visible = true;
+        let buffer = hardware();
+        for (row, line) in self.screen.iter().enumerate() {
+            for (col, character) in line.iter().enumerate() {
+                buffer.chars[row][col].write(*character);
             }
         }
+        self.set_cursor_x(self.cursor_x);
+    }
+
+    pub fn hide(&mut self) {
+        self.visible = false;
+    }
+
+    pub fn write_string(&mut self, s: &str) {
+        for c in s.chars() {
+            self.write_byte(code_page_437(c));
+        }
     }
 
     pub fn write_byte(&mut self, byte: u8) {
+        // New output always shows up at the bottom, except on the shell line
+        if self.row_position != SHELL_ROW {
+            self.scroll_to_bottom();
+        }
         match byte {
             b'\n' => self.new_line(),
             byte => {
@@ -86,16 +224,21 @@ impl Writer {
                     self.new_line();
                 }
 
-                self.buffer.chars[self.row_position][self.column_position].write(ScreenChar {
+                let character = ScreenChar {
                     ascii_character: byte,
                     color_code: self.color_code,
-                });
+                };
+                self.write_char(self.row_position, self.column_position, character);
                 self.column_position += 1;
             }
         }
     }
 
     pub fn set_cursor_x(&mut self, x: usize) {
+        self.cursor_x = x;
+        if !self.visible {
+            return;
+        }
         let position = TEXT_HEIGHT * BUFFER_WIDTH + x + 2;
         unsafe {
             self.cursor.port1.write(0x0F);
@@ -106,10 +249,11 @@ impl Writer {
     }
 
     pub fn init_shell(&mut self) {
-        self.buffer.chars[SHELL_ROW][0].write(ScreenChar {
+        let prompt = ScreenChar {
             ascii_character: b'>',
-            color_code: ColorCode::new(Color::Blue, Color::Black),
-        });
+            color_code: ColorCode::new(self.scheme.prompt, Color::Black),
+        };
+        self.write_char(SHELL_ROW, 0, prompt);
         self.set_cursor_x(0);
     }
 
@@ -127,14 +271,135 @@ impl Writer {
     }
 
     pub fn reset_color(&mut self) {
-        self.set_color(Color::Magenta);
+        self.set_color(self.scheme.text);
+    }
+
+    pub fn scheme(&self) -> ColorScheme {
+        self.scheme
+    }
+
+    /// Use the given colors for all text written from now on.
+    pub fn set_scheme(&mut self, scheme: ColorScheme) {
+        self.scheme = scheme;
+        self.reset_color();
+    }
+
+    /// Scroll the view back by the given amount of lines, as far as the scrollback goes.
+    pub fn scroll_up(&mut self, lines: usize) {
+        let scroll = min(self.scroll + lines, self.scrollback.len());
+        self.set_scroll(scroll);
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.set_scroll(self.scroll.saturating_sub(lines));
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.set_scroll(0);
+    }
+
+    fn set_scroll(&mut self, scroll: usize) {
+        if scroll == self.scroll {
+            return;
+        }
+        if self.saved_screen.is_none() {
+            let mut screen = Box::new([[BLANK; BUFFER_WIDTH]; TEXT_HEIGHT]);
+            for (row, line) in screen.iter_mut().enumerate() {
+                self.read_row(row, line);
+            }
+            self.saved_screen = Some(screen);
+        }
+        self.scroll = scroll;
+
+        // The view consists of the last `scroll` lines of the scrollback,
+        // followed by the top of the saved screen
+        let screen = self.saved_screen.as_ref().unwrap();
+        let history = self.scrollback.len() - scroll;
+        for row in 0..TEXT_HEIGHT {
+            let line = if row < scroll {
+                self.scrollback[history + row]
+            } else {
+                screen[row - scroll]
+            };
+            for (col, character) in line.iter().enumerate() {
+                self.write_char(row, col, *character);
+            }
+        }
+        if scroll == 0 {
+            self.saved_screen = None;
+        }
+    }
+
+    fn read_row(&self, row: usize, line: &mut Line) {
+        *line = self.screen[row];
+    }
+
+    fn write_char(&mut self, row: usize, col: usize, character: ScreenChar) {
+        self.screen[row][col] = character;
+        if self.visible {
+            hardware().chars[row][col].write(character);
+        }
+    }
+
+    /// Replace the text of the given row, cutting it off at the end of the row.
+    /// Does not move the position new text is written at.
+    pub fn write_row(&mut self, row: usize, text: &str) {
+        self.clear_row(row, 0);
+        for (col, c) in text.chars().take(BUFFER_WIDTH).enumerate() {
+            let character = ScreenChar {
+                ascii_character: code_page_437(c),
+                color_code: self.color_code,
+            };
+            self.write_char(row, col, character);
+        }
+    }
+
+    /// Save the screen including the shell line and clear it,
+    /// for programs drawing on the whole screen with `write_row`.
+    pub fn enter_alternate_screen(&mut self) {
+        self.scroll_to_bottom();
+        let saved = (self.screen.clone(), self.row_position, self.column_position);
+        self.alternate = Some(saved);
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row, 0);
+        }
+    }
+
+    /// Restore the screen saved by `enter_alternate_screen`.
+    pub fn leave_alternate_screen(&mut self) {
+        if let Some((screen, row, column)) = self.alternate.take() {
+            for (row, line) in screen.iter().enumerate() {
+                for (col, character) in line.iter().enumerate() {
+                    self.write_char(row, col, *character);
+                }
+            }
+            self.row_position = row;
+            self.column_position = column;
+        }
+    }
+
+    /// Clear all text above the shell line.
+    pub fn clear(&mut self) {
+        self.scroll_to_bottom();
+        for row in 0..TEXT_HEIGHT {
+            self.clear_row(row, 0);
+        }
+        self.row_position = TEXT_HEIGHT - 1;
+        self.column_position = 0;
     }
 
     fn new_line(&mut self) {
+        let mut top = [BLANK; BUFFER_WIDTH];
+        self.read_row(0, &mut top);
+        if self.scrollback.len() == SCROLLBACK_LINES {
+            self.scrollback.pop_front();
+        }
+        self.scrollback.push_back(top);
+
         for row in 1..TEXT_HEIGHT {
             for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
+                let character = self.screen[row][col];
+                self.write_char(row - 1, col, character);
             }
         }
         self.clear_row(TEXT_HEIGHT - 1, 0);
@@ -147,7 +412,7 @@ impl Writer {
             color_code: self.color_code,
         };
         for col in start..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+            self.write_char(row, col, blank);
         }
     }
 }
@@ -159,17 +424,22 @@ impl fmt::Write for Writer {
     }
 }
 
-lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        row_position: TEXT_HEIGHT - 1,
-        column_position: 0,
-        color_code: ColorCode::new(Color::Magenta, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-        cursor: Cursor {
-            port1: Port::new(0x3D4),
-            port2: Port::new(0x3D5)
-        }
-    });
+/// The characters of code page 437 above ASCII, which VGA text mode uses.
+const CODE_PAGE_437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+    ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+    αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{A0}";
+
+/// Convert a character to the byte displaying it in VGA text mode,
+/// falling back to a square for characters that cannot be displayed.
+fn code_page_437(c: char) -> u8 {
+    match c {
+        // printable ASCII byte or newline
+        ' '..='~' | '\n' => c as u8,
+        _ => CODE_PAGE_437_HIGH
+            .chars()
+            .position(|other| other == c)
+            .map_or(0xfe, |index| 0x80 + index as u8),
+    }
 }
 
 #[macro_export]
@@ -186,16 +456,36 @@ macro_rules! println {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     super::serial::_print(args);
-}
-
-pub fn vga_buffer<T: FnMut(&mut Writer)>(mut f: T) {
-    f(&mut WRITER.lock())
+    console::_print(args);
 }
 
 #[cfg(test)]
 mod tests {
-    use super::WRITER;
-    use crate::drivers::vga_buffer::TEXT_HEIGHT;
+    use super::{Writer, TEXT_HEIGHT};
+
+    #[test_case]
+    fn test_code_page_437() {
+        assert_eq!(super::code_page_437('a'), b'a');
+        assert_eq!(super::code_page_437('é'), 0x82);
+        assert_eq!(super::code_page_437('─'), 0xC4);
+        assert_eq!(super::code_page_437('字'), 0xfe);
+    }
+
+    #[test_case]
+    fn test_scrollback() {
+        use core::fmt::Write;
+
+        let mut writer = Writer::new(false);
+        writeln!(writer, "scrolled away").expect("writeln failed");
+        // Until the line is the first one pushed off the screen
+        for _ in 0..TEXT_HEIGHT - 1 {
+            writeln!(writer).expect("writeln failed");
+        }
+        writer.scroll_up(1);
+        assert_eq!(writer.screen[0][0].ascii_character, b's');
+        writer.scroll_down(1);
+        assert_ne!(writer.screen[0][0].ascii_character, b's');
+    }
 
     #[test_case]
     fn test_println_simple() {
@@ -212,16 +502,13 @@ mod tests {
     #[test_case]
     fn test_println_output() {
         use core::fmt::Write;
-        use x86_64::instructions::interrupts;
 
         let s = "Some test string that fits on a single line";
-        interrupts::without_interrupts(|| {
-            let mut writer = WRITER.lock();
-            writeln!(writer, "\n{}", s).expect("writeln failed");
-            for (i, c) in s.chars().enumerate() {
-                let screen_char = writer.buffer.chars[TEXT_HEIGHT - 2][i].read();
-                assert_eq!(char::from(screen_char.ascii_character), c);
-            }
-        });
+        let mut writer = Writer::new(false);
+        writeln!(writer, "\n{}", s).expect("writeln failed");
+        for (i, c) in s.chars().enumerate() {
+            let screen_char = writer.screen[TEXT_HEIGHT - 2][i];
+            assert_eq!(char::from(screen_char.ascii_character), c);
+        }
     }
 }
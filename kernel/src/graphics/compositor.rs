@@ -0,0 +1,428 @@
+//! A minimal compositor for programs drawing in windows.
+//! Every window has an off-screen surface its program draws to, which the
+//! compositor shows on the screen once the program presents it.
+//! The screen is composed one row at a time in a buffer before copying it
+//! to the screen, so overlapping windows do not flicker when redrawn
+//! without needing a buffer of the whole screen.
+//!
+//! Windows are kept in z-order. Keyboard input goes to the focused window,
+//! which is also drawn on top; Alt+Tab cycles focus between the windows
+//! and the shell.
+
+use crate::graphics::{self, font::GLYPH_SIZE, text::Font, Canvas, Color};
+use alloc::{collections::VecDeque, string::String, vec, vec::Vec};
+use spin::Mutex;
+
+/// Height of the title bar above the surface of every window.
+const TITLE_HEIGHT: usize = GLYPH_SIZE + 8;
+/// Width of the border around the sides and bottom of every window.
+const BORDER: usize = 2;
+/// Surfaces larger than this in either direction are refused.
+pub const MAX_SURFACE_SIZE: usize = 1024;
+/// Keys not yet read by a window's program are dropped beyond this.
+const INPUT_CAPACITY: usize = 64;
+/// Offset between windows opened one after another, so they do not fully overlap.
+const CASCADE: usize = 32;
+
+const BACKGROUND: Color = Color::hex(0x111111);
+const FRAME: Color = Color::hex(0x444444);
+const FRAME_FOCUSED: Color = Color::hex(0x512DA8);
+const TITLE: Color = Color::hex(0xFFFFFF);
+
+static COMPOSITOR: Mutex<Compositor> = Mutex::new(Compositor {
+    windows: Vec::new(),
+    focus: None,
+    damage: None,
+    next_id: 0,
+});
+
+pub type WindowId = usize;
+
+/// An off-screen image programs draw to.
+pub struct Surface {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Surface {
+    /// Create a surface filled with the given color,
+    /// or `None` if there is not enough memory for it.
+    pub fn new(width: usize, height: usize, color: Color) -> Option<Surface> {
+        let mut pixels = Vec::new();
+        pixels.try_reserve_exact(width * height).ok()?;
+        pixels.resize(width * height, color);
+        Some(Surface {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Canvas for Surface {
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    /// The smallest rectangle containing both rectangles.
+    fn union(self, other: Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+pub struct Window {
+    pub id: WindowId,
+    pub title: String,
+    /// Position of the top left corner of the window's frame.
+    pub x: usize,
+    pub y: usize,
+    surface: Surface,
+    /// Keys typed while the window was focused, not yet read by its program.
+    input: VecDeque<char>,
+}
+
+impl Window {
+    /// The area the window covers on the screen, including its frame.
+    pub fn frame(&self) -> Rect {
+        Rect {
+            x: self.x,
+            y: self.y,
+            width: self.surface.width + 2 * BORDER,
+            height: TITLE_HEIGHT + self.surface.height + BORDER,
+        }
+    }
+
+    /// Returns the color of the given pixel relative to the frame, which is inside it.
+    fn pixel(&self, x: usize, y: usize, focused: bool, font: &mut Font) -> Color {
+        let frame = if focused { FRAME_FOCUSED } else { FRAME };
+        if y < TITLE_HEIGHT {
+            let (left, top) = (BORDER + 2, (TITLE_HEIGHT - GLYPH_SIZE) / 2);
+            let row = y.wrapping_sub(top);
+            let character = x
+                .checked_sub(left)
+                .and_then(|x| self.title.chars().nth(x / GLYPH_SIZE));
+            return match character {
+                Some(c) if row < GLYPH_SIZE => {
+                    let bits = font.glyph(c)[row];
+                    let column = (x - left) % GLYPH_SIZE;
+                    if bits & (1 << column) != 0 {
+                        TITLE
+                    } else {
+                        frame
+                    }
+                }
+                _ => frame,
+            };
+        }
+
+        let (x, y) = (x.wrapping_sub(BORDER), y - TITLE_HEIGHT);
+        if x < self.surface.width && y < self.surface.height {
+            self.surface.pixel(x, y)
+        } else {
+            frame
+        }
+    }
+}
+
+struct Compositor {
+    /// All windows from bottom to top.
+    windows: Vec<Window>,
+    focus: Option<WindowId>,
+    /// The area of the screen that needs to be redrawn.
+    damage: Option<Rect>,
+    next_id: WindowId,
+}
+
+impl Compositor {
+    fn find(&mut self, id: WindowId) -> Option<&mut Window> {
+        self.windows.iter_mut().find(|window| window.id == id)
+    }
+
+    fn damage(&mut self, rect: Rect) {
+        self.damage = Some(match self.damage {
+            Some(damage) => damage.union(rect),
+            None => rect,
+        });
+    }
+
+    /// Focus the given window, drawing it on top of all others.
+    fn focus_window(&mut self, id: Option<WindowId>) {
+        self.focus = id;
+        if let Some(index) = self.windows.iter().position(|w| Some(w.id) == id) {
+            let window = self.windows.remove(index);
+            self.damage(window.frame());
+            self.windows.push(window);
+        }
+    }
+
+    /// Redraw the damaged area of the screen.
+    fn composite(&mut self) {
+        let (width, height) = match graphics::resolution() {
+            Some(resolution) => resolution,
+            None => return,
+        };
+        let damage = match self.damage.take() {
+            Some(damage) if damage.x < width && damage.y < height => damage,
+            _ => return,
+        };
+        let right = (damage.x + damage.width).min(width);
+        let bottom = (damage.y + damage.height).min(height);
+
+        let mut font = Font::builtin();
+        let mut row = vec![BACKGROUND; right - damage.x];
+        for y in damage.y..bottom {
+            row.fill(BACKGROUND);
+            for window in &self.windows {
+                let frame = window.frame();
+                if y < frame.y || y >= frame.y + frame.height {
+                    continue;
+                }
+                let focused = self.focus == Some(window.id);
+                let start = frame.x.max(damage.x);
+                let end = (frame.x + frame.width).min(right);
+                for x in start..end {
+                    row[x - damage.x] = window.pixel(x - frame.x, y - frame.y, focused, &mut font);
+                }
+            }
+            graphics::draw_row(damage.x, y, &row);
+        }
+    }
+}
+
+/// Open a window with a surface of the given size, placed above and focusing it.
+/// Returns `None` if the size is too large or there is not enough memory for it.
+pub fn open(title: &str, width: usize, height: usize) -> Option<WindowId> {
+    if width > MAX_SURFACE_SIZE || height > MAX_SURFACE_SIZE {
+        return None;
+    }
+    let surface = Surface::new(width, height, BACKGROUND)?;
+    let mut compositor = COMPOSITOR.lock();
+    let id = compositor.next_id;
+    compositor.next_id += 1;
+    let offset = CASCADE * (1 + compositor.windows.len() % 8);
+    compositor.windows.push(Window {
+        id,
+        title: title.into(),
+        x: offset,
+        y: offset,
+        surface,
+        input: VecDeque::new(),
+    });
+    compositor.focus_window(Some(id));
+    compositor.composite();
+    Some(id)
+}
+
+/// Close a window, returning whether it existed.
+pub fn close(id: WindowId) -> bool {
+    let mut compositor = COMPOSITOR.lock();
+    let index = match compositor.windows.iter().position(|w| w.id == id) {
+        Some(index) => index,
+        None => return false,
+    };
+    let window = compositor.windows.remove(index);
+    compositor.damage(window.frame());
+    if compositor.focus == Some(id) {
+        let below = compositor.windows.last().map(|w| w.id);
+        compositor.focus_window(below);
+    }
+    compositor.composite();
+    true
+}
+
+/// Run `f` with the surface of the given window, if it exists.
+/// Changes are shown once the window is presented.
+pub fn with_surface<T>(id: WindowId, f: impl FnOnce(&mut Surface) -> T) -> Option<T> {
+    COMPOSITOR
+        .lock()
+        .find(id)
+        .map(|window| f(&mut window.surface))
+}
+
+/// Show the current contents of the window's surface on the screen.
+pub fn present(id: WindowId) {
+    let mut compositor = COMPOSITOR.lock();
+    if let Some(frame) = compositor.find(id).map(|window| window.frame()) {
+        compositor.damage(frame);
+        compositor.composite();
+    }
+}
+
+/// Move a window's top left corner to the given position, returning whether it exists.
+pub fn move_to(id: WindowId, x: usize, y: usize) -> bool {
+    let mut compositor = COMPOSITOR.lock();
+    let old = match compositor.find(id) {
+        Some(window) => {
+            let old = window.frame();
+            window.x = x;
+            window.y = y;
+            old
+        }
+        None => return false,
+    };
+    let new = compositor.find(id).unwrap().frame();
+    compositor.damage(old);
+    compositor.damage(new);
+    compositor.composite();
+    true
+}
+
+/// Focus a window, or the shell if `None`. Returns whether the window exists.
+pub fn focus(id: Option<WindowId>) -> bool {
+    let mut compositor = COMPOSITOR.lock();
+    if id.map_or(false, |id| compositor.find(id).is_none()) {
+        return false;
+    }
+    let previous = compositor
+        .focus
+        .and_then(|id| compositor.find(id).map(|w| w.frame()));
+    if let Some(previous) = previous {
+        compositor.damage(previous);
+    }
+    compositor.focus_window(id);
+    compositor.composite();
+    true
+}
+
+/// Move focus to the next window from the top, and back to the shell after the last one.
+pub fn cycle_focus() {
+    let next = {
+        let compositor = COMPOSITOR.lock();
+        let mut ids = compositor.windows.iter().rev().map(|w| w.id);
+        // The focused window is on top, so the next one is right below it
+        if compositor.focus.is_some() {
+            ids.next();
+        }
+        ids.next()
+    };
+    focus(next);
+}
+
+/// Returns the focused window, or `None` if the shell is focused.
+pub fn focused() -> Option<WindowId> {
+    COMPOSITOR.lock().focus
+}
+
+/// Send a typed character to the focused window, if there is one.
+pub fn key_pressed(c: char) {
+    let mut compositor = COMPOSITOR.lock();
+    let window = compositor.focus.and_then(|id| compositor.find(id));
+    if let Some(window) = window {
+        if window.input.len() < INPUT_CAPACITY {
+            window.input.push_back(c);
+        }
+    }
+}
+
+/// Take the oldest key typed into the given window that was not read yet.
+pub fn next_key(id: WindowId) -> Option<char> {
+    COMPOSITOR.lock().find(id)?.input.pop_front()
+}
+
+/// Returns the id, title and frame of every window, from bottom to top.
+pub fn windows() -> Vec<(WindowId, String, Rect)> {
+    let compositor = COMPOSITOR.lock();
+    compositor
+        .windows
+        .iter()
+        .map(|window| (window.id, window.title.clone(), window.frame()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rect, Surface, Window, BORDER, TITLE, TITLE_HEIGHT};
+    use crate::graphics::{text::Font, Canvas, Color};
+    use alloc::collections::VecDeque;
+
+    fn window(title: &str) -> Window {
+        let mut surface = Surface::new(4, 3, Color::hex(0)).unwrap();
+        surface.fill_rect(1, 1, 10, 10, Color::hex(0xFF0000));
+        Window {
+            id: 0,
+            title: title.into(),
+            x: 10,
+            y: 20,
+            surface,
+            input: VecDeque::new(),
+        }
+    }
+
+    #[test_case]
+    fn frame() {
+        let window = window("test");
+        assert_eq!(
+            window.frame(),
+            Rect {
+                x: 10,
+                y: 20,
+                width: 4 + 2 * BORDER,
+                height: TITLE_HEIGHT + 3 + BORDER
+            }
+        );
+        let union = window.frame().union(Rect {
+            x: 0,
+            y: 30,
+            width: 5,
+            height: 100,
+        });
+        assert_eq!(
+            union,
+            Rect {
+                x: 0,
+                y: 20,
+                width: 18,
+                height: 110
+            }
+        );
+    }
+
+    #[test_case]
+    fn pixels() {
+        let window = window("|");
+        let mut font = Font::builtin();
+        let pixel = |x, y, font: &mut Font| window.pixel(x, y, false, font);
+
+        // The surface is clipped by the fill and surrounded by the frame
+        assert_eq!(pixel(BORDER, TITLE_HEIGHT, &mut font), Color::hex(0));
+        assert_eq!(
+            pixel(BORDER + 3, TITLE_HEIGHT + 2, &mut font),
+            Color::hex(0xFF0000)
+        );
+        assert_ne!(pixel(0, TITLE_HEIGHT, &mut font), Color::hex(0));
+        assert_ne!(pixel(BORDER, TITLE_HEIGHT + 3, &mut font), Color::hex(0));
+
+        // Some pixel of the title's glyph is drawn
+        let title = (0..TITLE_HEIGHT)
+            .flat_map(|y| (0..4 + 2 * BORDER).map(move |x| (x, y)))
+            .filter(|(x, y)| pixel(*x, *y, &mut font) == TITLE)
+            .count();
+        assert!(title > 0);
+    }
+}
@@ -0,0 +1,363 @@
+//! The built-in 8x8 bitmap font, covering printable ASCII, Latin-1
+//! and box drawing characters.
+//! ASCII glyphs are based on the public domain font8x8 by Daniel Hepper.
+//! Accented Latin-1 letters are composed of an ASCII letter and a diacritic,
+//! box drawing characters are generated from the lines they consist of.
+
+/// Width and height of every glyph, in pixels.
+pub const GLYPH_SIZE: usize = 8;
+
+type Glyph = [u8; GLYPH_SIZE];
+
+/// Returns the glyph of the given character, one byte per row.
+/// The lowest bit of each row is its leftmost pixel.
+/// Characters without a glyph are drawn as '?'.
+pub fn glyph(c: char) -> Glyph {
+    match c {
+        ' '..='~' => ascii(c),
+        '\u{A0}' => ascii(' '),
+        '\u{A1}'..='\u{FF}' => latin_1(c),
+        '\u{2500}'..='\u{259F}' => box_drawing(c),
+        _ => None,
+    }
+    .unwrap_or_else(|| FONT['?' as usize - ' ' as usize])
+}
+
+fn ascii(c: char) -> Option<Glyph> {
+    FONT.get((c as usize).checked_sub(' ' as usize)?).copied()
+}
+
+/// Marks placed above (or below, for the cedilla) a letter.
+#[derive(Copy, Clone)]
+enum Diacritic {
+    Grave,
+    Acute,
+    Circumflex,
+    Tilde,
+    Diaeresis,
+    Ring,
+    Cedilla,
+}
+
+impl Diacritic {
+    /// The two rows of the mark above lowercase letters.
+    fn rows(self) -> [u8; 2] {
+        match self {
+            Diacritic::Grave => [0x06, 0x0C],
+            Diacritic::Acute => [0x18, 0x0C],
+            Diacritic::Circumflex => [0x0C, 0x12],
+            Diacritic::Tilde => [0x16, 0x0D],
+            Diacritic::Diaeresis => [0x33, 0x00],
+            Diacritic::Ring => [0x0C, 0x0C],
+            Diacritic::Cedilla => [0x00, 0x00],
+        }
+    }
+
+    /// The single row of the mark above uppercase letters,
+    /// which leave less room for it.
+    fn compact(self) -> u8 {
+        match self {
+            Diacritic::Grave => 0x06,
+            Diacritic::Acute => 0x18,
+            Diacritic::Circumflex | Diacritic::Tilde => 0x1E,
+            Diacritic::Diaeresis => 0x33,
+            Diacritic::Ring => 0x0C,
+            Diacritic::Cedilla => 0x00,
+        }
+    }
+}
+
+fn latin_1(c: char) -> Option<Glyph> {
+    use Diacritic::*;
+    let (base, diacritic) = match c {
+        'À' => ('A', Grave),
+        'Á' => ('A', Acute),
+        'Â' => ('A', Circumflex),
+        'Ã' => ('A', Tilde),
+        'Ä' => ('A', Diaeresis),
+        'Å' => ('A', Ring),
+        'Ç' => ('C', Cedilla),
+        'È' => ('E', Grave),
+        'É' => ('E', Acute),
+        'Ê' => ('E', Circumflex),
+        'Ë' => ('E', Diaeresis),
+        'Ì' => ('I', Grave),
+        'Í' => ('I', Acute),
+        'Î' => ('I', Circumflex),
+        'Ï' => ('I', Diaeresis),
+        'Ñ' => ('N', Tilde),
+        'Ò' => ('O', Grave),
+        'Ó' => ('O', Acute),
+        'Ô' => ('O', Circumflex),
+        'Õ' => ('O', Tilde),
+        'Ö' => ('O', Diaeresis),
+        'Ù' => ('U', Grave),
+        'Ú' => ('U', Acute),
+        'Û' => ('U', Circumflex),
+        'Ü' => ('U', Diaeresis),
+        'Ý' => ('Y', Acute),
+        'à' => ('a', Grave),
+        'á' => ('a', Acute),
+        'â' => ('a', Circumflex),
+        'ã' => ('a', Tilde),
+        'ä' => ('a', Diaeresis),
+        'å' => ('a', Ring),
+        'ç' => ('c', Cedilla),
+        'è' => ('e', Grave),
+        'é' => ('e', Acute),
+        'ê' => ('e', Circumflex),
+        'ë' => ('e', Diaeresis),
+        'ì' => ('ı', Grave),
+        'í' => ('ı', Acute),
+        'î' => ('ı', Circumflex),
+        'ï' => ('ı', Diaeresis),
+        'ñ' => ('n', Tilde),
+        'ò' => ('o', Grave),
+        'ó' => ('o', Acute),
+        'ô' => ('o', Circumflex),
+        'õ' => ('o', Tilde),
+        'ö' => ('o', Diaeresis),
+        'ù' => ('u', Grave),
+        'ú' => ('u', Acute),
+        'û' => ('u', Circumflex),
+        'ü' => ('u', Diaeresis),
+        'ý' => ('y', Acute),
+        'ÿ' => ('y', Diaeresis),
+        _ => {
+            return LATIN_1_SYMBOLS
+                .iter()
+                .find(|(s, _)| *s == c)
+                .map(|(_, g)| *g)
+        }
+    };
+
+    let mut glyph = if base == 'ı' {
+        // Dotless i, to make room for the mark
+        let mut i = ascii('i')?;
+        i[0] = 0;
+        i
+    } else {
+        ascii(base)?
+    };
+    if let Cedilla = diacritic {
+        glyph[7] |= 0x18;
+    } else if base.is_ascii_uppercase() {
+        glyph.copy_within(0..GLYPH_SIZE - 1, 1);
+        glyph[0] = diacritic.compact();
+    } else {
+        let rows = diacritic.rows();
+        glyph[0] |= rows[0];
+        glyph[1] |= rows[1];
+    }
+    Some(glyph)
+}
+
+/// Glyphs of Latin-1 characters that are not accented letters.
+#[rustfmt::skip]
+static LATIN_1_SYMBOLS: [(char, Glyph); 16] = [
+    ('¡', [0x18, 0x00, 0x18, 0x18, 0x3C, 0x3C, 0x18, 0x00]),
+    ('¢', [0x18, 0x18, 0x7E, 0x03, 0x03, 0x7E, 0x18, 0x18]),
+    ('£', [0x1C, 0x36, 0x26, 0x0F, 0x06, 0x67, 0x3F, 0x00]),
+    ('§', [0x3C, 0x06, 0x1E, 0x33, 0x1E, 0x30, 0x1E, 0x00]),
+    ('©', [0x3E, 0x41, 0x5D, 0x45, 0x5D, 0x41, 0x3E, 0x00]),
+    ('«', [0x00, 0xCC, 0x66, 0x33, 0x66, 0xCC, 0x00, 0x00]),
+    ('°', [0x1C, 0x36, 0x36, 0x1C, 0x00, 0x00, 0x00, 0x00]),
+    ('±', [0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x3F, 0x00]),
+    ('²', [0x0E, 0x18, 0x0C, 0x06, 0x1E, 0x00, 0x00, 0x00]),
+    ('³', [0x0E, 0x18, 0x0C, 0x18, 0x0E, 0x00, 0x00, 0x00]),
+    ('µ', [0x00, 0x00, 0x66, 0x66, 0x66, 0x3E, 0x06, 0x03]),
+    ('·', [0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00]),
+    ('»', [0x00, 0x33, 0x66, 0xCC, 0x66, 0x33, 0x00, 0x00]),
+    ('¿', [0x0C, 0x00, 0x0C, 0x06, 0x03, 0x33, 0x1E, 0x00]),
+    ('×', [0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00, 0x00]),
+    ('÷', [0x00, 0x0C, 0x00, 0x3F, 0x00, 0x0C, 0x00, 0x00]),
+];
+
+/// Weight of a line in a box drawing character.
+#[derive(Copy, Clone, PartialEq)]
+enum Line {
+    None,
+    Light,
+    Heavy,
+    Double,
+}
+
+impl Line {
+    /// The rows or columns the line covers. Lines run along the
+    /// center of the glyph, double lines on both sides of it.
+    fn offsets(self) -> &'static [usize] {
+        match self {
+            Line::None => &[],
+            Line::Light => &[3],
+            Line::Heavy => &[3, 4],
+            Line::Double => &[2, 5],
+        }
+    }
+}
+
+/// Box drawing characters with the lines going up, down, left and right from their center.
+#[rustfmt::skip]
+static BOX_LINES: [(char, [Line; 4]); 37] = {
+    use Line::{Double as D, Heavy as H, Light as L, None as N};
+    [
+        ('─', [N, N, L, L]), ('━', [N, N, H, H]), ('│', [L, L, N, N]), ('┃', [H, H, N, N]),
+        ('┌', [N, L, N, L]), ('┐', [N, L, L, N]), ('└', [L, N, N, L]), ('┘', [L, N, L, N]),
+        ('├', [L, L, N, L]), ('┤', [L, L, L, N]), ('┬', [N, L, L, L]), ('┴', [L, N, L, L]),
+        ('┼', [L, L, L, L]),
+        ('┏', [N, H, N, H]), ('┓', [N, H, H, N]), ('┗', [H, N, N, H]), ('┛', [H, N, H, N]),
+        ('┣', [H, H, N, H]), ('┫', [H, H, H, N]), ('┳', [N, H, H, H]), ('┻', [H, N, H, H]),
+        ('╋', [H, H, H, H]),
+        ('═', [N, N, D, D]), ('║', [D, D, N, N]),
+        ('╔', [N, D, N, D]), ('╗', [N, D, D, N]), ('╚', [D, N, N, D]), ('╝', [D, N, D, N]),
+        ('╠', [D, D, N, D]), ('╣', [D, D, D, N]), ('╦', [N, D, D, D]), ('╩', [D, N, D, D]),
+        ('╬', [D, D, D, D]),
+        ('╴', [N, N, L, N]), ('╵', [L, N, N, N]), ('╶', [N, N, N, L]), ('╷', [N, L, N, N]),
+    ]
+};
+
+fn box_drawing(c: char) -> Option<Glyph> {
+    match c {
+        '█' => return Some([0xFF; GLYPH_SIZE]),
+        '▀' => return Some([0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]),
+        '▄' => return Some([0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]),
+        '▌' => return Some([0x0F; GLYPH_SIZE]),
+        '▐' => return Some([0xF0; GLYPH_SIZE]),
+        '░' => return Some([0x11, 0x44, 0x11, 0x44, 0x11, 0x44, 0x11, 0x44]),
+        '▒' => return Some([0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA, 0x55, 0xAA]),
+        '▓' => return Some([0xEE, 0xBB, 0xEE, 0xBB, 0xEE, 0xBB, 0xEE, 0xBB]),
+        _ => (),
+    }
+
+    let (_, [up, down, left, right]) = BOX_LINES.iter().find(|(b, _)| *b == c)?;
+    let mut glyph = [0; GLYPH_SIZE];
+    for (line, rows) in [(*up, 0..4), (*down, 3..GLYPH_SIZE)].iter().cloned() {
+        for row in rows {
+            for column in line.offsets() {
+                glyph[row] |= 1 << column;
+            }
+        }
+    }
+    for (line, columns) in [(*left, 0..4), (*right, 3..GLYPH_SIZE)].iter().cloned() {
+        for row in line.offsets() {
+            for column in columns.clone() {
+                glyph[*row] |= 1 << column;
+            }
+        }
+    }
+    Some(glyph)
+}
+
+#[rustfmt::skip]
+static FONT: [[u8; GLYPH_SIZE]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00], // !
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+    [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00], // #
+    [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00], // $
+    [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00], // %
+    [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00], // &
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // '
+    [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00], // (
+    [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00], // )
+    [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00], // *
+    [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ,
+    [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00], // .
+    [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00], // /
+    [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00], // 0
+    [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00], // 1
+    [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00], // 2
+    [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00], // 3
+    [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00], // 4
+    [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00], // 5
+    [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00], // 6
+    [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00], // 7
+    [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00], // 8
+    [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00], // 9
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00], // :
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ;
+    [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00], // <
+    [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00], // =
+    [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00], // >
+    [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00], // ?
+    [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00], // @
+    [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00], // A
+    [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00], // B
+    [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00], // C
+    [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00], // D
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00], // E
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00], // F
+    [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00], // G
+    [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00], // H
+    [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // I
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00], // J
+    [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00], // K
+    [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00], // L
+    [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00], // M
+    [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00], // N
+    [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00], // O
+    [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00], // P
+    [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00], // Q
+    [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00], // R
+    [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00], // S
+    [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // T
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00], // U
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // V
+    [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00], // W
+    [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00], // X
+    [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00], // Y
+    [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00], // Z
+    [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00], // [
+    [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00], // backslash
+    [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00], // ]
+    [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF], // _
+    [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // `
+    [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00], // a
+    [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00], // b
+    [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00], // c
+    [0x38, 0x30, 0x30, 0x3E, 0x33, 0x33, 0x6E, 0x00], // d
+    [0x00, 0x00, 0x1E, 0x33, 0x3F, 0x03, 0x1E, 0x00], // e
+    [0x1C, 0x36, 0x06, 0x0F, 0x06, 0x06, 0x0F, 0x00], // f
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F], // g
+    [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00], // h
+    [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // i
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E], // j
+    [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00], // k
+    [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // l
+    [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00], // m
+    [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00], // n
+    [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00], // o
+    [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F], // p
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78], // q
+    [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00], // r
+    [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00], // s
+    [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00], // t
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00], // u
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // v
+    [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00], // w
+    [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00], // x
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F], // y
+    [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00], // z
+    [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00], // {
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // |
+    [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00], // }
+    [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ~
+];
+
+#[test_case]
+fn unknown_characters() {
+    assert_eq!(glyph('字'), glyph('?'));
+    assert_eq!(glyph('\n'), glyph('?'));
+    assert_ne!(glyph('A'), glyph('?'));
+}
+
+#[test_case]
+fn composed_characters() {
+    assert_ne!(glyph('é'), glyph('?'));
+    assert_ne!(glyph('é'), glyph('e'));
+    assert_eq!(glyph('é')[2..], glyph('e')[2..]);
+    assert_eq!(glyph('─'), [0, 0, 0, 0xFF, 0, 0, 0, 0]);
+    assert_eq!(glyph('│'), [0x08; GLYPH_SIZE]);
+}
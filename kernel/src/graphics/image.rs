@@ -0,0 +1,160 @@
+//! Decoding images loaded from disk, so they can be drawn to the screen.
+//! Only uncompressed BMP files with 24 or 32 bits per pixel are supported,
+//! which is what most image editors export by default.
+
+use crate::graphics::Color;
+use alloc::vec::Vec;
+
+/// Size of the file header and the smallest info header that describes the pixels.
+const HEADER_SIZE: usize = 14 + 40;
+/// Compression method of BMP files that store pixels as-is.
+const BI_RGB: u32 = 0;
+
+/// A decoded image, stored row by row from the top left.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Color>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImageError {
+    /// The file does not start with a BMP header.
+    NotBmp,
+    /// The file ends before all pixels the header describes.
+    Truncated,
+    /// The pixels are stored in a format that cannot be decoded.
+    Unsupported { bits: u16, compression: u32 },
+}
+
+impl Image {
+    /// Decode a BMP file.
+    pub fn decode_bmp(data: &[u8]) -> Result<Image, ImageError> {
+        if data.len() < HEADER_SIZE || &data[0..2] != b"BM" {
+            return Err(ImageError::NotBmp);
+        }
+        let offset = u32_at(data, 10) as usize;
+        let width = u32_at(data, 18) as i32;
+        let height = u32_at(data, 22) as i32;
+        let bits = u16::from_le_bytes([data[28], data[29]]);
+        let compression = u32_at(data, 30);
+        if width < 0 || !matches!(bits, 24 | 32) || compression != BI_RGB {
+            return Err(ImageError::Unsupported { bits, compression });
+        }
+
+        // Rows are stored bottom-up unless the height is negative,
+        // and padded to a multiple of 4 bytes
+        let (width, top_down) = (width as usize, height < 0);
+        let height = height.unsigned_abs() as usize;
+        let bytes_per_pixel = bits as usize / 8;
+        let row_size = (width * bytes_per_pixel + 3) & !3;
+        let end = row_size
+            .checked_mul(height)
+            .and_then(|size| size.checked_add(offset));
+        if end.map_or(true, |end| end > data.len()) {
+            return Err(ImageError::Truncated);
+        }
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = if top_down { y } else { height - 1 - y };
+            let start = offset + row * row_size;
+            let row = &data[start..start + width * bytes_per_pixel];
+            pixels.extend(
+                row.chunks_exact(bytes_per_pixel)
+                    .map(|bgr| Color::from(bgr[2], bgr[1], bgr[0])),
+            );
+        }
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Returns the color of the pixel at the given position.
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Image, ImageError, HEADER_SIZE};
+    use alloc::vec::Vec;
+
+    /// Encode a BMP file of the given size, where every pixel is `[x, y, 0xFF]`
+    /// in blue, green, red order.
+    fn bmp(width: usize, height: i32, bits: u16) -> Vec<u8> {
+        let bytes_per_pixel = bits as usize / 8;
+        let row_size = (width * bytes_per_pixel + 3) & !3;
+        let rows = height.unsigned_abs() as usize;
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"BM");
+        data.extend_from_slice(&((HEADER_SIZE + row_size * rows) as u32).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(&(width as u32).to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&bits.to_le_bytes());
+        data.resize(HEADER_SIZE, 0);
+
+        for stored in 0..rows {
+            let y = if height < 0 {
+                stored
+            } else {
+                rows - 1 - stored
+            };
+            let start = data.len();
+            for x in 0..width {
+                data.extend_from_slice(&[x as u8, y as u8, 0xFF, 0][..bytes_per_pixel]);
+            }
+            data.resize(start + row_size, 0);
+        }
+        data
+    }
+
+    #[test_case]
+    fn decode() {
+        for (height, bits) in [(3, 24), (3, 32), (-3, 24)] {
+            let image = Image::decode_bmp(&bmp(5, height, bits)).unwrap();
+            assert_eq!((image.width, image.height), (5, 3));
+            for y in 0..3 {
+                for x in 0..5 {
+                    let color = image.pixel(x, y);
+                    assert_eq!(
+                        (color.red, color.green, color.blue),
+                        (0xFF, y as u8, x as u8)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test_case]
+    fn invalid() {
+        assert_eq!(Image::decode_bmp(b"GIF89a").err(), Some(ImageError::NotBmp));
+        assert_eq!(
+            Image::decode_bmp(&bmp(5, 3, 8)).err(),
+            Some(ImageError::Unsupported {
+                bits: 8,
+                compression: 0
+            })
+        );
+        let mut data = bmp(5, 3, 24);
+        data.pop();
+        assert_eq!(Image::decode_bmp(&data).err(), Some(ImageError::Truncated));
+    }
+}
@@ -1,7 +1,16 @@
 use alloc::slice;
 use bootloader::boot_info::{FrameBuffer, FrameBufferInfo};
 use conquer_once::spin::OnceCell;
+use font::GLYPH_SIZE;
+use image::Image;
 use spin::{Mutex, MutexGuard};
+use text::Font;
+
+pub mod compositor;
+pub mod font;
+pub mod image;
+pub mod psf;
+pub mod text;
 
 // TODO isn't this doubly syncronized?...
 static FRAMEBUFFER: OnceCell<Mutex<Framebuffer>> = OnceCell::uninit();
@@ -46,7 +55,7 @@ pub struct Framebuffer {
     bytes_per_pixel: usize,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Color {
     red: u8,
     green: u8,
@@ -58,7 +67,7 @@ impl Color {
         Color { red, green, blue }
     }
 
-    pub fn hex(hex: u32) -> Color {
+    pub const fn hex(hex: u32) -> Color {
         Color {
             red: (hex >> 16) as u8,
             green: (hex >> 8) as u8,
@@ -71,6 +80,26 @@ fn obtain_buffer() -> MutexGuard<'static, Framebuffer> {
     FRAMEBUFFER.get().unwrap().lock()
 }
 
+/// Returns the width and height of the screen in pixels,
+/// or `None` if graphics are not initialized yet.
+pub fn resolution() -> Option<(usize, usize)> {
+    FRAMEBUFFER.get().map(|buf| {
+        let buf = buf.lock();
+        (buf.width, buf.height)
+    })
+}
+
+/// Release the framebuffer lock, even if it is held by someone else.
+///
+/// # Safety
+/// Only to be used when whoever holds the lock will never continue,
+/// like when the kernel panicked.
+pub unsafe fn force_unlock() {
+    if let Some(buf) = FRAMEBUFFER.get() {
+        buf.force_unlock()
+    }
+}
+
 fn draw_pixel(x: usize, y: usize, color: Color) {
     let mut buf = obtain_buffer();
     let offset = y * buf.stride + (x * buf.bytes_per_pixel);
@@ -104,6 +133,121 @@ pub fn draw_rect(x: usize, y: usize, w: usize, h: usize, color: Color) {
     }
 }
 
+/// Draw a character of the built-in font with its top left corner at the given position.
+/// Every pixel of the glyph is drawn as a square of `scale` pixels.
+/// Pixels around the glyph are left untouched.
+pub fn draw_char(x: usize, y: usize, c: char, color: Color, scale: usize) {
+    let mut buf = obtain_buffer();
+    assert!((x + GLYPH_SIZE * scale) <= buf.width);
+    assert!((y + GLYPH_SIZE * scale) <= buf.height);
+
+    for (row, bits) in font::glyph(c).iter().enumerate() {
+        for column in (0..GLYPH_SIZE).filter(|column| bits & (1 << column) != 0) {
+            for dy in 0..scale {
+                let py = y + row * scale + dy;
+                let mut offset = py * buf.stride + (x + column * scale) * buf.bytes_per_pixel;
+                for _ in 0..scale {
+                    set_pixel(buf.buffer, offset, color);
+                    offset += buf.bytes_per_pixel;
+                }
+            }
+        }
+    }
+}
+
+/// Draw an image with its top left corner at the given position, see `Canvas::draw_image`.
+pub fn draw_image(x: usize, y: usize, image: &Image) {
+    obtain_buffer().draw_image(x, y, image)
+}
+
+/// Draw text with its top left corner at the given position, see `Canvas::draw_text`.
+pub fn draw_text(x: usize, y: usize, text: &str, font: &mut Font, color: Color, scale: usize) {
+    obtain_buffer().draw_text(x, y, text, font, color, scale)
+}
+
+/// Copy a row of pixels to the screen, starting at the given position.
+/// Pixels that do not fit on the screen are cut off.
+pub fn draw_row(x: usize, y: usize, row: &[Color]) {
+    let mut buf = obtain_buffer();
+    let (width, height) = buf.size();
+    if y < height {
+        for (px, color) in (x..width).zip(row) {
+            buf.set(px, y, *color);
+        }
+    }
+}
+
+/// Something to draw on, either the screen or an off-screen surface.
+/// All drawing cuts off the parts that do not fit on the canvas.
+pub trait Canvas {
+    /// Width and height in pixels.
+    fn size(&self) -> (usize, usize);
+
+    /// Set the pixel at the given position, which is inside of the canvas.
+    fn set(&mut self, x: usize, y: usize, color: Color);
+
+    fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: Color) {
+        let (width, height) = self.size();
+        for py in y..y.saturating_add(h).min(height) {
+            for px in x..x.saturating_add(w).min(width) {
+                self.set(px, py, color);
+            }
+        }
+    }
+
+    /// Draw an image with its top left corner at the given position.
+    fn draw_image(&mut self, x: usize, y: usize, image: &Image) {
+        let (width, height) = self.size();
+        for row in 0..image.height.min(height.saturating_sub(y)) {
+            for column in 0..image.width.min(width.saturating_sub(x)) {
+                self.set(x + column, y + row, image.pixel(column, row));
+            }
+        }
+    }
+
+    /// Draw text with its top left corner at the given position, starting a new line
+    /// at every '\n'. Every pixel of the font is drawn as a square of `scale` pixels.
+    fn draw_text(
+        &mut self,
+        x: usize,
+        y: usize,
+        text: &str,
+        font: &mut Font,
+        color: Color,
+        scale: usize,
+    ) {
+        let (canvas_width, canvas_height) = self.size();
+        if x >= canvas_width || y >= canvas_height {
+            return;
+        }
+
+        let (width, height) = font.glyph_size();
+        for (line, text) in text.split('\n').enumerate() {
+            let top = y + line * height * scale;
+            for (index, c) in text.chars().enumerate() {
+                let left = x + index * width * scale;
+                for (row, bits) in font.glyph(c).iter().enumerate() {
+                    for column in (0..width).filter(|column| bits & (1 << column) != 0) {
+                        let (px, py) = (left + column * scale, top + row * scale);
+                        self.fill_rect(px, py, scale, scale, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Canvas for Framebuffer {
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: Color) {
+        let offset = y * self.stride + x * self.bytes_per_pixel;
+        set_pixel(self.buffer, offset, color)
+    }
+}
+
 #[inline]
 fn set_pixel(buf: &mut [u8], offset: usize, color: Color) {
     buf[offset] = color.blue;
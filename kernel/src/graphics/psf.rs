@@ -0,0 +1,245 @@
+//! Parsing of PC Screen Fonts, the bitmap font format used by
+//! the Linux console. Both version 1 and 2 are supported,
+//! including their tables mapping characters to glyphs.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::str;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_UNICODE: u8 = 0x02 | 0x04;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_SEQUENCE: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_FLAG_UNICODE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_SEQUENCE: u8 = 0xFE;
+
+/// Glyphs wider than this cannot be drawn, since rows are stored as bit masks.
+pub const MAX_WIDTH: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum FontError {
+    /// The file does not start with a PSF header.
+    NotPsf,
+    /// The file ends before all glyphs the header describes.
+    Truncated,
+    /// The glyphs are wider than `MAX_WIDTH`.
+    TooWide(usize),
+}
+
+pub struct PsfFont {
+    pub width: usize,
+    pub height: usize,
+    /// Bitmaps of all glyphs, every row padded to full bytes
+    /// with the leftmost pixel in the highest bit.
+    glyphs: Vec<u8>,
+    count: usize,
+    /// Index of the glyph of each character, if the font has a table for them.
+    /// Fonts without one have a glyph for every code point below their glyph count.
+    unicode: Option<BTreeMap<char, usize>>,
+}
+
+impl PsfFont {
+    pub fn parse(data: &[u8]) -> Result<PsfFont, FontError> {
+        if data.starts_with(&PSF1_MAGIC) && data.len() >= 4 {
+            Self::parse_v1(data)
+        } else if data.starts_with(&PSF2_MAGIC) && data.len() >= 32 {
+            Self::parse_v2(data)
+        } else {
+            Err(FontError::NotPsf)
+        }
+    }
+
+    fn parse_v1(data: &[u8]) -> Result<PsfFont, FontError> {
+        let (mode, height) = (data[2], data[3] as usize);
+        let count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let mut font = Self::with_glyphs(data, 4, count, 8, height)?;
+
+        if mode & PSF1_MODE_UNICODE != 0 {
+            let table = &data[4 + count * height..];
+            let mut unicode = BTreeMap::new();
+            let entries = table
+                .chunks_exact(2)
+                .map(|entry| u16::from_le_bytes([entry[0], entry[1]]));
+            let mut glyph = 0;
+            let mut sequence = false;
+            for entry in entries {
+                match entry {
+                    PSF1_SEPARATOR => {
+                        glyph += 1;
+                        sequence = false;
+                    }
+                    PSF1_SEQUENCE => sequence = true,
+                    _ if sequence || glyph >= count => (),
+                    _ => {
+                        if let Some(c) = char::from_u32(entry as u32) {
+                            unicode.entry(c).or_insert(glyph);
+                        }
+                    }
+                }
+            }
+            font.unicode = Some(unicode);
+        }
+        Ok(font)
+    }
+
+    fn parse_v2(data: &[u8]) -> Result<PsfFont, FontError> {
+        let field = |index: usize| {
+            let offset = 8 + index * 4;
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ]) as usize
+        };
+        let (header_size, flags, count) = (field(0), field(1) as u32, field(2));
+        let (glyph_size, height, width) = (field(3), field(4), field(5));
+        if width == 0 || glyph_size != (width + 7) / 8 * height {
+            return Err(FontError::NotPsf);
+        }
+        let mut font = Self::with_glyphs(data, header_size, count, width, height)?;
+
+        if flags & PSF2_FLAG_UNICODE != 0 {
+            let table = &data[header_size + count * glyph_size..];
+            let mut unicode = BTreeMap::new();
+            for (glyph, entry) in table.split(|b| *b == PSF2_SEPARATOR).enumerate() {
+                if glyph >= count {
+                    break;
+                }
+                // Sequences of multiple characters, like letters with combining
+                // diacritics, follow the single characters and are ignored
+                let singles = match entry.iter().position(|b| *b == PSF2_SEQUENCE) {
+                    Some(end) => &entry[..end],
+                    None => entry,
+                };
+                for c in str::from_utf8(singles).unwrap_or("").chars() {
+                    unicode.entry(c).or_insert(glyph);
+                }
+            }
+            font.unicode = Some(unicode);
+        }
+        Ok(font)
+    }
+
+    /// Create a font without a character table from glyphs starting at `offset`.
+    fn with_glyphs(
+        data: &[u8],
+        offset: usize,
+        count: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<PsfFont, FontError> {
+        if width > MAX_WIDTH {
+            return Err(FontError::TooWide(width));
+        }
+        let size = (width + 7) / 8 * height;
+        let end = size
+            .checked_mul(count)
+            .and_then(|glyphs| glyphs.checked_add(offset));
+        let glyphs = match end {
+            Some(end) if end <= data.len() => data[offset..end].to_vec(),
+            _ => return Err(FontError::Truncated),
+        };
+        Ok(PsfFont {
+            width,
+            height,
+            glyphs,
+            count,
+            unicode: None,
+        })
+    }
+
+    /// Returns the glyph of the given character, one bit mask per row
+    /// with the leftmost pixel in the lowest bit like the built-in font.
+    pub fn glyph(&self, c: char) -> Option<Vec<u32>> {
+        let index = match &self.unicode {
+            Some(unicode) => *unicode.get(&c)?,
+            None => Some(c as usize).filter(|index| *index < self.count)?,
+        };
+        let row_size = (self.width + 7) / 8;
+        let start = index * row_size * self.height;
+        let rows = self.glyphs[start..start + row_size * self.height]
+            .chunks_exact(row_size)
+            .map(|row| {
+                let bits = row
+                    .iter()
+                    .fold(0u64, |bits, byte| (bits << 8) | *byte as u64);
+                let bits = (bits >> (row_size * 8 - self.width)) as u32;
+                bits.reverse_bits() >> (32 - self.width)
+            });
+        Some(rows.collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FontError, PsfFont, PSF1_MAGIC, PSF2_MAGIC};
+    use alloc::vec;
+
+    #[test_case]
+    fn version_1() {
+        let mut data = vec![PSF1_MAGIC[0], PSF1_MAGIC[1], 0x02, 2];
+        data.resize(4 + 256 * 2, 0);
+        // Glyph 1 is a diagonal, shared by 'A' and 'Ä'
+        data[4 + 2] = 0b1000_0000;
+        data[4 + 3] = 0b0100_0000;
+        for glyph in 0..256u16 {
+            let entries: &[u16] = match glyph {
+                1 => &[b'A' as u16, 'Ä' as u16, 0xFFFE, b'A' as u16, 0x0308],
+                _ => &[],
+            };
+            for entry in entries.iter().chain(&[0xFFFF]) {
+                data.extend_from_slice(&entry.to_le_bytes());
+            }
+        }
+
+        let font = PsfFont::parse(&data).unwrap();
+        assert_eq!((font.width, font.height), (8, 2));
+        assert_eq!(font.glyph('A'), Some(vec![0b01, 0b10]));
+        assert_eq!(font.glyph('Ä'), font.glyph('A'));
+        assert_eq!(font.glyph('B'), None);
+    }
+
+    #[test_case]
+    fn version_2() {
+        let (width, height, count) = (12u32, 3u32, 2u32);
+        let mut data = PSF2_MAGIC.to_vec();
+        for field in [0, 32, 0, count, 2 * height, height, width] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        // Glyph 1 has its leftmost and rightmost pixel set in every row
+        data.resize(32 + 2 * 6, 0);
+        for row in 0..3 {
+            data[32 + 6 + row * 2] = 0b1000_0000;
+            data[32 + 6 + row * 2 + 1] = 0b0001_0000;
+        }
+
+        let font = PsfFont::parse(&data).unwrap();
+        assert_eq!((font.width, font.height), (12, 3));
+        assert_eq!(font.glyph('\u{1}'), Some(vec![0b1000_0000_0001; 3]));
+        assert_eq!(font.glyph('\u{2}'), None);
+
+        // With a table mapping characters to glyphs
+        data[12] = 1;
+        data.extend_from_slice(b"?\xFF");
+        data.extend_from_slice("x→".as_bytes());
+        data.push(0xFE);
+        data.extend_from_slice("e\u{301}".as_bytes());
+        data.push(0xFF);
+        let font = PsfFont::parse(&data).unwrap();
+        assert_eq!(font.glyph('?'), Some(vec![0; 3]));
+        assert_eq!(font.glyph('x'), Some(vec![0b1000_0000_0001; 3]));
+        assert_eq!(font.glyph('→'), font.glyph('x'));
+        assert_eq!(font.glyph('e'), None);
+    }
+
+    #[test_case]
+    fn invalid() {
+        assert_eq!(PsfFont::parse(b"font").err(), Some(FontError::NotPsf));
+        let truncated = [PSF1_MAGIC[0], PSF1_MAGIC[1], 0, 16, 0];
+        assert_eq!(PsfFont::parse(&truncated).err(), Some(FontError::Truncated));
+    }
+}
@@ -0,0 +1,119 @@
+//! Fonts for drawing text to the framebuffer. Text can use the built-in font
+//! or PSF fonts loaded from disk, drawn at any integer scale.
+
+use crate::graphics::{
+    font,
+    font::GLYPH_SIZE,
+    psf::{FontError, PsfFont},
+};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+/// How many glyphs a font keeps at most before clearing its cache.
+const CACHE_SIZE: usize = 256;
+
+pub struct Font {
+    source: Source,
+    /// Glyphs already converted to bit masks, since composing built-in glyphs
+    /// and unpacking PSF bitmaps would otherwise happen for every character drawn.
+    cache: BTreeMap<char, Vec<u32>>,
+}
+
+enum Source {
+    Builtin,
+    Psf(PsfFont),
+}
+
+impl Font {
+    /// The built-in 8x8 font, see `graphics::font`.
+    pub fn builtin() -> Self {
+        Self::new(Source::Builtin)
+    }
+
+    /// Parse a font from the contents of a PSF file.
+    pub fn from_psf(data: &[u8]) -> Result<Self, FontError> {
+        PsfFont::parse(data).map(|font| Self::new(Source::Psf(font)))
+    }
+
+    fn new(source: Source) -> Self {
+        Self {
+            source,
+            cache: BTreeMap::new(),
+        }
+    }
+
+    /// Width and height of every glyph, in pixels.
+    pub fn glyph_size(&self) -> (usize, usize) {
+        match &self.source {
+            Source::Builtin => (GLYPH_SIZE, GLYPH_SIZE),
+            Source::Psf(font) => (font.width, font.height),
+        }
+    }
+
+    /// Returns the glyph of the given character, one bit mask per row
+    /// with the leftmost pixel in the lowest bit.
+    pub fn glyph(&mut self, c: char) -> &[u32] {
+        if self.cache.len() >= CACHE_SIZE && !self.cache.contains_key(&c) {
+            self.cache.clear();
+        }
+        let source = &self.source;
+        self.cache.entry(c).or_insert_with(|| source.glyph(c))
+    }
+
+    /// Returns the size of `text` in pixels when drawn at the given scale:
+    /// The width of its longest line and the height of all lines.
+    pub fn measure(&self, text: &str, scale: usize) -> (usize, usize) {
+        let (width, height) = self.glyph_size();
+        let longest = text.split('\n').map(|line| line.chars().count()).max();
+        let lines = text.split('\n').count();
+        (longest.unwrap_or(0) * width * scale, lines * height * scale)
+    }
+}
+
+impl Source {
+    /// Characters without a glyph are drawn as '?', or left
+    /// blank if the font does not have a glyph for that either.
+    fn glyph(&self, c: char) -> Vec<u32> {
+        match self {
+            Source::Builtin => font::glyph(c).iter().map(|row| *row as u32).collect(),
+            Source::Psf(font) => font
+                .glyph(c)
+                .or_else(|| font.glyph('?'))
+                .unwrap_or_else(|| vec![0; font.height]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Font, CACHE_SIZE};
+    use crate::graphics::font;
+
+    #[test_case]
+    fn builtin() {
+        let mut font = Font::builtin();
+        let expected = font::glyph('Ä');
+        assert!(font
+            .glyph('Ä')
+            .iter()
+            .eq(expected.iter().map(|row| *row as u32)));
+        let unknown = font.glyph('\u{1F600}').to_vec();
+        assert_eq!(unknown, font.glyph('?'));
+    }
+
+    #[test_case]
+    fn measure() {
+        let font = Font::builtin();
+        assert_eq!(font.measure("yacari", 1), (48, 8));
+        assert_eq!(font.measure("a\nlonger\n", 2), (96, 48));
+        assert_eq!(font.measure("", 3), (0, 24));
+    }
+
+    #[test_case]
+    fn cache_bounded() {
+        let mut font = Font::builtin();
+        for c in (0..CACHE_SIZE as u32 * 2).filter_map(|c| char::from_u32(0x2500 + c)) {
+            font.glyph(c);
+            assert!(font.cache.len() <= CACHE_SIZE);
+        }
+    }
+}
@@ -1,12 +1,15 @@
 #![no_std]
 #![cfg_attr(test, no_main)]
 #![feature(abi_x86_interrupt)]
+#![feature(asm)]
 #![feature(alloc_error_handler)]
 #![feature(custom_test_frameworks)]
 #![feature(const_mut_refs)]
 #![feature(destructuring_assignment)]
 #![allow(incomplete_features)]
 #![feature(const_generics)]
+#![feature(global_asm)]
+#![feature(panic_info_message)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
@@ -15,13 +18,21 @@ extern crate alloc;
 use core::panic::PanicInfo;
 
 pub mod allocator;
+pub mod backtrace;
+pub mod clipboard;
 pub mod drivers;
 pub mod graphics;
+pub mod log;
+pub mod panic;
+pub mod rng;
 pub mod scheduling;
 pub mod shell;
 pub mod vm;
 
-use crate::drivers::interrupts::{gdt, interrupts};
+use crate::drivers::{
+    interrupts::{gdt, interrupts},
+    timer,
+};
 #[cfg(test)]
 use bootloader::{entry_point, BootInfo};
 use x86_64::instructions::port::Port;
@@ -40,7 +51,12 @@ pub fn init() {
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    timer::init();
     x86_64::instructions::interrupts::enable();
+    timer::calibrate();
+    rng::init();
+    #[cfg(feature = "timings")]
+    yacari::set_clock(timer::nanos);
 }
 
 #[alloc_error_handler]
@@ -0,0 +1,239 @@
+//! Leveled logging with per-module filters.
+//! Messages are printed to the console with their level colored,
+//! unless they are filtered out. Filters apply to a module and all its
+//! submodules, with the most specific filter taking precedence.
+//! `STATIC_FILTERS` are applied at compile time, runtime filters can
+//! be changed with `set_level` (used by the `loglevel` shell command).
+
+use crate::drivers::serial;
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+use spin::Mutex;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub const ALL: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        Level::ALL
+            .iter()
+            .copied()
+            .find(|level| level.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// ANSI escape code of the color the level is printed in.
+    fn color(self) -> &'static str {
+        match self {
+            Level::Error => "\x1b[31m",
+            Level::Warn => "\x1b[33m",
+            Level::Info => "\x1b[32m",
+            Level::Debug => "\x1b[36m",
+            Level::Trace => "\x1b[90m",
+        }
+    }
+}
+
+/// The most verbose level that is compiled in at all.
+pub const STATIC_MAX_LEVEL: Level = if cfg!(debug_assertions) {
+    Level::Trace
+} else {
+    Level::Info
+};
+
+/// Modules whose messages above the given level are removed at compile time.
+pub const STATIC_FILTERS: &[(&str, Level)] = &[("yacuri::drivers::disk::ata_pio", Level::Warn)];
+
+/// The level used for modules without a runtime filter.
+static DEFAULT_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static FILTERS: Mutex<Filters> = Mutex::new(Filters(Vec::new()));
+
+/// Runtime filters, by module path.
+pub struct Filters(Vec<(String, Level)>);
+
+impl Filters {
+    /// Returns the level of the most specific filter matching the module, if any.
+    pub fn level(&self, module: &str) -> Option<Level> {
+        self.0
+            .iter()
+            .filter(|(path, _)| is_within(module, path))
+            .max_by_key(|(path, _)| path.len())
+            .map(|(_, level)| *level)
+    }
+
+    pub fn set(&mut self, module: &str, level: Level) {
+        self.0.retain(|(path, _)| path != module);
+        self.0.push((module.into(), level));
+    }
+}
+
+/// Whether messages of the given level and module are compiled in.
+/// Evaluated at compile time by the logging macros.
+pub const fn static_enabled(level: Level, module: &str) -> bool {
+    if level as u8 > STATIC_MAX_LEVEL as u8 {
+        return false;
+    }
+    let mut i = 0;
+    while i < STATIC_FILTERS.len() {
+        let (path, max) = STATIC_FILTERS[i];
+        if const_is_within(module.as_bytes(), path.as_bytes()) && level as u8 > max as u8 {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Whether messages of the given level and module are currently printed.
+pub fn enabled(level: Level, module: &str) -> bool {
+    let max = FILTERS.lock().level(module).unwrap_or_else(default_level);
+    level <= max
+}
+
+/// Set the level of the given module, or the default level if `None`.
+pub fn set_level(module: Option<&str>, level: Level) {
+    match module {
+        Some(module) => FILTERS.lock().set(module, level),
+        None => DEFAULT_LEVEL.store(level as u8, Ordering::Relaxed),
+    }
+}
+
+pub fn default_level() -> Level {
+    Level::ALL[DEFAULT_LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+/// Returns all runtime filters.
+pub fn filters() -> Vec<(String, Level)> {
+    FILTERS.lock().0.clone()
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, target: &str, args: fmt::Arguments) {
+    if enabled(level, target) {
+        serial::_print(format_args!(
+            "{}{:<5}\x1b[0m {}: {}\n",
+            level.color(),
+            level.name(),
+            target,
+            args
+        ));
+    }
+}
+
+/// Whether `module` is `path` or one of its submodules.
+fn is_within(module: &str, path: &str) -> bool {
+    module
+        .strip_prefix(path)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+const fn const_is_within(module: &[u8], path: &[u8]) -> bool {
+    if module.len() < path.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < path.len() {
+        if module[i] != path[i] {
+            return false;
+        }
+        i += 1;
+    }
+    module.len() == path.len() || module[path.len()] == b':'
+}
+
+/// Log a message with the given level, optionally for a
+/// target other than the current module.
+#[macro_export]
+macro_rules! log {
+    (target: $target:expr, $level:expr, $($arg:tt)+) => {{
+        const ENABLED: bool = $crate::log::static_enabled($level, $target);
+        if ENABLED {
+            $crate::log::_log($level, $target, format_args!($($arg)+));
+        }
+    }};
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log!(target: module_path!(), $level, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Error, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Warn, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Info, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Debug, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => ($crate::log!($crate::log::Level::Trace, $($arg)+));
+}
+
+#[cfg(test)]
+mod test {
+    use super::{static_enabled, Filters, Level};
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn filters() {
+        let mut filters = Filters(Vec::new());
+        filters.set("yacuri::vm", Level::Warn);
+        filters.set("yacuri::vm::cache", Level::Trace);
+        assert_eq!(filters.level("yacuri::vm::host"), Some(Level::Warn));
+        assert_eq!(filters.level("yacuri::vm::cache"), Some(Level::Trace));
+        assert_eq!(filters.level("yacuri::vmx"), None);
+        assert_eq!(filters.level("yacuri::shell"), None);
+    }
+
+    #[test_case]
+    fn static_filters() {
+        assert!(static_enabled(
+            Level::Warn,
+            "yacuri::drivers::disk::ata_pio"
+        ));
+        assert!(!static_enabled(
+            Level::Info,
+            "yacuri::drivers::disk::ata_pio"
+        ));
+        assert!(static_enabled(Level::Info, "yacuri::drivers::disk::fat"));
+    }
+}
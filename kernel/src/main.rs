@@ -12,12 +12,12 @@ use x86_64::VirtAddr;
 use yacuri::{
     allocator,
     allocator::{memory, memory::BootInfoFrameAllocator},
-    drivers::keyboard,
+    backtrace,
+    drivers::{acpi, apic, keyboard},
     graphics::init_graphics,
-    hlt_loop, kprintln, println,
-    scheduling::{executor::Executor, task::Task},
-    vm,
-    vm::test_app,
+    kprintln, println,
+    scheduling::{executor::Executor, smp, task::Task},
+    vm, warn,
 };
 
 entry_point!(kernel_main);
@@ -28,14 +28,14 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     yacuri::init();
     init_graphics(boot_info.framebuffer.as_mut().unwrap());
     init_memory(boot_info);
-
-    test_app();
+    backtrace::load_symbols();
 
     #[cfg(test)]
     test_main();
 
     let mut executor = Executor::new();
-    // executor.spawn(Task::new(keyboard::process_keypresses()));
+    vm::services::spawn_all();
+    // executor.spawn(Task::new("shell", keyboard::process_keypresses()));
     executor.run();
 }
 
@@ -44,14 +44,25 @@ fn init_memory(boot_info: &'static BootInfo) {
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
-    vm::init_code_heap(&mut mapper, &mut frame_allocator).expect("vm heap initialization failed");
+    vm::init_code_region(&mut mapper, &mut frame_allocator)
+        .expect("code region initialization failed");
+
+    // Without ACPI tables, the system keeps using the PICs and only the boot CPU
+    let rsdp = boot_info.rsdp_addr.into_option();
+    match rsdp.and_then(|rsdp| unsafe { acpi::madt(rsdp) }) {
+        Some(madt) => {
+            apic::init(&mut mapper, &mut frame_allocator, &madt)
+                .expect("apic initialization failed");
+            smp::init(&mut mapper, &mut frame_allocator, &madt).expect("smp initialization failed");
+        }
+        None => warn!("no ACPI tables found, only using the boot CPU"),
+    }
 }
 
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    kprintln!("{}", info);
-    hlt_loop()
+    yacuri::panic::panic_screen(info)
 }
 
 #[cfg(test)]
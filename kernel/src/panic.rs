@@ -0,0 +1,196 @@
+//! The panic screen, shown when the kernel panics.
+//! It covers the framebuffer with the panic message, where it happened,
+//! the registers and a backtrace, and writes the same to serial
+//! so it can be captured from QEMU's output.
+
+use crate::{
+    backtrace,
+    backtrace::Frames,
+    drivers::serial::SERIAL1,
+    graphics,
+    graphics::{font::GLYPH_SIZE, Color},
+    hlt_loop, kprintln,
+};
+use core::{
+    fmt,
+    fmt::Write,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use x86_64::registers::{
+    control::{Cr0, Cr2, Cr3, Cr4},
+    model_specific::GsBase,
+    rflags,
+};
+
+const BACKGROUND: u32 = 0x5C0000;
+const FOREGROUND: u32 = 0xFFFFFF;
+/// The size of every pixel of the font on screen.
+const SCALE: usize = 2;
+/// Space between the border of the screen and the text, in pixels.
+const MARGIN: usize = 32;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Show the panic screen and halt.
+/// Panics while showing it only print their message to serial.
+pub fn panic_screen(info: &PanicInfo) -> ! {
+    x86_64::instructions::interrupts::disable();
+    let registers = Registers::read();
+    let frames = Frames::current();
+
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        kprintln!("panicked while panicking: {}", info);
+        hlt_loop()
+    }
+    // Whoever holds the locks will never continue
+    unsafe {
+        SERIAL1.force_unlock();
+        graphics::force_unlock();
+    }
+
+    let mut out = PanicWriter {
+        screen: Screen::new(),
+    };
+    report(&mut out, info, &registers, frames).ok();
+    hlt_loop()
+}
+
+fn report(
+    out: &mut impl Write,
+    info: &PanicInfo,
+    registers: &Registers,
+    frames: Frames,
+) -> fmt::Result {
+    writeln!(out, "KERNEL PANIC\n")?;
+    match info.message() {
+        Some(message) => writeln!(out, "{}", message)?,
+        None => writeln!(out, "(no message)")?,
+    }
+    if let Some(location) = info.location() {
+        writeln!(out, "at {}", location)?;
+    }
+    if let Some(cpu) = registers.cpu {
+        writeln!(out, "on CPU {}", cpu)?;
+    }
+
+    writeln!(out, "\nREGISTERS")?;
+    writeln!(
+        out,
+        "rsp {:016x}  rbp {:016x}  rflags {:016x}",
+        registers.rsp, registers.rbp, registers.rflags
+    )?;
+    writeln!(
+        out,
+        "cr0 {:016x}  cr2 {:016x}  cr3 {:016x}  cr4 {:016x}",
+        registers.cr0, registers.cr2, registers.cr3, registers.cr4
+    )?;
+
+    writeln!(out, "\nBACKTRACE")?;
+    backtrace::write(out, frames)
+}
+
+/// The registers at the time of the panic, as far as they are still meaningful
+/// inside of the panic handler.
+struct Registers {
+    rsp: u64,
+    rbp: u64,
+    rflags: u64,
+    cr0: u64,
+    cr2: u64,
+    cr3: u64,
+    cr4: u64,
+    /// Index of the CPU, unless it panicked before its per-CPU data was set up.
+    cpu: Option<u32>,
+}
+
+impl Registers {
+    #[inline(always)]
+    fn read() -> Self {
+        let (rsp, rbp): (u64, u64);
+        unsafe {
+            asm!("mov {}, rsp", out(reg) rsp);
+            asm!("mov {}, rbp", out(reg) rbp);
+        }
+        let (level_4_table, _) = Cr3::read();
+        let cpu = if GsBase::read().is_null() {
+            None
+        } else {
+            Some(crate::drivers::interrupts::gdt::per_cpu().id)
+        };
+
+        Self {
+            rsp,
+            rbp,
+            rflags: rflags::read_raw(),
+            cr0: Cr0::read_raw(),
+            cr2: Cr2::read().as_u64(),
+            cr3: level_4_table.start_address().as_u64(),
+            cr4: Cr4::read_raw(),
+            cpu,
+        }
+    }
+}
+
+/// Writes to both serial and the screen.
+struct PanicWriter {
+    screen: Option<Screen>,
+}
+
+impl Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        SERIAL1.lock().write_str(s)?;
+        if let Some(screen) = &mut self.screen {
+            screen.write_str(s)?;
+        }
+        Ok(())
+    }
+}
+
+/// Text output on the framebuffer, starting at the top left.
+/// Text that does not fit on the screen anymore is dropped.
+struct Screen {
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+}
+
+impl Screen {
+    /// Clear the screen, unless graphics are not initialized yet.
+    fn new() -> Option<Self> {
+        let (width, height) = graphics::resolution()?;
+        graphics::draw_rect(0, 0, width, height, Color::hex(BACKGROUND));
+        Some(Self {
+            width,
+            height,
+            x: MARGIN,
+            y: MARGIN,
+        })
+    }
+
+    fn newline(&mut self) {
+        self.x = MARGIN;
+        self.y += GLYPH_SIZE * SCALE + SCALE;
+    }
+}
+
+impl Write for Screen {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let char_size = GLYPH_SIZE * SCALE;
+        for c in s.chars() {
+            if c == '\n' {
+                self.newline();
+                continue;
+            }
+            if self.x + char_size > self.width - MARGIN {
+                self.newline();
+            }
+            if self.y + char_size <= self.height - MARGIN {
+                graphics::draw_char(self.x, self.y, c, Color::hex(FOREGROUND), SCALE);
+            }
+            self.x += char_size;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! Random numbers for the kernel and programs. Numbers come from a xoshiro256**
+//! generator, seeded with RDRAND if the CPU supports it and with the jitter
+//! of TSC readings otherwise. Not suitable for cryptography.
+
+use crate::drivers::timer;
+use core::hint::spin_loop;
+use spin::Mutex;
+use x86_64::instructions::random::RdRand;
+
+/// How many TSC readings are mixed into the seed without RDRAND.
+const JITTER_SAMPLES: usize = 256;
+
+static RNG: Mutex<Xoshiro256> = Mutex::new(Xoshiro256 { state: [0; 4] });
+
+/// Seed the generator. Before this, it returns the same numbers on every boot.
+pub fn init() {
+    *RNG.lock() = Xoshiro256::from_seed(seed());
+}
+
+/// Returns a random number.
+pub fn next_u64() -> u64 {
+    RNG.lock().next()
+}
+
+/// Returns a random number in `low..high`, or `low` if the range is empty.
+pub fn range(low: i64, high: i64) -> i64 {
+    if high <= low {
+        return low;
+    }
+    let span = high.wrapping_sub(low) as u64;
+    // Reject numbers from the incomplete last span, which would make some results more likely
+    let limit = u64::MAX - u64::MAX % span;
+    let mut rng = RNG.lock();
+    loop {
+        let number = rng.next();
+        if number < limit {
+            return low.wrapping_add((number % span) as i64);
+        }
+    }
+}
+
+/// Returns a seed from RDRAND, or from TSC jitter if the CPU does not support it.
+fn seed() -> u64 {
+    if let Some(number) = RdRand::new().and_then(|rdrand| rdrand.get_u64()) {
+        return number;
+    }
+
+    // How long the same loop takes varies a bit every time, with the lowest bits of
+    // the TSC varying most. Mix every reading into the seed to collect that variation.
+    let mut seed = timer::tsc();
+    for _ in 0..JITTER_SAMPLES {
+        let start = timer::tsc();
+        for _ in 0..(seed % 64) {
+            spin_loop();
+        }
+        seed = splitmix64(seed ^ timer::tsc().wrapping_sub(start));
+    }
+    seed
+}
+
+/// One step of SplitMix64, which scrambles its input well enough
+/// to expand it into the state of `Xoshiro256`.
+fn splitmix64(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+struct Xoshiro256 {
+    state: [u64; 4],
+}
+
+impl Xoshiro256 {
+    fn from_seed(seed: u64) -> Self {
+        let mut state = [0; 4];
+        let mut x = seed;
+        for word in &mut state {
+            x = splitmix64(x);
+            *word = x;
+        }
+        Self { state }
+    }
+
+    fn next(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{next_u64, range, Xoshiro256};
+
+    #[test_case]
+    fn deterministic() {
+        let mut a = Xoshiro256::from_seed(42);
+        let mut b = Xoshiro256::from_seed(42);
+        let mut c = Xoshiro256::from_seed(43);
+        for _ in 0..100 {
+            let number = a.next();
+            assert_eq!(number, b.next());
+            assert_ne!(number, c.next());
+        }
+    }
+
+    #[test_case]
+    fn ranges() {
+        let mut seen = [false; 5];
+        for _ in 0..1000 {
+            let number = range(-2, 3);
+            assert!((-2..3).contains(&number));
+            seen[(number + 2) as usize] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+
+        assert_eq!(range(7, 7), 7);
+        assert_eq!(range(7, 3), 7);
+        assert!(range(i64::MIN, i64::MAX) < i64::MAX);
+        assert_ne!(next_u64(), next_u64());
+    }
+}
@@ -1,13 +1,36 @@
-use crate::scheduling::{
-    task::{Task, TaskId},
-    waker::TaskWaker,
+use crate::{
+    drivers::{interrupts::gdt, timer},
+    scheduling::{
+        inbox,
+        task::{Task, TaskId, TaskInfo, TaskState},
+        waker::TaskWaker,
+    },
 };
-use alloc::{collections::BTreeMap, sync::Arc};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
 use core::task::{Context, Poll, Waker};
 use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
 use x86_64::instructions::{interrupts, interrupts::enable_and_hlt};
 
+/// All tasks that were spawned and did not finish yet.
+/// Kept outside of the executor so that tasks can list them while being polled.
+static TASKS: Mutex<Vec<(TaskId, usize, Arc<TaskInfo>)>> = Mutex::new(Vec::new());
+
+/// A snapshot of a task and the time spent polling it.
+#[derive(Debug, Clone)]
+pub struct TaskStats {
+    pub id: u64,
+    pub name: String,
+    /// The index of the CPU whose executor runs the task.
+    pub cpu: usize,
+    pub state: TaskState,
+    pub polls: u64,
+    pub poll_nanos: u64,
+}
+
+/// Runs the tasks of a single CPU.
 pub struct Executor {
+    cpu: usize,
     tasks: BTreeMap<TaskId, Task>,
     task_queue: Arc<ArrayQueue<TaskId>>,
     waker_cache: BTreeMap<TaskId, Waker>,
@@ -16,6 +39,7 @@ pub struct Executor {
 impl Executor {
     pub fn spawn(&mut self, task: Task) {
         let task_id = task.id;
+        TASKS.lock().push((task_id, self.cpu, task.info.clone()));
         if self.tasks.insert(task.id, task).is_some() {
             panic!("task with same ID already in tasks");
         }
@@ -24,14 +48,40 @@ impl Executor {
 
     pub fn run(&mut self) -> ! {
         loop {
+            self.receive_tasks();
             self.run_ready_tasks();
             self.sleep_if_idle();
         }
     }
 
+    /// Returns all tasks that have not finished yet, ordered by their ID.
+    pub fn tasks() -> Vec<TaskStats> {
+        TASKS
+            .lock()
+            .iter()
+            .map(|(id, cpu, info)| TaskStats {
+                id: id.as_u64(),
+                name: info.name.clone(),
+                cpu: *cpu,
+                state: info.state(),
+                polls: info.polls(),
+                poll_nanos: info.poll_nanos(),
+            })
+            .collect()
+    }
+
+    /// Spawn all tasks that were sent to this CPU.
+    fn receive_tasks(&mut self) {
+        let received = core::mem::take(&mut *inbox(self.cpu).lock());
+        for (name, future) in received {
+            self.spawn(Task::new(&name, future));
+        }
+    }
+
     fn run_ready_tasks(&mut self) {
         // destructure `self` to avoid borrow checker errors
         let Self {
+            cpu,
             tasks,
             task_queue,
             waker_cache,
@@ -42,15 +92,22 @@ impl Executor {
                 Some(task) => task,
                 None => continue, // task no longer exists
             };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            let waker = waker_cache.entry(task_id).or_insert_with(|| {
+                TaskWaker::new(task_id, *cpu, task.info.clone(), task_queue.clone())
+            });
             let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
+
+            task.info.set_state(TaskState::Running);
+            let start = timer::nanos();
+            let poll = task.poll(&mut context);
+            task.info.polled(timer::nanos() - start);
+
+            match poll {
                 Poll::Ready(()) => {
                     // task done -> remove it and its cached waker
                     tasks.remove(&task_id);
                     waker_cache.remove(&task_id);
+                    TASKS.lock().retain(|(id, ..)| *id != task_id);
                 }
                 Poll::Pending => {}
             }
@@ -59,15 +116,17 @@ impl Executor {
 
     fn sleep_if_idle(&self) {
         interrupts::disable();
-        if self.task_queue.is_empty() {
+        if self.task_queue.is_empty() && inbox(self.cpu).lock().is_empty() {
             enable_and_hlt();
         } else {
             interrupts::enable();
         }
     }
 
+    /// Create the executor of the CPU this runs on.
     pub fn new() -> Self {
         Executor {
+            cpu: gdt::per_cpu().id as usize,
             tasks: BTreeMap::new(),
             task_queue: Arc::new(ArrayQueue::new(100)),
             waker_cache: BTreeMap::new(),
@@ -1,3 +1,41 @@
+use crate::scheduling::smp::MAX_CPUS;
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use spin::Mutex;
+
 pub mod executor;
+pub mod smp;
 pub mod task;
 pub mod waker;
+
+type SendFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Tasks sent to each CPU, waiting to be spawned by its executor.
+static INBOXES: [Mutex<Vec<(String, SendFuture)>>; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Mutex<Vec<(String, SendFuture)>> = Mutex::new(Vec::new());
+    [EMPTY; MAX_CPUS]
+};
+
+/// Spawn a task on one of the CPUs. Tasks are assigned to the other CPUs
+/// in turn, keeping the boot CPU free for the shell and interrupts.
+/// Only when there is no other CPU, tasks run on the boot CPU.
+pub fn spawn(name: &str, future: impl Future<Output = ()> + Send + 'static) {
+    static NEXT_CPU: AtomicUsize = AtomicUsize::new(0);
+    let cpus = smp::cpu_count();
+    let cpu = if cpus == 1 {
+        0
+    } else {
+        1 + NEXT_CPU.fetch_add(1, Ordering::Relaxed) % (cpus - 1)
+    };
+    inbox(cpu).lock().push((name.into(), Box::pin(future)));
+    smp::wake(cpu);
+}
+
+fn inbox(cpu: usize) -> &'static Mutex<Vec<(String, SendFuture)>> {
+    &INBOXES[cpu]
+}
@@ -0,0 +1,259 @@
+//! Starting the other CPUs of the system (application processors, APs).
+//! Every AP runs its own executor, and tasks are assigned to them statically
+//! when spawned with `scheduling::spawn`.
+
+use crate::{
+    allocator::memory::phys_to_virt,
+    drivers::{
+        acpi::Madt,
+        apic,
+        interrupts::{gdt, interrupts},
+        timer,
+        timer::TICKS_PER_SECOND,
+    },
+    info,
+    scheduling::executor::Executor,
+    warn,
+};
+use alloc::vec;
+use core::{
+    ptr,
+    sync::atomic::{AtomicU8, AtomicUsize, Ordering},
+};
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr,
+};
+
+/// The maximum amount of CPUs used, including the boot CPU.
+pub const MAX_CPUS: usize = 8;
+/// Physical (and virtual) address the trampoline is copied to.
+/// Needs to be below 1MiB, since APs start in real mode.
+const TRAMPOLINE: u64 = 0x8000;
+const AP_STACK_SIZE: usize = 4096 * 16;
+/// How long to wait for an AP to report that it started.
+const STARTUP_TIMEOUT_MS: u64 = 100;
+
+/// The amount of CPUs that finished starting up.
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+/// The index of the AP currently being started, which it claims before using
+/// anything given to it; `NOT_STARTING` if there is none or it was given up on.
+static STARTING: AtomicUsize = AtomicUsize::new(NOT_STARTING);
+const NOT_STARTING: usize = usize::MAX;
+/// The local APIC ID of every online CPU, by index.
+static APIC_IDS: [AtomicU8; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU8 = AtomicU8::new(0);
+    [ZERO; MAX_CPUS]
+};
+
+// The APs start in real mode at `TRAMPOLINE`, with this code copied there.
+// It switches straight into long mode with the kernel's page tables,
+// then calls `ap_main` with the stack and CPU index in the data at its end.
+// Since it is copied, all addresses are computed relative to `TRAMPOLINE`.
+global_asm!(
+    r#"
+.intel_syntax noprefix
+.section .text
+.global smp_trampoline_start
+.global smp_trampoline_end
+.global smp_trampoline_cr3
+.global smp_trampoline_stack
+.global smp_trampoline_entry
+.global smp_trampoline_cpu
+
+.code16
+smp_trampoline_start:
+    cli
+    cld
+    xor ax, ax
+    mov ds, ax
+
+    # PAE, PGE, OSFXSR and OSXMMEXCPT
+    mov eax, cr4
+    or eax, (1 << 5) | (1 << 7) | (1 << 9) | (1 << 10)
+    mov cr4, eax
+
+    mov eax, dword ptr [0x8000 + smp_trampoline_cr3 - smp_trampoline_start]
+    mov cr3, eax
+
+    # Long mode and no-execute
+    mov ecx, 0xC0000080
+    rdmsr
+    or eax, (1 << 8) | (1 << 11)
+    wrmsr
+
+    # Paging, protection and SSE (clear emulation, set monitor coprocessor)
+    mov eax, cr0
+    and eax, ~(1 << 2)
+    or eax, (1 << 31) | (1 << 1) | 1
+    mov cr0, eax
+
+    lgdt [0x8000 + smp_trampoline_gdt_ptr - smp_trampoline_start]
+    # jmp 0x8:smp_trampoline_long
+    .byte 0x66, 0xEA
+    .long 0x8000 + smp_trampoline_long - smp_trampoline_start
+    .word 0x8
+
+.code64
+smp_trampoline_long:
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    mov rsp, qword ptr [0x8000 + smp_trampoline_stack - smp_trampoline_start]
+    mov rdi, qword ptr [0x8000 + smp_trampoline_cpu - smp_trampoline_start]
+    mov rax, qword ptr [0x8000 + smp_trampoline_entry - smp_trampoline_start]
+    call rax
+    ud2
+
+.align 8
+smp_trampoline_gdt:
+    .quad 0
+    .quad 0x00209A0000000000
+smp_trampoline_gdt_ptr:
+    .word smp_trampoline_gdt_ptr - smp_trampoline_gdt - 1
+    .long 0x8000 + smp_trampoline_gdt - smp_trampoline_start
+
+.align 8
+smp_trampoline_cr3: .quad 0
+smp_trampoline_stack: .quad 0
+smp_trampoline_entry: .quad 0
+smp_trampoline_cpu: .quad 0
+smp_trampoline_end:
+.att_syntax prefix
+"#
+);
+
+extern "C" {
+    static smp_trampoline_start: u8;
+    static smp_trampoline_end: u8;
+    static smp_trampoline_cr3: u8;
+    static smp_trampoline_stack: u8;
+    static smp_trampoline_entry: u8;
+    static smp_trampoline_cpu: u8;
+}
+
+/// Start all APs described by the MADT, up to `MAX_CPUS`.
+/// The APIC needs to be initialized already.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    madt: &Madt,
+) -> Result<(), MapToError<Size4KiB>> {
+    let boot_cpu = apic::id();
+    APIC_IDS[0].store(boot_cpu, Ordering::Relaxed);
+    // The trampoline loads CR3 before it leaves 32-bit mode
+    let (pml4, _) = Cr3::read();
+    assert!(
+        pml4.start_address().as_u64() < 1 << 32,
+        "page tables need to be below 4 GiB to start APs"
+    );
+
+    // The trampoline keeps executing at the same address once paging is enabled
+    let frame = PhysFrame::containing_address(PhysAddr::new(TRAMPOLINE));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    match unsafe { mapper.identity_map(frame, flags, frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(MapToError::PageAlreadyMapped(_)) => (),
+        Err(err) => return Err(err),
+    }
+
+    unsafe { copy_trampoline() };
+    for apic_id in madt.processors.iter().copied().filter(|id| *id != boot_cpu) {
+        let index = ONLINE.load(Ordering::Acquire);
+        if index == MAX_CPUS {
+            break;
+        }
+        APIC_IDS[index].store(apic_id, Ordering::Relaxed);
+        unsafe { set_trampoline_data(index) };
+        STARTING.store(index, Ordering::Release);
+
+        apic::start_cpu(apic_id, (TRAMPOLINE / 4096) as u8);
+        let deadline = timer::ticks() + STARTUP_TIMEOUT_MS * TICKS_PER_SECOND as u64 / 1000;
+        while ONLINE.load(Ordering::Acquire) == index && timer::ticks() < deadline {
+            x86_64::instructions::hlt();
+        }
+        if ONLINE.load(Ordering::Acquire) == index {
+            // Stop the AP, unless it claimed its index in the meantime. Otherwise, it
+            // could start later with the stack and index given to the next one.
+            if STARTING.swap(NOT_STARTING, Ordering::AcqRel) == index {
+                apic::stop_cpu(apic_id);
+                warn!("CPU with APIC ID {} did not start", apic_id);
+            } else {
+                while ONLINE.load(Ordering::Acquire) == index {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+    info!("{} CPUs online", cpu_count());
+    Ok(())
+}
+
+/// The amount of CPUs running an executor.
+pub fn cpu_count() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// Wake up the CPU with the given index, if it is halted.
+pub fn wake(cpu: usize) {
+    if apic::available() && cpu < cpu_count() {
+        apic::send_ipi(
+            APIC_IDS[cpu].load(Ordering::Relaxed),
+            interrupts::InterruptIndex::Wakeup as u8,
+        );
+    }
+}
+
+unsafe fn copy_trampoline() {
+    let start = &smp_trampoline_start as *const u8;
+    let len = &smp_trampoline_end as *const u8 as usize - start as usize;
+    let target = phys_to_virt(PhysAddr::new(TRAMPOLINE)).as_mut_ptr::<u8>();
+    ptr::copy_nonoverlapping(start, target, len);
+}
+
+/// Fill in the data at the end of the trampoline for the AP with the given index.
+unsafe fn set_trampoline_data(index: usize) {
+    let stack = vec![0u8; AP_STACK_SIZE].leak();
+    // The stack needs to be 16-byte aligned before the call to `ap_main`
+    let stack_end = (stack.as_ptr() as u64 + AP_STACK_SIZE as u64) & !0xF;
+    let (cr3, _) = Cr3::read();
+
+    write_trampoline(&smp_trampoline_cr3, cr3.start_address().as_u64());
+    write_trampoline(&smp_trampoline_stack, stack_end);
+    write_trampoline(&smp_trampoline_entry, ap_main as u64);
+    write_trampoline(&smp_trampoline_cpu, index as u64);
+}
+
+/// Write to the copy of the given field of the trampoline.
+unsafe fn write_trampoline(field: &u8, value: u64) {
+    let offset = field as *const u8 as u64 - &smp_trampoline_start as *const u8 as u64;
+    let target = phys_to_virt(PhysAddr::new(TRAMPOLINE + offset));
+    ptr::write_volatile(target.as_mut_ptr::<u64>(), value);
+}
+
+/// Entry point of every AP, called by the trampoline with the index of the CPU.
+extern "C" fn ap_main(index: u64) -> ! {
+    let claimed = STARTING.compare_exchange(
+        index as usize,
+        NOT_STARTING,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+    if claimed.is_err() {
+        // Started too late, the boot CPU is about to stop this one
+        loop {
+            x86_64::instructions::hlt();
+        }
+    }
+    gdt::init_ap(index as u32);
+    interrupts::init_idt();
+    apic::enable();
+    ONLINE.fetch_add(1, Ordering::Release);
+    x86_64::instructions::interrupts::enable();
+    Executor::new().run()
+}
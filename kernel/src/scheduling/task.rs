@@ -1,8 +1,8 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, string::String, sync::Arc};
 use core::{
     future::Future,
     pin::Pin,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
     task::{Context, Poll},
 };
 
@@ -14,10 +14,15 @@ impl TaskId {
         static NEXT_ID: AtomicU64 = AtomicU64::new(0);
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
+
+    pub(super) fn as_u64(self) -> u64 {
+        self.0
+    }
 }
 
 pub struct Task {
     pub(super) id: TaskId,
+    pub(super) info: Arc<TaskInfo>,
     future: Pin<Box<dyn Future<Output = ()>>>,
 }
 
@@ -26,10 +31,75 @@ impl Task {
         self.future.as_mut().poll(context)
     }
 
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+    pub fn new(name: &str, future: impl Future<Output = ()> + 'static) -> Task {
         Task {
             id: TaskId::new(),
+            info: Arc::new(TaskInfo::new(name)),
             future: Box::pin(future),
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum TaskState {
+    /// The task is queued to be polled.
+    Ready,
+    /// The task is currently being polled.
+    Running,
+    /// The task is waiting to be woken up.
+    Waiting,
+}
+
+/// Statistics of a task, shared between the executor and the task's waker.
+/// Only uses atomics, since wakers might be called from interrupt handlers.
+pub(super) struct TaskInfo {
+    pub(super) name: String,
+    state: AtomicU8,
+    polls: AtomicU64,
+    poll_nanos: AtomicU64,
+}
+
+impl TaskInfo {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            state: AtomicU8::new(TaskState::Ready as u8),
+            polls: AtomicU64::new(0),
+            poll_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn state(&self) -> TaskState {
+        match self.state.load(Ordering::Acquire) {
+            0 => TaskState::Ready,
+            1 => TaskState::Running,
+            _ => TaskState::Waiting,
+        }
+    }
+
+    pub(super) fn set_state(&self, state: TaskState) {
+        self.state.store(state as u8, Ordering::Release)
+    }
+
+    /// Record a poll that took the given time, after which the task
+    /// is waiting unless it was woken up while being polled.
+    pub(super) fn polled(&self, nanos: u64) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.poll_nanos.fetch_add(nanos, Ordering::Relaxed);
+        let _ = self.state.compare_exchange(
+            TaskState::Running as u8,
+            TaskState::Waiting as u8,
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        );
+    }
+
+    pub(super) fn polls(&self) -> u64 {
+        self.polls.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn poll_nanos(&self) -> u64 {
+        self.poll_nanos.load(Ordering::Relaxed)
+    }
+}
@@ -1,21 +1,42 @@
-use crate::scheduling::task::TaskId;
+use crate::{
+    drivers::interrupts::gdt,
+    scheduling::{
+        smp,
+        task::{TaskId, TaskInfo, TaskState},
+    },
+};
 use alloc::{sync::Arc, task::Wake};
 use core::task::Waker;
 use crossbeam_queue::ArrayQueue;
 
 pub(super) struct TaskWaker {
     task_id: TaskId,
+    /// The CPU running the task.
+    cpu: usize,
+    info: Arc<TaskInfo>,
     task_queue: Arc<ArrayQueue<TaskId>>,
 }
 
 impl TaskWaker {
     fn wake_task(&self) {
+        self.info.set_state(TaskState::Ready);
         self.task_queue.push(self.task_id).expect("task_queue full");
+        // Wakers might be called from other CPUs, like in interrupt handlers
+        if gdt::per_cpu().id as usize != self.cpu {
+            smp::wake(self.cpu);
+        }
     }
 
-    pub(super) fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+    pub(super) fn new(
+        task_id: TaskId,
+        cpu: usize,
+        info: Arc<TaskInfo>,
+        task_queue: Arc<ArrayQueue<TaskId>>,
+    ) -> Waker {
         Waker::from(Arc::new(TaskWaker {
             task_id,
+            cpu,
+            info,
             task_queue,
         }))
     }
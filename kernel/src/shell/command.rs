@@ -1,48 +1,385 @@
 use alloc::{
+    collections::BTreeMap,
     format,
     string::{String, ToString},
+    vec::Vec,
 };
 use logos::{Lexer, Logos};
 
 #[derive(Debug)]
 pub enum Command {
-    Ls { directory: Option<String> },
+    Ls { args: LsArgs },
+    Du { directory: Option<String> },
+    Find { pattern: String },
     Cat { file: String },
+    Less { file: String },
+    Hexdump { file: String },
+    Img { file: String, x: usize, y: usize },
+    Stat { file: String },
     Cd { directory: String },
     Mkdir { directory: String },
     Put { file: String, text: String },
-    Exec { file: String },
+    Exec { args: ExecArgs },
+    Profile { file: String, args: Vec<String> },
+    Fmt { file: String },
+    Meminfo,
+    Beep { frequency: u32, ms: u64 },
+    Ps,
+    Jobs,
+    Kill { job: usize },
+    Loglevel { args: Vec<String> },
+    Run { file: String },
+    Help,
+    Explain { code: String },
+    Clear,
+    Echo { args: Vec<String> },
+    Alias { args: Vec<String> },
+    Sync,
+    Volinfo,
+    Win { args: Vec<String> },
+    Copy { args: Vec<String> },
+    Paste,
     Exit,
 }
 
+#[derive(Debug)]
+pub struct LsArgs {
+    pub directory: Option<String>,
+    /// Whether `-R` was given, to list subdirectories as well.
+    pub recursive: bool,
+    /// Whether `-l` was given, to show when files were modified.
+    pub long: bool,
+}
+
+#[derive(Debug)]
+pub struct ExecArgs {
+    pub file: String,
+    pub args: Vec<String>,
+    /// Whether `--stats` was given, to show the size of the program's code.
+    pub stats: bool,
+}
+
+/// Usage and description of every command, shown by `help`.
+pub const USAGE: &[(&str, &str)] = &[
+    (
+        "ls [-R] [-l] [dir]",
+        "list the files in a directory and their sizes",
+    ),
+    (
+        "du [dir]",
+        "show the size of a directory and its subdirectories",
+    ),
+    ("find <pattern>", "find files with names matching a pattern"),
+    ("cat <file>", "print a file"),
+    ("less <file>", "show a file one screen at a time"),
+    ("hexdump <file>", "print a file as hex and ASCII"),
+    ("img <file> [x y]", "draw a BMP image on the screen"),
+    (
+        "stat <file>",
+        "show size, timestamps and attributes of a file",
+    ),
+    ("cd <dir>", "change the working directory"),
+    ("mkdir <dir>", "create a directory"),
+    ("put <file> <text>", "write text to a file"),
+    (
+        "exec [--stats] <file> [args...]",
+        "run a program, showing its code size with --stats",
+    ),
+    (
+        "profile exec <file> [args...]",
+        "run a program and profile it",
+    ),
+    ("fmt <file>", "format the source code of a program"),
+    ("meminfo", "show heap usage"),
+    ("beep [hz] [ms]", "play a tone on the PC speaker"),
+    ("ps", "list running tasks"),
+    ("jobs", "list programs running in the background"),
+    ("kill <job>", "stop a background program, Ctrl+C stops exec"),
+    ("loglevel [module] [level]", "show or set log levels"),
+    ("run <file>", "run the commands in a file"),
+    ("help", "show this list"),
+    ("explain <code>", "explain a compile error, like E503"),
+    ("clear", "clear the screen"),
+    ("echo [args...]", "print the arguments"),
+    ("alias [name \"command\"]", "list aliases or define one"),
+    ("copy [text...]", "copy text to the clipboard"),
+    ("paste", "print the clipboard"),
+    ("sync", "write all changes to the filesystem to disk"),
+    (
+        "volinfo",
+        "show label, type and free space of the filesystem",
+    ),
+    (
+        "win [focus|close|move ...]",
+        "list, focus, close or move windows",
+    ),
+    ("exit", "unmount the filesystem and shut down"),
+];
+
+/// Frequency and duration of `beep` without arguments.
+const DEFAULT_BEEP: (u32, u64) = (440, 200);
+
+/// Names that expand to a command line when used as a command.
+/// Arguments given to an alias are appended to its expansion.
+#[derive(Debug, Default, Clone)]
+pub struct Aliases(BTreeMap<String, String>);
+
+impl Aliases {
+    pub fn define(&mut self, name: String, expansion: String) {
+        self.0.insert(name, expansion);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.iter()
+    }
+
+    /// Aliases used inside of the expansion of `name`,
+    /// which cannot expand to itself again.
+    fn without(&self, name: &str) -> Aliases {
+        let mut aliases = self.clone();
+        aliases.0.remove(name);
+        aliases
+    }
+}
+
 impl Command {
-    pub fn from(input: &str) -> Result<Option<Command>, String> {
-        let mut lexer = Lexer::<Token>::new(input);
-        let cmd = lexer.next();
+    /// Parse a line of input, which may consist of multiple
+    /// commands chained with `&&`, expanding the given aliases.
+    pub fn parse_line(input: &str, aliases: &Aliases) -> Result<Vec<Command>, String> {
+        Parser::new(input, aliases).line()
+    }
+
+    /// Find what the line expects at the given byte index, to complete the word there.
+    /// Returns `None` if nothing can be completed, like after a syntax error.
+    pub fn expected_at(input: &str, cursor: usize, aliases: &Aliases) -> Option<Completion> {
+        // Only what comes before the cursor decides what the word there is
+        let mut parser = Parser::new(&input[..cursor], aliases);
+        parser.completing = true;
+        parser.line().ok();
+        parser.completion
+    }
+}
+
+/// What kind of word a command line expects somewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    Command,
+    Path,
+    Number,
+    /// Any text, like the arguments of `echo`.
+    Text,
+}
+
+/// The word at the cursor, as found by `Command::expected_at`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    pub expected: Expected,
+    /// The byte index the word starts at, which is the cursor if it is not inside a word.
+    pub start: usize,
+}
+
+/// Parses the commands of a line, one token at a time.
+struct Parser<'a> {
+    lexer: Lexer<'a, Token>,
+    aliases: &'a Aliases,
+    /// Whether the input ends at a cursor, which the word at is completed.
+    completing: bool,
+    completion: Option<Completion>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str, aliases: &'a Aliases) -> Self {
+        Self {
+            lexer: Lexer::new(input),
+            aliases,
+            completing: false,
+            completion: None,
+        }
+    }
+
+    fn line(&mut self) -> Result<Vec<Command>, String> {
+        let mut commands = Vec::new();
+        loop {
+            self.expect_word(Expected::Command);
+            if let Some(expanded) = self.expand_alias()? {
+                commands.extend(expanded);
+            } else if let Some(command) = self.command()? {
+                commands.push(command);
+            } else {
+                break;
+            }
+
+            match self.lexer.next() {
+                Some(Token::AndAnd) => (),
+                None => break,
+                _ => {
+                    return Err(format!(
+                        "Expected '&&' or end of line, found '{}'.",
+                        self.lexer.slice()
+                    ))
+                }
+            }
+        }
+        Ok(commands)
+    }
+
+    fn command(&mut self) -> Result<Option<Command>, String> {
+        let cmd = self.lexer.next();
         match cmd {
-            Some(Token::Ls) => Ok(Some(Command::Ls {
-                directory: optional_path_arg(&mut lexer)?,
+            Some(Token::Ls) => {
+                let (mut recursive, mut long) = (false, false);
+                loop {
+                    self.expect_word(Expected::Path);
+                    match self.peek() {
+                        Some(Token::Recursive) => recursive = true,
+                        Some(Token::Long) => long = true,
+                        _ => break,
+                    }
+                    self.lexer.next();
+                }
+                Ok(Some(Command::Ls {
+                    args: LsArgs {
+                        directory: self.optional_path_arg()?,
+                        recursive,
+                        long,
+                    },
+                }))
+            }
+
+            Some(Token::Du) => Ok(Some(Command::Du {
+                directory: self.optional_path_arg()?,
+            })),
+
+            Some(Token::Find) => Ok(Some(Command::Find {
+                pattern: self.path_arg()?,
             })),
 
             Some(Token::Cat) => Ok(Some(Command::Cat {
-                file: path_arg(&mut lexer)?,
+                file: self.path_arg()?,
+            })),
+
+            Some(Token::Less) => Ok(Some(Command::Less {
+                file: self.path_arg()?,
+            })),
+
+            Some(Token::Hexdump) => Ok(Some(Command::Hexdump {
+                file: self.path_arg()?,
+            })),
+
+            Some(Token::Img) => {
+                let file = self.path_arg()?;
+                let (x, y) = if self.at_end() {
+                    (0, 0)
+                } else {
+                    (self.int_arg()?, self.int_arg()?)
+                };
+                Ok(Some(Command::Img { file, x, y }))
+            }
+
+            Some(Token::Stat) => Ok(Some(Command::Stat {
+                file: self.path_arg()?,
             })),
 
             Some(Token::Cd) => Ok(Some(Command::Cd {
-                directory: path_arg(&mut lexer)?,
+                directory: self.path_arg()?,
             })),
 
             Some(Token::Mkdir) => Ok(Some(Command::Mkdir {
-                directory: path_arg(&mut lexer)?,
+                directory: self.path_arg()?,
             })),
 
             Some(Token::Put) => Ok(Some(Command::Put {
-                file: path_arg(&mut lexer)?,
-                text: path_arg(&mut lexer)?, // todo technically not a path, eh whatever
+                file: self.path_arg()?,
+                text: self.text_arg()?,
             })),
 
-            Some(Token::Exec) => Ok(Some(Command::Exec {
-                file: path_arg(&mut lexer)?,
+            Some(Token::Exec) => {
+                self.expect_word(Expected::Path);
+                let stats = self.peek() == Some(Token::Stats);
+                if stats {
+                    self.lexer.next();
+                }
+                Ok(Some(Command::Exec {
+                    args: ExecArgs {
+                        file: self.path_arg()?,
+                        args: self.rest_args()?,
+                        stats,
+                    },
+                }))
+            }
+
+            Some(Token::Profile) => {
+                self.expect(Token::Exec)?;
+                Ok(Some(Command::Profile {
+                    file: self.path_arg()?,
+                    args: self.rest_args()?,
+                }))
+            }
+
+            Some(Token::Fmt) => Ok(Some(Command::Fmt {
+                file: self.path_arg()?,
+            })),
+
+            Some(Token::Meminfo) => Ok(Some(Command::Meminfo)),
+
+            Some(Token::Beep) => {
+                let mut args = [DEFAULT_BEEP.0 as usize, DEFAULT_BEEP.1 as usize];
+                for arg in &mut args {
+                    if self.at_end() {
+                        break;
+                    }
+                    *arg = self.int_arg()?;
+                }
+                Ok(Some(Command::Beep {
+                    frequency: args[0] as u32,
+                    ms: args[1] as u64,
+                }))
+            }
+
+            Some(Token::Ps) => Ok(Some(Command::Ps)),
+
+            Some(Token::Jobs) => Ok(Some(Command::Jobs)),
+
+            Some(Token::Kill) => Ok(Some(Command::Kill {
+                job: self.int_arg()?,
+            })),
+
+            Some(Token::Loglevel) => Ok(Some(Command::Loglevel {
+                args: self.rest_args()?,
+            })),
+
+            Some(Token::Run) => Ok(Some(Command::Run {
+                file: self.path_arg()?,
+            })),
+
+            Some(Token::Help) => Ok(Some(Command::Help)),
+
+            Some(Token::Explain) => Ok(Some(Command::Explain {
+                code: self.text_arg()?,
+            })),
+
+            Some(Token::Clear) => Ok(Some(Command::Clear)),
+
+            Some(Token::Echo) => Ok(Some(Command::Echo {
+                args: self.rest_args()?,
+            })),
+
+            Some(Token::Alias) => Ok(Some(Command::Alias {
+                args: self.rest_args()?,
+            })),
+
+            Some(Token::Copy) => Ok(Some(Command::Copy {
+                args: self.rest_args()?,
+            })),
+
+            Some(Token::Paste) => Ok(Some(Command::Paste)),
+
+            Some(Token::Sync) => Ok(Some(Command::Sync)),
+
+            Some(Token::Volinfo) => Ok(Some(Command::Volinfo)),
+
+            Some(Token::Win) => Ok(Some(Command::Win {
+                args: self.rest_args()?,
             })),
 
             Some(Token::Exit) => Ok(Some(Command::Exit)),
@@ -50,35 +387,119 @@ impl Command {
             None => Ok(None),
             _ => Err(format!(
                 "Expected a command, found '{}' ({:?}).",
-                lexer.slice(),
+                self.lexer.slice(),
                 cmd
             )),
         }
     }
-}
 
-fn path_arg(lexer: &mut Lexer<Token>) -> Result<String, String> {
-    match lexer.next() {
-        Some(Token::Word | Token::Path | Token::Int) => Ok(lexer.slice().to_string()),
-        Some(Token::Quote) => Ok(lexer.slice()[1..lexer.slice().len() - 1].to_string()),
-        _ => Err(format!("Expected path, found '{}'", lexer.slice())),
+    /// If the next command is an alias, parse its expansion
+    /// with the arguments given to it appended.
+    fn expand_alias(&mut self) -> Result<Option<Vec<Command>>, String> {
+        let mut peeked = self.lexer.clone();
+        if peeked.next() != Some(Token::Word) {
+            return Ok(None);
+        }
+        let name = peeked.slice();
+        let expansion = match self.aliases.0.get(name) {
+            Some(expansion) => expansion,
+            None => return Ok(None),
+        };
+
+        self.lexer.next();
+        let mut line = expansion.clone();
+        for arg in self.rest_args()? {
+            line.push_str(&format!(" \"{}\"", arg));
+        }
+        Command::parse_line(&line, &self.aliases.without(name)).map(Some)
     }
-}
 
-fn optional_path_arg(lexer: &mut Lexer<Token>) -> Result<Option<String>, String> {
-    match lexer.next() {
-        Some(Token::Word | Token::Path | Token::Int) => Ok(Some(lexer.slice().to_string())),
-        Some(Token::Quote) => Ok(Some(lexer.slice()[1..lexer.slice().len() - 1].to_string())),
-        None => Ok(None),
-        _ => Err(format!("Expected path, found '{}'", lexer.slice())),
+    /// Note that the next word is of the given kind, which is the word
+    /// to complete if it ends at the cursor or there is none.
+    fn expect_word(&mut self, expected: Expected) {
+        if !self.completing || self.completion.is_some() {
+            return;
+        }
+        let mut peeked = self.lexer.clone();
+        let input = self.lexer.source();
+        let start = match peeked.next() {
+            None => input.len(),
+            Some(_) if peeked.span().end == input.len() => peeked.span().start,
+            Some(_) => return,
+        };
+        self.completion = Some(Completion { expected, start });
+    }
+
+    fn path_arg(&mut self) -> Result<String, String> {
+        self.expect_word(Expected::Path);
+        self.word_arg("path")
+    }
+
+    fn text_arg(&mut self) -> Result<String, String> {
+        self.expect_word(Expected::Text);
+        self.word_arg("argument")
     }
-}
 
-fn _expect(expected: Token, was: Token) -> Result<(), String> {
-    if was == expected {
-        Ok(())
-    } else {
-        Err(format!("Expected '{:?}', found '{:?}'.", expected, was))
+    fn word_arg(&mut self, name: &str) -> Result<String, String> {
+        let slice = match self.lexer.next() {
+            Some(Token::Word | Token::Path | Token::Int | Token::Float) => self.lexer.slice(),
+            // Flags of commands are plain words to others, like programs
+            Some(Token::Recursive | Token::Long | Token::Stats) => self.lexer.slice(),
+            Some(Token::Quote) => &self.lexer.slice()[1..self.lexer.slice().len() - 1],
+            _ => return Err(format!("Expected {}, found '{}'", name, self.lexer.slice())),
+        };
+        Ok(slice.to_string())
+    }
+
+    fn int_arg(&mut self) -> Result<usize, String> {
+        self.expect_word(Expected::Number);
+        let number = match self.lexer.next() {
+            Some(Token::Int | Token::Path) => self.lexer.slice().parse().ok(),
+            _ => None,
+        };
+        number.ok_or_else(|| format!("Expected number, found '{}'", self.lexer.slice()))
+    }
+
+    fn optional_path_arg(&mut self) -> Result<Option<String>, String> {
+        self.expect_word(Expected::Path);
+        if self.at_end() {
+            Ok(None)
+        } else {
+            self.path_arg().map(Some)
+        }
+    }
+
+    /// Collect all remaining arguments until the end of the command.
+    fn rest_args(&mut self) -> Result<Vec<String>, String> {
+        let mut args = Vec::new();
+        loop {
+            self.expect_word(Expected::Text);
+            if self.at_end() {
+                break;
+            }
+            args.push(self.text_arg()?);
+        }
+        Ok(args)
+    }
+
+    /// Whether the current command has no more arguments.
+    fn at_end(&self) -> bool {
+        matches!(self.peek(), None | Some(Token::AndAnd))
+    }
+
+    fn peek(&self) -> Option<Token> {
+        self.lexer.clone().next()
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.lexer.next() {
+            Some(was) if was == expected => Ok(()),
+            _ => Err(format!(
+                "Expected '{:?}', found '{}'.",
+                expected,
+                self.lexer.slice()
+            )),
+        }
     }
 }
 
@@ -88,8 +509,26 @@ fn _expect(expected: Token, was: Token) -> Result<(), String> {
 enum Token {
     #[token("ls")]
     Ls,
+    #[token("-R")]
+    Recursive,
+    #[token("-l")]
+    Long,
+    #[token("--stats")]
+    Stats,
+    #[token("du")]
+    Du,
+    #[token("find")]
+    Find,
     #[token("cat")]
     Cat,
+    #[token("less")]
+    Less,
+    #[token("hexdump")]
+    Hexdump,
+    #[token("img")]
+    Img,
+    #[token("stat")]
+    Stat,
     #[token("cd")]
     Cd,
     #[token("mkdir")]
@@ -98,12 +537,53 @@ enum Token {
     Put,
     #[token("exec")]
     Exec,
+    #[token("profile")]
+    Profile,
+    #[token("fmt")]
+    Fmt,
+    #[token("meminfo")]
+    Meminfo,
+    #[token("beep")]
+    Beep,
+    #[token("ps")]
+    Ps,
+    #[token("jobs")]
+    Jobs,
+    #[token("kill")]
+    Kill,
+    #[token("loglevel")]
+    Loglevel,
+    #[token("run")]
+    Run,
+    #[token("help")]
+    Help,
+    #[token("explain")]
+    Explain,
+    #[token("clear")]
+    Clear,
+    #[token("echo")]
+    Echo,
+    #[token("alias")]
+    Alias,
+    #[token("copy")]
+    Copy,
+    #[token("paste")]
+    Paste,
+    #[token("sync")]
+    Sync,
+    #[token("volinfo")]
+    Volinfo,
+    #[token("win")]
+    Win,
     #[token("exit")]
     Exit,
 
+    #[token("&&")]
+    AndAnd,
+
     #[regex("[a-zA-Z_][a-zA-Z0-9_]*", priority = 2)]
     Word,
-    #[regex("[a-zA-Z0-9_/.]*")]
+    #[regex("[a-zA-Z0-9_/.:*?-]*")]
     Path,
     #[regex("\"[^\"]*\"")]
     Quote,
@@ -118,3 +598,144 @@ enum Token {
     #[error]
     Error,
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Aliases, Command, Completion, Expected, LsArgs};
+
+    #[test_case]
+    fn aliases() {
+        let mut aliases = Aliases::default();
+        aliases.define("ll".into(), "ls system".into());
+        aliases.define("greet".into(), "echo hello".into());
+        aliases.define("again".into(), "again".into());
+
+        let commands = Command::parse_line("ll && greet world", &aliases).unwrap();
+        assert!(matches!(
+            commands.as_slice(),
+            [
+                Command::Ls {
+                    args: LsArgs {
+                        directory: Some(dir),
+                        recursive: false,
+                        long: false
+                    }
+                },
+                Command::Echo { args },
+            ] if dir == "system" && args == &["hello", "world"]
+        ));
+        assert!(Command::parse_line("again", &aliases).is_err());
+    }
+
+    #[test_case]
+    fn recursive_ls() {
+        let commands = Command::parse_line("ls -R system && find *.yacari", &Aliases::default());
+        assert!(matches!(
+            commands.unwrap().as_slice(),
+            [
+                Command::Ls {
+                    args: LsArgs {
+                        directory: Some(dir),
+                        recursive: true,
+                        long: false
+                    }
+                },
+                Command::Find { pattern },
+            ] if dir == "system" && pattern == "*.yacari"
+        ));
+
+        let commands = Command::parse_line("ls -l -R && ls -l", &Aliases::default());
+        assert!(matches!(
+            commands.unwrap().as_slice(),
+            [
+                Command::Ls {
+                    args: LsArgs {
+                        directory: None,
+                        recursive: true,
+                        long: true
+                    }
+                },
+                Command::Ls {
+                    args: LsArgs {
+                        directory: None,
+                        recursive: false,
+                        long: true
+                    }
+                },
+            ]
+        ));
+    }
+
+    #[test_case]
+    fn image_position() {
+        let commands =
+            Command::parse_line("img logo.bmp && img logo.bmp 40 80", &Aliases::default());
+        assert!(matches!(
+            commands.unwrap().as_slice(),
+            [
+                Command::Img { file, x: 0, y: 0 },
+                Command::Img { x: 40, y: 80, .. },
+            ] if file == "logo.bmp"
+        ));
+        assert!(Command::parse_line("img logo.bmp 40", &Aliases::default()).is_err());
+    }
+
+    #[test_case]
+    fn kill() {
+        let commands = Command::parse_line("jobs && kill 3", &Aliases::default());
+        assert!(matches!(
+            commands.unwrap().as_slice(),
+            [Command::Jobs, Command::Kill { job: 3 }]
+        ));
+        assert!(Command::parse_line("kill", &Aliases::default()).is_err());
+        assert!(Command::parse_line("kill all", &Aliases::default()).is_err());
+    }
+
+    #[test_case]
+    fn expected_words() {
+        let mut aliases = Aliases::default();
+        aliases.define("ll".into(), "ls -l".into());
+        let expected = |input: &str| {
+            Command::expected_at(input, input.len(), &aliases)
+                .map(|Completion { expected, start }| (expected, start))
+        };
+
+        assert_eq!(expected(""), Some((Expected::Command, 0)));
+        assert_eq!(expected("ca"), Some((Expected::Command, 0)));
+        assert_eq!(expected("cat"), Some((Expected::Command, 0)));
+        assert_eq!(expected("cat "), Some((Expected::Path, 4)));
+        assert_eq!(expected("cat system/fo"), Some((Expected::Path, 4)));
+        assert_eq!(expected("ls -R "), Some((Expected::Path, 6)));
+        assert_eq!(expected("ll sys"), Some((Expected::Text, 3)));
+        assert_eq!(expected("exec prog.yacari a"), Some((Expected::Text, 17)));
+        assert_eq!(expected("img logo.bmp 4"), Some((Expected::Number, 13)));
+        assert_eq!(expected("cd a && l"), Some((Expected::Command, 8)));
+        assert_eq!(expected("cd a b && l"), None);
+
+        // Words after the cursor do not matter
+        let input = "cat file && ls";
+        assert_eq!(
+            Command::expected_at(input, 6, &aliases),
+            Some(Completion {
+                expected: Expected::Path,
+                start: 4
+            })
+        );
+    }
+
+    #[test_case]
+    fn exec_stats() {
+        let commands = Command::parse_line(
+            "exec --stats game.yacari -v && exec game.yacari --stats",
+            &Aliases::default(),
+        );
+        assert!(matches!(
+            commands.unwrap().as_slice(),
+            [
+                Command::Exec { args: first },
+                Command::Exec { args: second },
+            ] if first.file == "game.yacari" && first.stats && first.args == ["-v"]
+                && !second.stats && second.args == ["--stats"]
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+//! The shell configuration, read by every shell when it starts.
+//! Every line has the form `<key> = <value>`, lines starting with `#` are comments.
+//! Keys are:
+//! - `workdir`: the working directory to start in
+//! - `alias.<name>`: an alias, like defined with the `alias` command
+//! - `color.text`, `color.prompt`, `color.command` and `color.directory`:
+//!   the colors of the console, by name
+//! - `loglevel` and `loglevel.<module>`: the default log level or that of a module
+//! - `run`: a command line to run once everything else is applied,
+//!   which can be given multiple times to run several in order
+
+use crate::{
+    drivers::vga_buffer::{Color, ColorScheme},
+    log::Level,
+};
+use alloc::{format, string::String, vec::Vec};
+
+/// Where the configuration is read from, relative to the root directory.
+pub const CONFIG_FILE: &str = "system/shellrc";
+
+#[derive(Debug, Default)]
+pub struct Config {
+    pub working_dir: Option<String>,
+    pub aliases: Vec<(String, String)>,
+    pub colors: ColorScheme,
+    /// Log levels by module, `None` setting the default level.
+    pub log_levels: Vec<(Option<String>, Level)>,
+    pub startup: Vec<String>,
+}
+
+impl Config {
+    /// Parse a configuration, skipping lines that are invalid.
+    /// Returns their line number together with why they are invalid.
+    pub fn parse(text: &str) -> (Config, Vec<(usize, String)>) {
+        let mut config = Config::default();
+        let mut errors = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            if let Err(msg) = config.parse_line(line) {
+                errors.push((index + 1, msg));
+            }
+        }
+        (config, errors)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("expected '<key> = <value>', found '{}'", line))?;
+        let (key, value) = (key.trim(), value.trim());
+        if value.is_empty() {
+            return Err(format!("missing value for '{}'", key));
+        }
+
+        match key.split_once('.') {
+            None if key == "workdir" => self.working_dir = Some(value.into()),
+            None if key == "run" => self.startup.push(value.into()),
+            None if key == "loglevel" => self.log_levels.push((None, level(value)?)),
+            Some(("loglevel", module)) => {
+                self.log_levels.push((Some(module.into()), level(value)?))
+            }
+            Some(("alias", name)) => self.aliases.push((name.into(), value.into())),
+            Some(("color", part)) => {
+                let color =
+                    Color::from_name(value).ok_or_else(|| format!("unknown color '{}'", value))?;
+                match part {
+                    "text" => self.colors.text = color,
+                    "prompt" => self.colors.prompt = color,
+                    "command" => self.colors.command = color,
+                    "directory" => self.colors.directory = color,
+                    _ => return Err(format!("unknown key '{}'", key)),
+                }
+            }
+            _ => return Err(format!("unknown key '{}'", key)),
+        }
+        Ok(())
+    }
+}
+
+fn level(name: &str) -> Result<Level, String> {
+    Level::from_name(name).ok_or_else(|| format!("unknown level '{}'", name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+    use crate::{drivers::vga_buffer::Color, log::Level};
+    use alloc::{string::String, vec::Vec};
+
+    #[test_case]
+    fn parse() {
+        let text = "# comment\n\nworkdir = system\nalias.ll = ls -R \"a b\"\n\
+                    color.prompt = light_green\nloglevel = warn\nloglevel.yacari = error\n\
+                    run = echo hi\nrun = ls";
+        let (config, errors) = Config::parse(text);
+        assert!(errors.is_empty());
+        assert_eq!(config.working_dir.as_deref(), Some("system"));
+        assert_eq!(
+            config.aliases,
+            [(String::from("ll"), String::from("ls -R \"a b\""))]
+        );
+        assert_eq!(config.colors.prompt, Color::LightGreen);
+        assert_eq!(config.colors.text, Color::Magenta);
+        assert_eq!(
+            config.log_levels,
+            [
+                (None, Level::Warn),
+                (Some(String::from("yacari")), Level::Error)
+            ]
+        );
+        assert_eq!(config.startup, ["echo hi", "ls"]);
+    }
+
+    #[test_case]
+    fn errors() {
+        let text = "workdir\nrun =\ncolor.text = purple\ncolor.border = red\nshell = sh\nrun = ls";
+        let (config, errors) = Config::parse(text);
+        let lines = errors.iter().map(|(line, _)| *line).collect::<Vec<_>>();
+        assert_eq!(lines, [1, 2, 3, 4, 5]);
+        assert_eq!(config.startup, ["ls"]);
+    }
+}
@@ -1,57 +1,123 @@
 use crate::{
+    allocator,
+    allocator::HeapStats,
+    clipboard,
+    clipboard::CLIPBOARD_FILE,
     drivers::{
-        disk::fat::{FatDir, FatFs},
-        vga_buffer::{vga_buffer, Color},
+        console, disk,
+        disk::{
+            buffer::IoBuffer,
+            fat::{FatDir, FatEntry, FatFs, Metadata, VolumeInfo},
+            path, walk_dir,
+        },
+        keymap::{KeyPress, Modifiers},
+        speaker,
+        vga_buffer::{Color, ColorScheme},
     },
-    kprintln, print, println,
-    shell::command::Command,
+    graphics,
+    graphics::{compositor, image::Image},
+    kprint, log,
+    log::Level,
+    print, println,
+    scheduling::executor::Executor,
+    shell::{
+        command::{Aliases, Command, LsArgs, USAGE},
+        config::{Config, CONFIG_FILE},
+        pager::Pager,
+    },
+    vm,
+    vm::RunError,
     QemuExitCode,
 };
 use alloc::{
+    collections::BTreeMap,
     format,
     string::{String, ToString},
     vec::Vec,
 };
-use core::cmp::min;
-use fatfs::{Read, Seek, SeekFrom, Write};
+use core::{cmp::min, convert::TryFrom, fmt::Write as _, mem};
+use fatfs::{Date, DateTime, Write};
 use pc_keyboard::{DecodedKey, KeyCode};
+use yacari::{ErrorKind, ProgramError};
 
 mod command;
+mod config;
+mod pager;
+
+/// How deeply scripts may run other scripts.
+const MAX_SCRIPT_DEPTH: usize = 8;
+/// Bytes shown per line by `hexdump`.
+const HEXDUMP_WIDTH: usize = 16;
+/// Modifiers that make C and V copy the current command and paste into it.
+const CLIPBOARD_MODIFIERS: Modifiers = Modifiers {
+    shift: true,
+    ..Modifiers::CTRL
+};
 
 pub struct Shell {
+    /// The virtual console the shell reads input from and draws its line on.
+    console: usize,
     filesystem: Option<FatFs>,
-    working_dir: Option<String>,
+    /// Normalized, see `disk::path`.
+    working_dir: String,
     current_command: String,
     cursor_pos: usize,
+    /// How many scripts are currently running, to stop scripts that run themselves.
+    script_depth: usize,
+    aliases: Aliases,
+    /// The pager opened by `less`, which receives all key presses until it is closed.
+    pager: Option<Pager>,
 }
 
 impl Shell {
-    pub fn key_pressed(&mut self, key: DecodedKey) {
-        match key {
+    pub fn key_pressed(&mut self, press: KeyPress) {
+        if let Some(pager) = &mut self.pager {
+            if pager.key_pressed(press) {
+                console::with_console(self.console, |w| pager.draw(w));
+            } else {
+                self.pager = None;
+                console::with_console(self.console, |w| {
+                    w.leave_alternate_screen();
+                    w.init_shell();
+                });
+                self.redraw();
+            }
+            return;
+        }
+        if press.modifiers == CLIPBOARD_MODIFIERS {
+            match press.key {
+                DecodedKey::Unicode('C' | 'c') => {
+                    self.copy(&self.current_command.clone());
+                }
+                DecodedKey::Unicode('V' | 'v') => self.paste(),
+                _ => (),
+            }
+            self.redraw();
+            return;
+        }
+        if press.is_command() {
+            return;
+        }
+        match press.key {
             DecodedKey::Unicode('\x08') => {
-                if self.cursor_at_end() {
-                    self.current_command.pop();
-                } else {
-                    self.current_command.remove(self.cursor_pos - 1);
+                if self.cursor_pos > 0 {
+                    self.cursor_pos -= 1;
+                    let index = self.byte_index(self.cursor_pos);
+                    self.current_command.remove(index);
                 }
-
-                self.cursor_pos -= 1;
             }
             DecodedKey::Unicode('\n') => self.enter_pressed(),
             DecodedKey::Unicode(character) => {
-                if self.cursor_at_end() {
-                    self.current_command.push(character);
-                } else {
-                    self.current_command.insert(self.cursor_pos, character);
-                }
+                let index = self.byte_index(self.cursor_pos);
+                self.current_command.insert(index, character);
                 self.cursor_pos += 1;
             }
 
             DecodedKey::RawKey(KeyCode::ArrowLeft) => {
-                self.cursor_pos = self.cursor_pos.checked_sub(1).unwrap_or(self.cursor_pos)
+                self.cursor_pos = self.cursor_pos.saturating_sub(1)
             }
             DecodedKey::RawKey(KeyCode::ArrowRight) => {
-                self.cursor_pos = min(78, self.cursor_pos + 1)
+                self.cursor_pos = min(self.line_length(), self.cursor_pos + 1)
             }
 
             DecodedKey::RawKey(key) => print!("{:?}", key),
@@ -60,120 +126,626 @@ impl Shell {
     }
 
     fn enter_pressed(&mut self) {
-        vga_buffer(|w| w.set_color(Color::Yellow));
+        console::with_console(self.console, |w| w.set_color(w.scheme().command));
         println!("> {}", self.current_command);
-        vga_buffer(|w| w.reset_color());
+        console::with_console(self.console, |w| w.reset_color());
 
-        let command = Command::from(&self.current_command);
-        match command {
-            Ok(Some(command)) => self.execute_command(command),
-            Ok(None) => (),
-            Err(msg) => println!("Failed to parse command: {}", msg),
-        }
-
-        self.current_command.clear();
+        let line = mem::take(&mut self.current_command);
+        self.execute_line(&line);
         self.cursor_pos = 0;
     }
 
-    fn execute_command(&mut self, command: Command) {
-        match command {
-            Command::Ls { directory } => {
-                let dir = if let Some(directory) = directory {
-                    self.workdir().open_dir(&directory)
-                } else {
-                    Ok(self.workdir())
-                };
+    /// Parse and execute a line of commands, stopping at the first one that fails.
+    /// Returns whether all of them succeeded.
+    fn execute_line(&mut self, line: &str) -> bool {
+        match Command::parse_line(line, &self.aliases) {
+            Ok(commands) => commands
+                .into_iter()
+                .all(|command| self.execute_command(command)),
+            Err(msg) => {
+                println!("Failed to parse command: {}", msg);
+                false
+            }
+        }
+    }
 
-                if let Ok(dir) = dir {
-                    let mut count = 0;
-                    for r in dir.iter() {
-                        let entry = r.unwrap();
-                        println!("{}", entry.file_name());
-                        count += 1;
+    /// Execute the given command, returning whether it succeeded.
+    fn execute_command(&mut self, command: Command) -> bool {
+        let success = match command {
+            Command::Ls { args } => self.ls(&args),
+
+            Command::Du { directory } => {
+                match self.open_dir(directory.as_deref().unwrap_or(".")) {
+                    Some(dir) => {
+                        // Size of every directory by its path, including subdirectories
+                        let mut sizes = BTreeMap::<String, u64>::new();
+                        let mut total = 0;
+                        walk_dir(dir, &mut |path, entry| {
+                            if entry.is_dir() {
+                                sizes.entry(path.join("/")).or_default();
+                            } else {
+                                for depth in 1..path.len() {
+                                    *sizes.entry(path[..depth].join("/")).or_default() +=
+                                        entry.len();
+                                }
+                                total += entry.len();
+                            }
+                        });
+                        for (path, size) in sizes {
+                            println!("{:>10}  {}", size, path);
+                        }
+                        println!("{:>10}  {}", total, directory.as_deref().unwrap_or("."));
+                        true
+                    }
+                    None => {
+                        println!("du: unknown directory");
+                        false
                     }
-                    println!("total {}", count)
-                } else {
-                    println!("ls: unknown directory")
                 }
             }
 
+            Command::Find { pattern } => {
+                walk_dir(self.workdir(), &mut |path, _| {
+                    if glob_match(&pattern, path.last().unwrap()) {
+                        println!("{}", path.join("/"));
+                    }
+                });
+                true
+            }
+
             Command::Cat { file } => {
                 let content = self.read_file(&file);
-                if let Some(content) = content {
+                if let Some(content) = &content {
                     println!("{} ({} bytes):\n{}", file, content.len(), content)
                 }
+                content.is_some()
             }
 
-            Command::Cd { directory } => {
-                let exists = self.workdir().open_dir(&directory).is_ok();
-                match (exists, self.working_dir.clone()) {
-                    (true, Some(workd)) => {
-                        self.working_dir = Some(format!("{}/{}", workd, directory))
+            Command::Less { file } => {
+                let content = self.read_file(&file);
+                if let Some(content) = &content {
+                    let pager = Pager::new(&file, content);
+                    console::with_console(self.console, |w| {
+                        w.enter_alternate_screen();
+                        pager.draw(w);
+                    });
+                    self.pager = Some(pager);
+                }
+                content.is_some()
+            }
+
+            Command::Hexdump { file } => {
+                let content = self.read_bytes(&file);
+                if let Some(content) = &content {
+                    for (index, chunk) in content.chunks(HEXDUMP_WIDTH).enumerate() {
+                        println!("{}", hexdump_line(index * HEXDUMP_WIDTH, chunk));
+                    }
+                    println!("{:08x}", content.len());
+                }
+                content.is_some()
+            }
+
+            Command::Img { file, x, y } => {
+                match self.read_bytes(&file).map(|data| Image::decode_bmp(&data)) {
+                    Some(Ok(image)) => {
+                        graphics::draw_image(x, y, &image);
+                        true
+                    }
+                    Some(Err(err)) => {
+                        println!("img: failed to decode image: {:?}", err);
+                        false
                     }
-                    (true, None) => self.working_dir = Some(directory),
-                    _ => println!("cd: unknown directory"),
+                    None => false,
+                }
+            }
+
+            Command::Stat { file } => match self.entry(&file) {
+                Some(entry) => {
+                    let meta = Metadata::of(&entry);
+                    let kind = if meta.is_dir { "directory" } else { "file" };
+                    println!("{}: {}, {} bytes", meta.name, kind, meta.len);
+                    println!("created:    {}", format_date_time(meta.created));
+                    println!("modified:   {}", format_date_time(meta.modified));
+                    println!("accessed:   {}", format_date(meta.accessed));
+                    println!("attributes: {:?}", meta.attributes);
+                    true
+                }
+                None => {
+                    println!("stat: {} does not exist", file);
+                    false
+                }
+            },
+
+            Command::Cd { directory } => {
+                let target = self.resolve(&directory);
+                let exists = path::open_dir(self.root(), &target).is_some();
+                if exists {
+                    self.working_dir = target;
+                } else {
+                    println!("cd: unknown directory");
                 }
+                exists
             }
 
             Command::Mkdir { directory } => {
-                let res = self.workdir().create_dir(&directory);
-                if let Err(err) = res {
+                let res = self.root().create_dir(&self.resolve(&directory));
+                if let Err(err) = &res {
                     println!("mkdir: failed to create directory: {:?}", err);
                 }
+                res.is_ok() && self.sync()
             }
 
             Command::Put { file, text } => {
-                let file = self.workdir().create_file(&file);
-                if let Ok(mut file) = file {
+                let file = self.root().create_file(&self.resolve(&file));
+                let written = if let Ok(mut file) = file {
                     let res = file.write_all(text.as_bytes());
-                    if let Err(err) = res {
+                    if let Err(err) = &res {
                         println!("put: failed to write file: {:?}", err);
                     }
+                    res.is_ok()
                 } else {
-                    println!("put: failed to open file")
+                    println!("put: failed to open file");
+                    false
+                };
+                written && self.sync()
+            }
+
+            Command::Exec { args } => self.exec(&args.file, &args.args, false, args.stats),
+
+            Command::Profile { file, args } => self.exec(&file, &args, true, false),
+
+            Command::Fmt { file } => self.format(&file),
+
+            Command::Meminfo => {
+                print_heap("heap", allocator::stats());
+                print_heap("code heaps", vm::code_heap_stats());
+                print_heap("code region", vm::code_region_stats());
+                true
+            }
+
+            Command::Beep { frequency, ms } => {
+                speaker::beep(frequency, ms);
+                true
+            }
+
+            Command::Ps => {
+                println!(
+                    "{:>4} {:>3}  {:<8} {:>8} {:>10}  NAME",
+                    "ID", "CPU", "STATE", "POLLS", "TIME"
+                );
+                for task in Executor::tasks() {
+                    println!(
+                        "{:>4} {:>3}  {:<8} {:>8} {:>8}ms  {}",
+                        task.id,
+                        task.cpu,
+                        format!("{:?}", task.state),
+                        task.polls,
+                        task.poll_nanos / 1_000_000,
+                        task.name
+                    );
+                }
+                true
+            }
+
+            Command::Jobs => {
+                for (id, name) in vm::jobs::background() {
+                    println!("{:>4}  {}", id, name);
+                }
+                true
+            }
+
+            Command::Kill { job } => {
+                let killed = vm::jobs::kill(job);
+                if !killed {
+                    println!("kill: no background job {}", job);
+                }
+                killed
+            }
+
+            Command::Loglevel { args } => self.loglevel(&args),
+
+            Command::Run { file } => self.run_script(&file),
+
+            Command::Help => {
+                for (usage, description) in USAGE {
+                    println!("{:<32}{}", usage, description);
+                }
+                true
+            }
+
+            Command::Explain { code } => match ErrorKind::explain_code(&code) {
+                Some(explanation) => {
+                    print!("{}", explanation);
+                    true
+                }
+                None => {
+                    println!("explain: unknown error code '{}'", code);
+                    false
                 }
+            },
+
+            Command::Clear => {
+                console::with_console(self.console, |w| w.clear());
+                // Also clear the terminal connected to serial
+                kprint!("\x1b[2J\x1b[H");
+                true
+            }
+
+            Command::Echo { args } => {
+                println!("{}", args.join(" "));
+                true
             }
 
-            Command::Exec { file } => {
-                let file = self.read_file(&file);
-                if let Some(file) = file {
-                    println!("executing {} ({} bytes)...", file, file.len());
-                    kprintln!("{:#?}", yacari::execute_module::<()>(&file, &[]))
+            Command::Alias { args } => match <[String; 2]>::try_from(args) {
+                Ok([name, expansion]) => {
+                    self.aliases.define(name, expansion);
+                    true
+                }
+                Err(args) if args.is_empty() => {
+                    for (name, expansion) in self.aliases.iter() {
+                        println!("alias {} \"{}\"", name, expansion);
+                    }
+                    true
+                }
+                Err(_) => {
+                    println!("usage: alias [name \"command\"]");
+                    false
+                }
+            },
+
+            Command::Copy { args } => {
+                let saved = self.copy(&args.join(" "));
+                if !saved {
+                    println!("copy: failed to save clipboard to {}", CLIPBOARD_FILE);
                 }
+                true
+            }
+
+            Command::Paste => {
+                println!("{}", clipboard::paste());
+                true
             }
 
+            Command::Sync => self.sync(),
+
+            Command::Win { args } => self.windows(&args),
+
+            Command::Volinfo => match VolumeInfo::read(self.filesystem.as_ref().unwrap()) {
+                Ok(info) => {
+                    println!("label:    {}", info.label);
+                    println!("id:       {:08x}", info.id);
+                    println!("type:     {:?}", info.fat_type);
+                    println!(
+                        "size:     {} KiB in {} clusters of {} bytes",
+                        info.total_bytes() / 1024,
+                        info.total_clusters,
+                        info.cluster_size
+                    );
+                    println!(
+                        "free:     {} KiB in {} clusters",
+                        info.free_bytes() / 1024,
+                        info.free_clusters
+                    );
+                    true
+                }
+                Err(err) => {
+                    println!("volinfo: failed to read volume: {:?}", err);
+                    false
+                }
+            },
+
             Command::Exit => {
-                self.filesystem.take().unwrap().unmount().unwrap();
+                disk::unmount(self.filesystem.take().unwrap()).unwrap();
                 crate::exit_qemu(QemuExitCode::Success);
             }
-        }
+        };
         println!();
+        success
+    }
+
+    /// List the entries of a directory with their sizes, directories in their own color
+    /// and with a trailing `/`. Names are padded to line up the columns after them.
+    fn ls(&self, args: &LsArgs) -> bool {
+        let dir = match self.open_dir(args.directory.as_deref().unwrap_or(".")) {
+            Some(dir) => dir,
+            None => {
+                println!("ls: unknown directory");
+                return false;
+            }
+        };
+        let entries = if args.recursive {
+            let mut entries = Vec::new();
+            walk_dir(dir, &mut |path, entry| {
+                entries.push(Metadata {
+                    name: path.join("/"),
+                    ..Metadata::of(entry)
+                })
+            });
+            entries
+        } else {
+            disk::list_dir(&dir)
+        };
+
+        let name_width = |meta: &Metadata| meta.name.chars().count() + meta.is_dir as usize;
+        let width = entries.iter().map(name_width).max().unwrap_or(0);
+        let colors = self.color_scheme();
+        for meta in &entries {
+            if meta.is_dir {
+                self.print_colored(colors.directory, &format!("{}/", meta.name));
+            } else {
+                print!("{}", meta.name);
+            }
+            let size = if meta.is_dir {
+                String::new()
+            } else {
+                format_size(meta.len)
+            };
+            print!("{:pad$}  {:>9}", "", size, pad = width - name_width(meta));
+            if args.long {
+                print!("  {}", format_date_time(meta.modified));
+            }
+            println!();
+        }
+        println!("total {}", entries.len());
+        true
+    }
+
+    /// Run the program at the given path, optionally printing a profile
+    /// of it or the size of its code afterwards. Returns whether it exited with 0.
+    fn exec(&mut self, file: &str, args: &[String], profile: bool, stats: bool) -> bool {
+        println!("executing {}...", file);
+        let working_dir = Some(self.working_dir.as_str());
+        // Discard timings of anything compiled before this
+        #[cfg(feature = "timings")]
+        yacari::take_timings();
+        let result = if profile {
+            vm::profile_path(working_dir, file, args)
+                .map(|(code, profile)| (code, Some(profile), None))
+        } else if stats {
+            vm::exec_path_with_stats(working_dir, file, args)
+                .map(|(code, report)| (code, None, Some(report)))
+        } else {
+            vm::exec_path(working_dir, file, args).map(|code| (code, None, None))
+        };
+
+        match result {
+            Ok((code, profile, report)) => {
+                println!("{} exited with code {}", file, code);
+                #[cfg(feature = "timings")]
+                println!("compiled in {}", yacari::take_timings());
+                if let Some(profile) = profile {
+                    println!("{}", profile);
+                }
+                if let Some(report) = report {
+                    println!("{}", report);
+                }
+                code == 0
+            }
+            Err(RunError::Program(ProgramError::Read(errors))) => {
+                println!("exec: {} could not be compiled", file);
+                for error in errors {
+                    println!("{}", error);
+                }
+                false
+            }
+            Err(RunError::Program(ProgramError::Compile(errors))) => {
+                println!("exec: {} failed to compile", file);
+                vm::report_errors(file, &errors);
+                false
+            }
+            Err(RunError::Program(ProgramError::Exec(err))) => {
+                println!("exec: failed to start {}: {:?}", file, err);
+                false
+            }
+            Err(RunError::Program(ProgramError::Target(err))) => {
+                println!("exec: failed to link {}: {:?}", file, err);
+                false
+            }
+            Err(RunError::Program(ProgramError::Link(err))) => {
+                println!("exec: failed to link {}: {:?}", file, err);
+                false
+            }
+            Err(RunError::Panicked { message, location }) => {
+                println!("{} panicked at {}: {}", file, location, message);
+                false
+            }
+            Err(RunError::Interrupted) => {
+                println!("{} was killed", file);
+                false
+            }
+            Err(RunError::Capability(err)) => {
+                println!("exec: refusing to run {}: {:?}", file, err);
+                false
+            }
+            Err(RunError::Unmounted) => {
+                println!("exec: filesystem is unmounted");
+                false
+            }
+            Err(RunError::OutOfCodeMemory) => {
+                println!("exec: no code memory left for {}", file);
+                false
+            }
+        }
+    }
+
+    /// Execute the commands in the given file line by line,
+    /// stopping at the first line that fails.
+    fn run_script(&mut self, file: &str) -> bool {
+        if self.script_depth == MAX_SCRIPT_DEPTH {
+            println!("run: scripts nested too deeply");
+            return false;
+        }
+        let script = match self.read_file(file) {
+            Some(script) => script,
+            None => return false,
+        };
+
+        self.script_depth += 1;
+        let failed_line = script.lines().position(|line| !self.execute_line(line));
+        self.script_depth -= 1;
+
+        if let Some(index) = failed_line {
+            println!("run: stopping at {}:{}", file, index + 1);
+        }
+        failed_line.is_none()
+    }
+
+    /// Show the log levels with no arguments, otherwise set the level
+    /// of the given module, or the default level if no module is given.
+    fn loglevel(&self, args: &[String]) -> bool {
+        let (module, level) = match args {
+            [] => {
+                println!("default: {}", log::default_level().name());
+                for (module, level) in log::filters() {
+                    println!("{}: {}", module, level.name());
+                }
+                return true;
+            }
+            [level] => (None, level),
+            [module, level] => (Some(module.as_str()), level),
+            _ => {
+                println!("usage: loglevel [module] <level>");
+                return false;
+            }
+        };
+
+        match Level::from_name(level) {
+            Some(level) => {
+                log::set_level(module, level);
+                true
+            }
+            None => {
+                println!("loglevel: unknown level '{}'", level);
+                false
+            }
+        }
+    }
+
+    fn windows(&self, args: &[String]) -> bool {
+        let number = |arg: &String| arg.parse::<usize>().ok();
+        let done = match args {
+            [] => {
+                let focused = compositor::focused();
+                for (id, title, frame) in compositor::windows() {
+                    let marker = if focused == Some(id) { '*' } else { ' ' };
+                    println!(
+                        "{}{:>3}  {}x{} at {},{}  {}",
+                        marker, id, frame.width, frame.height, frame.x, frame.y, title
+                    );
+                }
+                return true;
+            }
+            [command, id] if command == "focus" => number(id).map(|id| compositor::focus(Some(id))),
+            [command, id] if command == "close" => number(id).map(compositor::close),
+            [command, id, x, y] if command == "move" => match (number(id), number(x), number(y)) {
+                (Some(id), Some(x), Some(y)) => Some(compositor::move_to(id, x, y)),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match done {
+            Some(true) => true,
+            Some(false) => {
+                println!("win: no window with id '{}'", args[1]);
+                false
+            }
+            None => {
+                println!("usage: win [focus|close <id>] [move <id> <x> <y>]");
+                false
+            }
+        }
+    }
+
+    /// Copy the given text to the clipboard and save it,
+    /// returning whether saving succeeded.
+    fn copy(&mut self, text: &str) -> bool {
+        clipboard::copy(text);
+        let saved = clipboard::save(&self.root());
+        saved && self.sync()
+    }
+
+    /// Write everything changed through the shell's filesystem to disk,
+    /// so that it is not lost when QEMU is closed without `exit`.
+    /// Called after every command that writes to the filesystem.
+    fn sync(&mut self) -> bool {
+        match disk::sync(self.filesystem.take().unwrap()) {
+            Ok(filesystem) => {
+                self.filesystem = Some(filesystem);
+                true
+            }
+            Err(err) => {
+                println!("sync: failed to write filesystem to disk: {:?}", err);
+                self.filesystem = disk::mount();
+                false
+            }
+        }
+    }
+
+    /// Insert the clipboard into the current command at the cursor,
+    /// with line breaks turned into spaces.
+    fn paste(&mut self) {
+        let text = clipboard::paste().replace(|c: char| c.is_control(), " ");
+        let index = self.byte_index(self.cursor_pos);
+        self.current_command.insert_str(index, &text);
+        self.cursor_pos += text.chars().count();
+    }
+
+    /// Format the source code in the given file, replacing its contents.
+    fn format(&mut self, rel_path: &str) -> bool {
+        let source = match self.read_file(rel_path) {
+            Some(source) => source,
+            None => return false,
+        };
+        let formatted = match yacari::tooling::format(&source) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                println!("fmt: {} failed to parse", rel_path);
+                vm::report_errors(rel_path, &[errors]);
+                return false;
+            }
+        };
+        if formatted == source {
+            return true;
+        }
+
+        let file = self.root().create_file(&self.resolve(rel_path));
+        let written = if let Ok(mut file) = file {
+            // Formatting can make files shorter, so cut off what is left of the old contents
+            let res = file
+                .write_all(formatted.as_bytes())
+                .and_then(|_| file.truncate());
+            if let Err(err) = &res {
+                println!("fmt: failed to write file: {:?}", err);
+            }
+            res.is_ok()
+        } else {
+            println!("fmt: failed to open file");
+            false
+        };
+        written && self.sync()
     }
 
     fn read_file(&mut self, rel_path: &str) -> Option<String> {
-        let obj = self.workdir().open_file(&rel_path);
-        if let Ok(mut obj) = obj {
-            let size = obj.seek(SeekFrom::End(0)).unwrap();
-            let mut buf = Vec::with_capacity(size as usize);
-            unsafe {
-                buf.set_len(size as usize);
+        let bytes = self.read_bytes(rel_path)?;
+        match core::str::from_utf8(&bytes) {
+            Ok(str) => Some(String::from(str)),
+            Err(_) => {
+                println!("error: file is not valid UTF-8, use hexdump to inspect it");
+                None
             }
+        }
+    }
 
-            obj.seek(SeekFrom::Start(0)).unwrap();
-            match obj.read(&mut buf) {
-                Ok(_) => (),
+    fn read_bytes(&mut self, rel_path: &str) -> Option<IoBuffer> {
+        let obj = self.root().open_file(&self.resolve(rel_path));
+        if let Ok(mut obj) = obj {
+            match IoBuffer::read_file(&mut obj) {
+                Ok(buf) => Some(buf),
                 Err(err) => {
                     println!("failed to read file: {:?}", err);
-                    return None;
+                    None
                 }
-            };
-
-            let str = String::from_utf8(buf);
-            if let Ok(str) = str {
-                Some(str)
-            } else {
-                println!("error: file is not valid UTF-8");
-                None
             }
         } else {
             println!("error: file does not exist");
@@ -181,37 +753,254 @@ impl Shell {
         }
     }
 
+    /// Find the directory entry of the given file or directory.
+    fn entry(&self, rel_path: &str) -> Option<FatEntry> {
+        let resolved = self.resolve(rel_path);
+        let (parent, name) = path::split(&resolved);
+        path::open_dir(self.root(), parent)?
+            .iter()
+            .filter_map(Result::ok)
+            .find(|entry| entry.file_name().eq_ignore_ascii_case(name))
+    }
+
+    fn root(&self) -> FatDir {
+        self.filesystem.as_ref().unwrap().root_dir()
+    }
+
     fn workdir(&self) -> FatDir {
-        if let Some(name) = &self.working_dir {
-            self.filesystem
-                .as_ref()
-                .unwrap()
-                .root_dir()
-                .open_dir(name)
-                .unwrap()
-        } else {
-            self.filesystem.as_ref().unwrap().root_dir()
-        }
+        path::open_dir(self.root(), &self.working_dir).unwrap()
+    }
+
+    /// Resolve a path given to a command against the working directory.
+    fn resolve(&self, path: &str) -> String {
+        path::resolve(&self.working_dir, path)
+    }
+
+    fn open_dir(&self, path: &str) -> Option<FatDir> {
+        path::open_dir(self.root(), &self.resolve(path))
     }
 
-    fn cursor_at_end(&self) -> bool {
-        self.cursor_pos == self.current_command.len()
+    /// The length of the current command in characters,
+    /// which is what the cursor position counts in.
+    fn line_length(&self) -> usize {
+        self.current_command.chars().count()
+    }
+
+    /// Convert a cursor position to an index into the current command.
+    fn byte_index(&self, cursor_pos: usize) -> usize {
+        self.current_command
+            .char_indices()
+            .nth(cursor_pos)
+            .map_or(self.current_command.len(), |(index, _)| index)
+    }
+
+    fn color_scheme(&self) -> ColorScheme {
+        console::with_console(self.console, |w| w.scheme()).unwrap_or_default()
+    }
+
+    /// Print `text` in the given color, then return to the usual text color.
+    fn print_colored(&self, color: Color, text: &str) {
+        console::with_console(self.console, |w| w.set_color(color));
+        print!("{}", text);
+        console::with_console(self.console, |w| w.reset_color());
     }
 
     fn redraw(&mut self) {
-        vga_buffer(|w| {
+        console::with_console(self.console, |w| {
             w.set_cursor_x(self.cursor_pos);
             w.write_shell_line(&self.current_command);
-        })
+        });
     }
 
-    pub fn new(filesystem: FatFs) -> Shell {
-        vga_buffer(|w| w.init_shell());
-        Shell {
+    pub fn new(console: usize, filesystem: FatFs) -> Shell {
+        console::with_console(console, |w| w.init_shell());
+        let mut shell = Shell {
+            console,
             filesystem: Some(filesystem),
-            working_dir: None,
+            working_dir: String::new(),
             current_command: "".to_string(),
             cursor_pos: 0,
+            script_depth: 0,
+            aliases: Aliases::default(),
+            pager: None,
+        };
+        shell.configure();
+        shell
+    }
+
+    /// Apply the settings in `CONFIG_FILE` if it exists, then run its startup commands.
+    /// Invalid lines are reported and skipped.
+    fn configure(&mut self) {
+        if self.root().open_file(CONFIG_FILE).is_err() {
+            return;
+        }
+        let text = match self.read_file(CONFIG_FILE) {
+            Some(text) => text,
+            None => return,
+        };
+        let (config, errors) = Config::parse(&text);
+        for (line, msg) in errors {
+            println!("{}:{}: {}", CONFIG_FILE, line, msg);
+        }
+
+        console::with_console(self.console, |w| {
+            w.set_scheme(config.colors);
+            w.init_shell();
+        });
+        for (module, level) in config.log_levels {
+            log::set_level(module.as_deref(), level);
+        }
+        for (name, expansion) in config.aliases {
+            self.aliases.define(name, expansion);
+        }
+        if let Some(dir) = config.working_dir {
+            let target = self.resolve(&dir);
+            if path::open_dir(self.root(), &target).is_some() {
+                self.working_dir = target;
+            } else {
+                println!("{}: unknown working directory '{}'", CONFIG_FILE, dir);
+            }
+        }
+        for line in config.startup {
+            self.execute_line(&line);
         }
     }
 }
+
+fn print_heap(name: &str, stats: HeapStats) {
+    println!(
+        "{}: {} KiB used, {} KiB free of {} KiB ({} allocations, {} KiB fragmented)",
+        name,
+        stats.used / 1024,
+        stats.free / 1024,
+        stats.size / 1024,
+        stats.allocations,
+        stats.fragmented / 1024
+    );
+}
+
+/// Format a line of `hexdump` output in the same layout as `hexdump -C`:
+/// The offset, the bytes in hex and the bytes as ASCII.
+fn hexdump_line(offset: usize, bytes: &[u8]) -> String {
+    let mut line = format!("{:08x} ", offset);
+    for index in 0..HEXDUMP_WIDTH {
+        if index % 8 == 0 {
+            line.push(' ');
+        }
+        match bytes.get(index) {
+            Some(byte) => write!(line, "{:02x} ", byte).unwrap(),
+            None => line.push_str("   "),
+        }
+    }
+    line.push_str(" |");
+    for byte in bytes {
+        let printable = byte.is_ascii_graphic() || *byte == b' ';
+        line.push(if printable { *byte as char } else { '.' });
+    }
+    line.push('|');
+    line
+}
+
+/// Whether the name matches the pattern, where `*` matches any amount of
+/// characters and `?` any single one. Case-insensitive like FAT file names.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // Where to continue after the last `*` if the rest does not match
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || c.eq_ignore_ascii_case(&name[n]) => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Format a size in bytes with the largest binary unit it is at least one of,
+/// rounded down to one decimal place for values below 10.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    // The size in 1/1024ths of the unit
+    let mut scaled = bytes;
+    let mut unit = 0;
+    while scaled >= 1024 * 1024 && unit < UNITS.len() - 1 {
+        scaled /= 1024;
+        unit += 1;
+    }
+    let tenths = scaled * 10 / 1024;
+    if tenths < 100 {
+        format!("{}.{} {}", tenths / 10, tenths % 10, UNITS[unit])
+    } else {
+        format!("{} {}", scaled / 1024, UNITS[unit])
+    }
+}
+
+fn format_date(date: Date) -> String {
+    format!("{:04}-{:02}-{:02}", date.year, date.month, date.day)
+}
+
+fn format_date_time(date_time: DateTime) -> String {
+    let time = date_time.time;
+    format!(
+        "{} {:02}:{:02}:{:02}",
+        format_date(date_time.date),
+        time.hour,
+        time.min,
+        time.sec
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_size, glob_match, hexdump_line};
+
+    #[test_case]
+    fn hexdump() {
+        assert_eq!(
+            hexdump_line(0x10, b"Hello, world!\n\x00\xff"),
+            "00000010  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|"
+        );
+        assert_eq!(
+            hexdump_line(0, b"yacari"),
+            "00000000  79 61 63 61 72 69                                 |yacari|"
+        );
+    }
+
+    #[test_case]
+    fn glob() {
+        assert!(glob_match("*.yacari", "main.yacari"));
+        assert!(glob_match("*.YACARI", "main.yacari"));
+        assert!(glob_match("m?in*", "main.yacari"));
+        assert!(glob_match("*a*i", "main.yacari"));
+        assert!(!glob_match("*.yacari", "main.yacari.bak"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test_case]
+    fn sizes() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(200 * 1024), "200 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
@@ -0,0 +1,191 @@
+//! A `less`-style pager, showing text one screen at a time.
+//! While it is open, the shell hands all key presses to it.
+
+use crate::drivers::{
+    keymap::KeyPress,
+    vga_buffer::{Writer, BUFFER_WIDTH, SHELL_ROW, TEXT_HEIGHT},
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::cmp::min;
+use pc_keyboard::{DecodedKey, KeyCode};
+
+pub struct Pager {
+    name: String,
+    /// The text split into rows of at most the screen width.
+    rows: Vec<String>,
+    /// The row shown at the top of the screen.
+    top: usize,
+    /// The search term being typed after pressing '/'.
+    input: Option<String>,
+    /// The last search term, repeated with 'n'.
+    search: Option<String>,
+    /// Shown on the status line instead of the position, until the next key press.
+    message: Option<String>,
+}
+
+impl Pager {
+    pub fn new(name: &str, text: &str) -> Pager {
+        let mut rows = Vec::new();
+        for line in text.lines() {
+            let chars = line.replace('\t', "    ").chars().collect::<Vec<_>>();
+            if chars.is_empty() {
+                rows.push(String::new());
+            }
+            rows.extend(chars.chunks(BUFFER_WIDTH).map(|row| row.iter().collect()));
+        }
+        Pager {
+            name: name.to_string(),
+            rows,
+            top: 0,
+            input: None,
+            search: None,
+            message: None,
+        }
+    }
+
+    /// Handle a key press, returning whether the pager is still open.
+    pub fn key_pressed(&mut self, press: KeyPress) -> bool {
+        self.message = None;
+        if let Some(input) = &mut self.input {
+            match press.key {
+                DecodedKey::Unicode('\n') => {
+                    self.search = self.input.take().filter(|term| !term.is_empty());
+                    self.find_next(self.top);
+                }
+                DecodedKey::Unicode('\x08') => {
+                    if input.pop().is_none() {
+                        self.input = None;
+                    }
+                }
+                DecodedKey::Unicode('\x1b') => self.input = None,
+                DecodedKey::Unicode(character) => input.push(character),
+                DecodedKey::RawKey(_) => (),
+            }
+            return true;
+        }
+
+        match press.key {
+            DecodedKey::Unicode('q') | DecodedKey::Unicode('\x1b') => return false,
+            DecodedKey::Unicode(' ') | DecodedKey::RawKey(KeyCode::PageDown) => {
+                self.scroll_to(self.top + TEXT_HEIGHT)
+            }
+            DecodedKey::Unicode('b') | DecodedKey::RawKey(KeyCode::PageUp) => {
+                self.scroll_to(self.top.saturating_sub(TEXT_HEIGHT))
+            }
+            DecodedKey::Unicode('j' | '\n') | DecodedKey::RawKey(KeyCode::ArrowDown) => {
+                self.scroll_to(self.top + 1)
+            }
+            DecodedKey::Unicode('k') | DecodedKey::RawKey(KeyCode::ArrowUp) => {
+                self.scroll_to(self.top.saturating_sub(1))
+            }
+            DecodedKey::Unicode('g') | DecodedKey::RawKey(KeyCode::Home) => self.scroll_to(0),
+            DecodedKey::Unicode('G') | DecodedKey::RawKey(KeyCode::End) => {
+                self.scroll_to(self.rows.len())
+            }
+            DecodedKey::Unicode('/') => self.input = Some(String::new()),
+            DecodedKey::Unicode('n') => self.find_next(self.top + 1),
+            _ => (),
+        }
+        true
+    }
+
+    /// Scroll to the given row, as far as there are rows below it to fill the screen.
+    fn scroll_to(&mut self, top: usize) {
+        self.top = min(top, self.rows.len().saturating_sub(TEXT_HEIGHT));
+    }
+
+    /// Scroll to the first row at or after `start` containing the search term,
+    /// continuing at the beginning when reaching the end.
+    fn find_next(&mut self, start: usize) {
+        let term = match &self.search {
+            Some(term) => term,
+            None => return,
+        };
+        let rows = &self.rows;
+        let found = (0..rows.len())
+            .map(|offset| (start + offset) % rows.len())
+            .find(|row| rows[*row].contains(term.as_str()));
+        match found {
+            Some(row) => self.top = row,
+            None => self.message = Some(format!("pattern not found: {}", term)),
+        }
+    }
+
+    pub fn draw(&self, writer: &mut Writer) {
+        for row in 0..TEXT_HEIGHT {
+            let text = self.rows.get(self.top + row).map_or("~", String::as_str);
+            writer.write_row(row, text);
+        }
+
+        let status = if let Some(input) = &self.input {
+            format!("/{}", input)
+        } else if let Some(message) = &self.message {
+            message.clone()
+        } else {
+            let bottom = min(self.top + TEXT_HEIGHT, self.rows.len());
+            let end = if bottom == self.rows.len() {
+                " (END)"
+            } else {
+                ""
+            };
+            format!(
+                "{} rows {}-{}/{}{}  q: quit  /: search",
+                self.name,
+                self.top + 1,
+                bottom,
+                self.rows.len(),
+                end
+            )
+        };
+        writer.write_row(SHELL_ROW, &status);
+        writer.set_cursor_x(status.chars().count().saturating_sub(2));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Pager;
+    use crate::drivers::{
+        keymap::{KeyPress, Modifiers},
+        vga_buffer::TEXT_HEIGHT,
+    };
+    use alloc::{format, string::String};
+    use pc_keyboard::DecodedKey;
+
+    fn type_keys(pager: &mut Pager, keys: &str) -> bool {
+        keys.chars().all(|key| {
+            pager.key_pressed(KeyPress {
+                key: DecodedKey::Unicode(key),
+                modifiers: Modifiers::NONE,
+            })
+        })
+    }
+
+    #[test_case]
+    fn pages() {
+        let text = (0..100)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        let mut pager = Pager::new("test", &text);
+        type_keys(&mut pager, " ");
+        assert_eq!(pager.top, TEXT_HEIGHT);
+        type_keys(&mut pager, "G");
+        assert_eq!(pager.top, 100 - TEXT_HEIGHT);
+        type_keys(&mut pager, "/line 42\n");
+        assert_eq!(pager.top, 42);
+        type_keys(&mut pager, "/missing\n");
+        assert_eq!(pager.top, 42);
+        assert!(pager.message.is_some());
+        assert!(!type_keys(&mut pager, "q"));
+    }
+
+    #[test_case]
+    fn wraps_long_lines() {
+        let pager = Pager::new("test", &"x".repeat(200));
+        assert_eq!(pager.rows.len(), 3);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::{
+    drivers::{disk, disk::FileSystem},
+    vm::KERNEL_FLAG,
+    warn,
+};
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::hint::spin_loop;
+use fatfs::DateTime;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use yacari::{
+    filesystem::File, CompileOptions, CompiledModule, CompiledProgram, ProgramError, SmolStr,
+};
+
+lazy_static! {
+    /// The cache shared by all programs, like those run by the shell and services.
+    static ref CACHE: Mutex<ModuleCache> = Mutex::new(ModuleCache::new());
+}
+
+/// Returns the compiled modules at the given path from the shared cache, compiling them
+/// if they are not cached or changed since they were cached.
+/// `path` should be absolute, as the cache does not know about working directories.
+/// The cache is only locked to look up and store entries, so programs using other
+/// paths are not held up by compiling. Other CPUs needing the same path wait until
+/// it is compiled instead of compiling it themselves.
+pub fn get(fs: &FileSystem, path: &str) -> Result<Vec<CompiledModule>, ProgramError> {
+    let stamp = Stamp::of(fs, path);
+    let previous = loop {
+        // Not matching on the lock directly, which would keep it locked while waiting
+        let claim = CACHE.lock().claim(path, &stamp);
+        match claim {
+            Claim::Cached(modules) => return Ok(modules),
+            Claim::Compiling => spin_loop(),
+            Claim::Claimed(previous) => break previous,
+        }
+    };
+
+    let result = load(fs, path, stamp, previous);
+    let mut cache = CACHE.lock();
+    match result {
+        Ok(entry) => {
+            let modules = entry.program.thaw();
+            cache.entries.insert(path.to_string(), Slot::Ready(entry));
+            Ok(modules)
+        }
+        Err(err) => {
+            cache.entries.remove(path);
+            Err(err)
+        }
+    }
+}
+
+/// A registry of compiled library modules, which allows reusing them
+/// across executions instead of parsing and compiling them every time.
+/// They are kept frozen, since programs using them run on all CPUs.
+/// Entries are keyed by path and checked against the directory entries of the
+/// files under the path, which are only read again once those changed.
+/// If a snapshot of a path exists (`<path>.snap`), it is loaded instead
+/// of compiling the files under the path, unless it was compiled with other flags
+/// or from other files. Such snapshots are replaced with the newly compiled modules.
+struct ModuleCache {
+    entries: BTreeMap<String, Slot>,
+}
+
+enum Slot {
+    /// A CPU is reading and compiling the files under the path.
+    Compiling,
+    Ready(CacheEntry),
+}
+
+struct CacheEntry {
+    stamp: Stamp,
+    hash: u64,
+    program: CompiledProgram,
+}
+
+enum Claim {
+    Cached(Vec<CompiledModule>),
+    Compiling,
+    /// The caller needs to compile the path, and gets the outdated entry if there is one.
+    Claimed(Option<CacheEntry>),
+}
+
+impl ModuleCache {
+    /// Returns the modules at the given path if they are cached and
+    /// their files still match `stamp`, or claims the path for compiling.
+    fn claim(&mut self, path: &str, stamp: &Stamp) -> Claim {
+        match self.entries.get(path) {
+            Some(Slot::Compiling) => return Claim::Compiling,
+            Some(Slot::Ready(entry)) if entry.stamp == *stamp => {
+                return Claim::Cached(entry.program.thaw())
+            }
+            _ => (),
+        }
+        match self.entries.insert(path.to_string(), Slot::Compiling) {
+            Some(Slot::Ready(entry)) => Claim::Claimed(Some(entry)),
+            _ => Claim::Claimed(None),
+        }
+    }
+
+    fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+/// What the files under a path looked like on disk, to notice
+/// when they changed without reading them.
+#[derive(PartialEq)]
+struct Stamp {
+    /// See `disk::writes`.
+    writes: usize,
+    /// The path, size and modification time of every file.
+    files: Vec<(String, u64, DateTime)>,
+}
+
+impl Stamp {
+    fn of(fs: &FileSystem, path: &str) -> Stamp {
+        Stamp {
+            writes: disk::writes(),
+            files: fs
+                .source_metadata(path)
+                .into_iter()
+                .map(|file| (file.name, file.len, file.modified))
+                .collect(),
+        }
+    }
+}
+
+/// Read the files under the given path and compile them, unless they are
+/// unchanged from the `previous` entry and only their stamp differs, like
+/// after writing other files.
+fn load(
+    fs: &FileSystem,
+    path: &str,
+    stamp: Stamp,
+    previous: Option<CacheEntry>,
+) -> Result<CacheEntry, ProgramError> {
+    let files = yacari::read_files(fs, &[path])?;
+    let hash = hash_files(&files);
+    if let Some(entry) = previous.filter(|entry| entry.hash == hash) {
+        return Ok(CacheEntry { stamp, ..entry });
+    }
+
+    let snapshot = format!("{}.snap", path.trim_end_matches('/'));
+    let modules = match load_snapshot(fs, &snapshot, &files) {
+        Some(modules) => modules,
+        None => {
+            let modules = yacari::compile_files_with(&files, &options())?;
+            if let Err(err) = fs.write(&snapshot, &yacari::save_snapshot(&modules)) {
+                warn!("failed to write snapshot {}: {:?}", snapshot, err);
+            }
+            modules
+        }
+    };
+    Ok(CacheEntry {
+        stamp,
+        hash,
+        program: CompiledProgram::freeze(&modules),
+    })
+}
+
+/// Load the snapshot at the given path, if it exists and was compiled
+/// from exactly the given files with the options of the cache.
+fn load_snapshot(fs: &FileSystem, path: &str, files: &[File]) -> Option<Vec<CompiledModule>> {
+    let data = fs.read(path)?;
+    let modules = match yacari::load_snapshot(&data) {
+        Ok(modules) => modules,
+        Err(err) => {
+            warn!("ignoring invalid snapshot {}: {:?}", path, err);
+            return None;
+        }
+    };
+
+    if modules
+        .iter()
+        .any(|module| module.flags() != options().flags)
+    {
+        warn!("ignoring snapshot {} compiled with other flags", path);
+        None
+    } else if modules.len() != files.len()
+        || !files
+            .iter()
+            .all(|file| modules.iter().any(|module| module.compiled_from(file)))
+    {
+        warn!("ignoring outdated snapshot {}", path);
+        None
+    } else {
+        Some(modules)
+    }
+}
+
+/// The options libraries are compiled with, which their snapshots need to match.
+/// Programs call any of their functions, so none of them are eliminated as dead code.
+fn options() -> CompileOptions {
+    CompileOptions {
+        eliminate_dead_code: false,
+        flags: vec![SmolStr::new_inline(KERNEL_FLAG)],
+        ..CompileOptions::default()
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+fn fnv1a(hash: &mut u64, bytes: &[u8]) {
+    for byte in bytes {
+        *hash ^= *byte as u64;
+        *hash = hash.wrapping_mul(0x100000001b3);
+    }
+}
+
+/// FNV-1a hash over the paths and contents of all files.
+fn hash_files(files: &[File]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    let mut feed = |bytes: &[u8]| fnv1a(&mut hash, bytes);
+
+    for file in files {
+        for segment in &file.path {
+            feed(segment.as_bytes());
+            feed(b"/");
+        }
+        feed(file.contents.as_bytes());
+        feed(&[0]);
+    }
+    hash
+}
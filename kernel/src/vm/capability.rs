@@ -0,0 +1,138 @@
+use crate::vm::host;
+use alloc::{string::String, vec::Vec};
+use yacari::filesystem::File;
+
+/// Line at the start of a file that requests capabilities,
+/// followed by their names separated by whitespace.
+const HEADER: &str = "// capabilities:";
+
+/// A group of host functions, which a program needs to request
+/// in its header and be granted before it can use them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Capability {
+    /// Drawing to the screen.
+    Graphics,
+    /// The filesystem, including the working directory.
+    Fs,
+    /// The arguments passed to the program.
+    Input,
+    /// The time since boot.
+    Time,
+    /// Usage statistics of the kernel and code heaps.
+    Memory,
+    /// Playing tones on the PC speaker.
+    Sound,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Capability::Graphics,
+        Capability::Fs,
+        Capability::Input,
+        Capability::Time,
+        Capability::Memory,
+        Capability::Sound,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "graphics" => Capability::Graphics,
+            "fs" => Capability::Fs,
+            "input" => Capability::Input,
+            "time" => Capability::Time,
+            "memory" => Capability::Memory,
+            "sound" => Capability::Sound,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum CapabilityError {
+    /// A capability with the given name does not exist.
+    Unknown(String),
+    /// The capability was requested, but not granted.
+    Denied(Capability),
+}
+
+/// Returns the capabilities requested by the header of any of the given files.
+pub fn requested(files: &[File]) -> Result<Vec<Capability>, CapabilityError> {
+    let mut capabilities = Vec::new();
+    for file in files {
+        let header = file
+            .contents
+            .lines()
+            .map(str::trim)
+            .take_while(|line| line.starts_with("//"));
+        for names in header.filter_map(|line| line.strip_prefix(HEADER)) {
+            for name in names.split_whitespace() {
+                let capability = Capability::from_name(name)
+                    .ok_or_else(|| CapabilityError::Unknown(name.into()))?;
+                if !capabilities.contains(&capability) {
+                    capabilities.push(capability);
+                }
+            }
+        }
+    }
+    Ok(capabilities)
+}
+
+/// Build the symbol table for a program that requested the given capabilities,
+/// failing if any of them were not granted. The default functions are always included.
+pub fn symbol_table(
+    requested: &[Capability],
+    granted: &[Capability],
+) -> Result<Vec<(&'static str, *const u8)>, CapabilityError> {
+    if let Some(denied) = requested.iter().find(|cap| !granted.contains(cap)) {
+        return Err(CapabilityError::Denied(*denied));
+    }
+    Ok(host::functions()
+        .iter()
+        .filter(|(capability, ..)| requested.contains(capability))
+        .map(|(_, name, ptr)| (*name, *ptr))
+        .chain(host::default_functions().iter().copied())
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{requested, symbol_table, Capability, CapabilityError};
+    use alloc::{vec, vec::Vec};
+    use yacari::{filesystem::File, SmolStr};
+
+    fn file(contents: &str) -> File {
+        File {
+            path: vec![SmolStr::new("test")],
+            contents: contents.into(),
+        }
+    }
+
+    #[test_case]
+    fn header() {
+        let files = [
+            file("// capabilities: graphics time\n// capabilities: graphics\nfun main() {}"),
+            file("fun a() {}\n// capabilities: fs"),
+        ];
+        assert_eq!(
+            requested(&files).unwrap(),
+            [Capability::Graphics, Capability::Time]
+        );
+        assert!(matches!(
+            requested(&[file("// capabilities: network")]),
+            Err(CapabilityError::Unknown(name)) if name == "network"
+        ));
+    }
+
+    #[test_case]
+    fn granted() {
+        let symbols = symbol_table(&[Capability::Time], &Capability::ALL).unwrap();
+        let names: Vec<_> = symbols.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["time_ms", "rand_i64", "rand_range"]);
+        let symbols = symbol_table(&[], &[]).unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert!(matches!(
+            symbol_table(&[Capability::Fs], &[Capability::Graphics]),
+            Err(CapabilityError::Denied(Capability::Fs))
+        ));
+    }
+}
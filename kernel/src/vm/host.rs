@@ -0,0 +1,438 @@
+use crate::{
+    allocator,
+    drivers::{
+        disk::{buffer::IoBuffer, FileSystem},
+        interrupts::gdt,
+        speaker, timer,
+    },
+    graphics::{
+        compositor, compositor::WindowId, draw_image, draw_rect, draw_text, image::Image,
+        text::Font, Canvas, Color,
+    },
+    rng,
+    scheduling::smp::MAX_CPUS,
+    vm::{capability::Capability, memory},
+};
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use spin::{Mutex, MutexGuard};
+use yacari::StrRef;
+
+/// State of the currently executing program, which it can
+/// query using host functions.
+struct Context {
+    /// The working directory relative to the disk root.
+    /// Empty when executing from the root.
+    working_dir: String,
+    /// Images loaded by the program, indexed by the handles it was given.
+    images: Vec<Image>,
+    /// Fonts loaded by the program, indexed the same way.
+    /// The built-in font is always loaded first.
+    fonts: Vec<Font>,
+    /// The font and scale `gfx_draw_text` uses.
+    font: usize,
+    text_scale: usize,
+    /// Windows opened by the program, closed once it exits.
+    windows: Vec<WindowId>,
+}
+
+/// The context of every CPU, since each of them might be running a program.
+static CONTEXTS: [Mutex<Context>; MAX_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Mutex<Context> = Mutex::new(Context {
+        working_dir: String::new(),
+        images: Vec::new(),
+        fonts: Vec::new(),
+        font: 0,
+        text_scale: 1,
+        windows: Vec::new(),
+    });
+    [EMPTY; MAX_CPUS]
+};
+
+/// Beeps played by programs are cut off after this many milliseconds.
+const MAX_BEEP_MS: i64 = 5000;
+/// Text drawn by programs is scaled by this at most.
+const MAX_TEXT_SCALE: i64 = 16;
+
+/// Returns all host functions, together with the capability
+/// a program needs to be granted to use them.
+pub fn functions() -> [(Capability, &'static str, *const u8); 23] {
+    [
+        (
+            Capability::Graphics,
+            "draw_rect",
+            test_draw_rect as *const u8,
+        ),
+        (
+            Capability::Graphics,
+            "gfx_draw_image",
+            gfx_draw_image as *const u8,
+        ),
+        (
+            Capability::Fs,
+            "gfx_load_image",
+            gfx_load_image as *const u8,
+        ),
+        (
+            Capability::Graphics,
+            "gfx_set_font",
+            gfx_set_font as *const u8,
+        ),
+        (
+            Capability::Graphics,
+            "gfx_draw_text",
+            gfx_draw_text as *const u8,
+        ),
+        (
+            Capability::Graphics,
+            "gfx_text_width",
+            gfx_text_width as *const u8,
+        ),
+        (
+            Capability::Graphics,
+            "gfx_text_height",
+            gfx_text_height as *const u8,
+        ),
+        (Capability::Fs, "gfx_load_font", gfx_load_font as *const u8),
+        (Capability::Graphics, "win_open", win_open as *const u8),
+        (Capability::Graphics, "win_close", win_close as *const u8),
+        (
+            Capability::Graphics,
+            "win_fill_rect",
+            win_fill_rect as *const u8,
+        ),
+        (
+            Capability::Graphics,
+            "win_draw_text",
+            win_draw_text as *const u8,
+        ),
+        (
+            Capability::Graphics,
+            "win_draw_image",
+            win_draw_image as *const u8,
+        ),
+        (
+            Capability::Graphics,
+            "win_present",
+            win_present as *const u8,
+        ),
+        (Capability::Input, "win_next_key", win_next_key as *const u8),
+        (Capability::Fs, "cwd_len", cwd_len as *const u8),
+        (Capability::Fs, "cwd_byte", cwd_byte as *const u8),
+        (Capability::Time, "time_ms", time_ms as *const u8),
+        (Capability::Sound, "beep", beep as *const u8),
+        (Capability::Memory, "heap_used", heap_used as *const u8),
+        (Capability::Memory, "heap_free", heap_free as *const u8),
+        (
+            Capability::Memory,
+            "code_heap_used",
+            code_heap_used as *const u8,
+        ),
+        (
+            Capability::Memory,
+            "code_heap_free",
+            code_heap_free as *const u8,
+        ),
+    ]
+}
+
+/// Returns the host functions every program can use,
+/// without requesting a capability.
+pub fn default_functions() -> [(&'static str, *const u8); 2] {
+    [
+        ("rand_i64", rand_i64 as *const u8),
+        ("rand_range", rand_range as *const u8),
+    ]
+}
+
+pub fn set_context(working_dir: &str) {
+    let mut context = context();
+    context.working_dir = working_dir.to_string();
+    context.images.clear();
+    context.fonts = vec![Font::builtin()];
+    context.font = 0;
+    context.text_scale = 1;
+}
+
+/// Release everything the program running on the current CPU held on to,
+/// once it exited. Its windows are closed.
+pub fn release_context() {
+    let mut context = context();
+    for window in context.windows.drain(..) {
+        compositor::close(window);
+    }
+    context.images.clear();
+    context.fonts.clear();
+}
+
+/// Returns the context of the program running on the current CPU.
+fn context() -> MutexGuard<'static, Context> {
+    CONTEXTS[gdt::per_cpu().id as usize].lock()
+}
+
+extern "C" fn test_draw_rect(x: i64, y: i64, w: i64, h: i64) {
+    draw_rect(
+        x as usize,
+        y as usize,
+        w as usize,
+        h as usize,
+        Color::from(81, 45, 168),
+    )
+}
+
+/// Load the BMP image at `path`, relative to the working directory.
+/// Returns a handle to draw it with, or -1 if it could not be loaded.
+extern "C" fn gfx_load_image(path: StrRef) -> i64 {
+    let image = read_file(unsafe { path.as_str() }).and_then(|data| Image::decode_bmp(&data).ok());
+    match image {
+        Some(image) => {
+            let mut context = context();
+            context.images.push(image);
+            context.images.len() as i64 - 1
+        }
+        None => -1,
+    }
+}
+
+/// Draw the image with the given handle with its top left corner at `x`, `y`.
+/// Invalid handles are ignored.
+extern "C" fn gfx_draw_image(image: i64, x: i64, y: i64) {
+    if let Some(image) = context().images.get(image as usize) {
+        draw_image(x as usize, y as usize, image)
+    }
+}
+
+/// Load the PSF font at `path`, relative to the working directory.
+/// Returns a handle to select it with, or -1 if it could not be loaded.
+extern "C" fn gfx_load_font(path: StrRef) -> i64 {
+    let font = read_file(unsafe { path.as_str() }).and_then(|data| Font::from_psf(&data).ok());
+    match font {
+        Some(font) => {
+            let mut context = context();
+            context.fonts.push(font);
+            context.fonts.len() as i64 - 1
+        }
+        None => -1,
+    }
+}
+
+/// Select the font with the given handle, 0 being the built-in font,
+/// and the scale to draw text at. Invalid handles are ignored.
+extern "C" fn gfx_set_font(font: i64, scale: i64) {
+    let mut context = context();
+    if (font as usize) < context.fonts.len() {
+        context.font = font as usize;
+    }
+    context.text_scale = scale.clamp(1, MAX_TEXT_SCALE) as usize;
+}
+
+/// Draw text in the selected font with its top left corner at `x`, `y`.
+/// `color` is given as 0xRRGGBB.
+extern "C" fn gfx_draw_text(x: i64, y: i64, text: StrRef, color: i64) {
+    if x < 0 || y < 0 {
+        return;
+    }
+    let mut context = context();
+    let Context {
+        fonts,
+        font,
+        text_scale,
+        ..
+    } = &mut *context;
+    let text = unsafe { text.as_str() };
+    let color = Color::hex(color as u32);
+    draw_text(
+        x as usize,
+        y as usize,
+        text,
+        &mut fonts[*font],
+        color,
+        *text_scale,
+    )
+}
+
+/// Width of `text` in pixels when drawn in the selected font.
+extern "C" fn gfx_text_width(text: StrRef) -> i64 {
+    let context = context();
+    let (width, _) =
+        context.fonts[context.font].measure(unsafe { text.as_str() }, context.text_scale);
+    width as i64
+}
+
+/// Height of `text` in pixels when drawn in the selected font.
+extern "C" fn gfx_text_height(text: StrRef) -> i64 {
+    let context = context();
+    let (_, height) =
+        context.fonts[context.font].measure(unsafe { text.as_str() }, context.text_scale);
+    height as i64
+}
+
+/// Open a window with a surface of the given size, on top of all others and focused.
+/// Returns a handle to draw to it with, or -1 if it could not be opened.
+extern "C" fn win_open(title: StrRef, width: i64, height: i64) -> i64 {
+    if width <= 0 || height <= 0 {
+        return -1;
+    }
+    match compositor::open(unsafe { title.as_str() }, width as usize, height as usize) {
+        Some(window) => {
+            context().windows.push(window);
+            window as i64
+        }
+        None => -1,
+    }
+}
+
+/// Close a window of the program. Invalid handles are ignored.
+extern "C" fn win_close(window: i64) {
+    let mut context = context();
+    if let Some(index) = context.windows.iter().position(|w| *w as i64 == window) {
+        compositor::close(context.windows.remove(index));
+    }
+}
+
+/// Fill a rectangle on the surface of a window with a color given as 0xRRGGBB.
+/// Drawing to a window is only shown on the screen once it is presented.
+extern "C" fn win_fill_rect(window: i64, x: i64, y: i64, w: i64, h: i64, color: i64) {
+    if x < 0 || y < 0 || w < 0 || h < 0 {
+        return;
+    }
+    let (x, y, w, h) = (x as usize, y as usize, w as usize, h as usize);
+    with_window(window, |surface, _| {
+        surface.fill_rect(x, y, w, h, Color::hex(color as u32))
+    });
+}
+
+/// Draw text on the surface of a window in the selected font, like `gfx_draw_text`.
+extern "C" fn win_draw_text(window: i64, x: i64, y: i64, text: StrRef, color: i64) {
+    if x < 0 || y < 0 {
+        return;
+    }
+    let text = unsafe { text.as_str() };
+    let color = Color::hex(color as u32);
+    with_window(window, |surface, context| {
+        let font = &mut context.fonts[context.font];
+        surface.draw_text(
+            x as usize,
+            y as usize,
+            text,
+            font,
+            color,
+            context.text_scale,
+        )
+    });
+}
+
+/// Draw an image on the surface of a window, like `gfx_draw_image`.
+extern "C" fn win_draw_image(window: i64, image: i64, x: i64, y: i64) {
+    if x < 0 || y < 0 {
+        return;
+    }
+    with_window(window, |surface, context| {
+        if let Some(image) = context.images.get(image as usize) {
+            surface.draw_image(x as usize, y as usize, image)
+        }
+    });
+}
+
+/// Show everything drawn to a window on the screen.
+extern "C" fn win_present(window: i64) {
+    if let Some(window) = own_window(&context(), window) {
+        compositor::present(window)
+    }
+}
+
+/// The oldest key typed into a window while it was focused and not read yet,
+/// or -1 if there is none. Keys are read by the keyboard task, so only programs
+/// not started from the shell on the boot CPU, like services, receive them.
+extern "C" fn win_next_key(window: i64) -> i64 {
+    own_window(&context(), window)
+        .and_then(compositor::next_key)
+        .map_or(-1, |c| c as i64)
+}
+
+/// Returns the window with the given handle, if the program opened it.
+fn own_window(context: &Context, window: i64) -> Option<WindowId> {
+    context
+        .windows
+        .iter()
+        .copied()
+        .find(|w| *w as i64 == window)
+}
+
+/// Run `f` with the surface of a window the program opened, if it did.
+fn with_window(window: i64, f: impl FnOnce(&mut compositor::Surface, &mut Context)) {
+    let mut context = context();
+    if let Some(window) = own_window(&context, window) {
+        compositor::with_surface(window, |surface| f(surface, &mut context));
+    }
+}
+
+/// Length of the working directory in bytes.
+extern "C" fn cwd_len() -> i64 {
+    context().working_dir.len() as i64
+}
+
+/// The byte at `index` of the working directory, or -1 if out of bounds.
+extern "C" fn cwd_byte(index: i64) -> i64 {
+    byte_at(&context().working_dir, index)
+}
+
+/// Milliseconds since boot.
+extern "C" fn time_ms() -> i64 {
+    (timer::ticks() * 1000 / timer::TICKS_PER_SECOND as u64) as i64
+}
+
+/// Play a tone of the given frequency in Hz for `ms` milliseconds,
+/// returning once it finished.
+extern "C" fn beep(frequency: i64, ms: i64) {
+    let frequency = frequency.clamp(0, speaker::MAX_FREQUENCY as i64) as u32;
+    speaker::beep(frequency, ms.clamp(0, MAX_BEEP_MS) as u64)
+}
+
+/// A random number.
+extern "C" fn rand_i64() -> i64 {
+    rng::next_u64() as i64
+}
+
+/// A random number in `lo..hi`, or `lo` if that is empty.
+extern "C" fn rand_range(lo: i64, hi: i64) -> i64 {
+    rng::range(lo, hi)
+}
+
+/// Bytes used on the kernel heap.
+extern "C" fn heap_used() -> i64 {
+    allocator::stats().used as i64
+}
+
+/// Bytes free on the kernel heap.
+extern "C" fn heap_free() -> i64 {
+    allocator::stats().free as i64
+}
+
+/// Bytes used by compiled code.
+extern "C" fn code_heap_used() -> i64 {
+    memory::code_heap_stats().used as i64
+}
+
+/// Bytes free on the code heap.
+extern "C" fn code_heap_free() -> i64 {
+    memory::code_heap_stats().free as i64
+}
+
+/// Read the file at `path`, relative to the working directory of the program.
+fn read_file(path: &str) -> Option<IoBuffer> {
+    let working_dir = context().working_dir.clone();
+    FileSystem::with_working_dir(Some(&working_dir)).and_then(|fs| fs.read(path))
+}
+
+fn byte_at(string: &str, index: i64) -> i64 {
+    string
+        .as_bytes()
+        .get(index as usize)
+        .map(|byte| *byte as i64)
+        .unwrap_or(-1)
+}
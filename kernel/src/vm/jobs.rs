@@ -0,0 +1,66 @@
+//! Programs that are currently running, which the user can stop:
+//! the program the shell waits for with Ctrl+C, and all others with `kill`.
+
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use yacari::InterruptHandle;
+
+struct Job {
+    id: usize,
+    name: String,
+    /// Whether the shell is waiting for the program, blocking the keyboard task.
+    foreground: bool,
+    handle: InterruptHandle,
+}
+
+static JOBS: Mutex<Vec<Job>> = Mutex::new(Vec::new());
+
+/// Add a program that started running under the given name.
+/// Returns its job ID, which is removed again with `finish`.
+pub fn start(name: &str, foreground: bool, handle: InterruptHandle) -> usize {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    JOBS.lock().push(Job {
+        id,
+        name: name.into(),
+        foreground,
+        handle,
+    });
+    id
+}
+
+/// Remove a program, once it has exited.
+pub fn finish(id: usize) {
+    JOBS.lock().retain(|job| job.id != id);
+}
+
+/// Returns the ID and name of every program running in the background.
+pub fn background() -> Vec<(usize, String)> {
+    JOBS.lock()
+        .iter()
+        .filter(|job| !job.foreground)
+        .map(|job| (job.id, job.name.clone()))
+        .collect()
+}
+
+/// Stop the background program with the given ID.
+/// Returns whether there is such a program.
+pub fn kill(id: usize) -> bool {
+    let jobs = JOBS.lock();
+    let job = jobs.iter().find(|job| job.id == id && !job.foreground);
+    if let Some(job) = job {
+        job.handle.interrupt();
+    }
+    job.is_some()
+}
+
+/// Stop the program the shell is waiting for, if there is one.
+/// Does not block, since it is called from the keyboard interrupt handler.
+pub fn kill_foreground() {
+    if let Some(jobs) = JOBS.try_lock() {
+        for job in jobs.iter().filter(|job| job.foreground) {
+            job.handle.interrupt();
+        }
+    }
+}
@@ -1,34 +1,83 @@
-use crate::allocator::prepare_pages;
-use alloc::boxed::Box;
+use crate::allocator::{prepare_pages, HeapStats};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::{alloc::Layout, ptr::NonNull};
 use linked_list_allocator::Heap;
+use spin::Mutex;
 use x86_64::structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Size4KiB};
-use yacari::MemoryManager;
+use yacari::{CodeMemory, MemoryManager};
 
-pub const CODE_HEAP_START: usize = 0x_6666_6666_0000;
-pub const CODE_HEAP_SIZE: usize = 2000 * 1024; // 2MB
+/// The region all code heaps are placed in, mapped when the kernel starts.
+pub const CODE_REGION_START: usize = 0x_6666_6666_0000;
+pub const CODE_REGION_SIZE: usize = 8000 * 1024; // 8MB
+/// The size of the code heap every program started with `run` gets.
+pub const PROGRAM_CODE_HEAP_SIZE: usize = 1000 * 1024; // 1MB
 pub const PAGE_SIZE: usize = 4096;
 
-struct YacariMemoryManager {
-    allocator: linked_list_allocator::Heap,
+/// Hands out the pages of the code region to code heaps.
+static CODE_REGION: Mutex<Heap> = Mutex::new(Heap::empty());
+
+/// All code heaps, which are removed once they are dropped.
+static CODE_HEAPS: Mutex<Vec<Weak<Mutex<CodeHeap>>>> = Mutex::new(Vec::new());
+
+/// A heap holding code compiled by the JIT, placed in the code region.
+struct CodeHeap {
+    allocator: Heap,
+    /// The amount of live allocations.
+    allocations: usize,
+    /// Bytes between the end of allocations and the next page,
+    /// which cannot be used since all allocations are page-aligned.
+    padding: usize,
 }
 
-impl YacariMemoryManager {
-    /// # Safety
-    /// Caller must ensure that the given memory is unused.
-    /// Function must be called only once.
-    unsafe fn init(heap_start: usize, heap_size: usize) {
-        let manager = YacariMemoryManager {
-            allocator: Heap::new(heap_start, heap_size),
-        };
-        yacari::set_manager(Box::new(manager))
+impl CodeHeap {
+    /// Reserve a heap of `size` bytes in the code region, rounded up to full pages.
+    /// Returns `None` if the region does not have enough space left.
+    fn new(size: usize) -> Option<Arc<Mutex<CodeHeap>>> {
+        let layout = layout_from_size(size + padding(size));
+        let start = CODE_REGION.lock().allocate_first_fit(layout).ok()?;
+        let mut allocator = Heap::empty();
+        unsafe { allocator.init(start.as_ptr() as usize, layout.size()) };
+
+        let heap = Arc::new(Mutex::new(CodeHeap {
+            allocator,
+            allocations: 0,
+            padding: 0,
+        }));
+        let mut heaps = CODE_HEAPS.lock();
+        heaps.retain(|heap| heap.strong_count() > 0);
+        heaps.push(Arc::downgrade(&heap));
+        Some(heap)
+    }
+
+    fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.allocator.size(),
+            used: self.allocator.used(),
+            free: self.allocator.free(),
+            fragmented: self.padding.min(self.allocator.free()),
+            allocations: self.allocations,
+        }
     }
+}
 
-    fn layout_from_size(size: usize) -> Layout {
-        Layout::from_size_align(size, PAGE_SIZE).unwrap()
+impl Drop for CodeHeap {
+    /// Give the pages of the heap back to the code region. All code on it
+    /// is gone at this point, since its program dropped the heap.
+    fn drop(&mut self) {
+        let start = NonNull::new(self.allocator.bottom() as *mut u8).unwrap();
+        let layout = layout_from_size(self.allocator.size());
+        unsafe { CODE_REGION.lock().deallocate(start, layout) }
     }
 }
 
+/// Allocates from a code heap on behalf of the JIT.
+struct YacariMemoryManager {
+    heap: Arc<Mutex<CodeHeap>>,
+}
+
 impl MemoryManager for YacariMemoryManager {
     fn page_size(&self) -> usize {
         PAGE_SIZE
@@ -41,27 +90,86 @@ impl MemoryManager for YacariMemoryManager {
     fn set_rw(&mut self, _ptr: *mut u8, _size: usize) {}
 
     fn alloc_page_aligned(&mut self, size: usize) -> *mut u8 {
-        self.allocator
-            .allocate_first_fit(Self::layout_from_size(size))
+        let mut heap = self.heap.lock();
+        heap.allocations += 1;
+        heap.padding += padding(size);
+        heap.allocator
+            .allocate_first_fit(layout_from_size(size))
             .unwrap()
             .as_ptr()
     }
 
     fn dealloc(&mut self, ptr: *mut u8, size: usize) {
+        let mut heap = self.heap.lock();
+        heap.allocations -= 1;
+        heap.padding -= padding(size);
         unsafe {
-            self.allocator
-                .deallocate(NonNull::new(ptr).unwrap(), Self::layout_from_size(size))
+            heap.allocator
+                .deallocate(NonNull::new(ptr).unwrap(), layout_from_size(size))
         }
     }
 }
 
-pub fn init_code_heap(
+fn layout_from_size(size: usize) -> Layout {
+    Layout::from_size_align(size, PAGE_SIZE).unwrap()
+}
+
+fn padding(size: usize) -> usize {
+    (PAGE_SIZE - size % PAGE_SIZE) % PAGE_SIZE
+}
+
+/// Returns memory for the code of a program on a heap of its own with `size` bytes,
+/// which is given back to the code region once the program is dropped.
+/// Returns `None` if the code region does not have enough space left.
+pub fn code_memory(size: usize) -> Option<CodeMemory> {
+    let heap = CodeHeap::new(size)?;
+    Some(CodeMemory::new(YacariMemoryManager { heap }))
+}
+
+/// Returns usage statistics of all code heaps together.
+pub fn code_heap_stats() -> HeapStats {
+    let heaps = CODE_HEAPS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect::<Vec<_>>();
+    let mut total = HeapStats::default();
+    for heap in heaps {
+        let stats = heap.lock().stats();
+        total.size += stats.size;
+        total.used += stats.used;
+        total.free += stats.free;
+        total.fragmented += stats.fragmented;
+        total.allocations += stats.allocations;
+    }
+    total
+}
+
+/// Returns usage statistics of the code region, with every code heap as an allocation.
+pub fn code_region_stats() -> HeapStats {
+    let heaps = CODE_HEAPS
+        .lock()
+        .iter()
+        .filter(|heap| heap.strong_count() > 0)
+        .count();
+    let region = CODE_REGION.lock();
+    HeapStats {
+        size: region.size(),
+        used: region.used(),
+        free: region.free(),
+        fragmented: 0,
+        allocations: heaps,
+    }
+}
+
+/// Map the code region, which code heaps are reserved in by `code_memory`.
+pub fn init_code_region(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
 ) -> Result<(), MapToError<Size4KiB>> {
-    prepare_pages(mapper, frame_allocator, CODE_HEAP_START, CODE_HEAP_SIZE)?;
+    prepare_pages(mapper, frame_allocator, CODE_REGION_START, CODE_REGION_SIZE)?;
     unsafe {
-        YacariMemoryManager::init(CODE_HEAP_START, CODE_HEAP_SIZE);
+        CODE_REGION.lock().init(CODE_REGION_START, CODE_REGION_SIZE);
     }
     Ok(())
 }
@@ -1,27 +1,195 @@
+mod cache;
+pub mod capability;
+mod host;
+pub mod jobs;
 mod memory;
+pub mod profiler;
+pub mod registry;
+pub mod services;
 
 use crate::{
-    drivers::disk::{fat::FatFs, FileSystem},
-    graphics::{draw_rect, Color},
-    scheduling::task::Task,
+    drivers::disk::FileSystem,
+    graphics, log,
+    log::Level,
+    vm::{
+        capability::{Capability, CapabilityError},
+        memory::PROGRAM_CODE_HEAP_SIZE,
+        profiler::Profile,
+    },
 };
-pub use memory::init_code_heap;
-
-pub fn test_app() {
-    yacari::execute_path::<_, ()>(
-        FileSystem::new(),
-        &["test_app", "system/yacuri"],
-        &[("draw_rect", test_draw_rect as *const u8)],
-    )
-    .unwrap();
-}
-
-fn test_draw_rect(x: i64, y: i64, w: i64, h: i64) {
-    draw_rect(
-        x as usize,
-        y as usize,
-        w as usize,
-        h as usize,
-        Color::from(81, 45, 168),
-    )
+use alloc::{format, string::String, vec::Vec};
+pub use memory::{code_heap_stats, code_memory, code_region_stats, init_code_region};
+use yacari::{
+    filesystem::File, line_column, CompileReport, Engine, Entry, Errors, ExecError, Panic,
+    ProgramError,
+};
+
+/// The library compiled alongside every program.
+const SYSTEM_LIBRARY: &str = "/system/yacuri";
+
+/// The flag everything is compiled with, so programs shared
+/// with other hosts can use `@cfg(kernel)` for kernel-only code.
+const KERNEL_FLAG: &str = "kernel";
+
+/// Why a program could not be run.
+#[derive(Debug)]
+pub enum RunError {
+    Program(ProgramError),
+    Capability(CapabilityError),
+    /// The program panicked, which only stops the program itself.
+    Panicked {
+        message: String,
+        /// Where the program panicked, as `file:line:column` if its source is known.
+        location: String,
+    },
+    /// The program was killed by the user.
+    Interrupted,
+    /// The filesystem was unmounted for shutdown.
+    Unmounted,
+    /// The code region has no space left for the code heap of the program.
+    OutOfCodeMemory,
+}
+
+impl From<ProgramError> for RunError {
+    fn from(err: ProgramError) -> Self {
+        RunError::Program(err)
+    }
+}
+
+impl From<CapabilityError> for RunError {
+    fn from(err: CapabilityError) -> Self {
+        RunError::Capability(err)
+    }
+}
+
+/// Compile and run the program at `path` together with the system library,
+/// returning its exit code. Programs started by the user are granted
+/// all capabilities they request.
+/// Relative paths are resolved against `working_dir`, which is made
+/// available to the program through host functions.
+/// The program reads its `args` with `get_env`, as `argc`, `arg0`, `arg1` and
+/// so on. The working directory and screen size can be read the same way,
+/// as `cwd`, `screen_width` and `screen_height`.
+pub fn exec_path(working_dir: Option<&str>, path: &str, args: &[String]) -> Result<i64, RunError> {
+    run(working_dir, path, args, &Capability::ALL, true, None, None)
+}
+
+/// Run a program like `exec_path`, returning how much machine code
+/// each of its functions was compiled to alongside its exit code.
+pub fn exec_path_with_stats(
+    working_dir: Option<&str>,
+    path: &str,
+    args: &[String],
+) -> Result<(i64, CompileReport), RunError> {
+    let mut report = CompileReport::default();
+    let code = run(
+        working_dir,
+        path,
+        args,
+        &Capability::ALL,
+        true,
+        None,
+        Some(&mut report),
+    )?;
+    Ok((code, report))
+}
+
+/// Run a program like `exec_path` while sampling where it spends its time.
+pub fn profile_path(
+    working_dir: Option<&str>,
+    path: &str,
+    args: &[String],
+) -> Result<(i64, Profile), RunError> {
+    let mut profile = Profile::default();
+    let code = run(
+        working_dir,
+        path,
+        args,
+        &Capability::ALL,
+        true,
+        Some(&mut profile),
+        None,
+    )?;
+    Ok((code, profile))
+}
+
+/// Log the errors the program at `path` failed to compile with.
+/// Compiler diagnostics use the `yacari` target, so they are filtered separately.
+pub fn report_errors(path: &str, errors: &[Errors]) {
+    for error in errors.iter().flatten() {
+        log!(target: "yacari", Level::Error, "{}: {}", path, error);
+    }
+}
+
+/// Run a program like `exec_path`, refusing to run it
+/// if it requests any capabilities that are not `granted`.
+/// It can be killed with Ctrl+C if it runs in the `foreground`
+/// of the shell, and with `jobs::kill` otherwise.
+/// If given a `profile`, it is filled with samples taken while the program ran,
+/// and a `report` is filled with the code size of the program once it is compiled.
+#[allow(clippy::too_many_arguments)]
+fn run(
+    working_dir: Option<&str>,
+    path: &str,
+    args: &[String],
+    granted: &[Capability],
+    foreground: bool,
+    profile: Option<&mut Profile>,
+    report: Option<&mut CompileReport>,
+) -> Result<i64, RunError> {
+    let fs = FileSystem::with_working_dir(working_dir).ok_or(RunError::Unmounted)?;
+    let files = yacari::read_files(&fs, &[path]).map_err(ProgramError::Read)?;
+    let symbols = capability::symbol_table(&capability::requested(&files)?, granted)?;
+
+    let (width, height) = graphics::resolution().unwrap_or((0, 0));
+    let engine = Engine::new(&fs)
+        .flag(KERNEL_FLAG)
+        .symbols(&symbols)
+        .library(cache::get(&fs, SYSTEM_LIBRARY)?)
+        .args(args)
+        .bind("cwd", working_dir.unwrap_or(""))
+        .bind("screen_width", width as i64)
+        .bind("screen_height", height as i64);
+    let modules = engine.compile_files(&files)?;
+
+    let memory = code_memory(PROGRAM_CODE_HEAP_SIZE).ok_or(RunError::OutOfCodeMemory)?;
+    host::set_context(working_dir.unwrap_or(""));
+    let mut program = engine.link_with_memory(&modules, memory)?;
+    if let Some(report) = report {
+        *report = program.compile_report();
+    }
+    let registration = registry::register(&program, &files);
+    let job = jobs::start(path, foreground, program.interrupt_handle());
+    if profile.is_some() {
+        profiler::start();
+    }
+    let result = program.run(Entry::MAIN);
+    if let Some(profile) = profile {
+        *profile = profiler::finish();
+    }
+    jobs::finish(job);
+    registry::unregister(registration);
+    host::release_context();
+    match result {
+        Err(ExecError::Panicked(panic)) => Err(RunError::Panicked {
+            location: panic_location(&panic, &files),
+            message: panic.message,
+        }),
+        Err(ExecError::Interrupted) => Err(RunError::Interrupted),
+        result => Ok(result.map_err(ProgramError::from)?),
+    }
+}
+
+/// Describe where a program panicked, with the line and column
+/// if the module it panicked in is one of `files`.
+fn panic_location(panic: &Panic, files: &[File]) -> String {
+    let path = panic.module.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    let file = format!("{}.yacari", path.join("/"));
+    match files.iter().find(|file| file.path == panic.module) {
+        Some(source) => {
+            let (line, column) = line_column(&source.contents, panic.start);
+            format!("{}:{}:{}", file, line, column)
+        }
+        None => format!("{} at offset {}", file, panic.start),
+    }
 }
@@ -0,0 +1,116 @@
+//! Sampling profiler for yacari programs. While enabled, the timer interrupt
+//! records the instruction pointer it interrupted, which are then attributed
+//! to the functions in the registry once the program has finished.
+
+use crate::vm::registry;
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// The maximum amount of samples recorded, about a minute at 1000 ticks per second.
+const MAX_SAMPLES: usize = 65536;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+static SAMPLES: [AtomicUsize; MAX_SAMPLES] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicUsize = AtomicUsize::new(0);
+    [EMPTY; MAX_SAMPLES]
+};
+
+/// Discard all previous samples and start recording.
+pub fn start() {
+    COUNT.store(0, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Release);
+}
+
+/// Called by the timer interrupt handler with the interrupted instruction.
+/// Does nothing unless the profiler was started.
+pub fn sample(ip: usize) {
+    if !ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+    let index = COUNT.fetch_add(1, Ordering::Relaxed);
+    if index < MAX_SAMPLES {
+        SAMPLES[index].store(ip, Ordering::Relaxed);
+    }
+}
+
+/// Stop recording and attribute all samples to the functions in the registry,
+/// which needs to still contain the profiled program.
+pub fn finish() -> Profile {
+    ENABLED.store(false, Ordering::Release);
+    let count = COUNT.swap(0, Ordering::Relaxed).min(MAX_SAMPLES);
+
+    let mut profile = Profile::default();
+    for sample in &SAMPLES[..count] {
+        match registry::function(sample.load(Ordering::Relaxed)) {
+            Some(name) => match profile.functions.iter_mut().find(|(f, _)| *f == name) {
+                Some((_, samples)) => *samples += 1,
+                None => profile.functions.push((name, 1)),
+            },
+            None => profile.outside += 1,
+        }
+    }
+    profile.functions.sort_by(|a, b| b.1.cmp(&a.1));
+    profile.total = count;
+    profile
+}
+
+/// A flat profile of a program run.
+#[derive(Debug, Default)]
+pub struct Profile {
+    /// Functions with the amount of samples taken inside them, most sampled first.
+    pub functions: Vec<(String, usize)>,
+    /// Samples taken outside of the program, like in host functions or the kernel.
+    pub outside: usize,
+    pub total: usize,
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} samples", self.total)?;
+        let total = self.total.max(1);
+        for (name, samples) in &self.functions {
+            writeln!(
+                f,
+                "{:>5.1}% {:>6}  {}",
+                percent(*samples, total),
+                samples,
+                name
+            )?;
+        }
+        write!(
+            f,
+            "{:>5.1}% {:>6}  <outside of program>",
+            percent(self.outside, total),
+            self.outside
+        )
+    }
+}
+
+fn percent(samples: usize, total: usize) -> f64 {
+    samples as f64 * 100.0 / total as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test_case]
+    fn samples_outside_program() {
+        start();
+        sample(0x1000);
+        sample(0x2000);
+        let profile = finish();
+        // The timer might have taken more samples in between
+        assert!(profile.total >= 2);
+        assert!(profile.functions.is_empty());
+        assert_eq!(profile.outside, profile.total);
+
+        sample(0x3000);
+        assert_eq!(finish().total, 0);
+    }
+}
@@ -0,0 +1,93 @@
+//! Registry of the machine code of running programs, used by the
+//! fault handlers to report where in a program a fault happened.
+
+use alloc::{format, string::String, vec::Vec};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use spin::Mutex;
+use yacari::{filesystem::File, line_column, Program};
+
+/// The machine code of a single compiled function.
+struct Region {
+    /// The registration the region belongs to.
+    program: usize,
+    function: String,
+    file: String,
+    code: Range<usize>,
+    /// Code ranges relative to the function start with their source line.
+    /// Modules without source available instead store their source offset.
+    lines: Vec<(Range<u32>, usize)>,
+    has_source: bool,
+}
+
+static REGIONS: Mutex<Vec<Region>> = Mutex::new(Vec::new());
+
+/// Register all functions of the given program, whose
+/// source is found in `files` if it was compiled from them.
+/// Returns a handle to remove them again with `unregister`.
+pub fn register(program: &Program, files: &[File]) -> usize {
+    static NEXT_PROGRAM: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT_PROGRAM.fetch_add(1, Ordering::Relaxed);
+    let mut regions = REGIONS.lock();
+    for map in program.source_maps() {
+        let source = files.iter().find(|file| file.path == map.module);
+        let lines = map
+            .locations
+            .iter()
+            .map(|(code, offset)| {
+                let line = source.map_or(*offset, |file| line_column(&file.contents, *offset).0);
+                (code.clone(), line)
+            })
+            .collect();
+
+        let path = map.module.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+        regions.push(Region {
+            program: id,
+            function: map.symbol.as_str().into(),
+            file: format!("{}.yacari", path.join("/")),
+            code: map.code.clone(),
+            lines,
+            has_source: source.is_some(),
+        });
+    }
+    id
+}
+
+/// Remove the functions of a program, once it has exited.
+pub fn unregister(program: usize) {
+    REGIONS.lock().retain(|region| region.program != program);
+}
+
+/// Returns the name of the registered function containing the given address.
+pub fn function(address: usize) -> Option<String> {
+    let regions = REGIONS.lock();
+    let region = regions.iter().find(|r| r.code.contains(&address))?;
+    Some(region.function.clone())
+}
+
+/// Describe the location of the instruction at the given address,
+/// if it is part of a registered function.
+/// Does not block, since it is called from fault handlers.
+pub fn describe(address: usize) -> Option<String> {
+    let regions = REGIONS.try_lock()?;
+    let region = regions.iter().find(|r| r.code.contains(&address))?;
+    let offset = (address - region.code.start) as u32;
+    let location = region
+        .lines
+        .iter()
+        .find(|(code, _)| code.contains(&offset))
+        .map(|(_, line)| *line);
+
+    Some(match location {
+        Some(line) if region.has_source => {
+            format!("{} in {}:{}", region.function, region.file, line)
+        }
+        Some(offset) => format!(
+            "{} in {} at offset {}",
+            region.function, region.file, offset
+        ),
+        None => format!("{} in {}", region.function, region.file),
+    })
+}
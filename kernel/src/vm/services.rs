@@ -0,0 +1,111 @@
+use crate::{
+    drivers::disk::FileSystem,
+    error, info, scheduling,
+    vm::{capability::Capability, report_errors, run, RunError},
+    warn,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use yacari::ProgramError;
+
+/// The manifest listing all services to start at boot.
+/// Every line has the form `<path> [capability...]`,
+/// lines starting with `#` are comments.
+const MANIFEST: &str = "system/init.txt";
+
+/// A yacari program started at boot.
+struct Service {
+    path: String,
+    /// The capabilities this service is granted.
+    granted: Vec<Capability>,
+}
+
+/// Read the service manifest and spawn a task for every service in it.
+/// Services are spread over all CPUs other than the boot CPU.
+pub fn spawn_all() {
+    let manifest = match FileSystem::new().and_then(|fs| fs.read_to_string(MANIFEST)) {
+        Some(manifest) => manifest,
+        None => {
+            warn!("failed to read {}, not starting any services", MANIFEST);
+            return;
+        }
+    };
+
+    for (index, line) in manifest.lines().enumerate() {
+        match parse_service(line) {
+            Ok(Some(service)) => {
+                let name = service.path.clone();
+                scheduling::spawn(&name, run_service(service))
+            }
+            Ok(None) => (),
+            Err(msg) => error!("{}:{}: {}", MANIFEST, index + 1, msg),
+        }
+    }
+}
+
+fn parse_service(line: &str) -> Result<Option<Service>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let mut parts = line.split_whitespace();
+    let path = parts.next().unwrap().to_string();
+    let granted = parts
+        .map(|name| {
+            Capability::from_name(name).ok_or_else(|| format!("unknown capability '{}'", name))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some(Service { path, granted }))
+}
+
+async fn run_service(service: Service) {
+    info!("starting {}", service.path);
+    match run(
+        None,
+        &service.path,
+        &[],
+        &service.granted,
+        false,
+        None,
+        None,
+    ) {
+        Ok(0) => info!("{} finished", service.path),
+        Ok(code) => warn!("{} exited with code {}", service.path, code),
+        Err(RunError::Program(ProgramError::Read(errors))) => {
+            error!("{} could not be compiled", service.path);
+            for error in errors {
+                error!("{}", error)
+            }
+        }
+        Err(RunError::Program(ProgramError::Compile(errors))) => {
+            error!("{} failed to compile", service.path);
+            report_errors(&service.path, &errors)
+        }
+        Err(RunError::Program(ProgramError::Exec(err))) => {
+            error!("{} failed to start: {:?}", service.path, err)
+        }
+        Err(RunError::Program(ProgramError::Target(err))) => {
+            error!("{} failed to link: {:?}", service.path, err)
+        }
+        Err(RunError::Program(ProgramError::Link(err))) => {
+            error!("{} failed to link: {:?}", service.path, err)
+        }
+        Err(RunError::Panicked { message, location }) => {
+            error!("{} panicked at {}: {}", service.path, location, message)
+        }
+        Err(RunError::Interrupted) => warn!("{} was killed", service.path),
+        Err(RunError::Capability(err)) => {
+            error!("{} was refused: {:?}", service.path, err)
+        }
+        Err(RunError::Unmounted) => {
+            error!("{} failed to start: filesystem unmounted", service.path)
+        }
+        Err(RunError::OutOfCodeMemory) => {
+            error!("{} failed to start: no code memory left", service.path)
+        }
+    }
+}
@@ -58,3 +58,18 @@ fn many_boxes() {
         assert_eq!(*x, i);
     }
 }
+
+#[test_case]
+fn stats() {
+    let before = allocator::stats();
+    let value = Box::new([0u8; 4096]);
+    let during = allocator::stats();
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert!(during.used >= before.used + 4096);
+    assert_eq!(during.used + during.free, during.size);
+
+    drop(value);
+    let after = allocator::stats();
+    assert_eq!(after.allocations, before.allocations);
+    assert_eq!(after.used, before.used);
+}
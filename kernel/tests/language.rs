@@ -0,0 +1,185 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(yacuri::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::{format, vec};
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use x86_64::VirtAddr;
+use yacari::{
+    compile_files,
+    filesystem::{memory_fs::MemoryFs, File},
+    read_files, testing, CodeMemory, Entry, ExecError, Program, SmolStr,
+};
+use yacuri::{
+    allocator,
+    allocator::{memory, memory::BootInfoFrameAllocator},
+    vm,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static mut BootInfo) -> ! {
+    yacuri::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_regions) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    vm::init_code_region(&mut mapper, &mut frame_allocator)
+        .expect("code region initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    yacuri::test_panic_handler(info)
+}
+
+/// The language tests of `lang/tests` made out of a single file and without
+/// host functions. They are embedded, since the test drive has no filesystem.
+const TESTS: &[(&str, &str)] = &[
+    (
+        "basic_funcs",
+        include_str!("../../lang/tests/basic_funcs.yacari"),
+    ),
+    ("closures", include_str!("../../lang/tests/closures.yacari")),
+    (
+        "constructors",
+        include_str!("../../lang/tests/constructors.yacari"),
+    ),
+    (
+        "default_args",
+        include_str!("../../lang/tests/default_args.yacari"),
+    ),
+    ("errors", include_str!("../../lang/tests/errors.yacari")),
+    (
+        "exit_code",
+        include_str!("../../lang/tests/exit_code.yacari"),
+    ),
+    (
+        "function_values",
+        include_str!("../../lang/tests/function_values.yacari"),
+    ),
+    ("globals", include_str!("../../lang/tests/globals.yacari")),
+    (
+        "local_functions",
+        include_str!("../../lang/tests/local_functions.yacari"),
+    ),
+    (
+        "operators",
+        include_str!("../../lang/tests/operators.yacari"),
+    ),
+    (
+        "overloads",
+        include_str!("../../lang/tests/overloads.yacari"),
+    ),
+    ("statics", include_str!("../../lang/tests/statics.yacari")),
+];
+
+/// A code heap of its own for every test program, given back after it ran.
+fn code_memory() -> CodeMemory {
+    vm::code_memory(256 * 1024).expect("code region has space for a test")
+}
+
+#[test_case]
+fn language_tests() {
+    for (name, source) in TESTS {
+        let file = File {
+            path: vec![SmolStr::new(name)],
+            contents: source.into(),
+        };
+        let result = testing::run_test_with_memory(&[file], &[], code_memory());
+        assert_eq!(result, Some(Ok(())), "{}", name);
+    }
+}
+
+/// The language tests of `lang/tests` made out of a directory of modules,
+/// embedded as an in-memory filesystem that is walked like the host's.
+const MODULE_TESTS: MemoryFs = MemoryFs::new(&[
+    (
+        "imports/main.yacari",
+        include_str!("../../lang/tests/imports/main.yacari"),
+    ),
+    (
+        "imports/shapes.yacari",
+        include_str!("../../lang/tests/imports/shapes.yacari"),
+    ),
+    (
+        "import_cycle/a.yacari",
+        include_str!("../../lang/tests/import_cycle/a.yacari"),
+    ),
+    (
+        "import_cycle/b.yacari",
+        include_str!("../../lang/tests/import_cycle/b.yacari"),
+    ),
+    (
+        "import_cycle/c.yacari",
+        include_str!("../../lang/tests/import_cycle/c.yacari"),
+    ),
+]);
+
+#[test_case]
+fn module_tests() {
+    for name in &["imports", "import_cycle"] {
+        let files = read_files(&MODULE_TESTS, &[*name]).unwrap();
+        let result = testing::run_test_with_memory(&files, &[], code_memory());
+        assert_eq!(result, Some(Ok(())), "{}", name);
+    }
+}
+
+/// Programs on code heaps of their own, which are given back once the programs are dropped.
+#[test_case]
+fn code_heaps() {
+    let file = File {
+        path: vec![SmolStr::new("heaps")],
+        contents: "fun main() -> i64 {\n    6 * 7\n}".into(),
+    };
+    let modules = compile_files(&[file]).unwrap();
+    let before = vm::code_region_stats();
+
+    let mut first =
+        Program::with_memory(&modules, &[], vm::code_memory(64 * 1024).unwrap()).unwrap();
+    let mut second =
+        Program::with_memory(&modules, &[], vm::code_memory(128 * 1024).unwrap()).unwrap();
+    assert_eq!(vm::code_region_stats().allocations, before.allocations + 2);
+    assert_eq!(first.run(Entry::MAIN), Ok(42));
+    assert_eq!(second.run(Entry::MAIN), Ok(42));
+
+    drop((first, second));
+    let after = vm::code_region_stats();
+    assert_eq!(after.allocations, before.allocations);
+    assert_eq!(after.used, before.used);
+}
+
+/// Division panics where the CPU would fault, which would run the faulting instruction forever.
+#[test_case]
+fn division_panics() {
+    let min = "(0 - 9223372036854775807) - 1";
+    let cases = [
+        ("7", "/", "0", "division by zero"),
+        ("7", "%", "0", "division by zero"),
+        (min, "/", "0 - 1", "division overflow"),
+    ];
+    for (a, op, b, message) in &cases {
+        let file = File {
+            path: vec![SmolStr::new("division")],
+            contents: format!(
+                "fun divide(a: i64, b: i64) -> i64 a {} b\nfun main() -> i64 divide({}, {})",
+                op, a, b
+            )
+            .into(),
+        };
+        let modules = compile_files(&[file]).unwrap();
+        let mut program = Program::with_memory(&modules, &[], code_memory()).unwrap();
+        match program.run(Entry::MAIN) {
+            Err(ExecError::Panicked(panic)) => assert_eq!(panic.message, *message),
+            res => panic!("expected a panic, got {:?}", res),
+        }
+    }
+}
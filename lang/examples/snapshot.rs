@@ -0,0 +1,71 @@
+//! Compiles a program ahead of time and saves it as a snapshot,
+//! which can then be executed without compiling it again.
+//! Usage: `cargo run --example snapshot -- [--timings] [--library] [--flag <flag>...] <output> <paths...>`
+//! `--timings` requires the `timings` feature. `--library` keeps all functions instead
+//! of eliminating those `main` does not use, since programs linking a library call them.
+//! Every `--flag` is set for conditional compilation, like `--flag kernel`
+//! for libraries the kernel loads.
+
+use std::{env, fs, process};
+use yacari::{filesystem::os_fs::OsFs, CompileOptions, SmolStr};
+
+fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let timings = args.first().map(String::as_str) == Some("--timings");
+    if timings {
+        args.remove(0);
+        enable_timings();
+    }
+    let mut options = CompileOptions::default();
+    if args.first().map(String::as_str) == Some("--library") {
+        args.remove(0);
+        options.eliminate_dead_code = false;
+    }
+    while args.first().map(String::as_str) == Some("--flag") && args.len() > 1 {
+        options.flags.push(SmolStr::new(&args[1]));
+        args.drain(..2);
+    }
+    if args.len() < 2 {
+        eprintln!("Usage: snapshot [--timings] [--library] [--flag <flag>...] <output> <paths...>");
+        process::exit(1);
+    }
+
+    let paths = args[1..].iter().map(String::as_str).collect::<Vec<_>>();
+    let files = match yacari::read_files(&OsFs, &paths) {
+        Ok(files) => files,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            process::exit(1);
+        }
+    };
+    match yacari::compile_files_with(&files, &options) {
+        Ok(modules) => fs::write(&args[0], yacari::save_snapshot(&modules)).unwrap(),
+        Err(errors) => {
+            eprintln!("Failed to compile: {:?}", errors);
+            process::exit(1);
+        }
+    }
+
+    #[cfg(feature = "timings")]
+    if timings {
+        println!("{}", yacari::take_timings());
+    }
+}
+
+#[cfg(feature = "timings")]
+fn enable_timings() {
+    yacari::set_clock(|| {
+        let now = std::time::SystemTime::now();
+        now.duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+    });
+}
+
+#[cfg(not(feature = "timings"))]
+fn enable_timings() {
+    eprintln!("--timings requires the `timings` feature");
+    process::exit(1);
+}
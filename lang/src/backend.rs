@@ -0,0 +1,30 @@
+//! The interface between the compiler and the code generators turning its IR
+//! into something that can run, like machine code for the JIT or WebAssembly.
+
+use crate::{compiler::ir, CompiledModule};
+
+pub(crate) trait Backend {
+    /// Why the backend could not take a module or link them.
+    type Error;
+
+    /// Generate code for all live functions of the module. Functions of
+    /// other modules it calls might only be generated after it.
+    fn module(&mut self, module: &ir::Module) -> Result<(), Self::Error>;
+
+    /// Resolve the calls between the generated modules,
+    /// once code for all of them was generated.
+    fn link(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Generate code for all given modules with the backend.
+pub(crate) fn lower<B: Backend>(
+    backend: &mut B,
+    modules: &[CompiledModule],
+) -> Result<(), B::Error> {
+    for module in modules {
+        backend.module(&*module.0.borrow())?;
+    }
+    backend.link()
+}
@@ -0,0 +1,224 @@
+//! A line editor for terminals, with the keys of readline: moving with the arrow keys,
+//! Home and End or Ctrl+A and Ctrl+E, deleting with Backspace, Delete, Ctrl+K and Ctrl+U,
+//! and going through earlier lines with Up and Down.
+//! The terminal is switched to raw mode with `stty` while a line is read;
+//! if input is not a terminal, lines are read as they are.
+
+use std::{
+    io::{self, BufRead, Read, StdinLock, Write},
+    process::{Command, Stdio},
+};
+
+const CTRL_A: u8 = 0x01;
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const CTRL_E: u8 = 0x05;
+const CTRL_K: u8 = 0x0B;
+const CTRL_U: u8 = 0x15;
+const BACKSPACE: u8 = 0x7F;
+const ESCAPE: u8 = 0x1B;
+
+pub struct Editor {
+    /// Earlier lines, oldest first.
+    history: Vec<String>,
+    /// The settings of the terminal to restore after reading a line,
+    /// or `None` if input is not a terminal.
+    terminal: Option<String>,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        let terminal = stty(&["-g"]).map(|settings| settings.trim().to_string());
+        Self {
+            history: Vec::new(),
+            terminal,
+        }
+    }
+
+    /// Read a line after showing `prompt`.
+    /// Returns `None` at the end of input, or when Ctrl+D is pressed on an empty line.
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        let line = match &self.terminal {
+            Some(settings) => {
+                stty(&["raw", "-echo"])?;
+                let line = LineState::new(prompt, &self.history).read(&mut io::stdin().lock());
+                stty(&[settings.as_str()]);
+                line
+            }
+            None => read_plain(prompt),
+        }?;
+
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        Some(line)
+    }
+}
+
+/// A line while it is being edited.
+struct LineState<'h> {
+    prompt: &'h str,
+    chars: Vec<char>,
+    cursor: usize,
+    history: &'h [String],
+    /// The index of the history entry shown, which is
+    /// `history.len()` for the line being entered.
+    entry: usize,
+    /// The line being entered, while an earlier one is shown.
+    entered: Vec<char>,
+}
+
+impl<'h> LineState<'h> {
+    fn new(prompt: &'h str, history: &'h [String]) -> Self {
+        Self {
+            prompt,
+            chars: Vec::new(),
+            cursor: 0,
+            history,
+            entry: history.len(),
+            entered: Vec::new(),
+        }
+    }
+
+    fn read(mut self, input: &mut StdinLock) -> Option<String> {
+        self.redraw();
+        loop {
+            match read_byte(input)? {
+                b'\r' | b'\n' => {
+                    print!("\r\n");
+                    return Some(self.chars.into_iter().collect());
+                }
+                CTRL_D if self.chars.is_empty() => {
+                    print!("\r\n");
+                    return None;
+                }
+                CTRL_D => self.delete(),
+                CTRL_C => {
+                    print!("^C\r\n");
+                    self.chars.clear();
+                    self.cursor = 0;
+                }
+                CTRL_A => self.cursor = 0,
+                CTRL_E => self.cursor = self.chars.len(),
+                CTRL_K => self.chars.truncate(self.cursor),
+                CTRL_U => {
+                    self.chars.drain(..self.cursor);
+                    self.cursor = 0;
+                }
+                BACKSPACE | 0x08 if self.cursor > 0 => {
+                    self.cursor -= 1;
+                    self.chars.remove(self.cursor);
+                }
+                ESCAPE => self.escape(input)?,
+                byte if byte >= 0x20 && byte != BACKSPACE => {
+                    if let Some(char) = read_char(byte, input) {
+                        self.chars.insert(self.cursor, char);
+                        self.cursor += 1;
+                    }
+                }
+                _ => (),
+            }
+            self.redraw();
+        }
+    }
+
+    /// Handle an escape sequence, which most keys besides characters send.
+    fn escape(&mut self, input: &mut StdinLock) -> Option<()> {
+        if !matches!(read_byte(input)?, b'[' | b'O') {
+            return Some(());
+        }
+        match read_byte(input)? {
+            b'A' => self.show_entry(self.entry.saturating_sub(1)),
+            b'B' if self.entry < self.history.len() => self.show_entry(self.entry + 1),
+            b'C' if self.cursor < self.chars.len() => self.cursor += 1,
+            b'D' if self.cursor > 0 => self.cursor -= 1,
+            b'H' => self.cursor = 0,
+            b'F' => self.cursor = self.chars.len(),
+            // Keys like `ESC [ 3 ~`, which is Delete
+            digit @ b'0'..=b'9' => {
+                let mut code = vec![digit];
+                while let byte @ b'0'..=b'9' = read_byte(input)? {
+                    code.push(byte)
+                }
+                match code.as_slice() {
+                    b"1" | b"7" => self.cursor = 0,
+                    b"3" => self.delete(),
+                    b"4" | b"8" => self.cursor = self.chars.len(),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+        Some(())
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn show_entry(&mut self, entry: usize) {
+        if self.entry == self.history.len() {
+            self.entered = self.chars.clone();
+        }
+        self.entry = entry;
+        self.chars = match self.history.get(entry) {
+            Some(line) => line.chars().collect(),
+            None => self.entered.clone(),
+        };
+        self.cursor = self.chars.len();
+    }
+
+    fn redraw(&self) {
+        let line = self.chars.iter().collect::<String>();
+        print!("\r\x1b[K{}{}", self.prompt, line);
+        let after = self.chars.len() - self.cursor;
+        if after > 0 {
+            print!("\x1b[{}D", after);
+        }
+        io::stdout().flush().ok();
+    }
+}
+
+fn read_byte(input: &mut StdinLock) -> Option<u8> {
+    let mut byte = [0];
+    match input.read(&mut byte) {
+        Ok(1) => Some(byte[0]),
+        _ => None,
+    }
+}
+
+/// Read the rest of the UTF-8 character starting with `first`.
+fn read_char(first: u8, input: &mut StdinLock) -> Option<char> {
+    let mut bytes = vec![first];
+    for _ in 1..first.leading_ones().max(1) {
+        bytes.push(read_byte(input)?);
+    }
+    std::str::from_utf8(&bytes).ok()?.chars().next()
+}
+
+fn read_plain(prompt: &str) -> Option<String> {
+    print!("{}", prompt);
+    io::stdout().flush().ok();
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(&['\n', '\r'][..]).to_string()),
+    }
+}
+
+/// Run `stty` on the terminal, returning its output if it succeeded.
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        String::from_utf8(output.stdout).ok()
+    } else {
+        None
+    }
+}
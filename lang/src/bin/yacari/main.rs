@@ -0,0 +1,188 @@
+//! The command line interface for running and checking programs on the host.
+//! Usage: `yacari <command> [args...]`, see `USAGE` for all commands.
+
+use std::{env, fs, process};
+use yacari::{
+    diagnostics_to_json,
+    filesystem::{os_fs::OsFs, File},
+    line_column, ExecError, ProgramError,
+};
+
+const USAGE: &str = "\
+Usage: yacari <command> [args...]
+
+Commands:
+    run <paths...>              run a program, exiting with its exit code
+    check [--json] <paths...>   report all problems of a program without running it
+    fmt [--check] <files...>    format source files, or only check if they are formatted
+    dump-clif <paths...>        print the Cranelift IR of a program
+    repl                        evaluate declarations and expressions as they are entered
+
+Paths are source files or directories containing them.";
+
+/// Exit code when the program could not be read or compiled.
+const FAILED: i32 = 1;
+/// Exit code when the command line was invalid.
+const INVALID_USAGE: i32 = 2;
+/// Exit code when the program panicked, matching Rust.
+const PANICKED: i32 = 101;
+
+mod editor;
+mod repl;
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => usage(),
+    };
+    if command == "repl" && args.is_empty() {
+        process::exit(repl::repl())
+    }
+    let (flag, paths) = match args.split_first() {
+        Some((flag, paths)) if flag.starts_with("--") => (Some(flag.as_str()), paths),
+        _ => (None, args),
+    };
+    if paths.is_empty() {
+        usage()
+    }
+    let paths = paths.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let code = match (command, flag) {
+        ("run", None) => run(&paths),
+        ("check", None) => check(&paths, false),
+        ("check", Some("--json")) => check(&paths, true),
+        ("fmt", None) => fmt(&paths, false),
+        ("fmt", Some("--check")) => fmt(&paths, true),
+        ("dump-clif", None) => dump_clif(&paths),
+        _ => usage(),
+    };
+    process::exit(code)
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(INVALID_USAGE)
+}
+
+fn run(paths: &[&str]) -> i32 {
+    let files = read(paths);
+    let modules = compile(&files);
+    match yacari::execute_modules(&modules, &[]) {
+        Ok(code) => code as i32,
+        Err(ProgramError::Exec(ExecError::Panicked(panic))) => {
+            let file = files.iter().find(|file| file.path == panic.module);
+            match file {
+                Some(file) => {
+                    let (line, column) = line_column(&file.contents, panic.start);
+                    let path = panic.module.join("/");
+                    eprintln!(
+                        "panicked at {}.yacari:{}:{}: {}",
+                        path, line, column, panic.message
+                    )
+                }
+                None => eprintln!("panicked: {}", panic.message),
+            }
+            PANICKED
+        }
+        Err(err) => {
+            eprintln!("error: failed to start the program: {:?}", err);
+            FAILED
+        }
+    }
+}
+
+fn check(paths: &[&str], json: bool) -> i32 {
+    let diagnostics = yacari::check_path(&OsFs, paths);
+    if json {
+        println!("{}", diagnostics_to_json(&diagnostics));
+    } else {
+        // Reading again is cheap, and only needed to show the lines of errors
+        let files = yacari::read_files(&OsFs, paths).unwrap_or_default();
+        for diagnostic in &diagnostics {
+            eprintln!("{}", diagnostic.render(&files));
+        }
+    }
+    if diagnostics.is_empty() {
+        0
+    } else {
+        FAILED
+    }
+}
+
+fn fmt(paths: &[&str], check: bool) -> i32 {
+    let mut code = 0;
+    for path in paths {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("error: failed to read {}: {}", path, err);
+                code = FAILED;
+                continue;
+            }
+        };
+        let formatted = match yacari::tooling::format(&source) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for error in errors {
+                    let (line, column) = line_column(&source, error.start());
+                    let kind = error.kind();
+                    eprintln!(
+                        "{}:{}:{}: error[{}]: {}",
+                        path,
+                        line,
+                        column,
+                        kind.code(),
+                        kind
+                    );
+                }
+                code = FAILED;
+                continue;
+            }
+        };
+
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", path);
+            code = FAILED;
+        } else if let Err(err) = fs::write(path, formatted) {
+            eprintln!("error: failed to write {}: {}", path, err);
+            code = FAILED;
+        }
+    }
+    code
+}
+
+fn dump_clif(paths: &[&str]) -> i32 {
+    let modules = compile(&read(paths));
+    print!("{}", yacari::dump_clif(&modules));
+    0
+}
+
+/// Read the source files at the given paths, exiting if any cannot be read.
+fn read(paths: &[&str]) -> Vec<File<'static>> {
+    match yacari::read_files(&OsFs, paths) {
+        Ok(files) => files,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("error: {}", error);
+            }
+            process::exit(FAILED)
+        }
+    }
+}
+
+/// Compile the given files, exiting with their diagnostics if they do not compile.
+fn compile(files: &[File]) -> Vec<yacari::CompiledModule> {
+    match yacari::compile_files(files) {
+        Ok(modules) => modules,
+        Err(errors) => {
+            for diagnostic in ProgramError::Compile(errors).diagnostics(files) {
+                eprintln!("{}", diagnostic.render(files));
+            }
+            process::exit(FAILED)
+        }
+    }
+}
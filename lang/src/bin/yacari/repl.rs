@@ -0,0 +1,82 @@
+//! The `repl` command, which evaluates declarations and expressions as they are entered.
+
+use crate::editor::Editor;
+use yacari::{filesystem::File, ExecError, ProgramError, Repl, SmolStr};
+
+const HELP: &str = "\
+Declarations like functions, classes and vals are kept for later input,
+expressions are evaluated and their value is printed.
+Input continues on the next line while brackets are open.
+
+Commands:
+    :type <expr>        show the type of an expression
+    :dump-clif <expr>   print the Cranelift IR of an expression
+    :help               show this help
+    :quit               exit, like Ctrl+D";
+
+const PROMPT: &str = ">>> ";
+/// The prompt of lines continuing the input.
+const CONTINUE: &str = "... ";
+
+pub fn repl() -> i32 {
+    let mut editor = Editor::new();
+    let mut repl = Repl::new(&[]);
+    println!("Enter :help for help.");
+    while let Some(input) = read_input(&mut editor) {
+        let (command, expr) = match input.trim().split_once(char::is_whitespace) {
+            Some((command, expr)) => (command, expr.trim()),
+            None => (input.trim(), ""),
+        };
+        let result = match command {
+            ":type" => repl.type_of(expr).map(Some),
+            ":dump-clif" => repl.dump_clif(expr).map(Some),
+            ":help" => Ok(Some(HELP.to_string())),
+            ":quit" => break,
+            _ if command.starts_with(':') => {
+                eprintln!("error: unknown command '{}', see :help", command);
+                continue;
+            }
+            _ => repl.eval(&input),
+        };
+
+        match result {
+            Ok(Some(output)) => println!("{}", output.trim_end()),
+            Ok(None) => (),
+            Err(err) if command.starts_with(':') => report(err, expr),
+            Err(err) => report(err, &input),
+        }
+    }
+    0
+}
+
+/// Read the next input, which continues over multiple lines until it is complete.
+fn read_input(editor: &mut Editor) -> Option<String> {
+    let mut input = editor.read_line(PROMPT)?;
+    while !input.starts_with(':') && !Repl::is_complete(&input) {
+        match editor.read_line(CONTINUE) {
+            Some(line) => {
+                input.push('\n');
+                input.push_str(&line);
+            }
+            None => break,
+        }
+    }
+    Some(input)
+}
+
+/// Print why the given input failed.
+fn report(err: ProgramError, input: &str) {
+    match err {
+        ProgramError::Exec(ExecError::Panicked(panic)) => eprintln!("panicked: {}", panic.message),
+        ProgramError::Exec(err) => eprintln!("error: failed to evaluate: {:?}", err),
+        err => {
+            let files = [File {
+                path: vec![SmolStr::new_inline("repl")],
+                contents: input.into(),
+            }];
+            for diagnostic in err.diagnostics(&files) {
+                eprintln!("{}", diagnostic.render(&files));
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{
+    compiler::{
+        ir::{Constant, FuncRef, IExpr, Module},
+        MutRc,
+    },
+    smol_str::SmolStr,
+};
+use alloc::vec::Vec;
+
+/// Mark all functions reachable from one of the given entry points,
+/// an exported function or a module initializer as live, and all others as dead, which causes the JIT to skip them.
+/// References to a function count as well, since it might
+/// be called indirectly through them.
+pub fn mark_live(modules: &[MutRc<Module>], entry_points: &[SmolStr]) {
+    let mut worklist = Vec::new();
+    for module in modules {
+        let init = module.borrow().init;
+        for (index, func) in module.borrow().funcs.iter().enumerate() {
+            func.live.set(false);
+            let exported = func.link_name.is_some() && !func.external;
+            if entry_points.contains(&func.name) || exported || init == Some(index) {
+                worklist.push(FuncRef {
+                    module: module.clone(),
+                    index,
+                });
+            }
+        }
+    }
+
+    while let Some(func_ref) = worklist.pop() {
+        let func = func_ref.resolve();
+        if func.live.replace(true) {
+            continue;
+        }
+
+        func.body.borrow().visit(&mut |expr| match &*expr.inner {
+            IExpr::Constant(Constant::Function(callee)) | IExpr::Closure { func: callee, .. } => {
+                worklist.push(callee.clone())
+            }
+            _ => (),
+        });
+    }
+}
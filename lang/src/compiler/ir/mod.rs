@@ -1,17 +1,21 @@
 use crate::{
     compiler::{mutrc_new, MutRc},
     error::{Error, ErrorKind::E201, Res},
-    lexer::Token,
+    lexer::{TKind, Token},
     parser::{ast, ast::Literal},
     smol_str::SmolStr,
 };
-use alloc::{boxed::Box, rc::Rc};
+use alloc::{
+    boxed::Box,
+    format,
+    rc::Rc,
+    string::{String, ToString},
+};
 use core::{
-    cell::{Ref, RefCell},
+    cell::{Cell, RefCell},
     fmt,
     fmt::Display,
 };
-use cranelift_module::FuncId;
 use hashbrown::HashSet;
 use indexmap::map::IndexMap;
 use smallvec::{
@@ -19,11 +23,22 @@ use smallvec::{
     SmallVec,
 };
 
+pub mod snapshot;
+
+/// A module of a program. Its functions and classes are reference counted
+/// individually, which allows resolving them without keeping the whole module
+/// borrowed, and adding items to it while others are being compiled.
 #[derive(Debug)]
 pub struct Module {
-    pub funcs: Vec<Function>,
-    pub classes: Vec<Class>,
+    pub funcs: Vec<Rc<Function>>,
+    pub classes: Vec<Rc<Class>>,
     pub reserved_names: HashSet<SmolStr>,
+    /// Warnings found while compiling, unless they were reported as errors.
+    pub warnings: Vec<Error>,
+    /// The index of the initializer in `funcs`, if this module has one.
+    pub init: Option<usize>,
+    /// The paths of the modules imported by this one, whose initializers run first.
+    pub imports: Vec<Vec<SmolStr>>,
     pub ast: ast::Module,
 }
 
@@ -41,6 +56,9 @@ impl Module {
             funcs: Vec::with_capacity(ast.functions.len()),
             classes: Vec::with_capacity(ast.classes.len()),
             reserved_names: HashSet::with_capacity(ast.functions.len()),
+            warnings: Vec::new(),
+            init: None,
+            imports: Vec::new(),
             ast,
         })
     }
@@ -50,14 +68,62 @@ impl Module {
 pub struct Class {
     pub name: SmolStr,
     pub content: RefCell<IndexMap<SmolStr, ClassContent>>,
+    /// The function creating values of this class, which is named after it
+    /// and takes all members as parameters. Set once the class is generated.
+    pub constructor: RefCell<Option<FuncRef>>,
     pub ast: RefCell<ast::Class>,
 }
 
+impl Class {
+    /// The members of this class, in the order their values are laid out.
+    pub fn members(&self) -> Vec<VarStore> {
+        self.content
+            .borrow()
+            .values()
+            .filter_map(|content| match content {
+                ClassContent::Member(member) => Some(member.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn member(&self, name: &str) -> Option<VarStore> {
+        match self.content.borrow().get(name) {
+            Some(ClassContent::Member(member)) => Some(member.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn constructor(&self) -> FuncRef {
+        self.constructor.borrow().clone().unwrap()
+    }
+
+    pub fn method(&self, name: &str) -> Option<FuncRef> {
+        match self.content.borrow().get(name) {
+            Some(ClassContent::Method(method)) => Some(method.clone()),
+            _ => None,
+        }
+    }
+
+    /// The value of the `static val` with the given name, which is
+    /// poison if evaluating it failed.
+    pub fn constant(&self, name: &str) -> Option<Expr> {
+        match self.content.borrow().get(name) {
+            Some(ClassContent::Const(value)) => {
+                Some(value.clone().map_or_else(Expr::poison, Expr::constant))
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ClassContent {
     Member(VarStore),
     Method(FuncRef),
     Function(FuncRef),
+    /// A `static val`, which is `None` if its value failed to evaluate.
+    Const(Option<Constant>),
 }
 
 #[derive(Debug)]
@@ -66,17 +132,74 @@ pub struct Function {
     pub params: SmallVec<[VarStore; 4]>,
     pub ret_type: Type,
     pub locals: SmallVec<[VarStore; 6]>,
+    /// The locals holding the values captured by this function if it is a closure,
+    /// in the order they are stored in its environment.
+    pub captures: SmallVec<[VarStore; 2]>,
+    /// For every parameter with a default value, the function computing it.
+    /// Calls leaving out the parameter call that function in its place.
+    pub defaults: SmallVec<[Option<FuncRef>; 4]>,
     pub body: RefCell<Expr>,
-    pub ir: RefCell<Option<FuncId>>,
+    /// The AST of the function, which is only needed while compiling it.
+    /// Functions loaded from a snapshot have an empty placeholder instead.
     pub ast: ast::Function,
+    /// If this function is implemented outside the program and linked by name.
+    pub external: bool,
+    /// The name given with `@export` or `@extern`, which the function is linked by
+    /// instead of its symbol. Exported functions are kept by dead code elimination.
+    pub link_name: Option<SmolStr>,
+    /// If this function has `@inline`, which inlines it regardless of its size.
+    pub inline: bool,
+    /// If other functions with the same name exist.
+    pub overloaded: Cell<bool>,
+    /// If this function is reachable from an entry point and needs to be JITted.
+    pub live: Cell<bool>,
 }
 
 impl Function {
+    /// The name of this function inside the JIT, given the path of its module.
+    /// The path keeps functions of different modules apart, and the
+    /// parameter types keep overloaded functions apart.
+    /// External functions keep their name, since they are linked by it,
+    /// and functions with a `link_name` use that instead.
+    pub fn symbol(&self, path: &[SmolStr]) -> SmolStr {
+        if let Some(name) = &self.link_name {
+            return name.clone();
+        }
+        if self.external {
+            return self.name.clone();
+        }
+        let mut symbol = String::new();
+        for segment in path {
+            symbol.push_str(segment);
+            symbol.push_str("::");
+        }
+        symbol.push_str(&self.name);
+        if self.overloaded.get() {
+            let params = self
+                .params
+                .iter()
+                .map(|p| p.ty.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            symbol.push_str(&format!("({})", params));
+        }
+        SmolStr::new(symbol)
+    }
+
+    pub fn signature(&self) -> FnSig {
+        FnSig {
+            params: self.params.iter().map(|p| p.ty.clone()).collect(),
+            ret_type: self.ret_type.clone(),
+        }
+    }
+
+    /// Add a new local to this function. Locals are indexed after the parameters,
+    /// which allows the JIT to keep both in a single list of variables.
     pub fn add_local(&self, name: SmolStr, ty: Type, mutable: bool) -> &VarStore {
         let local = VarStore {
             ty,
             name,
-            index: self.locals.len(),
+            index: self.params.len() + self.locals.len(),
             mutable,
         };
         unsafe {
@@ -85,16 +208,46 @@ impl Function {
         self.locals.last().unwrap()
     }
 
+    /// Add a new local holding the value of a captured variable,
+    /// which is loaded from the environment when the closure is called.
+    pub fn add_capture(&self, name: SmolStr, ty: Type) -> &VarStore {
+        let local = self.add_local(name, ty, false).clone();
+        unsafe {
+            self.unsafe_mut().captures.push(local);
+        }
+        self.captures.last().unwrap()
+    }
+
+    /// If this function is a closure, which takes its environment before all parameters.
+    pub fn is_closure(&self) -> bool {
+        !self.captures.is_empty()
+    }
+
+    /// If this function contains errors, either itself or by using poisoned types.
+    /// Code is never generated for its body, calling it panics instead.
+    /// Only programs compiled by `compile_files_recovering` contain such functions.
+    pub fn is_poisoned(&self) -> bool {
+        let mut vars = self.params.iter().chain(self.locals.iter());
+        if self.ret_type.is_poisoned() || vars.any(|var| var.ty.is_poisoned()) {
+            return true;
+        }
+        let mut poisoned = false;
+        self.body.borrow().visit(&mut |expr| {
+            poisoned |= matches!(&*expr.inner, IExpr::Poison) || expr.typ().is_poisoned()
+        });
+        poisoned
+    }
+
     /// # Safety
     /// This method allows getting a mutable reference from a immutable one.
     /// Very unsafe!
-    /// The main usage of this method is `add_local`, where it is used
-    /// to append to the list of locals.
-    /// This is required to allow borrowing locals (see `src/compiler/expr_compiler.rs`) of the
-    /// function immutably (which a RefCell, for example, would make impossible).
+    /// The main usage of this method is `add_local` and `add_capture`,
+    /// where it is used to append to the list of locals.
+    /// This is required to allow borrowing the function immutably
+    /// while compiling it (see `src/compiler/module/expr_compiler.rs`).
     ///
-    /// TODO: Is this even safe?! references are probably going to be invalid
-    /// if the vector has to reallocate since their memory location moves!!!
+    /// References to locals are invalidated once the list reallocates,
+    /// which is why the expression compiler keeps its own copies of locals in scope.
     unsafe fn unsafe_mut(&self) -> &mut Self {
         let ptr = self as *const Function;
         let mutptr = ptr as *mut Function;
@@ -109,8 +262,8 @@ pub struct FuncRef {
 }
 
 impl FuncRef {
-    pub fn resolve<'t>(&self) -> Ref<Function> {
-        Ref::map(self.module.borrow(), |module| &module.funcs[self.index])
+    pub fn resolve(&self) -> Rc<Function> {
+        self.module.borrow().funcs[self.index].clone()
     }
 
     pub fn new_last(module: &MutRc<Module>) -> Self {
@@ -134,8 +287,8 @@ pub struct ClassRef {
 }
 
 impl ClassRef {
-    pub fn resolve<'t>(&self) -> Ref<Class> {
-        Ref::map(self.module.borrow(), |module| &module.classes[self.index])
+    pub fn resolve(&self) -> Rc<Class> {
+        self.module.borrow().classes[self.index].clone()
     }
 }
 
@@ -159,32 +312,87 @@ pub enum Type {
     Poison,
     Bool,
     I64,
+    F32,
     F64,
+    String,
 
-    Function(FuncRef),
+    Function(Rc<FnSig>),
     Class(ClassRef),
 }
 
+/// The signature of a function, which is the type of function values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FnSig {
+    pub params: SmallVec<[Type; 4]>,
+    pub ret_type: Type,
+}
+
 impl Type {
+    /// The types environment bindings can be read as, see `IExpr::Env`.
+    pub const ENV: [Type; 4] = [Type::I64, Type::F64, Type::Bool, Type::String];
+
     pub fn is_int(&self) -> bool {
         *self == Type::I64 || *self == Type::Poison
     }
 
+    /// If this type is poison or contains it, like the signature of a function
+    /// with a poisoned parameter or a class with a poisoned member.
+    /// Values of such types cannot be laid out.
+    pub fn is_poisoned(&self) -> bool {
+        match self {
+            Type::Poison => true,
+            Type::Function(sig) => {
+                sig.ret_type.is_poisoned() || sig.params.iter().any(Type::is_poisoned)
+            }
+            // Only direct members, since classes can contain themselves through functions
+            Type::Class(class) => class.resolve().content.borrow().values().any(
+                |content| matches!(content, ClassContent::Member(member) if member.ty == Type::Poison),
+            ),
+            _ => false,
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        *self == Type::F32 || *self == Type::F64
+    }
+
+    pub fn is_class(&self) -> bool {
+        matches!(self, Type::Class(_))
+    }
+
+    /// If values of this type can be converted to the given type by `IExpr::Convert`.
+    pub fn convertible_to(&self, to: &Type) -> bool {
+        match to {
+            Type::String => *self == Type::I64 || *self == Type::Bool || self.is_float(),
+            _ => self.is_float() && to.is_float(),
+        }
+    }
+
     pub fn allow_math(&self) -> bool {
-        *self == Type::I64 || *self == Type::F64 || *self == Type::Poison
+        *self == Type::I64 || self.is_float() || *self == Type::Poison
     }
 
     pub fn allow_logic(&self) -> bool {
         *self == Type::Bool || *self == Type::Poison
     }
 
+    /// If the given binary operator can be applied to this type.
+    pub fn allow_binary(&self, op: TKind) -> bool {
+        match op {
+            _ if *self == Type::String => op == TKind::Plus,
+            TKind::And | TKind::Or => self.allow_logic(),
+            _ if op.is_binary_int() => self.is_int(),
+            _ => self.allow_math(),
+        }
+    }
+
     pub fn allow_assignment(&self) -> bool {
         *self != Type::Void
     }
 
-    pub fn into_fn(self) -> FuncRef {
+    pub fn into_fn(self) -> Rc<FnSig> {
         match self {
-            Self::Function(r) => r,
+            Self::Function(sig) => sig,
             _ => panic!(),
         }
     }
@@ -192,7 +400,34 @@ impl Type {
 
 impl Display for Type {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Type::Void => write!(f, "void"),
+            Type::Poison => write!(f, "<poison>"),
+            Type::Bool => write!(f, "bool"),
+            Type::I64 => write!(f, "i64"),
+            Type::F32 => write!(f, "f32"),
+            Type::F64 => write!(f, "f64"),
+            Type::String => write!(f, "str"),
+            Type::Function(sig) => write!(f, "{}", sig),
+            Type::Class(cls) => write!(f, "{}", cls.resolve().name),
+        }
+    }
+}
+
+impl Display for FnSig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "fun(")?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", param)?;
+        }
+        write!(f, ")")?;
+        if self.ret_type != Type::Void {
+            write!(f, " -> {}", self.ret_type)?;
+        }
+        Ok(())
     }
 }
 
@@ -200,6 +435,9 @@ impl Display for Type {
 pub struct Expr {
     pub inner: Box<IExpr>, // todo bump allocation
     ty: RefCell<Option<Type>>,
+    /// The source offset of the expression this was compiled from,
+    /// if it was not created by the compiler itself.
+    pub start: Option<usize>,
 }
 
 impl Expr {
@@ -232,8 +470,25 @@ impl Expr {
         })
     }
 
-    pub fn while_(cond: Expr, body: Expr) -> Expr {
-        Self::new(IExpr::While { cond, body })
+    /// Create a loop, which only has a value if it has an `else` branch
+    /// and all `break`s in it have a value of the same type, given in `breaks`.
+    pub fn while_(cond: Expr, body: Expr, els: Option<Expr>, breaks: &[Type]) -> Expr {
+        let phi = els
+            .as_ref()
+            .map_or(false, |els| breaks.iter().all(|ty| *ty == els.typ()));
+        Self::new(IExpr::While {
+            cond,
+            body,
+            els: els.unwrap_or_else(|| Self::zero()),
+            phi,
+        })
+    }
+
+    /// Leave the innermost loop. Without a value, this breaks with an empty block.
+    pub fn break_(value: Option<Expr>) -> Expr {
+        Self::new(IExpr::Break {
+            value: value.unwrap_or_else(|| Self::block(Vec::new())),
+        })
     }
 
     pub fn local(variable: &VarStore) -> Expr {
@@ -255,6 +510,38 @@ impl Expr {
         Self::with_typ(IExpr::Call { callee, args }, ret_type)
     }
 
+    pub fn convert(value: Expr, to: Type) -> Expr {
+        Self::new(IExpr::Convert { value, to })
+    }
+
+    pub fn closure(func: FuncRef, captures: SmallVec<[Expr; 2]>) -> Expr {
+        Self::new(IExpr::Closure { func, captures })
+    }
+
+    pub fn construct(class: ClassRef, members: SmallVec<[Expr; 4]>) -> Expr {
+        Self::new(IExpr::Construct { class, members })
+    }
+
+    pub fn member(object: Expr, member: &VarStore) -> Expr {
+        Self::new(IExpr::Member {
+            object,
+            index: member.index,
+            typ: member.ty.clone(),
+        })
+    }
+
+    pub fn panic(message: Expr) -> Expr {
+        Self::new(IExpr::Panic { message })
+    }
+
+    pub fn env(name: Expr, typ: Type) -> Expr {
+        Self::new(IExpr::Env { name, typ })
+    }
+
+    pub fn intrinsic(intrinsic: Intrinsic, args: SmallVec<[Expr; 2]>) -> Expr {
+        Self::new(IExpr::Intrinsic { intrinsic, args })
+    }
+
     pub fn typ(&self) -> Type {
         let mut cached = self.ty.borrow_mut();
         if let Some(ty) = &*cached {
@@ -266,6 +553,261 @@ impl Expr {
         }
     }
 
+    /// Call the given closure on this expression and all expressions inside it.
+    pub fn visit(&self, cls: &mut impl FnMut(&Expr)) {
+        cls(self);
+        match &*self.inner {
+            IExpr::Binary { left, right, .. } => {
+                left.visit(cls);
+                right.visit(cls);
+            }
+
+            IExpr::Block(exprs) => {
+                for expr in exprs {
+                    expr.visit(cls);
+                }
+            }
+
+            IExpr::If {
+                cond, then, els, ..
+            } => {
+                cond.visit(cls);
+                then.visit(cls);
+                els.visit(cls);
+            }
+
+            IExpr::While {
+                cond, body, els, ..
+            } => {
+                cond.visit(cls);
+                body.visit(cls);
+                els.visit(cls);
+            }
+
+            IExpr::Break { value } => value.visit(cls),
+
+            IExpr::Assign { store, value } => {
+                store.visit(cls);
+                value.visit(cls);
+            }
+
+            IExpr::Call { callee, args } => {
+                callee.visit(cls);
+                for arg in args {
+                    arg.visit(cls);
+                }
+            }
+
+            IExpr::Convert { value, .. } => value.visit(cls),
+
+            IExpr::Closure { captures, .. } => {
+                for capture in captures {
+                    capture.visit(cls);
+                }
+            }
+
+            IExpr::Construct { members, .. } => {
+                for member in members {
+                    member.visit(cls);
+                }
+            }
+
+            IExpr::Member { object, .. } => object.visit(cls),
+
+            IExpr::Panic { message } => message.visit(cls),
+
+            IExpr::Env { name, .. } => name.visit(cls),
+
+            IExpr::Intrinsic { args, .. } => {
+                for arg in args {
+                    arg.visit(cls);
+                }
+            }
+
+            IExpr::Constant(_) | IExpr::Variable { .. } | IExpr::Poison => (),
+        }
+    }
+
+    /// Call the given closure on all expressions inside this one and then
+    /// on this expression itself, which allows replacing them.
+    pub fn visit_mut(&mut self, cls: &mut impl FnMut(&mut Expr)) {
+        match &mut *self.inner {
+            IExpr::Binary { left, right, .. } => {
+                left.visit_mut(cls);
+                right.visit_mut(cls);
+            }
+
+            IExpr::Block(exprs) => {
+                for expr in exprs {
+                    expr.visit_mut(cls);
+                }
+            }
+
+            IExpr::If {
+                cond, then, els, ..
+            } => {
+                cond.visit_mut(cls);
+                then.visit_mut(cls);
+                els.visit_mut(cls);
+            }
+
+            IExpr::While {
+                cond, body, els, ..
+            } => {
+                cond.visit_mut(cls);
+                body.visit_mut(cls);
+                els.visit_mut(cls);
+            }
+
+            IExpr::Break { value } => value.visit_mut(cls),
+
+            IExpr::Assign { store, value } => {
+                store.visit_mut(cls);
+                value.visit_mut(cls);
+            }
+
+            IExpr::Call { callee, args } => {
+                callee.visit_mut(cls);
+                for arg in args {
+                    arg.visit_mut(cls);
+                }
+            }
+
+            IExpr::Convert { value, .. } => value.visit_mut(cls),
+
+            IExpr::Closure { captures, .. } => {
+                for capture in captures {
+                    capture.visit_mut(cls);
+                }
+            }
+
+            IExpr::Construct { members, .. } => {
+                for member in members {
+                    member.visit_mut(cls);
+                }
+            }
+
+            IExpr::Member { object, .. } => object.visit_mut(cls),
+
+            IExpr::Panic { message } => message.visit_mut(cls),
+
+            IExpr::Env { name, .. } => name.visit_mut(cls),
+
+            IExpr::Intrinsic { args, .. } => {
+                for arg in args {
+                    arg.visit_mut(cls);
+                }
+            }
+
+            IExpr::Constant(_) | IExpr::Variable { .. } | IExpr::Poison => (),
+        }
+        cls(self);
+    }
+
+    /// Create a copy of this expression, with the index of
+    /// all variables inside it changed by the given closure.
+    pub fn map_variables(&self, map: &impl Fn(usize) -> usize) -> Expr {
+        let inner = match &*self.inner {
+            IExpr::Binary { left, op, right } => IExpr::Binary {
+                left: left.map_variables(map),
+                op: op.clone(),
+                right: right.map_variables(map),
+            },
+
+            IExpr::Constant(constant) => IExpr::Constant(constant.clone()),
+
+            IExpr::Block(exprs) => {
+                IExpr::Block(exprs.iter().map(|e| e.map_variables(map)).collect())
+            }
+
+            IExpr::If {
+                cond,
+                then,
+                els,
+                phi,
+            } => IExpr::If {
+                cond: cond.map_variables(map),
+                then: then.map_variables(map),
+                els: els.map_variables(map),
+                phi: *phi,
+            },
+
+            IExpr::While {
+                cond,
+                body,
+                els,
+                phi,
+            } => IExpr::While {
+                cond: cond.map_variables(map),
+                body: body.map_variables(map),
+                els: els.map_variables(map),
+                phi: *phi,
+            },
+
+            IExpr::Break { value } => IExpr::Break {
+                value: value.map_variables(map),
+            },
+
+            IExpr::Variable { index, typ } => IExpr::Variable {
+                index: map(*index),
+                typ: typ.clone(),
+            },
+
+            IExpr::Assign { store, value } => IExpr::Assign {
+                store: store.map_variables(map),
+                value: value.map_variables(map),
+            },
+
+            IExpr::Call { callee, args } => IExpr::Call {
+                callee: callee.map_variables(map),
+                args: args.iter().map(|a| a.map_variables(map)).collect(),
+            },
+
+            IExpr::Convert { value, to } => IExpr::Convert {
+                value: value.map_variables(map),
+                to: to.clone(),
+            },
+
+            IExpr::Closure { func, captures } => IExpr::Closure {
+                func: func.clone(),
+                captures: captures.iter().map(|c| c.map_variables(map)).collect(),
+            },
+
+            IExpr::Construct { class, members } => IExpr::Construct {
+                class: class.clone(),
+                members: members.iter().map(|m| m.map_variables(map)).collect(),
+            },
+
+            IExpr::Member { object, index, typ } => IExpr::Member {
+                object: object.map_variables(map),
+                index: *index,
+                typ: typ.clone(),
+            },
+
+            IExpr::Panic { message } => IExpr::Panic {
+                message: message.map_variables(map),
+            },
+
+            IExpr::Env { name, typ } => IExpr::Env {
+                name: name.map_variables(map),
+                typ: typ.clone(),
+            },
+
+            IExpr::Intrinsic { intrinsic, args } => IExpr::Intrinsic {
+                intrinsic: *intrinsic,
+                args: args.iter().map(|a| a.map_variables(map)).collect(),
+            },
+
+            IExpr::Poison => IExpr::Poison,
+        };
+
+        Expr {
+            inner: Box::new(inner),
+            ty: RefCell::new(self.ty.borrow().clone()),
+            start: self.start,
+        }
+    }
+
     pub fn assignable(&self) -> bool {
         match &*self.inner {
             IExpr::Variable { .. } => true,
@@ -282,9 +824,12 @@ impl Expr {
 
             IExpr::Constant(Constant::Bool(_)) => Type::Bool,
             IExpr::Constant(Constant::Int(_)) => Type::I64,
+            IExpr::Constant(Constant::F32(_)) => Type::F32,
             IExpr::Constant(Constant::Float(_)) => Type::F64,
-            IExpr::Constant(Constant::String(_)) => unimplemented!(),
-            IExpr::Constant(Constant::Function(f)) => Type::Function(f.clone()),
+            IExpr::Constant(Constant::String(_)) => Type::String,
+            IExpr::Constant(Constant::Function(f)) => {
+                Type::Function(Rc::new(f.resolve().signature()))
+            }
             IExpr::Constant(Constant::Class(c)) => Type::Class(c.clone()),
 
             IExpr::Block(expr) => expr.last().map(|e| e.typ()).unwrap_or(Type::Void),
@@ -292,13 +837,33 @@ impl Expr {
             IExpr::If { phi, .. } if !phi => Type::Void,
             IExpr::If { then, .. } => then.typ(),
 
-            IExpr::While { .. } => Type::Void,
+            IExpr::While { phi, .. } if !phi => Type::Void,
+            IExpr::While { els, .. } => els.typ(),
+
+            IExpr::Break { .. } => Type::Void,
 
             IExpr::Variable { typ, .. } => typ.clone(),
 
             IExpr::Assign { value, .. } => value.typ(),
 
-            IExpr::Call { .. } => panic!(),
+            IExpr::Call { callee, .. } => match callee.typ() {
+                Type::Function(sig) => sig.ret_type.clone(),
+                _ => Type::Poison,
+            },
+
+            IExpr::Convert { to, .. } => to.clone(),
+
+            IExpr::Closure { func, .. } => Type::Function(Rc::new(func.resolve().signature())),
+
+            IExpr::Construct { class, .. } => Type::Class(class.clone()),
+
+            IExpr::Member { typ, .. } => typ.clone(),
+
+            IExpr::Panic { .. } => Type::Void,
+
+            IExpr::Env { typ, .. } => typ.clone(),
+
+            IExpr::Intrinsic { args, .. } => args[0].typ(),
         }
     }
 
@@ -306,6 +871,7 @@ impl Expr {
         Expr {
             inner: Box::new(inner),
             ty: RefCell::new(None),
+            start: None,
         }
     }
 
@@ -313,6 +879,7 @@ impl Expr {
         Expr {
             inner: Box::new(inner),
             ty: RefCell::new(Some(typ)),
+            start: None,
         }
     }
 }
@@ -338,9 +905,19 @@ pub enum IExpr {
         phi: bool,
     },
 
+    /// A loop, which evaluates `els` once the condition is false. If `phi` is set,
+    /// the loop has the value of `els` or of the `break` that left it.
     While {
         cond: Expr,
         body: Expr,
+        els: Expr,
+        phi: bool,
+    },
+
+    /// Leave the innermost loop of the function, with `value`
+    /// as the value of the loop if it has one.
+    Break {
+        value: Expr,
     },
 
     Variable {
@@ -353,10 +930,126 @@ pub enum IExpr {
         value: Expr,
     },
 
+    /// Call a function value. The callee is evaluated first, followed by the arguments
+    /// from left to right, which backends must keep even for direct calls.
     Call {
         callee: Expr,
         args: SmallVec<[Expr; 4]>,
     },
+
+    /// Convert a value to another type, like between `f32` and `f64`.
+    Convert {
+        value: Expr,
+        to: Type,
+    },
+
+    /// Create a closure, which is a function value together with
+    /// the values of the variables it captured, in the order of `Function::captures`.
+    Closure {
+        func: FuncRef,
+        captures: SmallVec<[Expr; 2]>,
+    },
+
+    /// Create a value of a class from the values of all its members.
+    Construct {
+        class: ClassRef,
+        members: SmallVec<[Expr; 4]>,
+    },
+
+    /// Read a member of a class value, with `index` being its position among the members.
+    Member {
+        object: Expr,
+        index: usize,
+        typ: Type,
+    },
+
+    /// Stop the program with the given message, located at the position of the expression.
+    Panic {
+        message: Expr,
+    },
+
+    /// Read the binding with the given name from the environment the host
+    /// passed to the program, as a value of `typ`. Strings are parsed, while
+    /// bindings that do not exist or are of another type read as zero, `false`
+    /// or an empty string.
+    Env {
+        name: Expr,
+        typ: Type,
+    },
+
+    /// Apply an intrinsic to its arguments, which all have the type of the result.
+    Intrinsic {
+        intrinsic: Intrinsic,
+        args: SmallVec<[Expr; 2]>,
+    },
+}
+
+/// Builtin functions the backends translate to instructions
+/// instead of calls, for math in hot loops like drawing pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Intrinsic {
+    /// The smaller of two numbers.
+    Min,
+    /// The larger of two numbers.
+    Max,
+    /// The absolute value of a number, wrapping for `i64::MIN`.
+    Abs,
+    /// The number of leading zero bits of an integer.
+    Clz,
+    /// The number of trailing zero bits of an integer.
+    Ctz,
+    /// The number of one bits of an integer.
+    Popcount,
+    /// Rotate the bits of an integer left by the given amount.
+    Rotl,
+    /// Rotate the bits of an integer right by the given amount.
+    Rotr,
+}
+
+impl Intrinsic {
+    pub const ALL: [Intrinsic; 8] = [
+        Intrinsic::Min,
+        Intrinsic::Max,
+        Intrinsic::Abs,
+        Intrinsic::Clz,
+        Intrinsic::Ctz,
+        Intrinsic::Popcount,
+        Intrinsic::Rotl,
+        Intrinsic::Rotr,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|i| i.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Intrinsic::Min => "min",
+            Intrinsic::Max => "max",
+            Intrinsic::Abs => "abs",
+            Intrinsic::Clz => "clz",
+            Intrinsic::Ctz => "ctz",
+            Intrinsic::Popcount => "popcount",
+            Intrinsic::Rotl => "rotl",
+            Intrinsic::Rotr => "rotr",
+        }
+    }
+
+    /// The number of arguments the intrinsic takes.
+    pub fn arity(self) -> usize {
+        match self {
+            Intrinsic::Min | Intrinsic::Max | Intrinsic::Rotl | Intrinsic::Rotr => 2,
+            _ => 1,
+        }
+    }
+
+    /// If the intrinsic can be applied to arguments of the given type.
+    pub fn allows(self, ty: &Type) -> bool {
+        match self {
+            Intrinsic::Min | Intrinsic::Max | Intrinsic::Abs => ty.allow_math(),
+            _ => ty.is_int(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -364,6 +1057,7 @@ pub enum Constant {
     Bool(bool),
     Int(i64),
     Float(f64),
+    F32(f32),
     String(SmolStr),
     Function(FuncRef),
     Class(ClassRef),
@@ -375,6 +1069,7 @@ impl Constant {
             Literal::Bool(b) => Self::Bool(*b),
             Literal::Int(i) => Self::Int(*i),
             Literal::Float(f) => Self::Float(*f),
+            Literal::F32(f) => Self::F32(*f),
             Literal::String(s) => Self::String(s.clone()),
         }
     }
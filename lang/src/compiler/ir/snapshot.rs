@@ -0,0 +1,868 @@
+//! A compact binary format for compiled modules, which allows compiling
+//! a program ahead of time and only running the JIT when executing it.
+//!
+//! The IR is a graph of `Rc`s, which is flattened by storing all
+//! references to functions and classes as a pair of indices:
+//! the index of the module in the snapshot and the index of the item inside it.
+//! All integers are stored as LEB128, strings are prefixed with their length.
+
+use crate::{
+    compiler::{
+        ir::{
+            Class, ClassContent, ClassRef, Constant, Expr, FnSig, FuncRef, Function, IExpr,
+            Intrinsic, Module, Type, VarStore,
+        },
+        MutRc,
+    },
+    lexer::{Lexer, TKind, Token},
+    parser::ast,
+    smol_str::SmolStr,
+};
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    iter::FromIterator,
+    str,
+};
+use hashbrown::HashSet;
+use indexmap::IndexMap;
+
+const MAGIC: &[u8; 4] = b"YSNP";
+/// Increased on every change to the format or the IR itself;
+/// snapshots of other versions are rejected.
+const VERSION: u64 = 18;
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotError {
+    /// The data is not a snapshot.
+    InvalidHeader,
+    /// The snapshot was created by a different version of the compiler.
+    UnsupportedVersion(u64),
+    /// The snapshot ended in the middle of an item.
+    UnexpectedEnd,
+    /// The snapshot contains an invalid tag or reference.
+    InvalidData,
+}
+
+type SRes<T> = Result<T, SnapshotError>;
+
+/// Serialize the given modules. All modules referenced by them
+/// need to be part of the snapshot as well.
+pub fn save(modules: &[MutRc<Module>]) -> Vec<u8> {
+    let mut writer = Writer {
+        buf: Vec::with_capacity(1024),
+        modules,
+    };
+    writer.buf.extend_from_slice(MAGIC);
+    writer.uint(VERSION);
+    writer.uint(modules.len() as u64);
+    for module in modules {
+        writer.module(&module.borrow());
+    }
+    writer.buf
+}
+
+/// Deserialize modules previously serialized with `save`.
+pub fn load(data: &[u8]) -> SRes<Vec<MutRc<Module>>> {
+    if data.get(..MAGIC.len()) != Some(&MAGIC[..]) {
+        return Err(SnapshotError::InvalidHeader);
+    }
+    let mut reader = Reader {
+        data,
+        pos: MAGIC.len(),
+        modules: Vec::new(),
+        refs: Vec::new(),
+    };
+    let version = reader.uint()?;
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    // All modules are created upfront, since items can reference later modules
+    for _ in 0..reader.len()? {
+        reader.modules.push(Module::from_ast(ast::Module {
+            path: Vec::new(),
+            functions: Vec::new(),
+            classes: Vec::new(),
+            globals: Vec::new(),
+            imports: Vec::new(),
+            local_functions: Vec::new(),
+            flags: Vec::new(),
+            source_hash: 0,
+        }));
+    }
+    for module in reader.modules.clone() {
+        reader.module(&mut module.borrow_mut())?;
+    }
+    if reader.pos != data.len() {
+        return Err(SnapshotError::InvalidData);
+    }
+    reader.check_refs()?;
+    Ok(reader.modules)
+}
+
+struct Writer<'m> {
+    buf: Vec<u8>,
+    modules: &'m [MutRc<Module>],
+}
+
+impl<'m> Writer<'m> {
+    fn module(&mut self, module: &Module) {
+        self.uint(module.ast.path.len() as u64);
+        for segment in &module.ast.path {
+            self.str(segment);
+        }
+        self.uint(module.ast.flags.len() as u64);
+        for flag in &module.ast.flags {
+            self.str(flag);
+        }
+        self.uint(module.ast.source_hash);
+
+        // Sorted to make snapshots of the same program identical
+        let mut names = module.reserved_names.iter().collect::<Vec<_>>();
+        names.sort();
+        self.uint(names.len() as u64);
+        for name in names {
+            self.str(name);
+        }
+
+        self.uint(module.classes.len() as u64);
+        for class in &module.classes {
+            self.class(class);
+        }
+        self.uint(module.funcs.len() as u64);
+        for func in &module.funcs {
+            self.function(func);
+        }
+
+        match module.init {
+            Some(init) => {
+                self.bool(true);
+                self.uint(init as u64);
+            }
+            None => self.bool(false),
+        }
+        self.uint(module.imports.len() as u64);
+        for path in &module.imports {
+            self.uint(path.len() as u64);
+            for segment in path {
+                self.str(segment);
+            }
+        }
+    }
+
+    fn class(&mut self, class: &Class) {
+        self.str(&class.name);
+        let content = class.content.borrow();
+        self.uint(content.len() as u64);
+        for (name, item) in content.iter() {
+            self.str(name);
+            match item {
+                ClassContent::Member(var) => {
+                    self.byte(0);
+                    self.var(var);
+                }
+                ClassContent::Method(func) => {
+                    self.byte(1);
+                    self.func_ref(func);
+                }
+                ClassContent::Function(func) => {
+                    self.byte(2);
+                    self.func_ref(func);
+                }
+                ClassContent::Const(value) => {
+                    self.byte(3);
+                    // Modules compiled while recovering from errors lack invalid values
+                    match value {
+                        Some(value) => {
+                            self.bool(true);
+                            self.constant(value);
+                        }
+                        None => self.bool(false),
+                    }
+                }
+            }
+        }
+        self.func_ref(&class.constructor());
+    }
+
+    fn function(&mut self, func: &Function) {
+        self.str(&func.name);
+        self.bool(func.external);
+        match &func.link_name {
+            Some(name) => {
+                self.bool(true);
+                self.str(name);
+            }
+            None => self.bool(false),
+        }
+        self.bool(func.inline);
+        self.bool(func.overloaded.get());
+        self.bool(func.live.get());
+        self.uint(func.params.len() as u64);
+        for param in &func.params {
+            self.var(param);
+        }
+        self.typ(&func.ret_type);
+        self.uint(func.locals.len() as u64);
+        for local in &func.locals {
+            self.var(local);
+        }
+        self.uint(func.captures.len() as u64);
+        for capture in &func.captures {
+            self.var(capture);
+        }
+        self.uint(func.defaults.len() as u64);
+        for default in &func.defaults {
+            match default {
+                Some(default) => {
+                    self.bool(true);
+                    self.func_ref(default);
+                }
+                None => self.bool(false),
+            }
+        }
+        self.expr(&func.body.borrow());
+    }
+
+    fn var(&mut self, var: &VarStore) {
+        self.str(&var.name);
+        self.uint(var.index as u64);
+        self.bool(var.mutable);
+        self.typ(&var.ty);
+    }
+
+    fn typ(&mut self, ty: &Type) {
+        match ty {
+            Type::Void => self.byte(0),
+            Type::Poison => self.byte(1),
+            Type::Bool => self.byte(2),
+            Type::I64 => self.byte(3),
+            Type::F64 => self.byte(4),
+            Type::Function(sig) => {
+                self.byte(5);
+                self.uint(sig.params.len() as u64);
+                for param in &sig.params {
+                    self.typ(param);
+                }
+                self.typ(&sig.ret_type);
+            }
+            Type::Class(cls) => {
+                self.byte(6);
+                self.item_ref(&cls.module, cls.index);
+            }
+            Type::F32 => self.byte(7),
+            Type::String => self.byte(8),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        // The type is kept since it cannot always be inferred, like on calls
+        match &*expr.ty.borrow() {
+            Some(ty) => {
+                self.bool(true);
+                self.typ(ty);
+            }
+            None => self.bool(false),
+        }
+        self.uint(expr.start.map_or(0, |start| start as u64 + 1));
+
+        match &*expr.inner {
+            IExpr::Poison => self.byte(0),
+
+            IExpr::Binary { left, op, right } => {
+                self.byte(1);
+                self.expr(left);
+                self.str(&op.lex);
+                self.uint(op.start as u64);
+                self.expr(right);
+            }
+
+            IExpr::Constant(constant) => {
+                self.byte(2);
+                self.constant(constant);
+            }
+
+            IExpr::Block(exprs) => {
+                self.byte(3);
+                self.uint(exprs.len() as u64);
+                for expr in exprs {
+                    self.expr(expr);
+                }
+            }
+
+            IExpr::If {
+                cond,
+                then,
+                els,
+                phi,
+            } => {
+                self.byte(4);
+                self.expr(cond);
+                self.expr(then);
+                self.expr(els);
+                self.bool(*phi);
+            }
+
+            IExpr::While {
+                cond,
+                body,
+                els,
+                phi,
+            } => {
+                self.byte(5);
+                self.expr(cond);
+                self.expr(body);
+                self.expr(els);
+                self.bool(*phi);
+            }
+
+            IExpr::Variable { index, typ } => {
+                self.byte(6);
+                self.uint(*index as u64);
+                self.typ(typ);
+            }
+
+            IExpr::Assign { store, value } => {
+                self.byte(7);
+                self.expr(store);
+                self.expr(value);
+            }
+
+            IExpr::Call { callee, args } => {
+                self.byte(8);
+                self.expr(callee);
+                self.uint(args.len() as u64);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+
+            IExpr::Convert { value, to } => {
+                self.byte(9);
+                self.expr(value);
+                self.typ(to);
+            }
+
+            IExpr::Closure { func, captures } => {
+                self.byte(10);
+                self.func_ref(func);
+                self.uint(captures.len() as u64);
+                for capture in captures {
+                    self.expr(capture);
+                }
+            }
+
+            IExpr::Construct { class, members } => {
+                self.byte(11);
+                self.item_ref(&class.module, class.index);
+                self.uint(members.len() as u64);
+                for member in members {
+                    self.expr(member);
+                }
+            }
+
+            IExpr::Member { object, index, typ } => {
+                self.byte(12);
+                self.expr(object);
+                self.uint(*index as u64);
+                self.typ(typ);
+            }
+
+            IExpr::Panic { message } => {
+                self.byte(13);
+                self.expr(message);
+            }
+
+            IExpr::Env { name, typ } => {
+                self.byte(14);
+                self.expr(name);
+                self.typ(typ);
+            }
+
+            IExpr::Intrinsic { intrinsic, args } => {
+                self.byte(15);
+                self.byte(Intrinsic::ALL.iter().position(|i| i == intrinsic).unwrap() as u8);
+                self.uint(args.len() as u64);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+
+            IExpr::Break { value } => {
+                self.byte(16);
+                self.expr(value);
+            }
+        }
+    }
+
+    fn constant(&mut self, constant: &Constant) {
+        match constant {
+            Constant::Bool(b) => {
+                self.byte(0);
+                self.bool(*b);
+            }
+            Constant::Int(i) => {
+                self.byte(1);
+                self.uint(((*i << 1) ^ (*i >> 63)) as u64);
+            }
+            Constant::Float(f) => {
+                self.byte(2);
+                self.buf.extend_from_slice(&f.to_bits().to_le_bytes());
+            }
+            Constant::String(s) => {
+                self.byte(3);
+                self.str(s);
+            }
+            Constant::Function(func) => {
+                self.byte(4);
+                self.func_ref(func);
+            }
+            Constant::Class(cls) => {
+                self.byte(5);
+                self.item_ref(&cls.module, cls.index);
+            }
+            Constant::F32(f) => {
+                self.byte(6);
+                self.buf.extend_from_slice(&f.to_bits().to_le_bytes());
+            }
+        }
+    }
+
+    fn func_ref(&mut self, func: &FuncRef) {
+        self.item_ref(&func.module, func.index)
+    }
+
+    fn item_ref(&mut self, module: &MutRc<Module>, index: usize) {
+        let module = self
+            .modules
+            .iter()
+            .position(|m| Rc::ptr_eq(m, module))
+            .expect("Snapshot is missing a referenced module");
+        self.uint(module as u64);
+        self.uint(index as u64);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.uint(s.len() as u64);
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn bool(&mut self, b: bool) {
+        self.byte(b as u8)
+    }
+
+    fn byte(&mut self, b: u8) {
+        self.buf.push(b)
+    }
+
+    fn uint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.buf.push(byte);
+                return;
+            }
+            self.buf.push(byte | 0x80);
+        }
+    }
+}
+
+/// The kind of item a reference inside a snapshot points to.
+#[derive(Copy, Clone)]
+enum RefKind {
+    Function,
+    Class,
+}
+
+struct Reader<'d> {
+    data: &'d [u8],
+    pos: usize,
+    modules: Vec<MutRc<Module>>,
+    /// All references read, which can only be checked
+    /// once all modules are loaded.
+    refs: Vec<(RefKind, usize, usize)>,
+}
+
+impl<'d> Reader<'d> {
+    fn module(&mut self, module: &mut Module) -> SRes<()> {
+        module.ast.path = self.list(Self::str)?;
+        module.ast.flags = self.list(Self::str)?;
+        module.ast.source_hash = self.uint()?;
+        let names: Vec<SmolStr> = self.list(Self::str)?;
+        module.reserved_names = names.into_iter().collect::<HashSet<_>>();
+        module.classes = self.list(|reader| reader.class().map(Rc::new))?;
+        module.funcs = self.list(|reader| reader.function().map(Rc::new))?;
+        module.init = if self.bool()? {
+            let init = self.len()?;
+            if init >= module.funcs.len() {
+                return Err(SnapshotError::InvalidData);
+            }
+            Some(init)
+        } else {
+            None
+        };
+        module.imports = self.list(|reader| reader.list(Self::str))?;
+        Ok(())
+    }
+
+    fn class(&mut self) -> SRes<Class> {
+        let name = self.str()?;
+        let len = self.len()?;
+        let mut content = IndexMap::with_capacity(len);
+        for _ in 0..len {
+            let key = self.str()?;
+            let item = match self.byte()? {
+                0 => ClassContent::Member(self.var()?),
+                1 => ClassContent::Method(self.func_ref()?),
+                2 => ClassContent::Function(self.func_ref()?),
+                3 => ClassContent::Const(if self.bool()? {
+                    Some(self.constant()?)
+                } else {
+                    None
+                }),
+                _ => return Err(SnapshotError::InvalidData),
+            };
+            content.insert(key, item);
+        }
+        let constructor = self.func_ref()?;
+
+        Ok(Class {
+            ast: RefCell::new(ast::Class {
+                name: ident(&name),
+                members: Vec::new(),
+                methods: Vec::new(),
+                functions: Vec::new(),
+                statics: Vec::new(),
+                init: None,
+                attributes: Vec::new(),
+            }),
+            content: RefCell::new(content),
+            constructor: RefCell::new(Some(constructor)),
+            name,
+        })
+    }
+
+    fn function(&mut self) -> SRes<Function> {
+        let name = self.str()?;
+        let external = self.bool()?;
+        let link_name = if self.bool()? {
+            Some(self.str()?)
+        } else {
+            None
+        };
+        let inline = self.bool()?;
+        let overloaded = self.bool()?;
+        let live = self.bool()?;
+        let params = self.list(Self::var)?;
+        let ret_type = self.typ()?;
+        let locals = self.list(Self::var)?;
+        let captures = self.list(Self::var)?;
+        let defaults = self.list(|reader| {
+            if reader.bool()? {
+                reader.func_ref().map(Some)
+            } else {
+                Ok(None)
+            }
+        })?;
+        let body = self.expr()?;
+
+        Ok(Function {
+            ast: ast::Function {
+                name: ident(&name),
+                params: Vec::new(),
+                ret_type: None,
+                body: None,
+                attributes: Vec::new(),
+            },
+            name,
+            params,
+            ret_type,
+            locals,
+            captures,
+            defaults,
+            body: RefCell::new(body),
+            external,
+            link_name,
+            inline,
+            overloaded: Cell::new(overloaded),
+            live: Cell::new(live),
+        })
+    }
+
+    fn var(&mut self) -> SRes<VarStore> {
+        Ok(VarStore {
+            name: self.str()?,
+            index: self.len()?,
+            mutable: self.bool()?,
+            ty: self.typ()?,
+        })
+    }
+
+    fn typ(&mut self) -> SRes<Type> {
+        Ok(match self.byte()? {
+            0 => Type::Void,
+            1 => Type::Poison,
+            2 => Type::Bool,
+            3 => Type::I64,
+            4 => Type::F64,
+            5 => Type::Function(Rc::new(FnSig {
+                params: self.list(Self::typ)?,
+                ret_type: self.typ()?,
+            })),
+            6 => {
+                let (module, index) = self.item_ref(RefKind::Class)?;
+                Type::Class(ClassRef { module, index })
+            }
+            7 => Type::F32,
+            8 => Type::String,
+            _ => return Err(SnapshotError::InvalidData),
+        })
+    }
+
+    fn expr(&mut self) -> SRes<Expr> {
+        let ty = if self.bool()? {
+            Some(self.typ()?)
+        } else {
+            None
+        };
+
+        let start = (self.uint()? as usize).checked_sub(1);
+
+        let inner = match self.byte()? {
+            0 => IExpr::Poison,
+
+            1 => {
+                let left = self.expr()?;
+                let lex = self.str()?;
+                let start = self.uint()? as usize;
+                let right = self.expr()?;
+                // Operators are always a single token, which gives back their kind
+                let kind = Lexer::new(&lex)
+                    .next()
+                    .map(|t| t.kind)
+                    .ok_or(SnapshotError::InvalidData)?;
+                IExpr::Binary {
+                    left,
+                    op: Token { kind, lex, start },
+                    right,
+                }
+            }
+
+            2 => IExpr::Constant(self.constant()?),
+
+            3 => IExpr::Block(self.list(Self::expr)?),
+
+            4 => IExpr::If {
+                cond: self.expr()?,
+                then: self.expr()?,
+                els: self.expr()?,
+                phi: self.bool()?,
+            },
+
+            5 => IExpr::While {
+                cond: self.expr()?,
+                body: self.expr()?,
+                els: self.expr()?,
+                phi: self.bool()?,
+            },
+
+            6 => IExpr::Variable {
+                index: self.len()?,
+                typ: self.typ()?,
+            },
+
+            7 => IExpr::Assign {
+                store: self.expr()?,
+                value: self.expr()?,
+            },
+
+            8 => IExpr::Call {
+                callee: self.expr()?,
+                args: self.list(Self::expr)?,
+            },
+
+            9 => IExpr::Convert {
+                value: self.expr()?,
+                to: self.typ()?,
+            },
+
+            10 => IExpr::Closure {
+                func: self.func_ref()?,
+                captures: self.list(Self::expr)?,
+            },
+
+            11 => {
+                let (module, index) = self.item_ref(RefKind::Class)?;
+                IExpr::Construct {
+                    class: ClassRef { module, index },
+                    members: self.list(Self::expr)?,
+                }
+            }
+
+            12 => IExpr::Member {
+                object: self.expr()?,
+                index: self.len()?,
+                typ: self.typ()?,
+            },
+
+            13 => IExpr::Panic {
+                message: self.expr()?,
+            },
+
+            14 => IExpr::Env {
+                name: self.expr()?,
+                typ: self.typ()?,
+            },
+
+            15 => IExpr::Intrinsic {
+                intrinsic: *Intrinsic::ALL
+                    .get(self.byte()? as usize)
+                    .ok_or(SnapshotError::InvalidData)?,
+                args: self.list(Self::expr)?,
+            },
+
+            16 => IExpr::Break {
+                value: self.expr()?,
+            },
+
+            _ => return Err(SnapshotError::InvalidData),
+        };
+
+        Ok(Expr {
+            inner: Box::new(inner),
+            ty: RefCell::new(ty),
+            start,
+        })
+    }
+
+    fn constant(&mut self) -> SRes<Constant> {
+        Ok(match self.byte()? {
+            0 => Constant::Bool(self.bool()?),
+            1 => {
+                let value = self.uint()?;
+                Constant::Int((value >> 1) as i64 ^ -((value & 1) as i64))
+            }
+            2 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.bytes(8)?);
+                Constant::Float(f64::from_bits(u64::from_le_bytes(bytes)))
+            }
+            3 => Constant::String(self.str()?),
+            4 => Constant::Function(self.func_ref()?),
+            5 => {
+                let (module, index) = self.item_ref(RefKind::Class)?;
+                Constant::Class(ClassRef { module, index })
+            }
+            6 => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(self.bytes(4)?);
+                Constant::F32(f32::from_bits(u32::from_le_bytes(bytes)))
+            }
+            _ => return Err(SnapshotError::InvalidData),
+        })
+    }
+
+    fn func_ref(&mut self) -> SRes<FuncRef> {
+        let (module, index) = self.item_ref(RefKind::Function)?;
+        Ok(FuncRef { module, index })
+    }
+
+    fn item_ref(&mut self, kind: RefKind) -> SRes<(MutRc<Module>, usize)> {
+        let module = self.len()?;
+        let index = self.len()?;
+        let module_rc = self
+            .modules
+            .get(module)
+            .ok_or(SnapshotError::InvalidData)?
+            .clone();
+        self.refs.push((kind, module, index));
+        Ok((module_rc, index))
+    }
+
+    fn check_refs(&self) -> SRes<()> {
+        for &(kind, module, index) in &self.refs {
+            let module = self.modules[module].borrow();
+            let len = match kind {
+                RefKind::Function => module.funcs.len(),
+                RefKind::Class => module.classes.len(),
+            };
+            if index >= len {
+                return Err(SnapshotError::InvalidData);
+            }
+        }
+        Ok(())
+    }
+
+    fn list<T, C: FromIterator<T>>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> SRes<T>,
+    ) -> SRes<C> {
+        let len = self.len()?;
+        (0..len).map(|_| item(self)).collect()
+    }
+
+    fn str(&mut self) -> SRes<SmolStr> {
+        let len = self.len()?;
+        let bytes = self.bytes(len)?;
+        str::from_utf8(bytes)
+            .map(SmolStr::new)
+            .map_err(|_| SnapshotError::InvalidData)
+    }
+
+    fn bool(&mut self) -> SRes<bool> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::InvalidData),
+        }
+    }
+
+    fn byte(&mut self) -> SRes<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> SRes<&'d [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .ok_or(SnapshotError::UnexpectedEnd)?;
+        let bytes = self
+            .data
+            .get(self.pos..end)
+            .ok_or(SnapshotError::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// A length or index, which can never be larger than the snapshot itself.
+    fn len(&mut self) -> SRes<usize> {
+        let value = self.uint()?;
+        if value > self.data.len() as u64 {
+            Err(SnapshotError::InvalidData)
+        } else {
+            Ok(value as usize)
+        }
+    }
+
+    fn uint(&mut self) -> SRes<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(SnapshotError::InvalidData)
+    }
+}
+
+fn ident(name: &SmolStr) -> Token {
+    Token {
+        kind: TKind::Identifier,
+        lex: name.clone(),
+        start: 0,
+    }
+}
@@ -1,13 +1,26 @@
 use crate::{
     compiler::{ir::Module, module::ModuleCompiler},
-    error::Errors,
+    error::{
+        Error,
+        ErrorKind::{E208, E209},
+        Errors,
+    },
     parser::ast,
+    smol_str::SmolStr,
+    vm::Target,
 };
-use alloc::{rc::Rc, vec::Vec};
-use core::cell::RefCell;
+use alloc::{rc::Rc, vec, vec::Vec};
+use core::{cell::RefCell, iter};
+use hashbrown::HashSet;
 
+mod dce;
 pub mod ir;
 pub mod module;
+#[cfg(debug_assertions)]
+pub mod validate;
+
+#[cfg(feature = "std")]
+pub(crate) use dce::mark_live;
 
 pub type MutRc<T> = Rc<RefCell<T>>;
 
@@ -15,43 +28,247 @@ fn mutrc_new<T>(inner: T) -> MutRc<T> {
     Rc::new(RefCell::new(inner))
 }
 
+/// Options changing how programs are compiled.
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    /// Only JIT functions reachable from an entry point.
+    /// Should be disabled when functions are looked up by name
+    /// after compilation, like in a REPL.
+    pub eliminate_dead_code: bool,
+    /// The maximum size of functions to inline into their callers,
+    /// counted in IR expressions. 0 disables inlining.
+    pub inline_threshold: usize,
+    /// Names of the functions that can be called from outside the program.
+    /// Dead code elimination keeps everything reachable from them.
+    pub entry_points: Vec<SmolStr>,
+    /// Report warnings, like unused variables, as errors stopping the program
+    /// from compiling. Otherwise they are kept with the compiled modules,
+    /// see `CompiledModule::warnings`.
+    pub warnings_as_errors: bool,
+    /// The edition of the language programs are written in.
+    pub edition: Edition,
+    /// The flags set for conditional compilation. Declarations with a `@cfg`
+    /// attribute are only compiled if it matches them, like `@cfg(kernel)`
+    /// needing the flag `kernel` and `@cfg(!kernel)` needing it to be unset.
+    pub flags: Vec<SmolStr>,
+    /// The machine to generate code for, which is the host by default.
+    /// Programs linked by an `Engine` are compiled for it.
+    pub target: Target,
+}
+
+/// A version of the language. Changes that would make programs behave differently
+/// or stop compiling only apply to newer editions, which programs opt into.
+/// This keeps programs stored on disk working the way they were written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Edition {
+    /// The first edition, which all programs written so far use.
+    V1,
+    /// Functions named `init` at the top of a module are its initializer,
+    /// which runs before the program is first called.
+    V2,
+}
+
+impl Edition {
+    pub const LATEST: Edition = Edition::V2;
+}
+
+impl Default for Edition {
+    fn default() -> Self {
+        Edition::V1
+    }
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            eliminate_dead_code: true,
+            inline_threshold: 10,
+            entry_points: vec![SmolStr::new_inline("main")],
+            warnings_as_errors: false,
+            edition: Edition::default(),
+            flags: Vec::new(),
+            target: Target::default(),
+        }
+    }
+}
+
 pub struct Compiler {
     modules: Vec<MutRc<Module>>,
     compilers: Vec<ModuleCompiler>,
+    options: CompileOptions,
 }
 
 impl Compiler {
-    pub fn consume(mut self) -> Result<Vec<MutRc<Module>>, Vec<Errors>> {
-        self.all_mods(ModuleCompiler::stage_1);
+    pub fn consume(self) -> Result<Vec<MutRc<Module>>, Vec<Errors>> {
+        let (modules, errors) = self.consume_recovering();
+        if errors.iter().all(Vec::is_empty) {
+            Ok(modules)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Compile all modules even if some of them contain errors,
+    /// returning the errors of every module alongside them.
+    /// Functions containing errors are poisoned, see `Function::is_poisoned`.
+    pub fn consume_recovering(mut self) -> (Vec<MutRc<Module>>, Vec<Errors>) {
+        // Every stage runs on all modules before the next one starts,
+        // so that modules can use each other regardless of their order
+        self.all_mods(ModuleCompiler::declare);
+        self.check_import_cycles();
+        let modules = self.modules.clone();
+        self.all_mods(|module| module.resolve_imports(&modules));
+        self.all_mods(ModuleCompiler::resolve);
+        self.check_exports();
+        self.all_mods(ModuleCompiler::generate);
+        self.all_mods(ModuleCompiler::optimize);
+        if self.options.eliminate_dead_code {
+            dce::mark_live(&self.modules, &self.options.entry_points);
+        }
         self.finish()
     }
 
+    /// Reject modules importing each other, either directly or through other
+    /// modules. The error is reported at the import closing the cycle.
+    fn check_import_cycles(&mut self) {
+        // The imports of every module as the indices of the imported modules
+        let imports = self
+            .modules
+            .iter()
+            .map(|module| {
+                module
+                    .borrow()
+                    .ast
+                    .imports
+                    .iter()
+                    .filter_map(|import| {
+                        let index = self.modules.iter().position(|module| {
+                            module
+                                .borrow()
+                                .ast
+                                .path
+                                .iter()
+                                .eq(import.path.iter().map(|segment| &segment.lex))
+                        })?;
+                        Some((index, import.path[0].start))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut visits = vec![Visit::New; self.modules.len()];
+        let mut cycles = Vec::new();
+        for module in 0..self.modules.len() {
+            if visits[module] == Visit::New {
+                visit_module(&imports, module, &mut visits, &mut Vec::new(), &mut cycles);
+            }
+        }
+
+        for (path, start) in cycles {
+            let module = *path.last().unwrap();
+            let cycle = path
+                .iter()
+                .chain(iter::once(&path[0]))
+                .map(|index| {
+                    let module = self.modules[*index].borrow();
+                    SmolStr::join(module.ast.path.iter().map(SmolStr::as_str), "::")
+                })
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            self.compilers[module]
+                .errors
+                .push(Error::new(start, E208 { cycle }));
+        }
+    }
+
+    /// Reject functions exported under the same name, since the JIT links
+    /// them by it. The error is reported at every export after the first.
+    fn check_exports(&mut self) {
+        let mut exported = HashSet::new();
+        for (module, compiler) in self.modules.iter().zip(&mut self.compilers) {
+            for func in module.borrow().funcs.iter().filter(|func| !func.external) {
+                let name = match &func.link_name {
+                    Some(name) => name,
+                    None => continue,
+                };
+                if !exported.insert(name.clone()) {
+                    let start = func
+                        .ast
+                        .attributes
+                        .iter()
+                        .find(|attribute| attribute.name.lex == "export")
+                        .map_or(func.ast.name.start, |attribute| attribute.start);
+                    compiler.errors.push(Error::new(start, E209(name.clone())));
+                }
+            }
+        }
+    }
+
     fn all_mods(&mut self, mut cls: impl FnMut(&mut ModuleCompiler)) {
         for compiler in self.compilers.iter_mut() {
             cls(compiler)
         }
     }
 
-    fn finish(self) -> Result<Vec<MutRc<Module>>, Vec<Errors>> {
-        let mut errors = Vec::new();
-        for comp in self.compilers {
-            if !comp.errors.is_empty() {
-                errors.push(comp.errors);
-            }
-        }
+    fn finish(self) -> (Vec<MutRc<Module>>, Vec<Errors>) {
+        let errors = self
+            .compilers
+            .into_iter()
+            .map(|comp| comp.errors)
+            .collect::<Vec<_>>();
 
-        if errors.is_empty() {
-            Ok(self.modules)
-        } else {
-            Err(errors)
+        // Programs with errors contain poison, which is only valid in poisoned functions
+        #[cfg(debug_assertions)]
+        if errors.iter().all(Vec::is_empty) {
+            for module in &self.modules {
+                validate::validate(&module.borrow());
+            }
         }
+        (self.modules, errors)
     }
 
-    pub fn new(modules: Vec<ast::Module>) -> Self {
+    pub fn new(modules: Vec<ast::Module>, options: &CompileOptions) -> Self {
         let modules: Vec<_> = modules.into_iter().map(Module::from_ast).collect();
         Self {
-            compilers: modules.iter().cloned().map(ModuleCompiler::new).collect(),
+            compilers: modules
+                .iter()
+                .map(|module| ModuleCompiler::new(module.clone(), options))
+                .collect(),
             modules,
+            options: options.clone(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Visit {
+    New,
+    Active,
+    Done,
+}
+
+/// Search the modules imported by the given module for cycles, with `path`
+/// being the modules leading to it. Found cycles are added to `cycles` as the
+/// modules forming them, together with the start of the import closing them.
+fn visit_module(
+    imports: &[Vec<(usize, usize)>],
+    module: usize,
+    visits: &mut [Visit],
+    path: &mut Vec<usize>,
+    cycles: &mut Vec<(Vec<usize>, usize)>,
+) {
+    visits[module] = Visit::Active;
+    path.push(module);
+    for &(imported, start) in &imports[module] {
+        match visits[imported] {
+            Visit::New => visit_module(imports, imported, visits, path, cycles),
+            Visit::Active => {
+                let first = path.iter().position(|m| *m == imported).unwrap();
+                cycles.push((path[first..].to_vec(), start));
+            }
+            Visit::Done => (),
         }
     }
+    path.pop();
+    visits[module] = Visit::Done;
 }
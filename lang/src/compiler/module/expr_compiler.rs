@@ -1,38 +1,90 @@
 use crate::{
     compiler::{
-        ir::{Constant, Expr, FuncRef, Function, Type, VarStore},
+        ir::{ClassRef, Constant, Expr, FuncRef, Function, IExpr, Intrinsic, Type, VarStore},
         module::ModuleCompiler,
     },
-    error::{ErrorKind, ErrorKind::*},
-    lexer::TKind,
+    error::{Error, ErrorKind, ErrorKind::*, Errors},
+    lexer::{TKind, Token},
     parser::{ast, ast::EExpr},
     smol_str::SmolStr,
 };
 use alloc::{string::ToString, vec, vec::Vec};
+use core::iter;
 use hashbrown::HashMap;
-use smallvec::SmallVec;
+use smallvec::{smallvec, SmallVec};
 
-type Environment<'e> = HashMap<SmolStr, &'e VarStore>;
+type Environment = HashMap<SmolStr, VarStore>;
 
 pub struct ExprCompiler<'e> {
     function: &'e Function,
     compiler: &'e ModuleCompiler,
-    environments: Vec<Environment<'e>>,
+    environments: Vec<Environment>,
+    /// Local functions in scope, one map for each environment.
+    /// Kept apart from variables, since they are visible in their entire block.
+    local_functions: Vec<HashMap<SmolStr, FuncRef>>,
+    /// The environments of the function this closure is declared in, if it is one.
+    /// Closures can only capture variables of that function, and not of
+    /// functions further out, unless that function captured them as well.
+    enclosing: Option<&'e [Environment]>,
+    /// The variables of the enclosing function captured by this closure,
+    /// in the order of `Function::captures`.
+    captured: SmallVec<[VarStore; 2]>,
+    /// The types of the values of all `break`s in each loop
+    /// the current expression is in, innermost last.
+    loops: Vec<SmallVec<[Type; 2]>>,
+    /// Variables declared in this function that were never used yet,
+    /// by the index of their local. Reported as warnings once out of scope.
+    unused: HashMap<usize, Token>,
+    pub errors: Errors,
 }
 
 impl<'e> ExprCompiler<'e> {
+    /// Compile the body of a class constructor. The members are mutable locals
+    /// initialized from the parameters, which the `init` block can change
+    /// before the value is created from them.
+    pub fn constructor(&mut self, class: ClassRef, init: &ast::Expr) -> Expr {
+        self.begin_scope();
+        let mut exprs = Vec::new();
+        let mut members = SmallVec::new();
+        for param in self.function.params.iter() {
+            let local = self
+                .function
+                .add_local(param.name.clone(), param.ty.clone(), true);
+            exprs.push(Expr::assign_local(local, Expr::local(param)));
+            members.push(Expr::local(local));
+            self.add_to_scope(local.clone());
+        }
+        exprs.push(self.expr(init));
+        exprs.push(Expr::construct(class, members));
+        self.end_scope();
+        Expr::block(exprs)
+    }
+
     pub fn expr(&mut self, expr: &ast::Expr) -> Expr {
+        let mut ir = self.compile(expr);
+        ir.start = Some(expr.start);
+        ir
+    }
+
+    fn compile(&mut self, expr: &ast::Expr) -> Expr {
         match &*expr.ty {
             EExpr::Literal(lit) => Expr::constant(Constant::from_literal(lit)),
 
             EExpr::Binary { left, op, right } => {
+                let target = left;
                 let left = self.expr(left);
                 let right = self.expr(right);
                 let lty = left.typ();
                 let rty = right.typ();
-                let logic = op.kind.is_binary_logic();
 
                 match () {
+                    // The error producing the poison was already reported
+                    _ if lty == Type::Poison || rty == Type::Poison => return Expr::poison(),
+
+                    _ if op.kind != TKind::Equal && lty.is_class() => {
+                        return self.operator(left, op, right)
+                    }
+
                     _ if lty != rty => self.err(
                         op.start,
                         E500 {
@@ -43,13 +95,11 @@ impl<'e> ExprCompiler<'e> {
 
                     _ if op.kind == TKind::Equal => {
                         // Assignment
-                        if !left.assignable() {
-                            self.err(op.start, E505)
-                        }
+                        self.check_assignment(target, &left, op.start);
                         return Expr::assign(left, right);
                     }
 
-                    _ if (logic && !lty.allow_logic()) || !logic && !lty.allow_math() => self.err(
+                    _ if !lty.allow_binary(op.kind) => self.err(
                         op.start,
                         E501 {
                             op: op.lex.clone(),
@@ -65,11 +115,24 @@ impl<'e> ExprCompiler<'e> {
 
             EExpr::Block(exprs) => {
                 self.begin_scope();
+                self.declare_local_functions(exprs);
                 let exprs = exprs.iter().map(|e| self.expr(e)).collect();
                 self.end_scope();
                 Expr::block(exprs)
             }
 
+            EExpr::LocalFunction { index, .. } => {
+                let func = self.compiler.local_functions[*index].clone();
+                self.local_function(&func);
+                Expr::block(Vec::new())
+            }
+
+            EExpr::Closure(index) => {
+                let func = self.compiler.local_functions[*index].clone();
+                let captured = self.closure(&func);
+                Expr::closure(func, captured.iter().map(Expr::local).collect())
+            }
+
             EExpr::If { cond, then, els } => {
                 let condition = self.expr(cond);
                 if condition.typ() != Type::Bool {
@@ -81,22 +144,54 @@ impl<'e> ExprCompiler<'e> {
                 Expr::if_(condition, then, els)
             }
 
-            EExpr::While { cond, body } => {
+            EExpr::While { cond, body, els } => {
                 let condition = self.expr(cond);
                 if condition.typ() != Type::Bool {
                     self.err(cond.start, E502);
                 }
+                self.loops.push(SmallVec::new());
                 let body = self.expr(body);
-                Expr::while_(condition, body)
+                let breaks = self.loops.pop().unwrap();
+                let els = els.as_ref().map(|e| self.expr(e));
+                Expr::while_(condition, body, els, &breaks)
+            }
+
+            EExpr::Break { value } => {
+                let value = value.as_ref().map(|v| self.expr(v));
+                match self.loops.last_mut() {
+                    Some(breaks) => {
+                        breaks.push(value.as_ref().map_or(Type::Void, Expr::typ));
+                        Expr::break_(value)
+                    }
+                    None => {
+                        self.err(expr.start, E522);
+                        Expr::poison()
+                    }
+                }
             }
 
             EExpr::Identifier(ident) => {
-                let local = self.find_local(&ident.lex);
+                let local = self.find_local(&ident.lex).cloned();
                 if let Some(local) = local {
-                    return Expr::local(local);
+                    self.unused.remove(&local.index);
+                    return Expr::local(&local);
                 }
-                let func = self.find_function(&ident.lex);
-                if let Some(func) = func {
+                if let Some(captured) = self.capture(ident) {
+                    return Expr::local(&captured);
+                }
+                if let Some(global) = self.compiler.globals.get(&ident.lex) {
+                    return global.clone().map_or_else(Expr::poison, Expr::constant);
+                }
+                let mut funcs = self.find_functions(&ident.lex);
+                if funcs.len() > 1 {
+                    self.err(
+                        ident.start,
+                        E513 {
+                            name: ident.lex.clone(),
+                        },
+                    );
+                    return Expr::poison();
+                } else if let Some(func) = funcs.pop() {
                     return Expr::constant(Constant::Function(func));
                 }
 
@@ -120,46 +215,104 @@ impl<'e> ExprCompiler<'e> {
                     self.err(name.start, E504 { ty: ty.to_string() })
                 }
 
+                // Shadowing is only allowed in nested scopes
+                if self.environments.last().unwrap().contains_key(&name.lex) {
+                    self.err(
+                        name.start,
+                        E516 {
+                            name: name.lex.clone(),
+                        },
+                    );
+                }
+
                 let local = self.function.add_local(name.lex.clone(), ty, !*final_);
-                self.add_to_scope(local);
-                Expr::assign_local(local, value)
+                let assign = Expr::assign_local(local, value);
+                if !name.lex.starts_with('_') {
+                    self.unused.insert(local.index, name.clone());
+                }
+                self.add_to_scope(local.clone());
+                assign
             }
 
             EExpr::Call { callee, args } => {
+                match self.unshadowed(callee).map(SmolStr::as_str) {
+                    Some("assert") => return self.assert(callee.start, args),
+                    Some("panic") => return self.panic(callee.start, args),
+                    Some("get_env") => return self.get_env(callee.start, args, Type::String),
+                    Some("get_env_int") => return self.get_env(callee.start, args, Type::I64),
+                    Some("get_env_float") => return self.get_env(callee.start, args, Type::F64),
+                    Some("get_env_bool") => return self.get_env(callee.start, args, Type::Bool),
+                    Some(name) => {
+                        if let Some(intrinsic) = Intrinsic::from_name(name) {
+                            return self.intrinsic(callee.start, intrinsic, args);
+                        }
+                    }
+                    None => (),
+                }
+                if let Some(to) = self.conversion(callee) {
+                    return self.convert(callee.start, to, args);
+                }
+
                 let start = callee.start;
-                let callee = self.expr(callee);
-                let fn_ref = if let Type::Function(fn_ref) = callee.typ() {
-                    fn_ref
+                let receiver = self.receiver(callee);
+                let mut values = args
+                    .iter()
+                    .map(|a| self.expr(&a.value))
+                    .collect::<SmallVec<[Expr; 4]>>();
+                let implicit = receiver.is_ok() as usize;
+                let callee = match receiver {
+                    Ok((method, object)) => {
+                        values.insert(0, object);
+                        Expr::constant(Constant::Function(method))
+                    }
+                    Err(Some(callee)) => callee,
+                    Err(None) => self.callee(callee, args, &values),
+                };
+                let sig = match callee.typ() {
+                    Type::Function(sig) => sig,
+                    Type::Poison => return Expr::poison(),
+                    _ => {
+                        self.err(
+                            start,
+                            E506 {
+                                ty: callee.typ().to_string(),
+                            },
+                        );
+                        return Expr::poison();
+                    }
+                };
+
+                let mut spilled = Vec::new();
+                let args = if let IExpr::Constant(Constant::Function(func)) = &*callee.inner {
+                    let func = func.resolve();
+                    self.direct_call_args(start, &func, args, values, implicit, &mut spilled)
                 } else {
-                    self.err(
-                        start,
-                        E506 {
-                            ty: callee.typ().to_string(),
-                        },
-                    );
-                    return Expr::poison();
+                    self.indirect_call_args(start, args, values)
+                };
+                let args = match args {
+                    Some(args) => args,
+                    None => return Expr::poison(),
                 };
-                let func = fn_ref.resolve();
 
-                let args = args
-                    .iter()
-                    .map(|a| self.expr(a))
-                    .collect::<SmallVec<[Expr; 4]>>();
-                if args.len() != func.params.len() {
+                if args.len() != sig.params.len() {
                     self.err(
                         start,
                         E507 {
-                            expected: func.params.len(),
+                            min: sig.params.len(),
+                            max: sig.params.len(),
                             found: args.len(),
                         },
                     );
                 }
-                for (i, (arg, param)) in args.iter().zip(func.params.iter()).enumerate() {
-                    if arg.typ() != param.ty {
+                let params = sig.params.iter().skip(implicit);
+                for (i, (arg, param)) in args.iter().skip(implicit).zip(params).enumerate() {
+                    if arg.typ() == Type::Poison || *param == Type::Poison {
+                        return Expr::poison();
+                    } else if arg.typ() != *param {
                         self.err(
                             start,
                             E508 {
-                                expected: param.ty.to_string(),
+                                expected: param.to_string(),
                                 found: arg.typ().to_string(),
                                 pos: i,
                             },
@@ -167,18 +320,698 @@ impl<'e> ExprCompiler<'e> {
                     }
                 }
 
-                Expr::call(callee, args, func.ret_type.clone())
+                let call = Expr::call(callee, args, sig.ret_type.clone());
+                if spilled.is_empty() {
+                    call
+                } else {
+                    spilled.push(call);
+                    Expr::block(spilled)
+                }
+            }
+
+            EExpr::Get { object, name } => {
+                if let Some(value) = self.static_value(object, name) {
+                    return value;
+                }
+                let object = self.expr(object);
+                self.get(object, name)
+            }
+
+            EExpr::Unary { .. } => {
+                self.err(expr.start, E517);
+                Expr::poison()
+            }
+        }
+    }
+
+    /// Bring the local functions declared in a block into scope,
+    /// which allows calling them before their declaration.
+    fn declare_local_functions(&mut self, exprs: &[ast::Expr]) {
+        for expr in exprs {
+            if let EExpr::LocalFunction { name, index } = &*expr.ty {
+                let func = self.compiler.local_functions[*index].clone();
+                let scope = self.local_functions.last_mut().unwrap();
+                if scope.insert(name.lex.clone(), func).is_some() {
+                    self.err(name.start, E201(name.lex.clone()));
+                }
+            }
+        }
+    }
+
+    /// Compile the body of a local function. It can use the local functions
+    /// in scope where it is declared, but none of the variables.
+    fn local_function(&mut self, func: &FuncRef) {
+        let func = func.resolve();
+        let mut compiler = ExprCompiler::new(self.compiler, &func);
+        compiler.local_functions = self.local_functions.clone();
+        let body = compiler.expr(func.ast.body.as_ref().unwrap());
+        self.errors.append(&mut compiler.errors);
+        *func.body.borrow_mut() = body;
+    }
+
+    /// Compile the body of a closure, returning the variables it captured.
+    fn closure(&mut self, func: &FuncRef) -> SmallVec<[VarStore; 2]> {
+        let func = func.resolve();
+        let mut compiler = ExprCompiler::new(self.compiler, &func);
+        compiler.local_functions = self.local_functions.clone();
+        compiler.enclosing = Some(&self.environments);
+        let body = compiler.expr(func.ast.body.as_ref().unwrap());
+        self.errors.append(&mut compiler.errors);
+        *func.body.borrow_mut() = body;
+        for var in &compiler.captured {
+            self.unused.remove(&var.index);
+        }
+        compiler.captured
+    }
+
+    /// Capture the variable with the given name from the enclosing function,
+    /// if this is a closure and it exists there. Captured variables are
+    /// copied into the closure when it is created, so only `val`s can be captured.
+    fn capture(&mut self, name: &Token) -> Option<VarStore> {
+        let var = self
+            .enclosing?
+            .iter()
+            .rev()
+            .filter_map(|env| env.get(&name.lex))
+            .next()?
+            .clone();
+        if var.mutable {
+            self.err(
+                name.start,
+                E519 {
+                    name: name.lex.clone(),
+                },
+            );
+        }
+
+        let local = self
+            .function
+            .add_capture(name.lex.clone(), var.ty.clone())
+            .clone();
+        self.environments[0].insert(local.name.clone(), local.clone());
+        self.captured.push(var);
+        Some(local)
+    }
+
+    /// If the given name is a variable, either of this function
+    /// or one that a closure can capture.
+    fn is_variable(&self, name: &str) -> bool {
+        self.find_local(name).is_some()
+            || self
+                .enclosing
+                .map_or(false, |envs| envs.iter().any(|env| env.contains_key(name)))
+    }
+
+    /// Read the member with the given name from a value.
+    fn get(&mut self, object: Expr, name: &Token) -> Expr {
+        let member = match object.typ() {
+            Type::Class(class) => class.resolve().member(&name.lex),
+            Type::Poison => return Expr::poison(),
+            _ => None,
+        };
+        match member {
+            Some(member) => Expr::member(object, &member),
+            None => {
+                self.err(
+                    name.start,
+                    E521 {
+                        ty: object.typ().to_string(),
+                        name: name.lex.clone(),
+                    },
+                );
+                Expr::poison()
+            }
+        }
+    }
+
+    /// If the object names a class, like in `Player.MAX_HP`, returns the
+    /// `static val` of the class with the given name.
+    fn static_value(&mut self, object: &ast::Expr, name: &Token) -> Option<Expr> {
+        let class = match &*object.ty {
+            EExpr::Identifier(class)
+                if !self.is_variable(&class.lex)
+                    && !self.compiler.globals.contains_key(&class.lex) =>
+            {
+                self.compiler.find_class(&class.lex)?
+            }
+            _ => return None,
+        };
+        let class = class.resolve();
+        let value = class.constant(&name.lex);
+        if value.is_none() {
+            self.err(
+                name.start,
+                E521 {
+                    ty: class.name.to_string(),
+                    name: name.lex.clone(),
+                },
+            );
+        }
+        Some(value.unwrap_or_else(Expr::poison))
+    }
+
+    /// If the callee is a method of a value, like `point.length()`, returns the
+    /// method and the value it is called on. Otherwise, returns the compiled callee
+    /// if compiling it could not be avoided while finding out.
+    fn receiver(&mut self, callee: &ast::Expr) -> Result<(FuncRef, Expr), Option<Expr>> {
+        let (object, name) = match &*callee.ty {
+            EExpr::Get { object, name } => (object, name),
+            _ => return Err(None),
+        };
+        let object = self.expr(object);
+        let method = match object.typ() {
+            Type::Class(class) => class.resolve().method(&name.lex),
+            _ => None,
+        };
+        match method {
+            Some(method) => Ok((method, object)),
+            None => Err(Some(self.get(object, name))),
+        }
+    }
+
+    /// Compile the callee of a call, which might be an
+    /// overloaded function that needs the arguments to be resolved.
+    fn callee(&mut self, callee: &ast::Expr, args: &[ast::Argument], values: &[Expr]) -> Expr {
+        if let EExpr::Identifier(name) = &*callee.ty {
+            let funcs = self.find_functions(&name.lex);
+            if funcs.len() > 1 && !self.is_variable(&name.lex) {
+                return self.resolve_overload(name, funcs, args, values);
+            }
+        }
+        self.expr(callee)
+    }
+
+    /// Returns the name of the given callee if it is an identifier that is
+    /// neither a variable, global nor function, which allows it to name a type or builtin.
+    fn unshadowed<'a>(&self, callee: &'a ast::Expr) -> Option<&'a SmolStr> {
+        match &*callee.ty {
+            EExpr::Identifier(name)
+                if !self.is_variable(&name.lex)
+                    && !self.compiler.globals.contains_key(&name.lex)
+                    && self.find_functions(&name.lex).is_empty() =>
+            {
+                Some(&name.lex)
+            }
+            _ => None,
+        }
+    }
+
+    /// If the given callee names a type that values can be converted to, like `f64(value)`.
+    /// Types shadowed by a variable or function are not considered.
+    /// Classes are never converted to, since their constructor is a function of the same name.
+    fn conversion(&self, callee: &ast::Expr) -> Option<Type> {
+        let name = self.unshadowed(callee)?;
+        match &name[..] {
+            "f32" => Some(Type::F32),
+            "f64" => Some(Type::F64),
+            "str" => Some(Type::String),
+            _ => None,
+        }
+    }
+
+    /// Apply a binary operator to a class value by calling the method
+    /// implementing it, like `plus` for `+`. Comparisons are derived from
+    /// `equals` returning a `bool` and `compare` returning an `i64` that is
+    /// negative, zero or positive when the value is less, equal or greater.
+    fn operator(&mut self, left: Expr, op: &Token, right: Expr) -> Expr {
+        let class = match left.typ() {
+            Type::Class(class) => class,
+            _ => unreachable!(),
+        };
+        let (name, ret) = match op.kind {
+            TKind::Plus => ("plus", None),
+            TKind::Minus => ("minus", None),
+            TKind::Star => ("times", None),
+            TKind::Slash => ("div", None),
+            TKind::Percent => ("rem", None),
+            TKind::EqualEqual | TKind::BangEqual => ("equals", Some(Type::Bool)),
+            TKind::Less | TKind::LessEqual | TKind::Greater | TKind::GreaterEqual => {
+                ("compare", Some(Type::I64))
+            }
+            _ => ("", None),
+        };
+
+        let method = match class.resolve().method(name) {
+            Some(method) => method,
+            None => {
+                self.err(
+                    op.start,
+                    E501 {
+                        op: op.lex.clone(),
+                        ty: left.typ().to_string(),
+                    },
+                );
+                return Expr::poison();
+            }
+        };
+
+        let func = method.resolve();
+        if let Some(ret) = ret.filter(|ret| *ret != func.ret_type) {
+            self.err(
+                op.start,
+                E520 {
+                    name: name.into(),
+                    ret: ret.to_string(),
+                },
+            );
+            return Expr::poison();
+        }
+        // The first parameter is `this`, leaving the right operand as the only argument
+        if func.params.len() != 2 {
+            self.err(
+                op.start,
+                E507 {
+                    min: func.params.len() - 1,
+                    max: func.params.len() - 1,
+                    found: 1,
+                },
+            );
+            return Expr::poison();
+        } else if func.params[1].ty != right.typ() {
+            self.err(
+                op.start,
+                E508 {
+                    expected: func.params[1].ty.to_string(),
+                    found: right.typ().to_string(),
+                    pos: 0,
+                },
+            );
+            return Expr::poison();
+        }
+
+        let ret_type = func.ret_type.clone();
+        drop(func);
+        let call = Expr::call(
+            Expr::constant(Constant::Function(method)),
+            smallvec![left, right],
+            ret_type,
+        );
+        match op.kind {
+            TKind::BangEqual => Expr::if_(
+                call,
+                Expr::constant(Constant::Bool(false)),
+                Some(Expr::constant(Constant::Bool(true))),
+            ),
+            TKind::Less | TKind::LessEqual | TKind::Greater | TKind::GreaterEqual => {
+                Expr::binary(call, op.clone(), Expr::constant(Constant::Int(0)))
+            }
+            _ => call,
+        }
+    }
+
+    /// Compile the single argument of a builtin, like a conversion.
+    fn single_arg(&mut self, start: usize, args: &[ast::Argument]) -> Option<Expr> {
+        if args.len() != 1 {
+            self.err(
+                start,
+                E507 {
+                    min: 1,
+                    max: 1,
+                    found: args.len(),
+                },
+            );
+            return None;
+        }
+        Some(self.expr(&args[0].value))
+    }
+
+    /// Stop the program with a message if the condition is false.
+    fn assert(&mut self, start: usize, args: &[ast::Argument]) -> Expr {
+        let condition = match self.single_arg(start, args) {
+            Some(condition) => condition,
+            None => return Expr::poison(),
+        };
+        match condition.typ() {
+            Type::Bool => (),
+            Type::Poison => return Expr::poison(),
+            _ => {
+                self.err(args[0].value.start, E502);
+                return Expr::poison();
+            }
+        }
+        let message = Expr::constant(Constant::String(SmolStr::new_inline("assertion failed")));
+        Expr::if_(
+            condition,
+            Expr::block(Vec::new()),
+            Some(Expr::panic(message)),
+        )
+    }
+
+    /// Stop the program with the given message.
+    fn panic(&mut self, start: usize, args: &[ast::Argument]) -> Expr {
+        let message = match self.single_arg(start, args) {
+            Some(message) => message,
+            None => return Expr::poison(),
+        };
+        match message.typ() {
+            Type::String => Expr::panic(message),
+            Type::Poison => Expr::poison(),
+            ty => {
+                self.err(
+                    start,
+                    E508 {
+                        expected: Type::String.to_string(),
+                        found: ty.to_string(),
+                        pos: 0,
+                    },
+                );
+                Expr::poison()
+            }
+        }
+    }
+
+    /// Read a binding of the environment the host passed to the program.
+    fn get_env(&mut self, start: usize, args: &[ast::Argument], typ: Type) -> Expr {
+        let name = match self.single_arg(start, args) {
+            Some(name) => name,
+            None => return Expr::poison(),
+        };
+        match name.typ() {
+            Type::String => Expr::env(name, typ),
+            Type::Poison => Expr::poison(),
+            ty => {
+                self.err(
+                    start,
+                    E508 {
+                        expected: Type::String.to_string(),
+                        found: ty.to_string(),
+                        pos: 0,
+                    },
+                );
+                Expr::poison()
+            }
+        }
+    }
+
+    /// Apply an intrinsic, which takes arguments of the same type like a binary operator.
+    fn intrinsic(&mut self, start: usize, intrinsic: Intrinsic, args: &[ast::Argument]) -> Expr {
+        let arity = intrinsic.arity();
+        if args.len() != arity {
+            self.err(
+                start,
+                E507 {
+                    min: arity,
+                    max: arity,
+                    found: args.len(),
+                },
+            );
+            return Expr::poison();
+        }
+        let values = args
+            .iter()
+            .map(|a| self.expr(&a.value))
+            .collect::<SmallVec<[Expr; 2]>>();
+        let ty = values[0].typ();
+        if values.iter().any(|v| v.typ() == Type::Poison) {
+            return Expr::poison();
+        }
+        if !intrinsic.allows(&ty) {
+            self.err(
+                start,
+                E501 {
+                    op: SmolStr::new_inline(intrinsic.name()),
+                    ty: ty.to_string(),
+                },
+            );
+            return Expr::poison();
+        }
+        if let Some(pos) = values.iter().position(|v| v.typ() != ty) {
+            self.err(
+                start,
+                E508 {
+                    expected: ty.to_string(),
+                    found: values[pos].typ().to_string(),
+                    pos,
+                },
+            );
+            return Expr::poison();
+        }
+        Expr::intrinsic(intrinsic, values)
+    }
+
+    fn convert(&mut self, start: usize, to: Type, args: &[ast::Argument]) -> Expr {
+        let value = match self.single_arg(start, args) {
+            Some(value) => value,
+            None => return Expr::poison(),
+        };
+        match value.typ() {
+            Type::Poison => Expr::poison(),
+            Type::String if to == Type::String => value,
+            Type::Class(class) if to == Type::String => self.to_string(start, class, value),
+            ty if ty.convertible_to(&to) => Expr::convert(value, to),
+            ty => {
+                self.err(
+                    start,
+                    E518 {
+                        from: ty.to_string(),
+                        to: to.to_string(),
+                    },
+                );
+                Expr::poison()
+            }
+        }
+    }
+
+    /// Convert a class value to a string by calling its `to_string` method,
+    /// which is how classes make their values displayable.
+    fn to_string(&mut self, start: usize, class: ClassRef, value: Expr) -> Expr {
+        let method = class.resolve().method("to_string").filter(|method| {
+            let method = method.resolve();
+            method.params.len() == 1 && method.ret_type == Type::String
+        });
+        match method {
+            Some(method) => Expr::call(
+                Expr::constant(Constant::Function(method)),
+                smallvec![value],
+                Type::String,
+            ),
+            None => {
+                self.err(
+                    start,
+                    E518 {
+                        from: value.typ().to_string(),
+                        to: Type::String.to_string(),
+                    },
+                );
+                Expr::poison()
+            }
+        }
+    }
+
+    fn resolve_overload(
+        &mut self,
+        name: &Token,
+        funcs: SmallVec<[FuncRef; 2]>,
+        args: &[ast::Argument],
+        values: &[Expr],
+    ) -> Expr {
+        let mut matching = funcs.into_iter().filter(|func| {
+            let func = func.resolve();
+            match Self::map_args(name.start, &func, args, 0) {
+                Ok(mapping) => mapping
+                    .iter()
+                    .zip(values.iter())
+                    .all(|(index, value)| func.params[*index].ty == value.typ()),
+                Err(_) => false,
+            }
+        });
+
+        match (matching.next(), matching.next()) {
+            (Some(func), None) => Expr::constant(Constant::Function(func)),
+
+            (None, _) => {
+                self.err(
+                    name.start,
+                    E514 {
+                        name: name.lex.clone(),
+                    },
+                );
+                Expr::poison()
+            }
+
+            (Some(_), Some(_)) => {
+                self.err(
+                    name.start,
+                    E513 {
+                        name: name.lex.clone(),
+                    },
+                );
+                Expr::poison()
+            }
+        }
+    }
+
+    /// Arguments to a function known at compile time, which can be named
+    /// and fall back to calling the function computing the parameter's default value.
+    /// The first `implicit` values are given without an argument, like `this` of methods.
+    ///
+    /// Arguments are evaluated left to right as written, followed by the defaults
+    /// of parameters without one. If that is not the order of the parameters,
+    /// the arguments are assigned to locals by the expressions added to `spilled`,
+    /// which need to be evaluated before the call.
+    fn direct_call_args(
+        &mut self,
+        start: usize,
+        func: &Function,
+        args: &[ast::Argument],
+        values: SmallVec<[Expr; 4]>,
+        implicit: usize,
+        spilled: &mut Vec<Expr>,
+    ) -> Option<SmallVec<[Expr; 4]>> {
+        let mapping = match Self::map_args(start, func, args, implicit) {
+            Ok(mapping) => (0..implicit)
+                .chain(mapping)
+                .collect::<SmallVec<[usize; 4]>>(),
+            Err((pos, err)) => {
+                self.err(pos, err);
+                return None;
+            }
+        };
+
+        // Parameters after the last argument are given after all arguments either way
+        let given = mapping.iter().max().map_or(0, |last| last + 1);
+        let in_order = mapping.iter().copied().eq(0..given);
+
+        let mut slots: SmallVec<[Option<Expr>; 4]> = func.params.iter().map(|_| None).collect();
+        for (index, value) in mapping.into_iter().zip(values) {
+            slots[index] = Some(if in_order {
+                value
+            } else {
+                self.spill(value, spilled)
+            });
+        }
+        for ((slot, default), param) in slots.iter_mut().zip(&func.defaults).zip(&func.params) {
+            if slot.is_none() {
+                *slot = default.as_ref().map(|default| {
+                    let callee = Expr::constant(Constant::Function(default.clone()));
+                    Expr::call(callee, SmallVec::new(), param.ty.clone())
+                });
+            }
+        }
+        Some(slots.into_iter().map(Option::unwrap).collect())
+    }
+
+    /// Assign a value to a new local, returning the value of that local instead.
+    /// Constants are kept, since evaluating them has no effect.
+    fn spill(&mut self, value: Expr, spilled: &mut Vec<Expr>) -> Expr {
+        let ty = value.typ();
+        if matches!(&*value.inner, IExpr::Constant(_)) || !ty.allow_assignment() || ty.is_poisoned()
+        {
+            return value;
+        }
+        let local = self
+            .function
+            .add_local(SmolStr::new_inline("argument"), ty, false)
+            .clone();
+        spilled.push(Expr::assign_local(&local, value));
+        Expr::local(&local)
+    }
+
+    /// Map each argument of a call to the index of the parameter it is
+    /// passed to, ensuring all parameters without a default are given.
+    /// The first `implicit` parameters are skipped, since they are given without an argument.
+    fn map_args(
+        start: usize,
+        func: &Function,
+        args: &[ast::Argument],
+        implicit: usize,
+    ) -> Result<SmallVec<[usize; 4]>, (usize, ErrorKind)> {
+        let arity_err = || {
+            let defaults = func.defaults.iter().skip(implicit);
+            let required = defaults.filter(|default| default.is_none());
+            (
+                start,
+                E507 {
+                    min: required.count(),
+                    max: func.params.len() - implicit,
+                    found: args.len(),
+                },
+            )
+        };
+        if args.len() + implicit > func.params.len() {
+            return Err(arity_err());
+        }
+
+        let mut mapping = SmallVec::new();
+        let mut named = false;
+        for (i, arg) in args.iter().enumerate() {
+            let index = match &arg.name {
+                Some(name) => {
+                    named = true;
+                    let index = func.params[implicit..]
+                        .iter()
+                        .position(|p| p.name == name.lex)
+                        .map(|index| index + implicit);
+                    index.ok_or_else(|| {
+                        (
+                            name.start,
+                            E509 {
+                                name: name.lex.clone(),
+                            },
+                        )
+                    })?
+                }
+
+                None if named => return Err((arg.value.start, E511)),
+                None => i + implicit,
+            };
+
+            if mapping.contains(&index) {
+                return Err((
+                    arg.value.start,
+                    E510 {
+                        name: func.params[index].name.clone(),
+                    },
+                ));
             }
+            mapping.push(index);
+        }
+
+        let mut required = func.defaults.iter().enumerate().skip(implicit);
+        if required.any(|(i, default)| default.is_none() && !mapping.contains(&i)) {
+            return Err(arity_err());
+        }
+        Ok(mapping)
+    }
+
+    /// Arguments to a function pointer, which only knows the
+    /// types of its parameters.
+    fn indirect_call_args(
+        &mut self,
+        start: usize,
+        args: &[ast::Argument],
+        values: SmallVec<[Expr; 4]>,
+    ) -> Option<SmallVec<[Expr; 4]>> {
+        if args.iter().any(|a| a.name.is_some()) {
+            self.err(start, E512);
+            return None;
+        }
+        Some(values)
+    }
+
+    /// Ensure the target of an assignment can be assigned to,
+    /// which excludes anything but variables as well as `val`s.
+    fn check_assignment(&mut self, target: &ast::Expr, store: &Expr, pos: usize) {
+        if !store.assignable() {
+            self.err(pos, E505);
+            return;
+        }
 
-            /*
-            EExpr::Unary { .. } => {}
-            */
-            _ => panic!("i can't compile this"),
+        if let EExpr::Identifier(name) = &*target.ty {
+            let immutable = self.find_local(&name.lex).map_or(false, |var| !var.mutable);
+            if immutable {
+                self.err(
+                    pos,
+                    E515 {
+                        name: name.lex.clone(),
+                    },
+                );
+            }
         }
     }
 
-    fn err(&self, _pos: usize, _err: ErrorKind) {
-        // self.compiler.errors
+    fn err(&mut self, pos: usize, err: ErrorKind) {
+        self.errors.push(Error::new(pos, err));
     }
 
     fn find_local(&self, name: &str) -> Option<&VarStore> {
@@ -187,23 +1020,41 @@ impl<'e> ExprCompiler<'e> {
             .rev()
             .filter_map(|env| env.get(name))
             .next()
-            .copied()
     }
 
-    fn find_function(&self, name: &str) -> Option<FuncRef> {
-        self.compiler
-            .module
-            .borrow()
-            .funcs
+    /// Find the functions with the given name, where local functions
+    /// shadow those of the module, which shadow those of imported modules.
+    fn find_functions(&self, name: &str) -> SmallVec<[FuncRef; 2]> {
+        let local = self
+            .local_functions
             .iter()
-            .position(|func| func.name == name)
-            .map(|index| FuncRef {
-                module: self.compiler.module.clone(),
-                index,
+            .rev()
+            .filter_map(|scope| scope.get(name))
+            .next();
+        if let Some(func) = local {
+            return smallvec![func.clone()];
+        }
+
+        iter::once(&self.compiler.module)
+            .chain(&self.compiler.imports)
+            .map(|module| {
+                module
+                    .borrow()
+                    .funcs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, func)| func.name == name)
+                    .map(|(index, _)| FuncRef {
+                        module: module.clone(),
+                        index,
+                    })
+                    .collect::<SmallVec<_>>()
             })
+            .find(|funcs| !funcs.is_empty())
+            .unwrap_or_default()
     }
 
-    fn add_to_scope(&mut self, var: &'e VarStore) {
+    fn add_to_scope(&mut self, var: VarStore) {
         self.environments
             .last_mut()
             .unwrap()
@@ -212,10 +1063,26 @@ impl<'e> ExprCompiler<'e> {
 
     fn begin_scope(&mut self) {
         self.environments.push(HashMap::new());
+        self.local_functions.push(HashMap::new());
     }
 
     fn end_scope(&mut self) {
-        self.environments.pop();
+        let env = self.environments.pop().unwrap();
+        self.local_functions.pop();
+
+        let mut unused = env
+            .values()
+            .filter_map(|var| self.unused.remove(&var.index))
+            .collect::<Vec<_>>();
+        unused.sort_by_key(|name| name.start);
+        for name in unused {
+            self.err(
+                name.start,
+                W100 {
+                    name: name.lex.clone(),
+                },
+            );
+        }
     }
 
     pub fn new(compiler: &'e ModuleCompiler, function: &'e Function) -> Self {
@@ -225,8 +1092,14 @@ impl<'e> ExprCompiler<'e> {
             environments: vec![function
                 .params
                 .iter()
-                .map(|p| (p.name.clone(), p))
+                .map(|p| (p.name.clone(), p.clone()))
                 .collect()],
+            local_functions: vec![HashMap::new()],
+            enclosing: None,
+            captured: SmallVec::new(),
+            loops: Vec::new(),
+            unused: HashMap::new(),
+            errors: Vec::new(),
         }
     }
 }
@@ -0,0 +1,290 @@
+use crate::{
+    compiler::{
+        ir::{ClassContent, Constant, Expr, Type},
+        module::ModuleCompiler,
+    },
+    error::{
+        Error,
+        ErrorKind::{E201, E203, E204, E205, E211, E500, E501},
+        Res,
+    },
+    lexer::{TKind, Token},
+    parser::{ast, ast::EExpr},
+    smol_str::SmolStr,
+};
+use alloc::{string::ToString, vec, vec::Vec};
+use core::mem;
+
+impl ModuleCompiler {
+    /// Evaluate the values of all globals of the module and `static val`s of its
+    /// classes. Both are constants folded at compile time, which means programs
+    /// never need to run code to initialize them before calling an entry point.
+    /// Values that failed to evaluate are poison.
+    pub(super) fn declare_globals(&mut self) {
+        let ast_globals = mem::replace(&mut self.module.borrow_mut().ast.globals, Vec::new());
+        let mut globals = Vec::new();
+        for global in ast_globals {
+            let reserved = self
+                .module
+                .borrow_mut()
+                .try_reserve_name(&global.name.lex, global.name.start);
+            match reserved {
+                Ok(()) => globals.push(Item {
+                    class: None,
+                    global,
+                }),
+                Err(err) => self.errors.push(err),
+            }
+        }
+        let class_count = self.module.borrow().classes.len();
+        for cls in 0..class_count {
+            self.class_statics(cls, &mut globals);
+        }
+
+        let classes = self
+            .module
+            .borrow()
+            .classes
+            .iter()
+            .map(|cls| cls.name.clone())
+            .collect();
+        let mut eval = ConstEval {
+            globals: &globals,
+            classes,
+            states: vec![State::Pending; globals.len()],
+            errors: Vec::new(),
+        };
+        for index in 0..globals.len() {
+            eval.global(index);
+        }
+
+        let ConstEval { states, errors, .. } = eval;
+        self.errors.extend(errors);
+        let module = self.module.borrow();
+        for (item, state) in globals.into_iter().zip(states) {
+            let value = match state {
+                State::Done(value) => value,
+                _ => unreachable!(),
+            };
+            match item.class {
+                // Classes have no other content yet, generating them appends it before the statics
+                Some(cls) => {
+                    module.classes[cls]
+                        .content
+                        .borrow_mut()
+                        .insert(item.global.name.lex, ClassContent::Const(value));
+                }
+                None => {
+                    self.globals.insert(item.global.name.lex, value);
+                }
+            }
+        }
+    }
+
+    /// Collect the `static val`s of a class, which share
+    /// their names with the other content of the class.
+    fn class_statics(&mut self, cls: usize, globals: &mut Vec<Item>) {
+        let module = self.module.borrow();
+        let mut ast = module.classes[cls].ast.borrow_mut();
+        let statics = mem::replace(&mut ast.statics, Vec::new());
+        let first = globals.len();
+        for global in statics {
+            let name = &global.name.lex;
+            let taken = ast.members.iter().any(|m| m.name.lex == *name)
+                || ast.methods.iter().any(|m| m.name.lex == *name)
+                || ast.functions.iter().any(|f| f.name.lex == *name)
+                || globals[first..].iter().any(|g| g.global.name.lex == *name);
+            if taken {
+                self.errors
+                    .push(Error::new(global.name.start, E201(name.clone())));
+            } else {
+                globals.push(Item {
+                    class: Some(cls),
+                    global,
+                });
+            }
+        }
+    }
+}
+
+/// A global, or a `static val` of the class with the given index.
+struct Item {
+    class: Option<usize>,
+    global: ast::Global,
+}
+
+#[derive(Clone)]
+enum State {
+    Pending,
+    Evaluating,
+    Done(Option<Constant>),
+}
+
+/// Evaluates the values of globals, in any order since
+/// globals can refer to those declared after them.
+struct ConstEval<'g> {
+    globals: &'g [Item],
+    /// The names of all classes of the module, by index.
+    classes: Vec<SmolStr>,
+    states: Vec<State>,
+    errors: Vec<Error>,
+}
+
+impl<'g> ConstEval<'g> {
+    /// Returns the value of the global with the given index,
+    /// evaluating it first if needed.
+    fn global(&mut self, index: usize) -> Option<Constant> {
+        match &self.states[index] {
+            State::Done(value) => return value.clone(),
+            State::Evaluating => {
+                let name = &self.globals[index].global.name;
+                self.errors
+                    .push(Error::new(name.start, E204(name.lex.clone())));
+                return None;
+            }
+            State::Pending => (),
+        }
+
+        self.states[index] = State::Evaluating;
+        let global = &self.globals[index].global;
+        let value = self
+            .expr(&global.value, &global.name.lex)
+            .map_err(|err| self.errors.push(err))
+            .ok()
+            .flatten();
+        self.states[index] = State::Done(value.clone());
+        value
+    }
+
+    /// Evaluate the value of the global with the given name.
+    /// Returns `None` when a global it refers to has already reported an error.
+    fn expr(&mut self, expr: &ast::Expr, name: &SmolStr) -> Res<Option<Constant>> {
+        match &*expr.ty {
+            EExpr::Literal(literal) => Ok(Some(Constant::from_literal(literal))),
+
+            EExpr::Identifier(ident) => match self.find(None, &ident.lex) {
+                Some(index) => Ok(self.global(index)),
+                None => Err(Error::new(expr.start, E203(name.clone()))),
+            },
+
+            // Static values of classes, like `Player.MAX_HP`
+            EExpr::Get {
+                object,
+                name: field,
+            } => {
+                let class = match &*object.ty {
+                    EExpr::Identifier(class) => self.classes.iter().position(|c| *c == class.lex),
+                    _ => None,
+                };
+                match class.and_then(|cls| self.find(Some(cls), &field.lex)) {
+                    Some(index) => Ok(self.global(index)),
+                    None => Err(Error::new(expr.start, E203(name.clone()))),
+                }
+            }
+
+            EExpr::Binary { left, op, right } if op.kind != TKind::Equal => {
+                let left = self.expr(left, name)?;
+                let right = self.expr(right, name)?;
+                match (left, right) {
+                    (Some(left), Some(right)) => binary(left, op, right).map(Some),
+                    _ => Ok(None),
+                }
+            }
+
+            _ => Err(Error::new(expr.start, E203(name.clone()))),
+        }
+    }
+
+    /// Find the global with the given name, either of the module or a class.
+    fn find(&self, class: Option<usize>, name: &str) -> Option<usize> {
+        self.globals
+            .iter()
+            .position(|g| g.class == class && g.global.name.lex == name)
+    }
+}
+
+/// Fold a binary operator on constants, the same way the JIT
+/// would compute it at runtime.
+fn binary(left: Constant, op: &Token, right: Constant) -> Res<Constant> {
+    let (lty, rty) = (typ(&left), typ(&right));
+    if lty != rty {
+        return Err(Error::new(
+            op.start,
+            E500 {
+                left: lty.to_string(),
+                right: rty.to_string(),
+            },
+        ));
+    }
+    if !lty.allow_binary(op.kind) {
+        return Err(Error::new(
+            op.start,
+            E501 {
+                op: op.lex.clone(),
+                ty: lty.to_string(),
+            },
+        ));
+    }
+
+    Ok(match (left, right) {
+        (Constant::Int(l), Constant::Int(r)) => match op.kind {
+            TKind::Plus => Constant::Int(l.wrapping_add(r)),
+            TKind::Minus => Constant::Int(l.wrapping_sub(r)),
+            TKind::Star => Constant::Int(l.wrapping_mul(r)),
+            TKind::Slash | TKind::Percent if r == 0 => return Err(Error::new(op.start, E205)),
+            TKind::Slash => match l.checked_div(r) {
+                Some(result) => Constant::Int(result),
+                None => return Err(Error::new(op.start, E211)),
+            },
+            TKind::Percent => Constant::Int(l.wrapping_rem(r)),
+            TKind::Ampersand => Constant::Int(l & r),
+            TKind::Pipe => Constant::Int(l | r),
+            TKind::Caret => Constant::Int(l ^ r),
+            TKind::LessLess => Constant::Int(l.wrapping_shl(r as u32)),
+            TKind::GreaterGreater => Constant::Int(l.wrapping_shr(r as u32)),
+            _ => Constant::Bool(compare(op.kind, &l, &r)),
+        },
+
+        (Constant::Float(l), Constant::Float(r)) => match op.kind {
+            TKind::Plus => Constant::Float(l + r),
+            TKind::Minus => Constant::Float(l - r),
+            TKind::Star => Constant::Float(l * r),
+            TKind::Slash => Constant::Float(l / r),
+            _ => Constant::Bool(compare(op.kind, &l, &r)),
+        },
+
+        (Constant::F32(l), Constant::F32(r)) => match op.kind {
+            TKind::Plus => Constant::F32(l + r),
+            TKind::Minus => Constant::F32(l - r),
+            TKind::Star => Constant::F32(l * r),
+            TKind::Slash => Constant::F32(l / r),
+            _ => Constant::Bool(compare(op.kind, &l, &r)),
+        },
+
+        (Constant::Bool(l), Constant::Bool(r)) => match op.kind {
+            TKind::And => Constant::Bool(l && r),
+            _ => Constant::Bool(l || r),
+        },
+
+        (Constant::String(l), Constant::String(r)) => {
+            Constant::String(SmolStr::from_fmt(format_args!("{}{}", l, r)))
+        }
+
+        _ => unreachable!(),
+    })
+}
+
+fn compare<T: PartialOrd>(op: TKind, left: &T, right: &T) -> bool {
+    match op {
+        TKind::EqualEqual => left == right,
+        TKind::BangEqual => left != right,
+        TKind::Greater => left > right,
+        TKind::GreaterEqual => left >= right,
+        TKind::Less => left < right,
+        _ => left <= right,
+    }
+}
+
+fn typ(constant: &Constant) -> Type {
+    Expr::constant(constant.clone()).typ()
+}
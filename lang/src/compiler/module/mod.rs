@@ -1,32 +1,57 @@
 mod expr_compiler;
+mod globals;
 mod passes;
 mod resolver;
 
 use crate::{
-    compiler::{ir::Module, MutRc},
+    compiler::{
+        ir::{Constant, FuncRef, Module},
+        CompileOptions, MutRc,
+    },
     error::Errors,
+    smol_str::SmolStr,
 };
 use alloc::vec::Vec;
+use hashbrown::HashMap;
 
 pub struct ModuleCompiler {
     pub(super) module: MutRc<Module>,
     pub(super) errors: Errors,
+    options: CompileOptions,
+    /// The modules imported by this one, in the order of their imports.
+    pub(super) imports: Vec<MutRc<Module>>,
+    /// Functions declared inside of blocks, in the order of `ast::Module::local_functions`.
+    local_functions: Vec<FuncRef>,
+    /// The functions computing default values of parameters,
+    /// by their index together with the index of their parameter.
+    defaults: Vec<(usize, usize)>,
+    /// The values of all globals, which uses are replaced with.
+    /// `None` for globals whose value failed to evaluate.
+    globals: HashMap<SmolStr, Option<Constant>>,
 }
 
 impl ModuleCompiler {
+    #[cfg(feature = "std")]
     pub fn consume(mut self) -> Result<MutRc<Module>, Errors> {
         self.run_all();
         if self.errors.is_empty() {
+            #[cfg(debug_assertions)]
+            crate::compiler::validate::validate(&self.module.borrow());
             Ok(self.module)
         } else {
             Err(self.errors)
         }
     }
 
-    pub fn new(module: MutRc<Module>) -> Self {
+    pub fn new(module: MutRc<Module>, options: &CompileOptions) -> Self {
         Self {
             module,
             errors: Vec::new(),
+            options: options.clone(),
+            imports: Vec::new(),
+            local_functions: Vec::new(),
+            defaults: Vec::new(),
+            globals: HashMap::new(),
         }
     }
 }
@@ -0,0 +1,124 @@
+use crate::compiler::{
+    ir::{Constant, Expr, Function, IExpr, Module, VarStore},
+    module::ModuleCompiler,
+    MutRc,
+};
+use alloc::{rc::Rc, vec::Vec};
+use core::mem;
+use smallvec::SmallVec;
+
+/// A function that can be inlined, copied out of the module
+/// to allow modifying the functions it gets inlined into.
+struct Inlinable {
+    /// All variables of the function, parameters followed by locals.
+    vars: SmallVec<[VarStore; 6]>,
+    body: Expr,
+}
+
+impl Inlinable {
+    fn new(
+        func: &Function,
+        index: usize,
+        module: &MutRc<Module>,
+        threshold: usize,
+    ) -> Option<Self> {
+        if func.external {
+            return None;
+        }
+        let body = func.body.borrow();
+        // Inlined bodies replace the call, so they need to have the same type
+        if body.typ() != func.ret_type {
+            return None;
+        }
+
+        let mut size = 0;
+        let mut recursive = false;
+        body.visit(&mut |expr| {
+            size += 1;
+            if let IExpr::Constant(Constant::Function(callee)) = &*expr.inner {
+                recursive |= callee.index == index && Rc::ptr_eq(&callee.module, module);
+            }
+        });
+        if (size > threshold && !func.inline) || recursive {
+            return None;
+        }
+
+        Some(Self {
+            vars: func
+                .params
+                .iter()
+                .chain(func.locals.iter())
+                .cloned()
+                .collect(),
+            body: body.map_variables(&|index| index),
+        })
+    }
+}
+
+impl ModuleCompiler {
+    /// Inline calls to small, non-recursive functions of this module,
+    /// as well as functions with `@inline` regardless of their size.
+    /// All variables of the inlined function become new locals
+    /// of the caller, with the arguments assigned to its parameters.
+    pub(super) fn inline_functions(&mut self) {
+        let threshold = self.options.inline_threshold;
+        if threshold == 0 {
+            return;
+        }
+
+        let inlinable = self
+            .module
+            .borrow()
+            .funcs
+            .iter()
+            .enumerate()
+            .map(|(index, func)| Inlinable::new(func, index, &self.module, threshold))
+            .collect::<Vec<_>>();
+
+        let funcs = self.module.borrow().funcs.clone();
+        for func in &funcs {
+            func.body.borrow_mut().visit_mut(&mut |expr| {
+                let callee = match &*expr.inner {
+                    IExpr::Call { callee, .. } => callee,
+                    _ => return,
+                };
+                let inlined = match &*callee.inner {
+                    IExpr::Constant(Constant::Function(func))
+                        if Rc::ptr_eq(&func.module, &self.module) =>
+                    {
+                        inlinable[func.index].as_ref()
+                    }
+                    _ => None,
+                };
+                if let Some(inlined) = inlined {
+                    *expr = inline_call(expr, inlined, func);
+                }
+            });
+        }
+    }
+}
+
+fn inline_call(call: &mut Expr, func: &Inlinable, caller: &Function) -> Expr {
+    let args = match &mut *call.inner {
+        IExpr::Call { args, .. } => mem::take(args),
+        _ => unreachable!(),
+    };
+
+    let offset = caller.params.len() + caller.locals.len();
+    let vars = func
+        .vars
+        .iter()
+        .map(|var| {
+            caller
+                .add_local(var.name.clone(), var.ty.clone(), var.mutable)
+                .clone()
+        })
+        .collect::<SmallVec<[VarStore; 6]>>();
+
+    let mut block = Vec::with_capacity(args.len() + 1);
+    for (arg, param) in args.into_iter().zip(&vars) {
+        block.push(Expr::assign_local(param, arg));
+    }
+    block.push(func.body.map_variables(&|index| index + offset));
+    Expr::block(block)
+}
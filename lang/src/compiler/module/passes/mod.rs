@@ -1,138 +1,554 @@
 use crate::{
     compiler::{
-        ir::{Class, ClassContent, Expr, FuncRef, Function, Type, VarStore},
+        ir::{Class, ClassContent, ClassRef, Expr, FuncRef, Function, Module, Type, VarStore},
         module::{expr_compiler::ExprCompiler, ModuleCompiler},
+        Edition, MutRc,
     },
-    error::Res,
+    error::{
+        Error,
+        ErrorKind::{E201, E202, E206, E207, E210, E508},
+        Res,
+    },
+    lexer::Token,
     parser::ast,
+    smol_str::SmolStr,
+};
+use alloc::{boxed::Box, format, rc::Rc, string::ToString, vec, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    iter, mem, ptr,
 };
-use alloc::vec::Vec;
-use core::{cell::RefCell, mem};
 use indexmap::IndexMap;
 use smallvec::SmallVec;
 
+mod inline;
+
 impl ModuleCompiler {
+    /// Run all stages on this module alone, which cannot import any others.
+    #[cfg(feature = "std")]
     pub fn run_all(&mut self) {
-        self.stage_1();
+        self.declare();
+        self.resolve_imports(&[]);
+        self.resolve();
+        self.generate();
+        self.optimize();
     }
 
-    pub fn stage_1(&mut self) {
-        self.declare_classes().unwrap();
-        self.declare_functions().unwrap();
-        self.generate_classes().unwrap();
-        self.generate_functions().unwrap();
+    /// Declare the classes of this module, which allows other modules
+    /// to use them before any signatures are resolved.
+    pub fn declare(&mut self) {
+        self.declare_classes();
+    }
+
+    /// Find the modules imported by this module among all modules of the program.
+    pub fn resolve_imports(&mut self, modules: &[MutRc<Module>]) {
+        let imports = mem::replace(&mut self.module.borrow_mut().ast.imports, Vec::new());
+        for import in imports {
+            let found = modules.iter().find(|module| {
+                module
+                    .borrow()
+                    .ast
+                    .path
+                    .iter()
+                    .eq(import.path.iter().map(|segment| &segment.lex))
+            });
+            match found {
+                Some(module) => {
+                    let path = module.borrow().ast.path.clone();
+                    self.module.borrow_mut().imports.push(path);
+                    self.imports.push(module.clone());
+                }
+                None => {
+                    let path = import.path.iter().map(|segment| segment.lex.as_str());
+                    let path = SmolStr::join(path, "::");
+                    self.errors
+                        .push(Error::new(import.path[0].start, E207(path)));
+                }
+            }
+        }
     }
 
-    fn declare_classes(&mut self) -> Res<()> {
+    /// Resolve the signatures of all functions and the contents of all classes.
+    /// Needs the classes of all imported modules to be declared.
+    pub fn resolve(&mut self) {
+        self.declare_functions();
+        self.declare_globals();
+        self.generate_classes();
+        self.declare_local_functions();
+    }
+
+    /// Generate the bodies of all functions. Needs all imported
+    /// modules to be resolved, since bodies can use their functions.
+    pub fn generate(&mut self) {
+        self.generate_functions();
+    }
+
+    /// Optimizations on the finished IR, which only run on modules without errors.
+    pub fn optimize(&mut self) {
+        if self.errors.is_empty() {
+            self.inline_functions();
+        }
+    }
+
+    fn declare_classes(&mut self) {
         let ast_cls = mem::replace(&mut self.module.borrow_mut().ast.classes, Vec::new());
         for cls in ast_cls {
-            self.module
+            let reserved = self
+                .module
                 .borrow_mut()
-                .try_reserve_name(&cls.name.lex, cls.name.start)?;
+                .try_reserve_name(&cls.name.lex, cls.name.start);
+            if let Err(err) = reserved {
+                self.errors.push(err);
+                continue;
+            }
 
-            self.module.borrow_mut().classes.push(Class {
+            self.module.borrow_mut().classes.push(Rc::new(Class {
                 name: cls.name.lex.clone(),
-                content: RefCell::new(IndexMap::with_capacity(
-                    cls.methods.len() + cls.members.len() + cls.functions.len() + 2,
-                )),
+                content: RefCell::new(IndexMap::new()),
+                constructor: RefCell::new(None),
                 ast: RefCell::new(cls),
-            })
+            }))
         }
-        Ok(())
     }
 
-    fn declare_functions(&mut self) -> Res<()> {
+    fn declare_functions(&mut self) {
         let ast_fns = mem::replace(&mut self.module.borrow_mut().ast.functions, Vec::new());
         for func in ast_fns {
+            if let Err(err) = self.declare_module_function(func) {
+                self.errors.push(err);
+            }
+        }
+    }
+
+    fn declare_module_function(&mut self, func: ast::Function) -> Res<()> {
+        let name = func.name.clone();
+        let overloads = self
+            .module
+            .borrow()
+            .funcs
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.name == name.lex)
+            .map(|(index, _)| index)
+            .collect::<SmallVec<[usize; 2]>>();
+        if overloads.is_empty() {
             self.module
                 .borrow_mut()
-                .try_reserve_name(&func.name.lex, func.name.start)?;
+                .try_reserve_name(&name.lex, name.start)?;
+        }
+
+        let func = self.declare_function(func);
+        if !overloads.is_empty() {
+            self.add_overload(&func, &overloads, &name)?;
+        }
+        if name.lex == "init" && self.options.edition >= Edition::V2 {
+            self.declare_init(func, &name)?;
+        }
+        Ok(())
+    }
+
+    /// Make the given function the initializer of this module,
+    /// which is called without arguments before the program is first called.
+    fn declare_init(&mut self, func: FuncRef, name: &Token) -> Res<()> {
+        let init = func.resolve();
+        if init.external || !init.params.is_empty() || init.ret_type != Type::Void {
+            return Err(Error::new(name.start, E210));
+        }
+        self.module.borrow_mut().init = Some(func.index);
+        Ok(())
+    }
 
-            self.declare_function(func)?;
+    /// Add a function to the overload set of all others with the same name.
+    /// Overloads must differ in their parameter types, and since
+    /// external functions are linked by name, they cannot be overloaded.
+    fn add_overload(&mut self, func: &FuncRef, others: &[usize], name: &Token) -> Res<()> {
+        let module = self.module.borrow();
+        let params = module.funcs[func.index].signature().params;
+        for &index in others {
+            let other = &module.funcs[index];
+            if other.external || module.funcs[func.index].external {
+                return Err(Error::new(name.start, E201(name.lex.clone())));
+            }
+            if other.signature().params == params {
+                return Err(Error::new(name.start, E202(name.lex.clone())));
+            }
+        }
+
+        for &index in others.iter().chain(iter::once(&func.index)) {
+            module.funcs[index].overloaded.set(true);
         }
         Ok(())
     }
 
-    fn declare_function(&mut self, func: ast::Function) -> Res<FuncRef> {
+    fn declare_function(&mut self, func: ast::Function) -> FuncRef {
         let params = func
             .params
             .iter()
             .enumerate()
-            .map(|(index, param)| {
-                Ok(VarStore {
-                    ty: self.resolve_ty(&param.ty)?,
-                    name: param.name.clone(),
-                    index,
-                    mutable: false,
-                })
+            .map(|(index, param)| VarStore {
+                ty: self.resolve_ty_or_poison(&param.ty),
+                name: param.name.lex.clone(),
+                index,
+                mutable: false,
             })
-            .collect::<Res<SmallVec<_>>>()?;
+            .collect::<SmallVec<_>>();
         let ret_type = func
             .ret_type
             .as_ref()
-            .map(|t| self.resolve_ty(&t))
-            .unwrap_or(Ok(Type::Void))?;
+            .map(|t| self.resolve_ty_or_poison(&t))
+            .unwrap_or(Type::Void);
+        self.push_function(func, params, ret_type)
+    }
+
+    /// Add a function with the given already resolved signature to the module.
+    fn push_function(
+        &mut self,
+        mut func: ast::Function,
+        params: SmallVec<[VarStore; 4]>,
+        ret_type: Type,
+    ) -> FuncRef {
+        let name = func.name.clone();
+        let defaults = func
+            .params
+            .iter_mut()
+            .zip(&params)
+            .map(|(param, store)| {
+                let default = param.default.take()?;
+                Some(self.declare_default(&name, param, store, default))
+            })
+            .collect();
 
-        self.module.borrow_mut().funcs.push(Function {
+        let mut link_name = None;
+        let mut inline = false;
+        for attribute in &func.attributes {
+            match attribute.name.lex.as_str() {
+                "export" | "extern" => link_name = Some(attribute.args[0].value.lex.clone()),
+                "inline" => inline = true,
+                _ => (),
+            }
+        }
+
+        self.module.borrow_mut().funcs.push(Rc::new(Function {
             name: func.name.lex.clone(),
             body: RefCell::new(Expr::poison()),
             params,
             locals: SmallVec::new(),
+            captures: SmallVec::new(),
+            defaults,
             ret_type,
-            ir: RefCell::new(None),
+            external: func.body.is_none(),
+            link_name,
+            inline,
             ast: func,
-        });
+            overloaded: Cell::new(false),
+            live: Cell::new(true),
+        }));
+
+        FuncRef::new_last(&self.module)
+    }
+
+    /// Add a function computing the default value of the given parameter.
+    /// Its body is compiled like any other, in the scope of this module
+    /// instead of that of the caller, and checked against the type of the parameter.
+    fn declare_default(
+        &mut self,
+        func: &Token,
+        param: &ast::Parameter,
+        store: &VarStore,
+        default: ast::Expr,
+    ) -> FuncRef {
+        let base = SmolStr::from_fmt(format_args!("{}({})", func.lex, param.name.lex));
+        let mut name = base.clone();
+        let mut count = 1;
+        while self.module.borrow().funcs.iter().any(|f| f.name == name) {
+            count += 1;
+            name = SmolStr::from_fmt(format_args!("{}#{}", base, count));
+        }
 
-        Ok(FuncRef::new_last(&self.module))
+        let ast = ast::Function {
+            name: Token {
+                lex: name,
+                ..param.name.clone()
+            },
+            params: Vec::new(),
+            ret_type: None,
+            body: Some(default),
+            attributes: Vec::new(),
+        };
+        let default = self.push_function(ast, SmallVec::new(), store.ty.clone());
+        self.defaults.push((default.index, store.index));
+        default
     }
 
-    fn generate_classes(&mut self) -> Res<()> {
+    fn generate_classes(&mut self) {
         let module = self.module.clone();
-        for cls in module.borrow().classes.iter() {
-            let mut ast = cls.ast.borrow_mut();
-            for (index, member) in ast.members.iter().enumerate() {
+        let class_count = module.borrow().classes.len();
+        let mut member_starts = Vec::with_capacity(class_count);
+        for cls in 0..class_count {
+            // The module cannot stay borrowed, since declaring functions modifies it
+            let (name, members, methods, functions, init) = {
+                let module = module.borrow();
+                let mut ast = module.classes[cls].ast.borrow_mut();
+                (
+                    ast.name.clone(),
+                    mem::replace(&mut ast.members, Vec::new()),
+                    mem::replace(&mut ast.methods, Vec::new()),
+                    mem::replace(&mut ast.functions, Vec::new()),
+                    ast.init.take(),
+                )
+            };
+
+            member_starts.push(members.iter().map(|m| m.name.start).collect());
+            // Statics were added while declaring globals, but members need to come first
+            let statics = mem::replace(
+                &mut *module.borrow().classes[cls].content.borrow_mut(),
+                IndexMap::new(),
+            );
+            let mut content = IndexMap::with_capacity(
+                members.len() + methods.len() + functions.len() + statics.len() + 2,
+            );
+            for (index, member) in members.iter().enumerate() {
                 let store = VarStore {
-                    ty: self.resolve_ty(&member.ty)?,
+                    ty: self.resolve_ty_or_poison(&member.ty),
                     name: member.name.lex.clone(),
                     index,
                     mutable: member.mutable,
                 };
-                cls.content
-                    .borrow_mut()
-                    .insert(member.name.lex.clone(), ClassContent::Member(store.clone()));
+                content.insert(member.name.lex.clone(), ClassContent::Member(store));
+            }
+            let constructor = self.declare_constructor(&name, &content, members, init);
+            *module.borrow().classes[cls].constructor.borrow_mut() = Some(constructor);
+            for mut method in methods {
+                // Methods take the value they are called on as an implicit first parameter,
+                // and are named after their class to keep them apart from other functions
+                let method_name = method.name.lex.clone();
+                method.name.lex = SmolStr::from_fmt(format_args!("{}::{}", name.lex, method_name));
+                method.params.insert(
+                    0,
+                    ast::Parameter {
+                        name: Token {
+                            lex: SmolStr::new_inline("this"),
+                            ..name.clone()
+                        },
+                        ty: ast::Type::Ident(name.clone()),
+                        default: None,
+                    },
+                );
+                let fun = self.declare_function(method);
+                content.insert(method_name, ClassContent::Method(fun));
             }
+            for function in functions {
+                let name = function.name.lex.clone();
+                let fun = self.declare_function(function);
+                content.insert(name, ClassContent::Function(fun));
+            }
+            content.extend(statics);
+
+            *module.borrow().classes[cls].content.borrow_mut() = content;
+        }
+        self.check_recursive_classes(&member_starts);
+    }
+
+    /// Declare the constructor of a class, which takes the values of all members,
+    /// falling back to their defaults, and runs the `init` block of the class.
+    fn declare_constructor(
+        &mut self,
+        name: &Token,
+        content: &IndexMap<SmolStr, ClassContent>,
+        members: Vec<ast::Member>,
+        init: Option<ast::Expr>,
+    ) -> FuncRef {
+        let params = content
+            .values()
+            .filter_map(|content| match content {
+                ClassContent::Member(member) => Some(VarStore {
+                    mutable: false,
+                    ..member.clone()
+                }),
+                _ => None,
+            })
+            .collect();
+        let body = init.unwrap_or_else(|| ast::Expr {
+            ty: Box::new(ast::EExpr::Block(Vec::new())),
+            start: name.start,
+        });
+        let ast = ast::Function {
+            name: name.clone(),
+            params: members
+                .into_iter()
+                .map(|member| ast::Parameter {
+                    name: member.name,
+                    ty: member.ty,
+                    default: member.default,
+                })
+                .collect(),
+            ret_type: Some(ast::Type::Ident(name.clone())),
+            body: Some(body),
+            attributes: Vec::new(),
+        };
+        let ret_type = self.find_class(&name.lex).map_or(Type::Poison, Type::Class);
+        self.push_function(ast, params, ret_type)
+    }
+
+    /// Reject classes containing themselves, either directly or through
+    /// members of other classes. Class values are stored inline, which would
+    /// make them infinitely large. The member closing the cycle is made poison.
+    fn check_recursive_classes(&mut self, member_starts: &[Vec<usize>]) {
+        let module = self.module.clone();
+        let module = module.borrow();
+        let mut visits = vec![Visit::New; module.classes.len()];
+        let mut cycles = Vec::new();
+        for cls in 0..module.classes.len() {
+            visit_class(&module, cls, &mut visits, &mut Vec::new(), &mut cycles);
+        }
+
+        for (path, name) in cycles {
+            let (cls, member) = *path.last().unwrap();
+            let cycle = path
+                .iter()
+                .map(|(cls, member)| {
+                    let class = &module.classes[*cls];
+                    format!("{}.{}", class.name, class.members()[*member].name)
+                })
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            self.errors
+                .push(Error::new(member_starts[cls][member], E206 { name, cycle }));
 
-            for method in ast.methods.drain(..) {
-                let name = method.name.lex.clone();
-                let fun = self.declare_function(method)?;
-                cls.content
-                    .borrow_mut()
-                    .insert(name, ClassContent::Method(fun));
+            let mut content = module.classes[cls].content.borrow_mut();
+            for item in content.values_mut() {
+                match item {
+                    ClassContent::Member(store) if store.index == member => store.ty = Type::Poison,
+                    _ => (),
+                }
             }
+        }
+    }
 
-            for function in ast.functions.drain(..) {
-                let name = function.name.lex.clone();
-                let fun = self.declare_function(function)?;
-                cls.content
-                    .borrow_mut()
-                    .insert(name, ClassContent::Function(fun));
+    /// Declare the functions inside of blocks, which are only visible inside
+    /// their block. Their names start with the functions they are nested in,
+    /// which keeps them apart from functions with the same name elsewhere.
+    fn declare_local_functions(&mut self) {
+        let ast_fns = mem::replace(
+            &mut self.module.borrow_mut().ast.local_functions,
+            Vec::new(),
+        );
+        for mut local in ast_fns {
+            let base = SmolStr::from_fmt(format_args!("{}::{}", local.parent, local.func.name.lex));
+            let mut name = base.clone();
+            let mut count = 1;
+            while self.module.borrow().funcs.iter().any(|f| f.name == name) {
+                count += 1;
+                name = SmolStr::from_fmt(format_args!("{}#{}", base, count));
             }
+
+            local.func.name.lex = name;
+            let func = self.declare_function(local.func);
+            self.local_functions.push(func);
         }
-        Ok(())
     }
 
-    fn generate_functions(&self) -> Res<()> {
-        for func in self
-            .module
-            .borrow()
-            .funcs
-            .iter()
-            .filter(|f| f.ast.body.is_some())
-        {
+    fn generate_functions(&mut self) {
+        let module = self.module.clone();
+        let mut errors = Vec::new();
+        // Only the list is copied, so that compiling bodies can declare more functions
+        let funcs = module.borrow().funcs.clone();
+        for (index, func) in funcs.iter().enumerate().filter(|(index, f)| {
+            // Local functions are compiled along with their block
+            !f.external
+                && !self
+                    .local_functions
+                    .iter()
+                    .any(|local| local.index == *index)
+        }) {
             let mut compiler = ExprCompiler::new(self, func);
-            let body = compiler.expr(&func.ast.body.as_ref().unwrap());
+            let ast = func.ast.body.as_ref().unwrap();
+            let body = match self.constructed_class(index) {
+                Some(class) => compiler.constructor(class, ast),
+                None => compiler.expr(ast),
+            };
+            errors.append(&mut compiler.errors);
+
+            let param = self.defaults.iter().find(|(default, _)| *default == index);
+            if let Some(&(_, pos)) = param {
+                let found = body.typ();
+                if found != func.ret_type && !found.is_poisoned() && !func.ret_type.is_poisoned() {
+                    let expected = func.ret_type.to_string();
+                    let found = found.to_string();
+                    errors.push(Error::new(
+                        ast.start,
+                        E508 {
+                            expected,
+                            found,
+                            pos,
+                        },
+                    ));
+                }
+            }
             *func.body.borrow_mut() = body;
         }
-        Ok(())
+        if !self.options.warnings_as_errors {
+            let (warnings, rest) = errors
+                .into_iter()
+                .partition(|err: &Error| err.kind().is_warning());
+            errors = rest;
+            module.borrow_mut().warnings = warnings;
+        }
+        self.errors.append(&mut errors);
+    }
+
+    /// Returns the class the function with the given index is the constructor of.
+    fn constructed_class(&self, func: usize) -> Option<ClassRef> {
+        let module = self.module.borrow();
+        let index = module.classes.iter().position(|class| {
+            class
+                .constructor
+                .borrow()
+                .as_ref()
+                .map_or(false, |constructor| constructor.index == func)
+        })?;
+        Some(ClassRef {
+            module: self.module.clone(),
+            index,
+        })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Visit {
+    New,
+    Active,
+    Done,
+}
+
+/// Search the classes contained in the given class for cycles, with `path` being
+/// the members leading to it. Found cycles are added to `cycles` as the members
+/// forming them, together with the name of the class they start at.
+fn visit_class(
+    module: &Module,
+    cls: usize,
+    visits: &mut [Visit],
+    path: &mut Vec<(usize, usize)>,
+    cycles: &mut Vec<(Vec<(usize, usize)>, SmolStr)>,
+) {
+    visits[cls] = Visit::Active;
+    for member in module.classes[cls].members() {
+        // Classes of imported modules are checked by their own module
+        let contained = match member.ty {
+            Type::Class(class) if ptr::eq(class.module.as_ptr(), module) => class.index,
+            _ => continue,
+        };
+        path.push((cls, member.index));
+        match visits[contained] {
+            Visit::New => visit_class(module, contained, visits, path, cycles),
+            Visit::Active => {
+                let start = path.iter().position(|(c, _)| *c == contained).unwrap();
+                let name = module.classes[contained].name.clone();
+                cycles.push((path[start..].to_vec(), name));
+            }
+            Visit::Done => (),
+        }
+        path.pop();
     }
+    visits[cls] = Visit::Done;
 }
@@ -1,36 +1,71 @@
 use crate::{
     compiler::{
-        ir::{ClassRef, Type},
+        ir::{ClassRef, FnSig, Type},
         module::ModuleCompiler,
     },
     error::{Error, ErrorKind::E200, Res},
     parser::ast,
     smol_str::SmolStr,
 };
+use alloc::rc::Rc;
+use core::iter;
 
 impl ModuleCompiler {
     pub fn resolve_ty(&self, ty: &ast::Type) -> Res<Type> {
-        self.resolve_ty_name(&ty.name.lex, ty.name.start)
+        match ty {
+            ast::Type::Ident(name) => self.resolve_ty_name(&name.lex, name.start),
+
+            ast::Type::Function { params, ret_type } => {
+                let params = params
+                    .iter()
+                    .map(|param| self.resolve_ty(param))
+                    .collect::<Res<_>>()?;
+                let ret_type = ret_type
+                    .as_ref()
+                    .map(|t| self.resolve_ty(t))
+                    .unwrap_or(Ok(Type::Void))?;
+                Ok(Type::Function(Rc::new(FnSig { params, ret_type })))
+            }
+        }
+    }
+
+    /// Resolve a type, recording an error and returning poison if it does not exist.
+    pub fn resolve_ty_or_poison(&mut self, ty: &ast::Type) -> Type {
+        self.resolve_ty(ty).unwrap_or_else(|err| {
+            self.errors.push(err);
+            Type::Poison
+        })
     }
 
     fn resolve_ty_name(&self, name: &SmolStr, position: usize) -> Res<Type> {
         match &name[..] {
             "bool" => Ok(Type::Bool),
             "i64" => Ok(Type::I64),
+            "f32" => Ok(Type::F32),
             "f64" => Ok(Type::F64),
+            "str" => Ok(Type::String),
             _ => self
-                .module
-                .borrow_mut()
-                .classes
-                .iter()
-                .position(|cls| cls.name == *name)
-                .map(|index| {
-                    Type::Class(ClassRef {
-                        module: self.module.clone(),
-                        index,
-                    })
-                })
+                .find_class(name)
+                .map(Type::Class)
                 .ok_or_else(|| Error::new(position, E200(name.clone()))),
         }
     }
+
+    /// Find the class with the given name, where classes of this module
+    /// shadow those of imported modules.
+    pub fn find_class(&self, name: &str) -> Option<ClassRef> {
+        iter::once(&self.module)
+            .chain(&self.imports)
+            .find_map(|module| {
+                let index = module
+                    .borrow()
+                    .classes
+                    .iter()
+                    .position(|cls| cls.name == name)?;
+                Some(ClassRef {
+                    module: module.clone(),
+                    index,
+                })
+            })
+    }
 }
@@ -0,0 +1,140 @@
+use crate::compiler::ir::{Expr, Function, IExpr, Module, Type};
+use alloc::{format, string::String};
+
+/// Check the IR of a module for internal consistency, panicking on the first problem.
+/// Only runs in debug builds between compilation and JIT: broken IR is always a compiler
+/// bug, and this produces much better reports than the panics cranelift would give.
+/// Expects that compilation did not report any errors.
+pub fn validate(module: &Module) {
+    for func in module.funcs.iter().filter(|f| !f.external) {
+        if let Err(msg) = check_vars(func) {
+            panic!("Invalid IR in function '{}': {}", func.name, msg);
+        }
+        func.body.borrow().visit(&mut |expr| {
+            if let Err(msg) = check_expr(func, expr) {
+                panic!("Invalid IR in function '{}': {}", func.name, msg);
+            }
+        });
+    }
+}
+
+/// The JIT assumes parameters and locals share a single, contiguous index space.
+fn check_vars(func: &Function) -> Result<(), String> {
+    let vars = func.params.iter().chain(func.locals.iter());
+    for (i, var) in vars.enumerate() {
+        if var.index != i {
+            return Err(format!(
+                "variable '{}' has index {} but is at position {}",
+                var.name, var.index, i
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_expr(func: &Function, expr: &Expr) -> Result<(), String> {
+    match &*expr.inner {
+        IExpr::Variable { index, typ } => {
+            let var = func
+                .params
+                .iter()
+                .chain(func.locals.iter())
+                .nth(*index)
+                .ok_or_else(|| format!("variable index {} out of bounds", index))?;
+            if var.ty != *typ {
+                return Err(format!(
+                    "variable '{}' used as {} but is {}",
+                    var.name, typ, var.ty
+                ));
+            }
+        }
+
+        IExpr::If {
+            then,
+            els,
+            phi: true,
+            ..
+        } if then.typ() != els.typ() => {
+            return Err(format!(
+                "phi on branches of different types ({} and {})",
+                then.typ(),
+                els.typ()
+            ))
+        }
+
+        IExpr::Call { callee, args } => match callee.typ() {
+            Type::Function(sig) if sig.params.len() != args.len() => {
+                return Err(format!(
+                    "call with {} arguments to function taking {}",
+                    args.len(),
+                    sig.params.len()
+                ))
+            }
+            Type::Function(_) => (),
+            ty => return Err(format!("call to value of type {}", ty)),
+        },
+
+        IExpr::Convert { value, to } if !value.typ().convertible_to(to) => {
+            return Err(format!("conversion from {} to {}", value.typ(), to))
+        }
+
+        IExpr::Closure { func, captures } => {
+            let func = func.resolve();
+            let types = captures.iter().map(|c| c.typ());
+            if !types.eq(func.captures.iter().map(|c| c.ty.clone())) {
+                return Err(format!("closure of '{}' with wrong captures", func.name));
+            }
+        }
+
+        IExpr::Construct { class, members } => {
+            let class = class.resolve();
+            let types = members.iter().map(|m| m.typ());
+            if !types.eq(class.members().into_iter().map(|m| m.ty)) {
+                return Err(format!(
+                    "construction of '{}' with wrong members",
+                    class.name
+                ));
+            }
+        }
+
+        IExpr::Member { object, index, typ } => {
+            let member = match object.typ() {
+                Type::Class(class) => class.resolve().members().into_iter().nth(*index),
+                ty => return Err(format!("member access on value of type {}", ty)),
+            };
+            if member.map_or(true, |m| m.ty != *typ) {
+                return Err(format!("member {} read as {} does not exist", index, typ));
+            }
+        }
+
+        IExpr::Panic { message } if message.typ() != Type::String => {
+            return Err(format!("panic with message of type {}", message.typ()))
+        }
+
+        IExpr::Env { name, .. } if name.typ() != Type::String => {
+            return Err(format!(
+                "environment binding with name of type {}",
+                name.typ()
+            ))
+        }
+
+        IExpr::Env { typ, .. } if !Type::ENV.contains(typ) => {
+            return Err(format!("environment binding read as {}", typ))
+        }
+
+        IExpr::Intrinsic { intrinsic, args } => {
+            let ty = args[0].typ();
+            if args.len() != intrinsic.arity() || args.iter().any(|a| a.typ() != ty) {
+                return Err(format!("{} with wrong arguments", intrinsic.name()));
+            }
+            if !intrinsic.allows(&ty) {
+                return Err(format!("{} applied to {}", intrinsic.name(), ty));
+            }
+        }
+
+        IExpr::Poison => return Err(String::from("poison value without any reported errors")),
+
+        _ => (),
+    }
+    Ok(())
+}
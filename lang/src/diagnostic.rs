@@ -0,0 +1,193 @@
+//! Errors located in the source files they were found in, for tools like editors
+//! or CI that consume them as JSON instead of parsing the messages meant for users.
+//! The JSON is written by hand, which keeps it usable without `std`.
+
+use crate::{
+    error::{Errors, ProgramError},
+    filesystem::File,
+    lexer::Lexer,
+    vm::line_column,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Write;
+
+/// A problem found in a source file of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The path of the file, like `std/collections.yacari`.
+    pub file: String,
+    /// The source offsets of the token the problem was found at.
+    pub start: usize,
+    pub end: usize,
+    /// The line and column of `start`, both starting at 1.
+    pub line: usize,
+    pub column: usize,
+    /// The code of the error, like `E503`.
+    /// `None` for files that could not be read at all.
+    pub code: Option<&'static str>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// This diagnostic as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut json = String::with_capacity(96 + self.message.len());
+        json.push_str("{\"file\":");
+        json_str(&mut json, &self.file);
+        write!(
+            json,
+            ",\"start\":{},\"end\":{},\"line\":{},\"column\":{},\"code\":",
+            self.start, self.end, self.line, self.column
+        )
+        .unwrap();
+        match self.code {
+            Some(code) => json_str(&mut json, code),
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"message\":");
+        json_str(&mut json, &self.message);
+        json.push('}');
+        json
+    }
+
+    /// This diagnostic in a form meant for users, showing the line it
+    /// was found at if it is in one of `files`:
+    ///
+    /// ```text
+    /// error[E503]: Unknown variable 'count'.
+    ///  --> main.yacari:2:5
+    ///   |
+    /// 2 |     count + 1
+    ///   |     ^^^^^
+    /// ```
+    pub fn render(&self, files: &[File]) -> String {
+        let mut out = match self.code {
+            Some(code) => format!("error[{}]: {}\n", code, self.message),
+            None => format!("error: {}\n", self.message),
+        };
+        let source = files
+            .iter()
+            .find(|file| file_path(file) == self.file)
+            .map(|file| &*file.contents);
+        let line = match source.and_then(|source| source.lines().nth(self.line - 1)) {
+            Some(line) => line,
+            None => {
+                writeln!(out, " --> {}", self.file).unwrap();
+                return out;
+            }
+        };
+
+        let number = self.line.to_string();
+        let margin = " ".repeat(number.len());
+        writeln!(
+            out,
+            "{}--> {}:{}:{}",
+            margin, self.file, self.line, self.column
+        )
+        .unwrap();
+        writeln!(out, "{} |", margin).unwrap();
+        writeln!(out, "{} | {}", number, line).unwrap();
+        // Errors at the end of the file can be past the end of the line
+        let column = (self.column - 1).min(line.len());
+        // Only underline up to the end of the line for tokens spanning multiple
+        let width = line[column..]
+            .chars()
+            .take(self.end - self.start)
+            .count()
+            .max(1);
+        let indent = " ".repeat(line[..column].chars().count());
+        writeln!(out, "{} | {}{}", margin, indent, "^".repeat(width)).unwrap();
+        out
+    }
+}
+
+/// The given diagnostics as a JSON array.
+pub fn diagnostics_to_json(diagnostics: &[Diagnostic]) -> String {
+    let objects = diagnostics
+        .iter()
+        .map(Diagnostic::to_json)
+        .collect::<Vec<_>>();
+    format!("[{}]", objects.join(","))
+}
+
+impl ProgramError {
+    /// The problems that stopped the program from compiling, located in
+    /// `files`, the files the program was compiled from.
+    /// Errors during execution are not found in any file and have no diagnostics.
+    pub fn diagnostics(&self, files: &[File]) -> Vec<Diagnostic> {
+        match self {
+            ProgramError::Read(errors) => errors
+                .iter()
+                .map(|err| Diagnostic {
+                    file: err.path.clone(),
+                    start: 0,
+                    end: 0,
+                    line: 1,
+                    column: 1,
+                    code: None,
+                    message: err.to_string(),
+                })
+                .collect(),
+            ProgramError::Compile(errors) => compile_diagnostics(files, errors),
+            ProgramError::Target(_) | ProgramError::Link(_) | ProgramError::Exec(_) => Vec::new(),
+        }
+    }
+}
+
+/// Locate compile errors, which contain the errors of each file in order.
+fn compile_diagnostics(files: &[File], errors: &[Errors]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (file, errors) in files.iter().zip(errors) {
+        let path = file_path(file);
+        for error in errors {
+            let start = error.start();
+            let (line, column) = line_column(&file.contents, start);
+            diagnostics.push(Diagnostic {
+                file: path.clone(),
+                start,
+                end: token_end(&file.contents, start),
+                line,
+                column,
+                code: Some(error.kind().code()),
+                message: error.kind().to_string(),
+            })
+        }
+    }
+    diagnostics
+}
+
+/// The path of the file as shown in diagnostics, like `std/collections.yacari`.
+pub(crate) fn file_path(file: &File) -> String {
+    let path = file.path.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    format!("{}.yacari", path.join("/"))
+}
+
+/// Returns the end of the token starting at the given offset,
+/// or the offset itself if there is none.
+fn token_end(source: &str, start: usize) -> usize {
+    let token = source.get(start..).and_then(|rest| Lexer::new(rest).next());
+    match token {
+        Some(token) if token.start == 0 => start + token.lex.len(),
+        _ => start,
+    }
+}
+
+fn json_str(json: &mut String, string: &str) {
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
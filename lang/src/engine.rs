@@ -0,0 +1,209 @@
+//! A configurable way of compiling and running programs, for hosts that need
+//! more control than the `execute_*` functions give them.
+
+#[cfg(feature = "std")]
+use crate::AbiValue;
+use crate::{
+    compile_files_with,
+    filesystem::{File, Filesystem},
+    read_files, Binding, CodeMemory, CompileOptions, CompiledModule, Edition, Entry, Program,
+    ProgramError, SmolStr, StateError, SymbolTable, Target,
+};
+use alloc::vec::Vec;
+
+/// Compiles and runs the program at a set of paths.
+/// Every setting has a default, so hosts only need to set what they care about:
+///
+/// ```ignore
+/// let code = Engine::new(fs)
+///     .path("programs/game")
+///     .symbols(&symbols)
+///     .library(stdlib)
+///     .entry(Entry::new("start"))
+///     .execute()?;
+/// ```
+pub struct Engine<'e, FS: Filesystem> {
+    fs: FS,
+    paths: Vec<&'e str>,
+    symbols: SymbolTable<'e>,
+    options: CompileOptions,
+    libraries: Vec<CompiledModule>,
+    bindings: Vec<(SmolStr, Binding)>,
+    entry: Entry<'e>,
+}
+
+impl<'e, FS: Filesystem> Engine<'e, FS> {
+    /// An engine reading the program from `fs`, without any paths, symbols
+    /// or libraries, compiled with the default options and starting at `main`.
+    pub fn new(fs: FS) -> Self {
+        Self {
+            fs,
+            paths: Vec::new(),
+            symbols: &[],
+            options: CompileOptions::default(),
+            libraries: Vec::new(),
+            bindings: Vec::new(),
+            entry: Entry::MAIN,
+        }
+    }
+
+    /// Add a file or directory to the program.
+    pub fn path(mut self, path: &'e str) -> Self {
+        self.paths.push(path);
+        self
+    }
+
+    /// Add files or directories to the program.
+    pub fn paths(mut self, paths: &[&'e str]) -> Self {
+        self.paths.extend_from_slice(paths);
+        self
+    }
+
+    /// Set the host functions available to `extern fun`s.
+    pub fn symbols(mut self, symbols: SymbolTable<'e>) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Set the options the program is compiled with.
+    /// The entry is always kept by dead code elimination, even
+    /// if it is not one of the given `entry_points`.
+    pub fn options(mut self, options: CompileOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Set the maximum size of functions to inline; 0 disables inlining.
+    pub fn inline_threshold(mut self, threshold: usize) -> Self {
+        self.options.inline_threshold = threshold;
+        self
+    }
+
+    /// Set whether warnings stop the program from compiling, see `CompileOptions`.
+    pub fn warnings_as_errors(mut self, enable: bool) -> Self {
+        self.options.warnings_as_errors = enable;
+        self
+    }
+
+    /// Set a flag for conditional compilation, see `CompileOptions::flags`.
+    pub fn flag(mut self, flag: &str) -> Self {
+        self.options.flags.push(SmolStr::new(flag));
+        self
+    }
+
+    /// Set the edition of the language the program is written in.
+    pub fn edition(mut self, edition: Edition) -> Self {
+        self.options.edition = edition;
+        self
+    }
+
+    /// Set the machine to generate code for, see `CompileOptions::target`.
+    pub fn target(mut self, target: Target) -> Self {
+        self.options.target = target;
+        self
+    }
+
+    /// Link already compiled modules into the program, like a standard library.
+    /// Without any, the program only consists of the files at its paths.
+    pub fn library(mut self, modules: impl IntoIterator<Item = CompiledModule>) -> Self {
+        self.libraries.extend(modules);
+        self
+    }
+
+    /// Bind a value in the environment of the program, see `Program::bind`.
+    pub fn bind(mut self, name: &'e str, value: impl Into<Binding>) -> Self {
+        self.bindings.push((SmolStr::new(name), value.into()));
+        self
+    }
+
+    /// Pass arguments to the program, like those given on a command line. They are
+    /// bound in its environment: `argc` is their amount, `arg0`, `arg1` and so on
+    /// are the arguments, which `get_env_int` and the other typed variants parse.
+    pub fn args<S: AsRef<str>>(mut self, args: &[S]) -> Self {
+        self.bindings
+            .push((SmolStr::new_inline("argc"), Binding::Int(args.len() as i64)));
+        for (index, arg) in args.iter().enumerate() {
+            let name = SmolStr::from_fmt(format_args!("arg{}", index));
+            self.bindings.push((name, arg.as_ref().into()));
+        }
+        self
+    }
+
+    /// Set the function to start executing the program at, `main` by default.
+    pub fn entry(mut self, entry: Entry<'e>) -> Self {
+        self.entry = entry;
+        self
+    }
+
+    /// Read all files at the paths of the program.
+    pub fn read(&self) -> Result<Vec<File<'_>>, ProgramError> {
+        Ok(read_files(&self.fs, &self.paths)?)
+    }
+
+    /// Read and compile the program, returning its modules
+    /// followed by the modules of all libraries.
+    pub fn compile(&self) -> Result<Vec<CompiledModule>, ProgramError> {
+        self.compile_files(&self.read()?)
+    }
+
+    /// Compile the program from files returned by `read`, for hosts
+    /// that need to look at the sources before compiling them.
+    /// Fails if a module of the program has the same path as one of a library.
+    pub fn compile_files(&self, files: &[File]) -> Result<Vec<CompiledModule>, ProgramError> {
+        let mut options = self.options.clone();
+        if !options.entry_points.iter().any(|e| e == self.entry.name) {
+            options.entry_points.push(SmolStr::new(self.entry.name));
+        }
+        let mut modules = compile_files_with(files, &options)?;
+        modules.extend(self.libraries.iter().cloned());
+
+        // Linking would fail as well, but only after generating code for the modules before
+        for (index, module) in modules.iter().enumerate() {
+            let path = &module.0.borrow().ast.path;
+            if modules[..index]
+                .iter()
+                .any(|other| other.0.borrow().ast.path == *path)
+            {
+                return Err(StateError::DefinedTwice(path.clone()).into());
+            }
+        }
+        Ok(modules)
+    }
+
+    /// Link compiled modules into a program using the symbols and bindings of this engine.
+    /// Fails if code cannot be generated for the target of its options.
+    #[cfg(feature = "std")]
+    pub fn link(&self, modules: &[CompiledModule]) -> Result<Program, ProgramError> {
+        self.link_with_memory(modules, CodeMemory::default())
+    }
+
+    /// Link compiled modules into a program placing its code in the given memory,
+    /// like `link`. Hosts use this to give every program a code heap of its own.
+    pub fn link_with_memory(
+        &self,
+        modules: &[CompiledModule],
+        memory: CodeMemory,
+    ) -> Result<Program, ProgramError> {
+        let mut program = Program::for_target(modules, self.symbols, memory, &self.options.target)?;
+        for (name, value) in &self.bindings {
+            program.bind(name, value.clone());
+        }
+        Ok(program)
+    }
+
+    /// Compile the program and call its entry, which needs to return a value of type `T`.
+    #[cfg(feature = "std")]
+    pub fn run<T: AbiValue>(&self) -> Result<T, ProgramError> {
+        let modules = self.compile()?;
+        Ok(self.link(&modules)?.call(self.entry)?)
+    }
+
+    /// Compile the program and call its entry, returning its exit code.
+    /// If the entry returns an `i64`, it is used as the exit code;
+    /// otherwise, the exit code is always 0.
+    #[cfg(feature = "std")]
+    pub fn execute(&self) -> Result<i64, ProgramError> {
+        let modules = self.compile()?;
+        Ok(self.link(&modules)?.run(self.entry)?)
+    }
+}
@@ -1,6 +1,12 @@
-use crate::{lexer::TKind, smol_str::SmolStr};
+use crate::{
+    explain::explanation,
+    filesystem::FileError,
+    lexer::TKind,
+    smol_str::SmolStr,
+    vm::{StateError, TargetError},
+};
 use alloc::{string::String, vec::Vec};
-use core::fmt::Display;
+use core::fmt::{Display, Formatter, Result as FmtResult};
 
 pub type Res<T> = Result<T, Error>;
 pub type Errors = Vec<Error>;
@@ -15,6 +21,23 @@ impl Error {
     pub fn new(start: usize, kind: ErrorKind) -> Self {
         Self { start, kind }
     }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// The source offset the error was found at.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Move the error back by `offset`, for source that was compiled
+    /// after other code. Errors before the offset move to the start.
+    #[cfg(feature = "std")]
+    pub(crate) fn moved_back(mut self, offset: usize) -> Self {
+        self.start = self.start.saturating_sub(offset);
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -28,11 +51,48 @@ pub enum ErrorKind {
     E101,
     // Expected declaration.
     E102,
+    // Invalid number literal '{}'.
+    E103(SmolStr),
+    // Expression is nested too deeply.
+    E104,
+    // Invalid escape sequence '\{}'.
+    E105(char),
+    // Unknown attribute '{}'.
+    E106(SmolStr),
+    // Invalid arguments for attribute '{}'.
+    E107(SmolStr),
+    // Attribute '{}' is not allowed here.
+    E108(SmolStr),
 
     // Cannot find type '{}'.
     E200(SmolStr),
     // Name '{}' already used.
     E201(SmolStr),
+    // Function '{}' already declared with the same parameter types.
+    E202(SmolStr),
+    // Value of global '{}' is not a constant expression.
+    E203(SmolStr),
+    // Global '{}' depends on its own value.
+    E204(SmolStr),
+    // Division by zero in constant expression.
+    E205,
+    // Class '{}' contains itself through '{}'.
+    E206 {
+        name: SmolStr,
+        cycle: String,
+    },
+    // Cannot find module '{}'.
+    E207(SmolStr),
+    // Modules import each other through '{}'.
+    E208 {
+        cycle: String,
+    },
+    // Name '{}' already exported by another function.
+    E209(SmolStr),
+    // Module initializer 'init' cannot take parameters or return a value.
+    E210,
+    // Division overflows in constant expression.
+    E211,
 
     // L/R side of binary expression must have same type (left is '{}', right is '{}').
     E500 {
@@ -60,9 +120,10 @@ pub enum ErrorKind {
     E506 {
         ty: String,
     },
-    // Expected {} function arguments but found {}.
+    // Expected {} to {} function arguments but found {}.
     E507 {
-        expected: usize,
+        min: usize,
+        max: usize,
         found: usize,
     },
     // Expected parameter {} to be of type {} but found {}.
@@ -71,10 +132,334 @@ pub enum ErrorKind {
         found: String,
         pos: usize,
     },
+    // Function has no parameter named '{}'.
+    E509 {
+        name: SmolStr,
+    },
+    // Parameter '{}' was given more than once.
+    E510 {
+        name: SmolStr,
+    },
+    // Positional arguments cannot follow named arguments.
+    E511,
+    // Named arguments can only be used when calling a function directly.
+    E512,
+    // Reference to overloaded function '{}' is ambiguous.
+    E513 {
+        name: SmolStr,
+    },
+    // No overload of function '{}' matches the given arguments.
+    E514 {
+        name: SmolStr,
+    },
+    // Cannot assign to immutable variable '{}'.
+    E515 {
+        name: SmolStr,
+    },
+    // Variable '{}' is already declared in this scope.
+    E516 {
+        name: SmolStr,
+    },
+    // This kind of expression is not supported yet.
+    E517,
+    // Cannot convert '{}' to '{}'.
+    E518 {
+        from: String,
+        to: String,
+    },
+    // Cannot capture mutable variable '{}' in a closure.
+    E519 {
+        name: SmolStr,
+    },
+    // Operator method '{}' needs to return '{}'.
+    E520 {
+        name: SmolStr,
+        ret: String,
+    },
+    // Type '{}' has no member '{}'.
+    E521 {
+        ty: String,
+        name: SmolStr,
+    },
+    // Break outside of a loop.
+    E522,
+
+    // Warnings, which only stop a program from compiling
+    // with `CompileOptions::warnings_as_errors`.
+    // Unused variable '{}'.
+    W100 {
+        name: SmolStr,
+    },
+}
+
+impl ErrorKind {
+    /// The code of this error, like `E503`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::E100 { .. } => "E100",
+            ErrorKind::E101 => "E101",
+            ErrorKind::E102 => "E102",
+            ErrorKind::E103(_) => "E103",
+            ErrorKind::E104 => "E104",
+            ErrorKind::E105(_) => "E105",
+            ErrorKind::E106(_) => "E106",
+            ErrorKind::E107(_) => "E107",
+            ErrorKind::E108(_) => "E108",
+            ErrorKind::E200(_) => "E200",
+            ErrorKind::E201(_) => "E201",
+            ErrorKind::E202(_) => "E202",
+            ErrorKind::E203(_) => "E203",
+            ErrorKind::E204(_) => "E204",
+            ErrorKind::E205 => "E205",
+            ErrorKind::E206 { .. } => "E206",
+            ErrorKind::E207(_) => "E207",
+            ErrorKind::E208 { .. } => "E208",
+            ErrorKind::E209(_) => "E209",
+            ErrorKind::E210 => "E210",
+            ErrorKind::E211 => "E211",
+            ErrorKind::E500 { .. } => "E500",
+            ErrorKind::E501 { .. } => "E501",
+            ErrorKind::E502 => "E502",
+            ErrorKind::E503 { .. } => "E503",
+            ErrorKind::E504 { .. } => "E504",
+            ErrorKind::E505 => "E505",
+            ErrorKind::E506 { .. } => "E506",
+            ErrorKind::E507 { .. } => "E507",
+            ErrorKind::E508 { .. } => "E508",
+            ErrorKind::E509 { .. } => "E509",
+            ErrorKind::E510 { .. } => "E510",
+            ErrorKind::E511 => "E511",
+            ErrorKind::E512 => "E512",
+            ErrorKind::E513 { .. } => "E513",
+            ErrorKind::E514 { .. } => "E514",
+            ErrorKind::E515 { .. } => "E515",
+            ErrorKind::E516 { .. } => "E516",
+            ErrorKind::E517 => "E517",
+            ErrorKind::E518 { .. } => "E518",
+            ErrorKind::E519 { .. } => "E519",
+            ErrorKind::E520 { .. } => "E520",
+            ErrorKind::E521 { .. } => "E521",
+            ErrorKind::E522 => "E522",
+            ErrorKind::W100 { .. } => "W100",
+        }
+    }
+
+    /// Whether this is a warning, which does not stop the program from compiling by default.
+    pub fn is_warning(&self) -> bool {
+        self.code().starts_with('W')
+    }
+
+    /// A detailed description of this error, with an example causing it.
+    pub fn explain(&self) -> &'static str {
+        explanation(self.code()).unwrap()
+    }
+
+    /// The description of the error with the given code, like `E503`,
+    /// or `None` if there is no such error.
+    pub fn explain_code(code: &str) -> Option<&'static str> {
+        explanation(&code.to_uppercase())
+    }
+}
+
+/// The short message of an error, without its code.
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            ErrorKind::E100 { expected, found } => {
+                write!(f, "Expected '{:?}', found '{:?}'.", expected, found)
+            }
+            ErrorKind::E101 => write!(f, "Expected expression."),
+            ErrorKind::E102 => write!(f, "Expected declaration."),
+            ErrorKind::E103(lit) => write!(f, "Invalid number literal '{}'.", lit),
+            ErrorKind::E104 => write!(f, "Expression is nested too deeply."),
+            ErrorKind::E105(c) => write!(f, "Invalid escape sequence '\\{}'.", c),
+            ErrorKind::E106(name) => write!(f, "Unknown attribute '{}'.", name),
+            ErrorKind::E107(name) => write!(f, "Invalid arguments for attribute '{}'.", name),
+            ErrorKind::E108(name) => write!(f, "Attribute '{}' is not allowed here.", name),
+            ErrorKind::E200(name) => write!(f, "Cannot find type '{}'.", name),
+            ErrorKind::E201(name) => write!(f, "Name '{}' already used.", name),
+            ErrorKind::E202(name) => write!(
+                f,
+                "Function '{}' already declared with the same parameter types.",
+                name
+            ),
+            ErrorKind::E203(name) => write!(
+                f,
+                "Value of global '{}' is not a constant expression.",
+                name
+            ),
+            ErrorKind::E204(name) => write!(f, "Global '{}' depends on its own value.", name),
+            ErrorKind::E205 => write!(f, "Division by zero in constant expression."),
+            ErrorKind::E206 { name, cycle } => {
+                write!(f, "Class '{}' contains itself through '{}'.", name, cycle)
+            }
+            ErrorKind::E207(name) => write!(f, "Cannot find module '{}'.", name),
+            ErrorKind::E208 { cycle } => {
+                write!(f, "Modules import each other through '{}'.", cycle)
+            }
+            ErrorKind::E209(name) => {
+                write!(f, "Name '{}' already exported by another function.", name)
+            }
+            ErrorKind::E210 => write!(
+                f,
+                "Module initializer 'init' cannot take parameters or return a value."
+            ),
+            ErrorKind::E211 => write!(f, "Division overflows in constant expression."),
+            ErrorKind::E500 { left, right } => write!(
+                f,
+                "L/R side of binary expression must have same type (left is '{}', right is '{}').",
+                left, right
+            ),
+            ErrorKind::E501 { op, ty } => {
+                write!(f, "Operator '{}' not applicable to type '{}'.", op, ty)
+            }
+            ErrorKind::E502 => write!(f, "Condition must be of type bool."),
+            ErrorKind::E503 { name } => write!(f, "Unknown variable '{}'.", name),
+            ErrorKind::E504 { ty } => write!(f, "Cannot assign type '{}' to a variable.", ty),
+            ErrorKind::E505 => write!(f, "Cannot assign to this."),
+            ErrorKind::E506 { ty } => write!(f, "Can only call functions, not '{}'.", ty),
+            ErrorKind::E507 { min, max, found } => write!(
+                f,
+                "Expected {} to {} function arguments but found {}.",
+                min, max, found
+            ),
+            ErrorKind::E508 {
+                expected,
+                found,
+                pos,
+            } => write!(
+                f,
+                "Expected parameter {} to be of type {} but found {}.",
+                pos, expected, found
+            ),
+            ErrorKind::E509 { name } => write!(f, "Function has no parameter named '{}'.", name),
+            ErrorKind::E510 { name } => {
+                write!(f, "Parameter '{}' was given more than once.", name)
+            }
+            ErrorKind::E511 => write!(f, "Positional arguments cannot follow named arguments."),
+            ErrorKind::E512 => write!(
+                f,
+                "Named arguments can only be used when calling a function directly."
+            ),
+            ErrorKind::E513 { name } => write!(
+                f,
+                "Reference to overloaded function '{}' is ambiguous.",
+                name
+            ),
+            ErrorKind::E514 { name } => write!(
+                f,
+                "No overload of function '{}' matches the given arguments.",
+                name
+            ),
+            ErrorKind::E515 { name } => {
+                write!(f, "Cannot assign to immutable variable '{}'.", name)
+            }
+            ErrorKind::E516 { name } => {
+                write!(f, "Variable '{}' is already declared in this scope.", name)
+            }
+            ErrorKind::E517 => write!(f, "This kind of expression is not supported yet."),
+            ErrorKind::E518 { from, to } => write!(f, "Cannot convert '{}' to '{}'.", from, to),
+            ErrorKind::E519 { name } => write!(
+                f,
+                "Cannot capture mutable variable '{}' in a closure.",
+                name
+            ),
+            ErrorKind::E520 { name, ret } => {
+                write!(f, "Operator method '{}' needs to return '{}'.", name, ret)
+            }
+            ErrorKind::E521 { ty, name } => {
+                write!(f, "Type '{}' has no member '{}'.", ty, name)
+            }
+            ErrorKind::E522 => write!(f, "Break outside of a loop."),
+            ErrorKind::W100 { name } => write!(f, "Unused variable '{}'.", name),
+        }
+    }
 }
 
 impl Display for Error {
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "{:?}", self)
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "{} at offset {}: {}",
+            self.kind.code(),
+            self.start,
+            self.kind
+        )
+    }
+}
+
+/// An error preventing a compiled program from being executed.
+#[derive(Debug, PartialEq)]
+pub enum ExecError {
+    /// The program has no function with the given name,
+    /// or it was removed by dead code elimination.
+    NoSuchFunction(SmolStr),
+    /// The given name is not a function.
+    NotAFunction(SmolStr),
+    /// The function with the given name takes parameters or
+    /// does not return what the caller expected.
+    WrongSignature(SmolStr),
+    /// The program panicked, either by calling `panic` or a failed `assert`.
+    Panicked(Panic),
+    /// The program was stopped through an `InterruptHandle`.
+    Interrupted,
+    /// The program was not finalized yet, so none of its functions can be called.
+    NotFinalized,
+}
+
+/// A panic of a program, which stops it from running any further.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Panic {
+    pub message: String,
+    /// The path of the module the panic happened in.
+    pub module: Vec<SmolStr>,
+    /// The source offset of the `panic` or `assert` call.
+    pub start: usize,
+}
+
+/// An error preventing a program from running, either when reading
+/// its source files, during compilation or during execution.
+#[derive(Debug)]
+pub enum ProgramError {
+    /// Some source files of the program could not be read.
+    Read(Vec<FileError>),
+    /// The program failed to compile, containing the errors of each module
+    /// in the order of its files, which is empty for modules without any.
+    Compile(Vec<Errors>),
+    /// The program could not be linked for the target of its `CompileOptions`.
+    Target(TargetError),
+    /// The modules of the program could not be linked together,
+    /// like when two of them have the same path.
+    Link(StateError),
+    Exec(ExecError),
+}
+
+impl From<Vec<FileError>> for ProgramError {
+    fn from(errors: Vec<FileError>) -> Self {
+        ProgramError::Read(errors)
+    }
+}
+
+impl From<Vec<Errors>> for ProgramError {
+    fn from(errors: Vec<Errors>) -> Self {
+        ProgramError::Compile(errors)
+    }
+}
+
+impl From<TargetError> for ProgramError {
+    fn from(err: TargetError) -> Self {
+        ProgramError::Target(err)
+    }
+}
+
+impl From<StateError> for ProgramError {
+    fn from(err: StateError) -> Self {
+        ProgramError::Link(err)
+    }
+}
+
+impl From<ExecError> for ProgramError {
+    fn from(err: ExecError) -> Self {
+        ProgramError::Exec(err)
     }
 }
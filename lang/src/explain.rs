@@ -0,0 +1,625 @@
+//! Detailed explanations of all error codes, shown by `ErrorKind::explain`.
+
+/// Returns the explanation of the error with the given code, like `E503`.
+pub(crate) fn explanation(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "E100" => {
+            "\
+E100: Unexpected token.
+
+The parser expected a specific token, like a closing parenthesis or a colon
+before a type, but found something else. This is usually caused by a typo
+or an unfinished expression just before the reported location.
+
+Example:
+
+    fun add(a: i64, b i64) -> i64 a + b
+                      ^ expected ':'
+"
+        }
+        "E101" => {
+            "\
+E101: Expected expression.
+
+A value was expected, but the parser found a token that cannot start one,
+like an operator or a closing bracket.
+
+Example:
+
+    fun main() -> i64 1 + * 2
+                          ^ '*' cannot start an expression
+"
+        }
+        "E102" => {
+            "\
+E102: Expected declaration.
+
+Modules only contain functions, classes and `val` globals, and classes only
+contain members, methods, `static` declarations and an `init` block.
+Anything else at that level is rejected.
+
+Example:
+
+    class Point {
+        x + 1
+        ^ not a declaration, members look like `val x: i64`
+    }
+"
+        }
+        "E103" => {
+            "\
+E103: Invalid number literal.
+
+A number literal could not be parsed into a value of its type, usually
+because it does not fit into it.
+
+Example:
+
+    fun main() -> i64 99999999999999999999
+"
+        }
+        "E104" => {
+            "\
+E104: Expression is nested too deeply.
+
+The compiler limits how deeply expressions can be nested to avoid running
+out of stack. Split the expression up using local variables.
+"
+        }
+        "E105" => {
+            "\
+E105: Invalid escape sequence.
+
+Strings support the escape sequences `\\n`, `\\t`, `\\r`, `\\0`, `\\\\` and `\\\"`.
+Any other character after a backslash is an error.
+
+Example:
+
+    fun main() -> str \"tab:\\q\"
+"
+        }
+        "E106" => {
+            "\
+E106: Unknown attribute.
+
+Attributes start with `@` in front of a declaration. The attributes are:
+- `@cfg(flags)`, which compiles the declaration only if the compile flags match
+- `@inline`, which inlines calls to the function regardless of its size
+- `@export(\"name\")`, which links the function under the given name
+- `@extern(\"symbol\")`, which links an `extern fun` to the given host symbol
+
+Example:
+
+    @config(kernel)
+    fun main() {}
+"
+        }
+        "E107" => {
+            "\
+E107: Invalid arguments for attribute.
+
+The attribute was given arguments it does not accept. `@cfg` takes one or
+more flag names, each of which can be negated with `!`. The declaration is
+only compiled if all of them match the flags given to the compiler.
+`@export` and `@extern` take a single string, which cannot be empty,
+and `@inline` takes no arguments at all.
+
+Example:
+
+    @cfg(\"kernel\")
+    fun main() {}
+"
+        }
+        "E108" => {
+            "\
+E108: Attribute is not allowed here.
+
+Attributes can only be put in front of imports, globals, classes and
+functions, including the methods and static functions of classes.
+`@cfg` is allowed on all of them. `@inline` and `@export` are only allowed
+on functions with a body, `@extern` only on `extern fun`s.
+
+Example:
+
+    class Point {
+        @cfg(kernel)
+        val x: i64
+    }
+"
+        }
+        "E200" => {
+            "\
+E200: Cannot find type.
+
+A type was named that is neither builtin, like `i64` or `str`,
+nor a class declared in the module.
+
+Example:
+
+    fun main(point: Pointt) {}
+                    ^ no class named 'Pointt'
+"
+        }
+        "E201" => {
+            "\
+E201: Name already used.
+
+Classes, functions and globals of a module share their names, and so do the
+members, methods and statics of a class. Each name can only be declared once,
+unless all declarations are functions with different parameter types.
+
+Example:
+
+    val size = 4
+    fun size() -> i64 4
+"
+        }
+        "E202" => {
+            "\
+E202: Function already declared with the same parameter types.
+
+Functions with the same name are overloads of each other, which need
+different parameter types to tell them apart at call sites.
+
+Example:
+
+    fun area(width: i64) -> i64 width * width
+    fun area(side: i64) -> i64 side * side
+"
+        }
+        "E203" => {
+            "\
+E203: Value of global is not a constant expression.
+
+Globals and `static val`s are evaluated while compiling, so their values can
+only use literals, other globals and statics as well as binary operators.
+
+Example:
+
+    val start = now()
+                ^ functions cannot be called in constant expressions
+"
+        }
+        "E204" => {
+            "\
+E204: Global depends on its own value.
+
+A global refers to itself, either directly or through other globals,
+which means its value cannot be computed.
+
+Example:
+
+    val a = b + 1
+    val b = a * 2
+"
+        }
+        "E205" => {
+            "\
+E205: Division by zero in constant expression.
+
+A constant expression divides by zero, which would fail
+when running the program as well.
+
+Example:
+
+    val per_item = 100 / 0
+"
+        }
+        "E206" => {
+            "\
+E206: Class contains itself.
+
+Values of classes contain the values of all their members, so a class cannot
+contain itself, either directly or through members of other classes.
+The reported path shows the members forming the cycle.
+
+Example:
+
+    class Node {
+        val value: i64
+        val next: Node
+    }
+"
+        }
+        "E207" => {
+            "\
+E207: Cannot find module.
+
+An import names a module that is not part of the program. Modules are
+named after the path of their file, with `::` separating directories.
+
+Example:
+
+    import std::colections
+"
+        }
+        "E208" => {
+            "\
+E208: Modules import each other.
+
+Imports between modules cannot form a cycle, either directly or through
+other modules. The reported path shows the imports forming the cycle;
+move the items both modules need into a module of their own.
+
+Example:
+
+    // game/player.yacari
+    import game::world
+
+    // game/world.yacari
+    import game::player
+"
+        }
+        "E209" => {
+            "\
+E209: Name already exported.
+
+Functions with `@export` are linked under the given name, which
+needs to be unique among all functions of the program.
+
+Example:
+
+    @export(\"on_key\")
+    fun handle_key(code: i64) {}
+
+    @export(\"on_key\")
+    fun log_key(code: i64) {}
+"
+        }
+        "E210" => {
+            "\
+E210: Module initializer with parameters or return value.
+
+Since edition V2, a function named `init` at the top of a module is its
+initializer, which runs once before the program is first called. Since
+nothing calls it directly, it cannot take any parameters or return a value.
+
+Example:
+
+    fun init(count: i64) -> bool {
+        true
+    }
+"
+        }
+        "E211" => {
+            "\
+E211: Division overflows in constant expression.
+
+A constant expression divides the smallest integer by -1, which gives
+a result too large for `i64`. This would fail when running the program as well.
+
+Example:
+
+    val MIN = (0 - 9223372036854775807) - 1
+    val overflow = MIN / (0 - 1)
+"
+        }
+        "E500" => {
+            "\
+E500: Binary expression with different types.
+
+Both sides of a binary operator need to have the same type,
+numbers of different types are never converted implicitly.
+
+Example:
+
+    fun half(value: i64) -> f64 value / 2.0
+"
+        }
+        "E501" => {
+            "\
+E501: Operator not applicable to type.
+
+The operator cannot be used with values of this type. Classes support
+operators by implementing methods like `plus` for `+` or `compare` for `<`.
+
+Example:
+
+    fun main() -> bool true + false
+"
+        }
+        "E502" => {
+            "\
+E502: Condition must be of type bool.
+
+The conditions of `if`, `while` and `assert` need to be `bool`,
+other values are never treated as true or false.
+
+Example:
+
+    fun main(count: i64) -> i64 if (count) 1 else 0
+"
+        }
+        "E503" => {
+            "\
+E503: Unknown variable.
+
+The name does not refer to a variable, parameter, global
+or function that is visible at this point.
+
+Example:
+
+    fun main() -> i64 {
+        count + 1
+        ^ 'count' was never declared
+    }
+"
+        }
+        "E504" => {
+            "\
+E504: Cannot assign type to a variable.
+
+Variables cannot hold values of this type, like the result
+of a function that does not return anything.
+
+Example:
+
+    fun log() {}
+    fun main() {
+        val result = log()
+    }
+"
+        }
+        "E505" => {
+            "\
+E505: Cannot assign to this.
+
+Only variables can be assigned to. Globals, statics,
+members and other expressions cannot.
+
+Example:
+
+    val limit = 10
+    fun main() { limit = 20 }
+"
+        }
+        "E506" => {
+            "\
+E506: Can only call functions.
+
+A value that is not a function was called.
+
+Example:
+
+    fun main() -> i64 {
+        val count = 3
+        count()
+    }
+"
+        }
+        "E507" => {
+            "\
+E507: Wrong number of function arguments.
+
+The function was called with fewer or more arguments than it takes.
+Parameters with a default value can be left out.
+
+Example:
+
+    fun add(a: i64, b: i64) -> i64 a + b
+    fun main() -> i64 add(1)
+"
+        }
+        "E508" => {
+            "\
+E508: Argument of the wrong type.
+
+The argument given for a parameter has a different type than the parameter.
+
+Example:
+
+    fun double(value: i64) -> i64 value * 2
+    fun main() -> i64 double(true)
+"
+        }
+        "E509" => {
+            "\
+E509: Function has no parameter with that name.
+
+A named argument was given for a parameter that does not exist.
+
+Example:
+
+    fun greet(name: str) -> str name
+    fun main() -> str greet(nam = \"you\")
+"
+        }
+        "E510" => {
+            "\
+E510: Parameter given more than once.
+
+A parameter was given both as a positional and named argument,
+or as named argument multiple times.
+
+Example:
+
+    fun greet(name: str) -> str name
+    fun main() -> str greet(\"you\", name = \"me\")
+"
+        }
+        "E511" => {
+            "\
+E511: Positional arguments cannot follow named arguments.
+
+Once an argument is given by name, all following ones need to be named too.
+
+Example:
+
+    fun sub(a: i64, b: i64) -> i64 a - b
+    fun main() -> i64 sub(a = 2, 1)
+"
+        }
+        "E512" => {
+            "\
+E512: Named arguments can only be used when calling a function directly.
+
+Function values, like closures stored in variables,
+do not know the names of their parameters.
+
+Example:
+
+    fun main() -> i64 {
+        val double = fun(value: i64) -> i64 value * 2
+        double(value = 2)
+    }
+"
+        }
+        "E513" => {
+            "\
+E513: Reference to overloaded function is ambiguous.
+
+An overloaded function was used as a value, which does not tell
+which of the overloads is meant. Wrap the call in a closure instead.
+
+Example:
+
+    fun show(value: i64) -> str str(value)
+    fun show(value: bool) -> str str(value)
+    fun main() { val f = show }
+"
+        }
+        "E514" => {
+            "\
+E514: No overload matches the given arguments.
+
+None of the functions with this name take arguments of the given types.
+
+Example:
+
+    fun show(value: i64) -> str str(value)
+    fun show(value: bool) -> str str(value)
+    fun main() -> str show(1.5)
+"
+        }
+        "E515" => {
+            "\
+E515: Cannot assign to immutable variable.
+
+Variables declared with `val` cannot be changed, use `var` instead.
+
+Example:
+
+    fun main() -> i64 {
+        val count = 1
+        count = 2
+        count
+    }
+"
+        }
+        "E516" => {
+            "\
+E516: Variable already declared in this scope.
+
+Each scope can only contain one variable with a given name. Inner scopes,
+like the body of an `if`, can shadow variables of outer ones.
+
+Example:
+
+    fun main() {
+        val count = 1
+        val count = 2
+    }
+"
+        }
+        "E517" => {
+            "\
+E517: Expression not supported yet.
+
+The expression parses fine, but the compiler cannot compile it yet.
+This currently applies to unary operators like `-x` and `!x`,
+which can be written as `0 - x` and `x == false` instead.
+"
+        }
+        "E518" => {
+            "\
+E518: Cannot convert between these types.
+
+Conversions like `f64(value)` only work between `f32` and `f64`.
+Numbers and `bool`s can be converted to `str`, as well as
+values of classes with a `to_string` method returning `str`.
+
+Example:
+
+    fun main() -> f64 f64(1)
+"
+        }
+        "E519" => {
+            "\
+E519: Cannot capture mutable variable in a closure.
+
+Closures capture a copy of the variables they use, so changes would not be
+visible to either side. Only variables declared with `val` can be captured.
+
+Example:
+
+    fun main() -> i64 {
+        var count = 1
+        val get = fun() -> i64 count
+        get()
+    }
+"
+        }
+        "E520" => {
+            "\
+E520: Operator method has the wrong return type.
+
+Methods implementing operators need to return a specific type:
+`equals` returns `bool`, and `compare` returns `i64`.
+
+Example:
+
+    class Money {
+        val cents: i64
+        fun equals(other: Money) -> i64 this.cents - other.cents
+    }
+"
+        }
+        "E521" => {
+            "\
+E521: Type has no member with that name.
+
+The value has no member with this name,
+or the class has no `static val` with it.
+
+Example:
+
+    class Point { val x: i64 }
+    fun main() -> i64 Point(1).y
+"
+        }
+        "E522" => {
+            "\
+E522: Break outside of a loop.
+
+`break` leaves the innermost `while` loop, so it can only be used inside the
+body of one. Functions and closures declared in a loop cannot leave it.
+
+Example:
+
+    fun main() {
+        val stop = fun() { break }
+        while (true) stop()
+    }
+"
+        }
+        "W100" => {
+            "\
+W100: Unused variable.
+
+The variable is declared, but never used afterwards. This is often a sign of
+a typo or a leftover from an earlier version of the code. Remove the variable,
+or start its name with an underscore if it is unused on purpose.
+This is a warning, which only stops the program from compiling with the
+`warnings_as_errors` compile option.
+
+Example:
+
+    fun main() -> i64 {
+        val count = 5
+        0
+    }
+"
+        }
+        _ => return None,
+    })
+}
@@ -1,37 +1,157 @@
 use crate::smol_str::SmolStr;
-use alloc::{string::String, vec::Vec};
+use alloc::{borrow::Cow, string::String, vec::Vec};
+use core::fmt::Display;
 
 #[derive(Debug)]
-pub struct File {
+pub struct File<'src> {
     pub path: Vec<SmolStr>,
-    pub contents: String,
+    /// Borrowed when the filesystem already keeps the source in memory,
+    /// so it is parsed in place instead of being copied first.
+    pub contents: Cow<'src, str>,
+}
+
+/// A source file that was found while walking a directory, but could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileError {
+    /// The path of the file on the filesystem, for showing to the user.
+    pub path: String,
+    pub kind: FileErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileErrorKind {
+    /// The file is not valid UTF-8.
+    InvalidUtf8,
+    /// Reading the file failed.
+    Unreadable,
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.kind {
+            FileErrorKind::InvalidUtf8 => write!(f, "{}: file is not valid UTF-8", self.path),
+            FileErrorKind::Unreadable => write!(f, "{}: failed to read file", self.path),
+        }
+    }
 }
 
 pub trait Filesystem {
-    fn walk_directory<T: FnMut(File)>(&self, path: &str, cls: T);
+    /// Call `cls` with every source file at `path`, which is either
+    /// a source file or a directory that is walked recursively.
+    /// Source files that cannot be read are passed as errors instead of
+    /// being skipped, so that a program is never compiled without them.
+    /// Files can borrow their contents from the filesystem.
+    fn walk_directory<'fs, T: FnMut(Result<File<'fs>, FileError>)>(&'fs self, path: &str, cls: T);
+}
+
+impl<F: Filesystem> Filesystem for &F {
+    fn walk_directory<'fs, T: FnMut(Result<File<'fs>, FileError>)>(&'fs self, path: &str, cls: T) {
+        (**self).walk_directory(path, cls)
+    }
+}
+
+/// A filesystem kept in memory, made from `(path, contents)` pairs with paths
+/// separated by `/`. Walking it gives the same module paths as walking the
+/// same files with `OsFs`, so tests can use it regardless of the host, like
+/// in the kernel. An empty path walks every file, without a directory name.
+pub mod memory_fs {
+    use super::{File, FileError, Filesystem};
+    use crate::smol_str::SmolStr;
+    use alloc::{borrow::Cow, vec, vec::Vec};
+
+    pub struct MemoryFs<'m> {
+        files: &'m [(&'m str, &'m str)],
+    }
+
+    impl<'m> MemoryFs<'m> {
+        pub const fn new(files: &'m [(&'m str, &'m str)]) -> Self {
+            Self { files }
+        }
+    }
+
+    impl Filesystem for MemoryFs<'_> {
+        fn walk_directory<'fs, T: FnMut(Result<File<'fs>, FileError>)>(
+            &'fs self,
+            path: &str,
+            mut cls: T,
+        ) {
+            let mut files = self
+                .files
+                .iter()
+                .filter_map(|(file, contents)| Some((module_path(path, file)?, *contents)))
+                .collect::<Vec<_>>();
+            // Sort like `OsFs` does, instead of depending on the order of the list
+            files.sort_by(|a, b| a.0.cmp(&b.0));
+            for (path, contents) in files {
+                cls(Ok(File {
+                    path,
+                    contents: Cow::Borrowed(contents),
+                }))
+            }
+        }
+    }
+
+    /// Returns the module path of `file` when walking `root`,
+    /// or `None` if it is not a source file inside of it.
+    fn module_path(root: &str, file: &str) -> Option<Vec<SmolStr>> {
+        let (root, file) = (root.trim_matches('/'), file.trim_matches('/'));
+        let name = |path: &str| SmolStr::new(path.rsplit('/').next().unwrap());
+        if file == root {
+            return Some(vec![name(file.strip_suffix(".yacari")?)]);
+        }
+
+        let relative = if root.is_empty() {
+            file
+        } else {
+            file.strip_prefix(root)?.strip_prefix('/')?
+        };
+        let mut path = Vec::with_capacity(5);
+        if !root.is_empty() {
+            path.push(name(root));
+        }
+        path.extend(
+            relative
+                .strip_suffix(".yacari")?
+                .split('/')
+                .map(SmolStr::new),
+        );
+        Some(path)
+    }
 }
 
 #[cfg(feature = "std")]
 pub mod os_fs {
-    use super::File as YFile;
+    use super::{File as YFile, FileError, FileErrorKind};
     use crate::{filesystem::Filesystem, smol_str::SmolStr};
-    use alloc::vec::Vec;
-    use std::{fs, path::PathBuf};
+    use alloc::{string::ToString, vec::Vec};
+    use std::{fs, io::ErrorKind, path::PathBuf};
 
     pub struct OsFs;
     impl Filesystem for OsFs {
-        fn walk_directory<T: FnMut(YFile)>(&self, path: &str, mut cls: T) {
+        fn walk_directory<'fs, T: FnMut(Result<YFile<'fs>, FileError>)>(
+            &'fs self,
+            path: &str,
+            mut cls: T,
+        ) {
             let dir = PathBuf::from(path);
             let mut path = Vec::with_capacity(5);
             walk_file(dir, &mut path, &mut cls)
         }
     }
 
-    fn walk_file<T: FnMut(YFile)>(input: PathBuf, path: &mut Vec<SmolStr>, cls: &mut T) {
+    fn walk_file<'fs, T: FnMut(Result<YFile<'fs>, FileError>)>(
+        input: PathBuf,
+        path: &mut Vec<SmolStr>,
+        cls: &mut T,
+    ) {
         path.push(stem_to_smol(&input));
         if let Ok(dir) = input.read_dir() {
-            for file in dir {
-                let file = file.expect("Failed to read file").path();
+            // Directory order differs between platforms, sort to stay deterministic
+            let mut files = dir
+                .map(|file| file.expect("Failed to read file").path())
+                .collect::<Vec<_>>();
+            files.sort();
+            for file in files {
                 walk_file(file, path, cls)
             }
         } else if *input
@@ -39,11 +159,22 @@ pub mod os_fs {
             .map(|ext| ext == "yacari")
             .get_or_insert(false)
         {
-            cls(YFile {
-                path: path.clone(),
-                contents: fs::read_to_string(&input).expect("Failed to read file."),
-            });
+            let file = match fs::read_to_string(&input) {
+                Ok(contents) => Ok(YFile {
+                    path: path.clone(),
+                    contents: contents.into(),
+                }),
+                Err(err) => Err(FileError {
+                    path: input.display().to_string(),
+                    kind: match err.kind() {
+                        ErrorKind::InvalidData => FileErrorKind::InvalidUtf8,
+                        _ => FileErrorKind::Unreadable,
+                    },
+                }),
+            };
+            cls(file);
         }
+        path.pop();
     }
 
     pub fn stem_to_smol(path: &PathBuf) -> SmolStr {
@@ -1,15 +1,59 @@
-use crate::smol_str::SmolStr;
+use crate::{
+    smol_str::SmolStr,
+    timings::{self, Stage},
+};
+use alloc::collections::VecDeque;
 pub use logos::{Logos, Span};
 
+/// How many tokens are lexed at once when the buffer runs empty.
+const BATCH_SIZE: usize = 32;
+
+#[derive(Clone)]
 pub struct Lexer<'l> {
     logos: logos::Lexer<'l, TKind>,
+    buffer: VecDeque<Token>,
 }
 
 impl<'l> Lexer<'l> {
     pub fn new(input: &'l str) -> Self {
         Self {
             logos: TKind::lexer(input),
+            buffer: VecDeque::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    /// Returns the next token without consuming it.
+    pub fn peek(&mut self) -> Option<&Token> {
+        if self.buffer.is_empty() {
+            self.fill();
         }
+        self.buffer.front()
+    }
+
+    /// If the source contains a line break between the given offsets.
+    pub fn line_break_between(&self, start: usize, end: usize) -> bool {
+        let source = self.logos.source();
+        source[start.min(source.len())..end.min(source.len())].contains('\n')
+    }
+
+    fn fill(&mut self) {
+        let (logos, buffer) = (&mut self.logos, &mut self.buffer);
+        timings::measure(Stage::Lex, || {
+            for _ in 0..BATCH_SIZE {
+                match logos.next() {
+                    Some(kind) => buffer.push_back(Token {
+                        kind,
+                        // Fixed tokens point to their static lexeme instead of copying it
+                        lex: match kind.lexeme() {
+                            Some(lexeme) => SmolStr::new_static(lexeme),
+                            None => SmolStr::new(logos.slice()),
+                        },
+                        start: logos.span().start,
+                    }),
+                    None => break,
+                }
+            }
+        })
     }
 }
 
@@ -17,14 +61,10 @@ impl<'l> Iterator for Lexer<'l> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let kind = self.logos.next()?;
-        let lexeme = self.logos.slice();
-        let span = self.logos.span();
-        Some(Token {
-            kind,
-            lex: SmolStr::new(lexeme),
-            start: span.start,
-        })
+        if self.buffer.is_empty() {
+            self.fill();
+        }
+        self.buffer.pop_front()
     }
 }
 
@@ -71,10 +111,20 @@ pub enum TKind {
     Slash,
     #[token("*")]
     Star,
+    #[token("%")]
+    Percent,
+    #[token("&")]
+    Ampersand,
+    #[token("|")]
+    Pipe,
+    #[token("^")]
+    Caret,
     #[token("->")]
     Arrow,
     #[token("?")]
     QuestionMark,
+    #[token("@")]
+    At,
 
     #[token("!")]
     Bang,
@@ -92,10 +142,14 @@ pub enum TKind {
     Less,
     #[token("<=")]
     LessEqual,
+    #[token("<<")]
+    LessLess,
+    #[token(">>")]
+    GreaterGreater,
 
     #[regex("[a-zA-Z_][a-zA-Z0-9_]*")]
     Identifier,
-    #[regex("\"[^\"]*\"")]
+    #[regex(r#""([^"\\]|\\.)*""#)]
     String,
     #[regex(r"[0-9]+(?:(i|u)(size|8|16|32|64))?")]
     Int,
@@ -164,16 +218,84 @@ pub enum TKind {
 }
 
 impl TKind {
+    /// Returns the text of tokens that always look the same,
+    /// or `None` for identifiers, literals and errors.
+    pub fn lexeme(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::LeftParen => "(",
+            Self::RightParen => ")",
+            Self::LeftBracket => "[",
+            Self::RightBracket => "]",
+            Self::LeftBrace => "{",
+            Self::RightBrace => "}",
+            Self::Tilde => "~",
+            Self::Comma => ",",
+            Self::Dot => ".",
+            Self::Minus => "-",
+            Self::Plus => "+",
+            Self::Semicolon => ";",
+            Self::Colon => ":",
+            Self::ColonColon => "::",
+            Self::Slash => "/",
+            Self::Star => "*",
+            Self::Percent => "%",
+            Self::Ampersand => "&",
+            Self::Pipe => "|",
+            Self::Caret => "^",
+            Self::Arrow => "->",
+            Self::QuestionMark => "?",
+            Self::At => "@",
+            Self::Bang => "!",
+            Self::BangEqual => "!=",
+            Self::Equal => "=",
+            Self::EqualEqual => "==",
+            Self::Greater => ">",
+            Self::GreaterEqual => ">=",
+            Self::Less => "<",
+            Self::LessEqual => "<=",
+            Self::LessLess => "<<",
+            Self::GreaterGreater => ">>",
+            Self::And => "and",
+            Self::Break => "break",
+            Self::Class => "class",
+            Self::Else => "else",
+            Self::Enum => "enum",
+            Self::Extern => "extern",
+            Self::False => "false",
+            Self::For => "for",
+            Self::Fun => "fun",
+            Self::If => "if",
+            Self::Import => "import",
+            Self::In => "in",
+            Self::Interface => "interface",
+            Self::Is => "is",
+            Self::Null => "null",
+            Self::Or => "or",
+            Self::Return => "return",
+            Self::Static => "static",
+            Self::True => "true",
+            Self::Var => "var",
+            Self::Val => "val",
+            Self::When => "when",
+            Self::While => "while",
+            _ => return None,
+        })
+    }
+
     pub fn infix_binding_power(&self) -> Option<(u8, u8)> {
         Some(match self {
-            Self::Equal => (6, 5),
-            Self::Or => (10, 9),
-            Self::And => (12, 11),
-            Self::BangEqual | Self::EqualEqual => (14, 13),
-            Self::Less | Self::LessEqual | Self::Greater | Self::GreaterEqual => (16, 15),
-            Self::Plus | Self::Minus => (16, 15),
-            Self::Star | Self::Slash => (18, 17),
-            Self::Is => (20, 19),
+            Self::Equal => (2, 1),
+            Self::Or => (4, 3),
+            Self::And => (6, 5),
+            Self::BangEqual | Self::EqualEqual => (8, 7),
+            Self::Less | Self::LessEqual | Self::Greater | Self::GreaterEqual => (10, 9),
+            Self::Pipe => (12, 11),
+            Self::Caret => (14, 13),
+            Self::Ampersand => (16, 15),
+            Self::LessLess | Self::GreaterGreater => (18, 17),
+            Self::Plus | Self::Minus => (20, 19),
+            Self::Star | Self::Slash | Self::Percent => (22, 21),
+            Self::Is => (24, 23),
             _ => return None,
         })
     }
@@ -198,6 +320,19 @@ impl TKind {
             _ => false,
         }
     }
+
+    /// Binary operators that only apply to integers.
+    pub fn is_binary_int(&self) -> bool {
+        matches!(
+            self,
+            TKind::Percent
+                | TKind::Ampersand
+                | TKind::Pipe
+                | TKind::Caret
+                | TKind::LessLess
+                | TKind::GreaterGreater
+        )
+    }
 }
 
 #[cfg(test)]
@@ -216,4 +351,40 @@ mod test {
         lex("{ 5 }", &[LeftBrace, Int, RightBrace]);
         lex("{ 5 \n 5 }", &[LeftBrace, Int, Int, RightBrace]);
     }
+
+    #[test]
+    fn int_operators() {
+        lex("5 % 2", &[Int, Percent, Int]);
+        lex(
+            "a & b | c ^ d",
+            &[
+                Identifier, Ampersand, Identifier, Pipe, Identifier, Caret, Identifier,
+            ],
+        );
+        lex(
+            "1 << 2 >> 3 < 4",
+            &[Int, LessLess, Int, GreaterGreater, Int, Less, Int],
+        );
+    }
+
+    #[test]
+    fn strings() {
+        lex(r#""a" + "b""#, &[String, Plus, String]);
+        lex(r#""say \"hi\"\\" "\n""#, &[String, String]);
+    }
+
+    #[test]
+    fn lexemes() {
+        // Enough tokens to need several batches
+        let input = "fun main() -> i64 { val some_long_identifier_name = 1.5 + 2 }\n".repeat(10);
+        let mut lexer = Lexer::new(&input);
+        let mut count = 0;
+        while let Some(peeked) = lexer.peek().cloned() {
+            let token = lexer.next().unwrap();
+            assert_eq!(token.start, peeked.start);
+            assert_eq!(token.lex, input[token.start..token.start + token.lex.len()]);
+            count += 1;
+        }
+        assert_eq!(count, 140);
+    }
 }
@@ -3,100 +3,738 @@
 
 extern crate alloc;
 
-use crate::{compiler::Compiler, error::Errors, parser::Parser, vm::JIT};
+use crate::{compiler::Compiler, parser::Parser, vm::JIT};
 
-use crate::{compiler::module::ModuleCompiler, filesystem::Filesystem};
-use alloc::{vec, vec::Vec};
+use crate::{
+    filesystem::{File, FileError, Filesystem},
+    timings::Stage,
+};
+use alloc::{string::String, vec, vec::Vec};
+#[cfg(feature = "std")]
+use compiler::module::ModuleCompiler;
+use core::mem;
 
-use crate::compiler::ir::Module;
-pub use crate::vm::SymbolTable;
+use crate::compiler::{
+    ir::{Function, Module, Type},
+    MutRc,
+};
+#[cfg(feature = "wasm")]
+pub use crate::wasm::WasmError;
+pub use crate::{
+    compiler::{ir::snapshot::SnapshotError, CompileOptions, Edition},
+    diagnostic::{diagnostics_to_json, Diagnostic},
+    engine::Engine,
+    error::{Error, ErrorKind, Errors, ExecError, Panic, ProgramError},
+    vm::{
+        line_column, AbiValue, Binding, Callback, CodeMemory, CompileReport, FunctionSize, Hook,
+        InterruptHandle, SourceMap, StateError, StrRef, SymbolTable, Target, TargetError,
+    },
+};
 #[cfg(feature = "core")]
-pub use cranelift_jit::{set_manager, MemoryManager};
-pub use smol_str::SmolStr;
+pub use cranelift_jit::MemoryManager;
+pub use smol_str::{SmolStr, SmolStrBuilder};
+#[cfg(feature = "timings")]
+pub use timings::{set_clock, take_timings, Clock, Timings};
+
+#[cfg(feature = "std")]
+pub use repl::Repl;
 
 #[cfg(feature = "std")]
 extern crate std;
 
+mod backend;
 mod compiler;
+mod diagnostic;
+mod engine;
 mod error;
+mod explain;
 pub mod filesystem;
 mod lexer;
 mod parser;
+#[cfg(feature = "std")]
+mod repl;
 mod smol_str;
+pub mod testing;
+mod timings;
+pub mod tooling;
 mod vm;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-pub fn execute_module<T>(program: &str, symbols: SymbolTable) -> Result<T, Errors> {
-    let parse = Parser::new(program).parse(vec![SmolStr::new_inline("script")])?;
-    let ir = ModuleCompiler::new(Module::from_ast(parse)).consume()?;
-    let mut jit = JIT::new(symbols);
-    jit.jit_module(&*ir.borrow());
-    Ok(jit.exec("main"))
+#[cfg(feature = "std")]
+pub fn execute_module<T: AbiValue>(program: &str, symbols: SymbolTable) -> Result<T, ProgramError> {
+    let options = CompileOptions::default();
+    let parse = timings::measure(Stage::Parse, || {
+        Parser::new(program).parse(vec![SmolStr::new_inline("script")])
+    })
+    .map_err(|errors| vec![errors])?;
+    let ir = timings::measure(Stage::Ir, || {
+        ModuleCompiler::new(Module::from_ast(parse), &options).consume()
+    })
+    .map_err(|errors| vec![errors])?;
+    compiler::mark_live(&[ir.clone()], &options.entry_points);
+    let mut program = Program::new(&[CompiledModule(ir)], symbols)?;
+    Ok(program.call(Entry::MAIN)?)
 }
 
 #[cfg(feature = "std")]
-pub fn execute_with_os_fs<T>(paths: &[&str], symbols: SymbolTable) -> Result<T, Vec<Errors>> {
+pub fn execute_with_os_fs<T: AbiValue>(
+    paths: &[&str],
+    symbols: SymbolTable,
+) -> Result<T, ProgramError> {
     execute_path(filesystem::os_fs::OsFs, paths, symbols)
 }
 
-pub fn execute_path<FS: Filesystem, T>(
+#[cfg(feature = "std")]
+pub fn execute_path<FS: Filesystem, T: AbiValue>(
     fs: FS,
     paths: &[&str],
     symbols: SymbolTable,
-) -> Result<T, Vec<Errors>> {
-    let mut modules = Vec::with_capacity(20);
-    let mut errors = Vec::new();
+) -> Result<T, ProgramError> {
+    Engine::new(fs).paths(paths).symbols(symbols).run()
+}
 
-    for path in paths {
-        fs.walk_directory(path, |file| {
-            let parse = Parser::new(&file.contents).parse(file.path);
-            match parse {
-                Ok(module) => modules.push(module),
-                Err(err) => errors.push(err),
+/// Execute a program like `execute_path`, starting at the given entry
+/// instead of `main`.
+#[cfg(feature = "std")]
+pub fn execute_entry<FS: Filesystem, T: AbiValue>(
+    fs: FS,
+    paths: &[&str],
+    symbols: SymbolTable,
+    entry: Entry,
+) -> Result<T, ProgramError> {
+    Engine::new(fs)
+        .paths(paths)
+        .symbols(symbols)
+        .entry(entry)
+        .run()
+}
+
+/// Execute a program like `execute_path`, returning its exit code.
+/// If `main` returns an `i64`, it is used as the exit code;
+/// otherwise, the exit code is always 0.
+#[cfg(feature = "std")]
+pub fn execute_program<FS: Filesystem>(
+    fs: FS,
+    paths: &[&str],
+    symbols: SymbolTable,
+) -> Result<i64, ProgramError> {
+    Engine::new(fs).paths(paths).symbols(symbols).execute()
+}
+
+/// Execute a program made out of already compiled modules,
+/// returning its exit code like `execute_program`.
+#[cfg(feature = "std")]
+pub fn execute_modules(
+    modules: &[CompiledModule],
+    symbols: SymbolTable,
+) -> Result<i64, ProgramError> {
+    Ok(Program::new(modules, symbols)?.run(Entry::MAIN)?)
+}
+
+/// A function to start executing a program at.
+#[derive(Debug, Copy, Clone)]
+pub struct Entry<'e> {
+    /// The path of the module containing the function,
+    /// or `None` to use the first module that contains it.
+    pub module: Option<&'e [&'e str]>,
+    /// The name of the function. It needs to take no parameters,
+    /// unless it is retrieved with `Program::callback`.
+    pub name: &'e str,
+}
+
+impl<'e> Entry<'e> {
+    pub const MAIN: Entry<'static> = Entry::new("main");
+
+    pub const fn new(name: &'e str) -> Self {
+        Self { module: None, name }
+    }
+
+    pub const fn in_module(module: &'e [&'e str], name: &'e str) -> Self {
+        Self {
+            module: Some(module),
+            name,
+        }
+    }
+}
+
+/// A program linked into executable code. Its entry points can be
+/// called any amount of times without compiling the program again,
+/// like `init` once and then `update` for every frame of an app.
+/// Functions removed by dead code elimination cannot be called;
+/// see `CompileOptions::entry_points`.
+pub struct Program {
+    jit: JIT,
+    modules: Vec<CompiledModule>,
+    /// The symbols of the module initializers that did not run yet, in the order they run.
+    inits: Vec<SmolStr>,
+}
+
+impl Program {
+    /// Link the modules into code placed in memory of the operating system,
+    /// which is only available with `std`; see `with_memory`.
+    #[cfg(feature = "std")]
+    pub fn new(modules: &[CompiledModule], symbols: SymbolTable) -> Result<Self, ProgramError> {
+        Self::with_memory(modules, symbols, CodeMemory::default())
+    }
+
+    /// Link the modules into code placed in the given memory,
+    /// which lives as long as the program.
+    /// Fails if two of the modules have the same path.
+    pub fn with_memory(
+        modules: &[CompiledModule],
+        symbols: SymbolTable,
+        memory: CodeMemory,
+    ) -> Result<Self, ProgramError> {
+        Ok(Self {
+            jit: jit_modules(JIT::new(symbols, memory), modules)?,
+            modules: modules.to_vec(),
+            inits: init_order(modules),
+        })
+    }
+
+    /// Link the modules into code for the given target, placed in the given memory.
+    /// Fails if code cannot be generated for the target, if it cannot run on the host,
+    /// or if two of the modules have the same path.
+    pub fn for_target(
+        modules: &[CompiledModule],
+        symbols: SymbolTable,
+        memory: CodeMemory,
+        target: &Target,
+    ) -> Result<Self, ProgramError> {
+        Ok(Self {
+            jit: jit_modules(JIT::for_target(symbols, memory, target)?, modules)?,
+            modules: modules.to_vec(),
+            inits: init_order(modules),
+        })
+    }
+
+    /// Run the initializers of all modules, unless they already ran.
+    /// `call` and `run` do this before calling their entry, while hosts only using
+    /// hooks need to do it first. If an initializer panics, the others are skipped.
+    pub fn initialize(&mut self) -> Result<(), ExecError> {
+        for symbol in mem::take(&mut self.inits) {
+            self.jit.exec::<()>(&symbol)?;
+        }
+        Ok(())
+    }
+
+    /// Call the given entry, which needs to return a value of type `T`.
+    pub fn call<T: AbiValue>(&mut self, entry: Entry) -> Result<T, ExecError> {
+        let (symbol, _) = self.find(entry, |func| {
+            func.params.is_empty() && T::matches(&func.ret_type)
+        })?;
+        self.initialize()?;
+        // The JIT reports the symbol, while callers know the entry by its name
+        self.jit.exec(&symbol).map_err(|err| match err {
+            ExecError::WrongSignature(_) => ExecError::WrongSignature(entry.name.into()),
+            err => err,
+        })
+    }
+
+    /// Call the given entry like `call`, without checking that it returns a value of type `T`.
+    /// This allows returning classes, which have no `AbiValue`.
+    ///
+    /// # Safety
+    /// The entry needs to return a value with the C ABI of `T`,
+    /// like a `#[repr(C)]` struct with the same fields as a returned class.
+    pub unsafe fn call_unchecked<T>(&mut self, entry: Entry) -> Result<T, ExecError> {
+        let (symbol, _) = self.find(entry, |func| func.params.is_empty())?;
+        self.initialize()?;
+        self.jit.exec_unchecked(&symbol)
+    }
+
+    /// Call the given entry, returning its exit code like `execute_program`.
+    pub fn run(&mut self, entry: Entry) -> Result<i64, ExecError> {
+        let (symbol, ret_type) = self.find(entry, |func| func.params.is_empty())?;
+        self.initialize()?;
+        if ret_type == Type::I64 {
+            self.jit.exec::<i64>(&symbol)
+        } else {
+            self.jit.exec::<()>(&symbol).map(|_| 0)
+        }
+    }
+
+    /// Returns a function pointer to the given entry. Unlike `call`,
+    /// this allows calling functions that take parameters,
+    /// like hooks that the host calls whenever an event occurs.
+    /// The returned hook borrows this program, since its code is freed with it.
+    /// A callback that panicked returns zeroed values; check `take_panic` after calling it.
+    pub fn callback<F: Callback>(&self, entry: Entry) -> Result<Hook<'_, F>, ExecError> {
+        let (symbol, _) = self.find(entry, |func| F::matches(&func.signature()))?;
+        let ptr = self
+            .jit
+            .function_ptr(&symbol)
+            .ok_or_else(|| ExecError::NoSuchFunction(entry.name.into()))?;
+        Ok(unsafe { Hook::new(F::from_ptr(ptr)) })
+    }
+
+    /// Returns a function pointer to the function exported under the given name
+    /// with `@export`, like `callback`. This allows hosts to find hooks by the same
+    /// name in every program, regardless of the module or name of the function.
+    pub fn exported<F: Callback>(&self, name: &str) -> Result<Hook<'_, F>, ExecError> {
+        let func = self
+            .modules
+            .iter()
+            .find_map(|module| {
+                let module = module.0.borrow();
+                module
+                    .funcs
+                    .iter()
+                    .find(|func| !func.external && func.link_name.as_deref() == Some(name))
+                    .cloned()
+            })
+            .ok_or_else(|| ExecError::NoSuchFunction(name.into()))?;
+        if !F::matches(&func.signature()) {
+            return Err(ExecError::WrongSignature(name.into()));
+        }
+        let ptr = self
+            .jit
+            .function_ptr(name)
+            .ok_or_else(|| ExecError::NoSuchFunction(name.into()))?;
+        Ok(unsafe { Hook::new(F::from_ptr(ptr)) })
+    }
+
+    /// Bind `value` to `name` in the environment of the program, which it
+    /// reads with `get_env(name)` or the typed `get_env_int`, `get_env_float`
+    /// and `get_env_bool`. Bindings can be changed between calls.
+    pub fn bind(&mut self, name: &str, value: impl Into<Binding>) {
+        self.jit.bind(SmolStr::new(name), value.into())
+    }
+
+    /// Returns the panic of a callback called since the last check, if one panicked.
+    pub fn take_panic(&self) -> Option<Panic> {
+        self.jit.take_panic()
+    }
+
+    /// Returns a handle to stop the program while it runs, for example
+    /// from an interrupt handler when the user asks to kill it.
+    /// Calls made while the program is stopped fail with `ExecError::Interrupted`.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.jit.interrupt_handle()
+    }
+
+    /// Returns the source maps of all functions in this program,
+    /// which allow finding the source of an instruction.
+    pub fn source_maps(&self) -> impl Iterator<Item = &SourceMap> {
+        self.jit.source_maps()
+    }
+
+    /// Returns the machine code size of every function in this program,
+    /// which shows what it uses the code heap of the host for.
+    pub fn compile_report(&self) -> CompileReport {
+        self.jit.compile_report()
+    }
+
+    /// Returns the source map of the function containing the instruction
+    /// at the given address, together with the source offset it was compiled from.
+    pub fn locate(&self, address: usize) -> Option<(&SourceMap, Option<usize>)> {
+        self.source_maps()
+            .find(|map| map.code.contains(&address))
+            .map(|map| (map, map.lookup(address)))
+    }
+
+    /// Returns the symbol and return type of the given entry.
+    /// Overloaded entries resolve to the first version that `fits`.
+    fn find(
+        &self,
+        entry: Entry,
+        fits: impl Fn(&Function) -> bool,
+    ) -> Result<(SmolStr, Type), ExecError> {
+        let mut found = false;
+        for module in &self.modules {
+            let module = module.0.borrow();
+            if let Some(path) = entry.module {
+                if !module
+                    .ast
+                    .path
+                    .iter()
+                    .map(SmolStr::as_str)
+                    .eq(path.iter().copied())
+                {
+                    continue;
+                }
+            }
+
+            for func in module
+                .funcs
+                .iter()
+                .filter(|func| func.name == entry.name && !func.external && func.live.get())
+            {
+                if fits(func) {
+                    return Ok((func.symbol(&module.ast.path), func.ret_type.clone()));
+                }
+                found = true;
+            }
+        }
+
+        if found {
+            Err(ExecError::WrongSignature(entry.name.into()))
+        } else {
+            Err(ExecError::NoSuchFunction(entry.name.into()))
+        }
+    }
+}
+
+/// A module compiled to IR. It can be kept around and linked into
+/// multiple executions without needing to be recompiled.
+/// Modules share their IR and can only be used by the thread that compiled
+/// them; use `CompiledProgram` to move them to another thread.
+#[derive(Clone)]
+pub struct CompiledModule(MutRc<Module>);
+
+impl CompiledModule {
+    /// Take the warnings found while compiling this module, like unused variables.
+    /// Always empty when compiled with `CompileOptions::warnings_as_errors`.
+    pub fn take_warnings(&self) -> Errors {
+        mem::take(&mut self.0.borrow_mut().warnings)
+    }
+
+    /// Returns the flags this module was compiled with, see `CompileOptions::flags`.
+    /// Modules loaded from a snapshot keep them, which allows rejecting
+    /// snapshots compiled with the wrong flags.
+    pub fn flags(&self) -> Vec<SmolStr> {
+        self.0.borrow().ast.flags.clone()
+    }
+
+    /// Whether this module was compiled from the given file, with the same path and contents.
+    /// Modules loaded from a snapshot are outdated if their files changed since.
+    pub fn compiled_from(&self, file: &File) -> bool {
+        let module = self.0.borrow();
+        module.ast.path == file.path
+            && module.ast.source_hash == parser::hash_source(&file.contents)
+    }
+}
+
+/// A compiled program frozen into an immutable form, which unlike
+/// `CompiledModule` is `Send` and `Sync`. This allows compiling a program
+/// on one thread or task and linking and executing it on another.
+#[derive(Clone)]
+pub struct CompiledProgram {
+    snapshot: Vec<u8>,
+}
+
+impl CompiledProgram {
+    /// Freeze the given modules, which need to contain all modules of the program.
+    pub fn freeze(modules: &[CompiledModule]) -> Self {
+        Self {
+            snapshot: save_snapshot(modules),
+        }
+    }
+
+    /// Returns new modules of this program, owned by the calling thread.
+    pub fn thaw(&self) -> Vec<CompiledModule> {
+        // Snapshots created by `freeze` always come from this compiler version
+        load_snapshot(&self.snapshot).expect("frozen program is a valid snapshot")
+    }
+
+    /// Link this program into executable code placed in memory
+    /// of the operating system, like `Program::new`.
+    #[cfg(feature = "std")]
+    pub fn link(&self, symbols: SymbolTable) -> Result<Program, ProgramError> {
+        Program::new(&self.thaw(), symbols)
+    }
+
+    /// Link this program into executable code placed in the given memory.
+    pub fn link_with_memory(
+        &self,
+        symbols: SymbolTable,
+        memory: CodeMemory,
+    ) -> Result<Program, ProgramError> {
+        Program::with_memory(&self.thaw(), symbols, memory)
+    }
+}
+
+/// Parse and compile the given files into modules.
+pub fn compile_files(files: &[File]) -> Result<Vec<CompiledModule>, Vec<Errors>> {
+    compile_files_with(files, &CompileOptions::default())
+}
+
+/// Parse and compile the given files into modules, using the given options.
+pub fn compile_files_with(
+    files: &[File],
+    options: &CompileOptions,
+) -> Result<Vec<CompiledModule>, Vec<Errors>> {
+    let mut modules = Vec::with_capacity(files.len());
+    let mut errors = Vec::with_capacity(files.len());
+
+    for file in files {
+        let parse = timings::measure(Stage::Parse, || {
+            Parser::new(&file.contents)
+                .with_flags(&options.flags)
+                .parse(file.path.clone())
+        });
+        match parse {
+            Ok(module) => {
+                modules.push(module);
+                errors.push(Vec::new());
             }
+            Err(err) => errors.push(err),
+        }
+    }
+    if modules.len() != files.len() {
+        return Err(errors);
+    }
+
+    let ir = timings::measure(Stage::Ir, || Compiler::new(modules, options).consume())?;
+    Ok(ir.into_iter().map(CompiledModule).collect())
+}
+
+/// Parse and compile the given files into modules, even if some of them
+/// contain errors. The errors of every file are returned alongside the modules.
+/// Functions containing errors panic when called, which allows running
+/// the rest of the program, as long as it does not call them.
+pub fn compile_files_recovering(
+    files: &[File],
+    options: &CompileOptions,
+) -> (Vec<CompiledModule>, Vec<Errors>) {
+    let mut modules = Vec::with_capacity(files.len());
+    let mut parse_errors = Vec::with_capacity(files.len());
+    for file in files {
+        let (module, errors) = timings::measure(Stage::Parse, || {
+            Parser::new(&file.contents)
+                .with_flags(&options.flags)
+                .parse_recovering(file.path.clone())
+        });
+        modules.push(module);
+        parse_errors.push(errors);
+    }
+
+    let (ir, mut errors) = timings::measure(Stage::Ir, || {
+        Compiler::new(modules, options).consume_recovering()
+    });
+    for (errors, parse_errors) in errors.iter_mut().zip(parse_errors) {
+        errors.splice(0..0, parse_errors);
+    }
+    (ir.into_iter().map(CompiledModule).collect(), errors)
+}
+
+/// Compile the program at the given paths without executing it,
+/// returning everything that stops it from compiling.
+pub fn check_path<FS: Filesystem>(fs: &FS, paths: &[&str]) -> Vec<Diagnostic> {
+    let files = match read_files(fs, paths) {
+        Ok(files) => files,
+        Err(errors) => return ProgramError::Read(errors).diagnostics(&[]),
+    };
+    match compile_files(&files) {
+        Ok(_) => Vec::new(),
+        Err(errors) => ProgramError::Compile(errors).diagnostics(&files),
+    }
+}
+
+/// Serialize compiled modules into a snapshot, which allows executing them
+/// later without compiling them again. Since modules reference each other,
+/// all modules of a program need to be saved together.
+pub fn save_snapshot(modules: &[CompiledModule]) -> Vec<u8> {
+    let modules = modules.iter().map(|m| m.0.clone()).collect::<Vec<_>>();
+    compiler::ir::snapshot::save(&modules)
+}
+
+/// Load modules from a snapshot created by `save_snapshot`.
+pub fn load_snapshot(data: &[u8]) -> Result<Vec<CompiledModule>, SnapshotError> {
+    let modules = compiler::ir::snapshot::load(data)?;
+    Ok(modules.into_iter().map(CompiledModule).collect())
+}
+
+/// Parse the given source, for fuzzing the parser.
+/// This never panics, regardless of input.
+#[cfg(feature = "std")]
+pub fn fuzz_parse(src: &str) -> Result<(), Errors> {
+    Parser::new(src)
+        .parse(vec![SmolStr::new_inline("fuzz")])
+        .map(|_| ())
+}
+
+/// Parse and compile the given source without executing it,
+/// for fuzzing the compiler. This never panics, regardless of input.
+#[cfg(feature = "std")]
+pub fn fuzz_compile(src: &str) -> Result<(), Vec<Errors>> {
+    let file = File {
+        path: vec![SmolStr::new_inline("fuzz")],
+        contents: src.into(),
+    };
+    compile_files(&[file]).map(|_| ())
+}
+
+/// Read all files at the given paths. Files are sorted by their module path,
+/// so that compilation does not depend on the order of the filesystem.
+/// Fails with every file that could not be read, if any.
+pub fn read_files<'fs, FS: Filesystem>(
+    fs: &'fs FS,
+    paths: &[&str],
+) -> Result<Vec<File<'fs>>, Vec<FileError>> {
+    let mut files = Vec::with_capacity(20);
+    let mut errors = Vec::new();
+    for path in paths {
+        fs.walk_directory(path, |file| match file {
+            Ok(file) => files.push(file),
+            Err(err) => errors.push(err),
         })
     }
     if !errors.is_empty() {
         return Err(errors);
     }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Returns the Cranelift IR of all functions in the given modules,
+/// as it is before being optimized and compiled to machine code.
+pub fn dump_clif(modules: &[CompiledModule]) -> String {
+    // Nothing is compiled to machine code, so no memory is needed
+    let mut jit = JIT::new(&[], CodeMemory::default());
+    let mut clif = String::new();
+    for module in modules {
+        jit.write_clif(&*module.0.borrow(), &mut clif);
+    }
+    clif
+}
+
+/// Compile the given modules into a WebAssembly module, which imports
+/// all `extern fun`s from `env` and exports all other functions.
+/// Not all of the language is supported yet, see `WasmError`.
+#[cfg(feature = "wasm")]
+pub fn compile_wasm(modules: &[CompiledModule]) -> Result<Vec<u8>, Vec<WasmError>> {
+    let mut wasm = wasm::Wasm::default();
+    match backend::lower(&mut wasm, modules) {
+        Ok(()) => wasm.finish(),
+        Err(never) => match never {},
+    }
+}
+
+fn jit_modules(mut jit: JIT, modules: &[CompiledModule]) -> Result<JIT, StateError> {
+    backend::lower(&mut jit, modules)?;
+    Ok(jit)
+}
 
-    let ir = Compiler::new(modules).consume()?;
-    let mut jit = JIT::new(symbols);
+/// Returns the symbols of all module initializers. They run after those of the
+/// modules they import, and otherwise in the order of the modules.
+fn init_order(modules: &[CompiledModule]) -> Vec<SmolStr> {
+    fn visit(
+        modules: &[CompiledModule],
+        index: usize,
+        visited: &mut [bool],
+        inits: &mut Vec<SmolStr>,
+    ) {
+        if mem::replace(&mut visited[index], true) {
+            return;
+        }
+        let module = modules[index].0.borrow();
+        for import in &module.imports {
+            if let Some(imported) = modules
+                .iter()
+                .position(|m| m.0.borrow().ast.path == *import)
+            {
+                visit(modules, imported, visited, inits);
+            }
+        }
+        if let Some(init) = module.init {
+            inits.push(module.funcs[init].symbol(&module.ast.path));
+        }
+    }
 
-    for module in &ir {
-        jit.jit_module(&*module.borrow());
+    let mut visited = vec![false; modules.len()];
+    let mut inits = Vec::new();
+    for index in 0..modules.len() {
+        visit(modules, index, &mut visited, &mut inits);
     }
-    Ok(jit.exec("main"))
+    inits
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{execute_module, execute_with_os_fs};
+    use crate::{
+        check_path, compile_files, compile_files_recovering, compile_files_with,
+        compiler::{
+            ir::{Expr, IExpr},
+            validate::validate,
+        },
+        diagnostics_to_json, dump_clif, execute_module, execute_modules, execute_program,
+        execute_with_os_fs,
+        filesystem::{
+            memory_fs::MemoryFs, os_fs::OsFs, File, FileError, FileErrorKind, Filesystem,
+        },
+        fuzz_compile, fuzz_parse, line_column, load_snapshot,
+        parser::Parser,
+        read_files, save_snapshot,
+        testing::{self, Expectation, TestFailure},
+        tooling::{highlight, parse_file, Symbol, SymbolKind, TokenClass},
+        vm::{StateError, JIT},
+        AbiValue, CodeMemory, CompileOptions, CompiledModule, CompiledProgram, Diagnostic, Edition,
+        Engine, Entry, ErrorKind, Errors, ExecError, Hook, Program, ProgramError, Repl, SmolStr,
+        SnapshotError, StrRef, Target, TargetError,
+    };
+    use alloc::{borrow::Cow, string::String, vec, vec::Vec};
     extern crate std;
     use crate::vm::SymbolTable;
-    use core::fmt::Debug;
-    use std::format;
+    use core::{cell::RefCell, fmt::Debug};
+    use std::{format, string::ToString};
+    use target_lexicon::Triple;
 
-    fn directory<T: Debug + PartialEq>(dir: &str, expect: T, symbols: SymbolTable) {
+    fn directory<T: AbiValue + Debug + PartialEq>(dir: &str, expect: T, symbols: SymbolTable) {
         let res = execute_with_os_fs::<T>(&[dir], symbols).unwrap();
         assert_eq!(res, expect)
     }
 
-    fn file<T: Debug + PartialEq>(input: &str, expect: T) {
+    fn file<T: AbiValue + Debug + PartialEq>(input: &str, expect: T) {
         file_(input, expect, &[])
     }
 
-    fn file_<T: Debug + PartialEq>(input: &str, expect: T, symbols: SymbolTable) {
+    fn file_<T: AbiValue + Debug + PartialEq>(input: &str, expect: T, symbols: SymbolTable) {
         let res = execute_module::<T>(input, symbols).unwrap();
         assert_eq!(res, expect)
     }
 
-    fn expr<T: Debug + PartialEq>(input: &str, ret_type: &str, expect: T) {
+    fn compile(input: &str, options: &CompileOptions) -> Vec<CompiledModule> {
+        compile_files_with(&[module(&["test"], input)], options).unwrap()
+    }
+
+    fn module<'a>(path: &[&str], src: impl Into<Cow<'a, str>>) -> File<'a> {
+        File {
+            path: path.iter().copied().map(SmolStr::new).collect(),
+            contents: src.into(),
+        }
+    }
+
+    std::thread_local! {
+        static CALLS: RefCell<Vec<i64>> = RefCell::new(Vec::new());
+    }
+
+    extern "C" fn record(value: i64) -> i64 {
+        CALLS.with(|calls| calls.borrow_mut().push(value));
+        value
+    }
+
+    fn recorded() -> Vec<i64> {
+        CALLS.with(RefCell::take)
+    }
+
+    fn expr<T: AbiValue + Debug + PartialEq>(input: &str, ret_type: &str, expect: T) {
         file::<T>(
             &format!("fun main() {} {{ {} \n }}", ret_type, input),
             expect,
         );
     }
 
+    fn file_err(input: &str, code: &str) {
+        let errors = match execute_module::<()>(input, &[]) {
+            Err(ProgramError::Compile(errors)) => errors.into_iter().flatten().collect::<Vec<_>>(),
+            res => panic!("expected {}, got {:?}", code, res),
+        };
+        assert!(
+            errors
+                .iter()
+                .any(|err| format!("{:?}", err.kind()).starts_with(code)),
+            "expected {}, got {:?}",
+            code,
+            errors
+        );
+    }
+
+    fn expr_err(input: &str, code: &str) {
+        file_err(&format!("fun main() {{ {} \n }}", input), code);
+    }
+
     fn expr_none(input: &str) {
         expr(input, "", ())
     }
@@ -120,6 +758,36 @@ mod test {
         expr_i64("64 / 8", 8);
     }
 
+    #[test]
+    fn int_binary() {
+        expr_i64("17 % 5", 2);
+        expr_i64("12 & 10", 8);
+        expr_i64("12 | 3", 15);
+        expr_i64("12 ^ 10", 6);
+        expr_i64("1 << 4", 16);
+        expr_i64("0 - 64 >> 2", -16);
+        expr_i64("1 | 2 << 1 + 1", 9);
+    }
+
+    #[test]
+    fn floats() {
+        expr("1.5 + 2.25", "-> f64", 3.75);
+        expr("10.0 / 4.0 - 0.5 * 2.0", "-> f64", 1.5);
+        expr("1.5f64", "-> f64", 1.5);
+        expr("2.0f32 * 1.5f32", "-> f32", 3.0f32);
+        expr_bool("1.5 < 2.0", true);
+        expr_bool("2.5f32 >= 2.5f32", true);
+        expr_bool("0.1 + 0.2 == 0.3", false);
+        expr("f64(1.5f32)", "-> f64", 1.5);
+        expr("f32(2.5) + 0.5f32", "-> f32", 3.0f32);
+        expr("val a = 0.1f32 \n f64(a) == 0.1", "-> bool", false);
+
+        expr_err("1.5 + 1.5f32", "E500");
+        expr_err("1.5 % 2.0", "E501");
+        expr_err("f64(1)", "E518");
+        expr_err("f32(1.0, 2.0)", "E507");
+    }
+
     #[test]
     fn logic() {
         expr_bool("5 == 5", true);
@@ -154,6 +822,19 @@ mod test {
     fn while_() {
         expr_i64("var a = 3 \n while (a < 10) { a = a + 1 } \n a", 10);
         expr_i64("var a = 3 \n while (a > 10) { a = a + 1 } \n a", 3);
+        expr_i64(
+            "var a = 0 \n while (true) { a = a + 1 \n if (a == 5) break } \n a",
+            5,
+        );
+        expr_i64("while (false) break 1 else 7", 7);
+        expr_i64(
+            "var a = 0 \n while (a < 10) { a = a + 1 \n if (a == 5) break a * 2 } else 0",
+            10,
+        );
+        expr_none("while (true) break 1");
+        expr_err("break", "E522");
+        expr_err("while (true) { val f = fun() { break } }", "E522");
+        expr_err("val a = while (true) break 1 \n a", "E504");
     }
 
     #[test]
@@ -169,36 +850,1682 @@ mod test {
     }
 
     #[test]
-    fn basic_funcs() {
-        file(include_str!("../tests/basic_funcs.yacari"), 422);
+    fn immutable() {
+        expr_err("val a = 44 \n a = 4", "E515");
+        expr_err("val c = 24 \n c = c + 2", "E515");
+        file_err("fun main() { f(1) }\nfun f(a: i64) { a = 2 }", "E515");
     }
 
     #[test]
-    fn basic_modules() {
-        directory(
-            "tests/basic_modules",
-            13,
-            &[("hello", (|| 13) as fn() -> i64 as *const u8)],
+    fn shadowing() {
+        expr_i64("val a = 1 \n val b = { val a = 2 \n a } \n a + b", 3);
+        expr_i64("var a = 1 \n if (true) { val a = 5 \n a } \n a", 1);
+        expr_err("val a = 1 \n val a = 2", "E516");
+    }
+
+    #[test]
+    fn invalid_input() {
+        expr_err("99999999999999999999", "E103");
+        expr_err("5i64", "E103");
+        expr_err(&"(".repeat(1000), "E104");
+        expr_err("-5", "E517");
+        expr_err("\"\\q\"", "E105");
+        file_err("fun main(a: Unknown) {}", "E200");
+    }
+
+    #[test]
+    fn fuzz_regressions() {
+        let inputs = [
+            "",
+            "fun",
+            "fun main() -> fun(",
+            "class A { fun get() -> i64 5 }\nfun main() {}",
+            "fun main() {}\nclass main {}",
+            "fun a() {}\nextern fun a(b: i64)",
+        ];
+        for input in &inputs {
+            let _ = fuzz_parse(input);
+            let _ = fuzz_compile(input);
+        }
+        let _ = fuzz_compile(&format!("fun main() {}", "- ".repeat(10000)));
+    }
+
+    #[test]
+    fn params_and_locals() {
+        file(
+            "fun main() -> i64 f(2)\nfun f(a: i64) -> i64 { val b = 3 \n val c = a * b \n c + a }",
+            8,
         );
     }
 
     #[test]
-    fn basic_ffi() {
-        #[repr(C)]
-        #[derive(Debug, PartialEq)]
-        struct TestStruct {
-            a: i64,
-            b: i64,
+    fn tail_calls() {
+        file(
+            "fun main() -> i64 count(0, 1000000)
+            fun count(acc: i64, n: i64) -> i64 {
+                if (n == 0) acc
+                else count(acc + 1, n - 1)
+            }",
+            1000000i64,
+        );
+        file(
+            "fun main() -> i64 {
+                spin(1000000)
+                5
+            }
+            fun spin(n: i64) {
+                if (n > 0) spin(n - 1)
+            }",
+            5i64,
+        );
+    }
+
+    #[test]
+    fn default_args() {
+        // Defaults are compiled in the scope of their function instead of the caller's
+        let files = [
+            module(
+                &["app"],
+                "import lib\nfun main() -> i64 {\n val SCALE = 100\n scaled(7)\n}",
+            ),
+            module(
+                &["lib"],
+                "val SCALE = 3\nfun base() -> i64 2\n\
+                 fun scaled(value: i64, factor: i64 = SCALE * base()) -> i64 value * factor",
+            ),
+        ];
+        assert_eq!(
+            execute_modules(&compile_files(&files).unwrap(), &[]).unwrap(),
+            42
+        );
+
+        // and checked once, even without any calls
+        let files = [module(
+            &["test"],
+            "fun f(a: i64 = true) -> i64 a\nfun main() {}",
+        )];
+        let errors = compile_files(&files).err().unwrap();
+        assert_eq!(errors[0][0].kind().code(), "E508");
+    }
+
+    #[test]
+    fn module_initializers() {
+        let module = |path: &[&str], src: &str| {
+            module(
+                path,
+                format!("extern fun record(value: i64) -> i64\n{}", src),
+            )
+        };
+        let files = [
+            module(
+                &["app"],
+                "import lib::a\nfun init() { record(1) }\nfun main() -> i64 { record(0) \n 5 }",
+            ),
+            module(&["lib", "a"], "import lib::b\nfun init() { record(2) }"),
+            module(&["lib", "b"], "fun init() { record(3) }"),
+            module(
+                &["other"],
+                "fun init() { record(4) }\nfun helper() -> i64 7",
+            ),
+        ];
+        let symbols: SymbolTable = &[("record", record as *const u8)];
+        let options = CompileOptions {
+            edition: Edition::V2,
+            ..CompileOptions::default()
+        };
+        let mut program =
+            Program::new(&compile_files_with(&files, &options).unwrap(), symbols).unwrap();
+        assert_eq!(program.run(Entry::MAIN), Ok(5));
+        assert_eq!(program.run(Entry::MAIN), Ok(5));
+        assert_eq!(recorded(), [3, 2, 1, 4, 0, 0]);
+
+        // Initializers also run before other entries, and in separately compiled modules
+        let options = CompileOptions {
+            entry_points: vec![SmolStr::new_inline("helper")],
+            ..options
+        };
+        let mut modules = compile_files_with(&files[3..], &options).unwrap();
+        modules.extend(compile_files_with(&files[..3], &options).unwrap());
+        let mut program = Program::new(&modules, symbols).unwrap();
+        assert_eq!(program.call::<i64>(Entry::new("helper")), Ok(7));
+        assert_eq!(recorded(), [4, 3, 2, 1]);
+
+        let errors = compile_files_with(&[module(&["app"], "fun init() -> i64 1")], &options).err();
+        assert_eq!(errors.unwrap()[0][0].kind().code(), "E210");
+        // Before the edition introducing them, `init` is an ordinary function
+        let modules = compile_files(&[module(&["app"], "fun init() -> i64 1\nfun main() {}")]);
+        assert!(modules.is_ok());
+    }
+
+    #[test]
+    fn evaluation_order() {
+        let run = |src: &str| {
+            let symbols: SymbolTable = &[("record", record as *const u8)];
+            let src = format!("extern fun record(value: i64) -> i64\n{}", src);
+            let result = execute_module::<i64>(&src, symbols).unwrap();
+            (result, recorded())
+        };
+        let sub = "fun sub(a: i64, b: i64) -> i64 a - b\n";
+
+        assert_eq!(
+            run(&format!(
+                "{}fun main() -> i64 sub(record(1), sub(record(2), record(3)))",
+                sub
+            )),
+            (2, vec![1, 2, 3])
+        );
+        assert_eq!(
+            run(&format!(
+                "{}fun main() -> i64 sub(b = record(1), a = record(2))",
+                sub
+            )),
+            (1, vec![1, 2])
+        );
+        assert_eq!(
+            run("fun main() -> i64 f(record(1), c = record(2))\n\
+                 fun f(a: i64, b: i64 = record(3), c: i64 = record(4)) -> i64 a + b + c"),
+            (6, vec![1, 2, 3])
+        );
+        assert_eq!(
+            run(
+                "class C { val v: i64 \n fun sub(a: i64, b: i64) -> i64 this.v + a - b }\n\
+                 fun main() -> i64 C(record(1)).sub(b = record(2), a = record(3))"
+            ),
+            (2, vec![1, 2, 3])
+        );
+        assert_eq!(
+            run("fun main() -> i64 pick(record(1))(record(2))\n\
+                 fun pick(a: i64) -> fun(i64) -> i64 double\n\
+                 fun double(a: i64) -> i64 a * 2"),
+            (4, vec![1, 2])
+        );
+        assert_eq!(
+            run("fun main() -> i64 record(1) - record(2)"),
+            (0 - 1, vec![1, 2])
+        );
+    }
+
+    #[test]
+    fn local_functions() {
+        file_err("fun main() { val a = 1 \n fun f() -> i64 a }", "E503");
+        file_err("fun main() { { fun f() {} } \n f() }", "E503");
+        file_err("fun main() { fun f() {} \n fun f() {} }", "E201");
+    }
+
+    #[test]
+    fn closures() {
+        file_err("fun main() { var a = 1 \n val f = fun() -> i64 a }", "E519");
+        file_err("fun main() { val f = fun() -> i64 a \n val a = 1 }", "E503");
+        // Environments are capped, since they are only freed with the program
+        match execute_module::<i64>(
+            "fun main() -> i64 { val a = 1 \n while (true) { val f = fun() -> i64 a } \n 0 }",
+            &[],
+        ) {
+            Err(ProgramError::Exec(ExecError::Panicked(panic))) => {
+                assert_eq!(panic.message, "too many closures")
+            }
+            res => panic!("expected a panic, got {:?}", res),
         }
+    }
 
-        fn make_struct() -> TestStruct {
-            TestStruct { a: 24, b: 457 }
+    #[test]
+    fn operators() {
+        // Division rounds towards zero, like the remainder
+        let div = |a: &str, b: &str| {
+            let src = format!(
+                "fun main() -> i64 div({a}, {b}) * 10 + rem({a}, {b})\n\
+                 fun div(a: i64, b: i64) -> i64 a / b\n\
+                 fun rem(a: i64, b: i64) -> i64 a % b",
+                a = a,
+                b = b
+            );
+            execute_module::<i64>(&src, &[]).unwrap()
+        };
+        assert_eq!(div("0 - 7", "2"), -3 * 10 - 1);
+        assert_eq!(div("7", "0 - 2"), -3 * 10 + 1);
+        assert_eq!(div("0 - 8", "0 - 2"), 4 * 10);
+        let class = "class A { val a: i64 \n fun equals(o: A) -> i64 0 } \n";
+        file_err(&format!("{}fun main() -> A A(1) + A(2)", class), "E501");
+        file_err(&format!("{}fun main() -> bool A(1) == A(2)", class), "E520");
+        file_err(&format!("{}fun main() -> i64 A(1).b", class), "E521");
+        file_err(&format!("{}fun main() -> A A(true)", class), "E508");
+        file_err(&format!("{}fun main() -> A A(1, 2)", class), "E507");
+    }
+
+    #[test]
+    fn constructors() {
+        // Constructors are functions named after their class
+        let class = "class A { val a: i64 \n val b: i64 = 2 } \n";
+        file(
+            &format!("{}fun main() -> i64 A(b = 1, a = 41).a + A(1).b", class),
+            43,
+        );
+        file(
+            &format!("{}fun main() -> i64 {{ val f = A \n f(1, 2).b }}", class),
+            2,
+        );
+        file_err(&format!("{}fun main() -> A A()", class), "E507");
+        file_err(&format!("{}fun main() -> A A(1, c = 2)", class), "E509");
+        file_err("class A { init {} \n init {} }", "E201");
+    }
+
+    #[test]
+    fn explain() {
+        let codes = (100..109).chain(200..212).chain(500..523);
+        for code in codes
+            .map(|code| format!("E{}", code))
+            .chain(std::iter::once("W100".into()))
+        {
+            let explanation = ErrorKind::explain_code(&code).unwrap();
+            assert!(explanation.starts_with(&format!("{}: ", code)));
         }
+        assert!(ErrorKind::explain_code("e503").is_some());
+        assert_eq!(ErrorKind::explain_code("E999"), None);
 
-        file_(
-            include_str!("../tests/basic_ffi.yacari"),
-            make_struct(),
-            &[("make_struct", make_struct as *const u8)],
+        let errors = match execute_module::<()>("fun main() { x }", &[]) {
+            Err(ProgramError::Compile(errors)) => errors.into_iter().flatten().collect::<Vec<_>>(),
+            res => panic!("expected E503, got {:?}", res),
+        };
+        assert_eq!(
+            format!("{}", errors[0]),
+            "E503 at offset 13: Unknown variable 'x'."
+        );
+        assert!(errors[0].kind().explain().starts_with("E503: "));
+    }
+
+    #[test]
+    fn recursive_classes() {
+        file(
+            "class A { val b: B }\nclass B { val x: i64 }\nfun main() -> i64 A(B(42)).b.x",
+            42,
+        );
+        file_err("class Node { val next: Node }\nfun main() {}", "E206");
+
+        let errors = match execute_module::<()>("class A { val b: B }\nclass B { val a: A }", &[]) {
+            Err(ProgramError::Compile(errors)) => errors.into_iter().flatten().collect::<Vec<_>>(),
+            res => panic!("expected E206, got {:?}", res),
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            format!("{:?}", errors[0].kind()),
+            r#"E206 { name: "A", cycle: "A.b -> B.a" }"#
+        );
+    }
+
+    #[test]
+    fn dead_code() {
+        file(DEAD_CODE, 42);
+
+        let live = |eliminate_dead_code| {
+            let options = CompileOptions {
+                eliminate_dead_code,
+                inline_threshold: 0,
+                ..CompileOptions::default()
+            };
+            let modules = compile(DEAD_CODE, &options);
+            let module = modules[0].0.borrow();
+            module
+                .funcs
+                .iter()
+                .map(|f| f.live.get())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(live(true), [true, true, false, false]);
+        assert_eq!(live(false), [true, true, true, true]);
+    }
+
+    const DEAD_CODE: &str = "fun main() -> i64 used()\n\
+                             fun used() -> i64 42\n\
+                             fun unused() -> i64 missing()\n\
+                             extern fun missing() -> i64";
+
+    #[test]
+    fn inlining() {
+        file(INLINING, 45);
+
+        let main_calls = |source: &str, inline_threshold| {
+            let options = CompileOptions {
+                inline_threshold,
+                ..CompileOptions::default()
+            };
+            let modules = compile(source, &options);
+            let module = modules[0].0.borrow();
+            let mut calls = 0;
+            module.funcs[0].body.borrow().visit(&mut |expr| {
+                if let IExpr::Call { .. } = &*expr.inner {
+                    calls += 1;
+                }
+            });
+            calls
+        };
+        assert_eq!(main_calls(INLINING, 0), 3);
+        // `countdown` is recursive and stays a call
+        assert_eq!(main_calls(INLINING, 10), 1);
+        assert_eq!(main_calls(INLINING, 1), 3);
+        // `@inline` ignores the size, but not whether inlining is enabled
+        let marked = INLINING.replace("fun add", "@inline fun add");
+        assert_eq!(main_calls(&marked, 1), 2);
+        assert_eq!(main_calls(&marked, 0), 3);
+    }
+
+    const INLINING: &str = "fun main() -> i64 add(40, double(2)) + countdown(1)\n\
+                            fun add(a: i64, b: i64) -> i64 a + b\n\
+                            fun double(a: i64) -> i64 { val b = a * 2 \n b }\n\
+                            fun countdown(a: i64) -> i64 if (a == 0) 1 else countdown(a - 1)";
+
+    #[test]
+    #[should_panic(expected = "poison value")]
+    fn validate_poison() {
+        let modules = compile(
+            "fun main() -> i64 { val a = 4 \n a }",
+            &CompileOptions::default(),
         );
+        let module = modules[0].0.borrow();
+        *module.funcs[0].body.borrow_mut() = Expr::poison();
+        validate(&module);
+    }
+
+    #[test]
+    #[should_panic(expected = "variable index 5 out of bounds")]
+    fn validate_variables() {
+        let modules = compile(
+            "fun main() -> i64 { val a = 4 \n a }",
+            &CompileOptions::default(),
+        );
+        let module = modules[0].0.borrow();
+        let body = module.funcs[0]
+            .body
+            .borrow()
+            .map_variables(&|index| index + 5);
+        *module.funcs[0].body.borrow_mut() = body;
+        validate(&module);
+    }
+
+    #[test]
+    fn basic_modules() {
+        directory(
+            "tests/basic_modules",
+            13,
+            &[("hello", (|| 13) as fn() -> i64 as *const u8)],
+        );
+    }
+
+    #[test]
+    fn directives() {
+        let mut paths = std::fs::read_dir("tests")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut ran = 0;
+        for path in paths {
+            let path = path.to_str().unwrap();
+            // Unreadable files are tested on their own
+            let files = match read_files(&OsFs, &[path]) {
+                Ok(files) => files,
+                Err(_) => continue,
+            };
+            if let Some(result) = testing::run_test(&files, &[]) {
+                assert_eq!(result, Ok(()), "{}", path);
+                ran += 1;
+            }
+        }
+        assert!(ran > 10);
+
+        let file = |src| module(&["test"], src);
+        assert_eq!(testing::run_test(&[file("fun main() {}")], &[]), None);
+        assert_eq!(
+            testing::run_test(&[file("// expect: 4\nfun main() -> i64 5")], &[]),
+            Some(Err(TestFailure::WrongValue {
+                expected: 4,
+                found: 5
+            }))
+        );
+        assert_eq!(
+            testing::run_test(&[file("// expect: 4\n// expect-error: E503")], &[]),
+            Some(Err(TestFailure::InvalidDirective {
+                file: "test.yacari".into(),
+                line: 2
+            }))
+        );
+        assert_eq!(
+            testing::expectation(&[file("// expect-error: E503\n// expect-error: E500")]),
+            Ok(Some(Expectation::Errors(vec![
+                SmolStr::new_inline("E503"),
+                SmolStr::new_inline("E500")
+            ])))
+        );
+    }
+
+    #[test]
+    fn imports() {
+        directory("tests/imports", 42, &[]);
+
+        let file = module(&["main"], "import std::colections\nfun main() {}");
+        let errors = compile_files(&[file]).err().unwrap();
+        assert_eq!(errors[0].len(), 1);
+        assert!(matches!(errors[0][0].kind(), ErrorKind::E207(path) if path == "std::colections"));
+    }
+
+    #[test]
+    fn exit_code() {
+        let code = execute_program(OsFs, &["tests/exit_code.yacari"], &[]).unwrap();
+        assert_eq!(code, 3);
+    }
+
+    #[test]
+    fn exec_errors() {
+        let exec_err = |input: &str| match execute_module::<i64>(input, &[]) {
+            Err(ProgramError::Exec(err)) => err,
+            res => panic!("expected exec error, got {:?}", res),
+        };
+        let main = SmolStr::new_inline("main");
+        assert_eq!(
+            exec_err("fun other() {}"),
+            ExecError::NoSuchFunction(main.clone())
+        );
+        assert_eq!(
+            exec_err("fun main() {}"),
+            ExecError::WrongSignature(main.clone())
+        );
+        assert_eq!(
+            exec_err("fun main(a: i64) -> i64 a"),
+            ExecError::WrongSignature(main.clone())
+        );
+        assert_eq!(
+            exec_err("fun main() -> bool true"),
+            ExecError::WrongSignature(main.clone())
+        );
+
+        let modules = compile("fun main() -> i64 42", &CompileOptions::default());
+        let mut program = Program::new(&modules, &[]).unwrap();
+        let wrong = || ExecError::WrongSignature(main.clone());
+        assert_eq!(program.call::<bool>(Entry::MAIN), Err(wrong()));
+        assert_eq!(program.call::<f64>(Entry::MAIN), Err(wrong()));
+        assert_eq!(program.call::<StrRef>(Entry::MAIN), Err(wrong()));
+        assert_eq!(program.call::<i64>(Entry::MAIN), Ok(42));
+    }
+
+    #[test]
+    fn reuse_compiled() {
+        let modules =
+            compile_files(&read_files(&OsFs, &["tests/exit_code.yacari"]).unwrap()).unwrap();
+        assert_eq!(execute_modules(&modules, &[]).unwrap(), 3);
+        assert_eq!(execute_modules(&modules, &[]).unwrap(), 3);
+    }
+
+    #[test]
+    fn engine() {
+        let engine = Engine::new(OsFs)
+            .path("tests/exit_code.yacari")
+            .inline_threshold(0);
+        assert_eq!(engine.execute().unwrap(), 3);
+        assert_eq!(engine.run::<i64>().unwrap(), 3);
+
+        let library = module(&["library"], "fun answer() -> i64 42");
+        let options = CompileOptions {
+            entry_points: vec![SmolStr::new_inline("answer")],
+            ..CompileOptions::default()
+        };
+        let library = compile_files_with(&[library], &options).unwrap();
+        let engine = engine
+            .library(library)
+            .entry(Entry::in_module(&["library"], "answer"));
+        assert_eq!(engine.run::<i64>().unwrap(), 42);
+        assert_eq!(engine.compile().unwrap().len(), 2);
+
+        let missing = Engine::new(OsFs)
+            .path("tests/exit_code.yacari")
+            .entry(Entry::new("start"));
+        assert!(matches!(
+            missing.execute(),
+            Err(ProgramError::Exec(ExecError::NoSuchFunction(name))) if name == "start"
+        ));
+
+        // Modules of the program and of its libraries need different paths
+        let files = read_files(&OsFs, &["tests/exit_code.yacari"]).unwrap();
+        let program = compile_files(&files).unwrap();
+        let engine = Engine::new(OsFs)
+            .path("tests/exit_code.yacari")
+            .library(program.clone());
+        assert!(matches!(
+            engine.compile(),
+            Err(ProgramError::Link(StateError::DefinedTwice(path))) if path == ["exit_code"]
+        ));
+        let twice = [program.clone(), program].concat();
+        assert!(matches!(
+            Program::new(&twice, &[]),
+            Err(ProgramError::Link(StateError::DefinedTwice(path))) if path == ["exit_code"]
+        ));
+    }
+
+    #[test]
+    fn warnings() {
+        let file = || {
+            module(
+                &["warnings"],
+                "fun main() -> i64 {\n    val unused = 1\n    val _ignored = 2\n    \
+                       val used = 3\n    val captured = 4\n    val f = fun() -> i64 captured\n    \
+                       used + f()\n}",
+            )
+        };
+        let modules = compile_files(&[file()]).unwrap();
+        let warnings = modules[0].take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0].kind(), ErrorKind::W100 { name } if name == "unused"));
+        assert!(modules[0].take_warnings().is_empty());
+
+        let options = CompileOptions {
+            warnings_as_errors: true,
+            ..CompileOptions::default()
+        };
+        match compile_files_with(&[file()], &options) {
+            Err(errors) => assert_eq!(errors[0][0].kind().code(), "W100"),
+            Ok(_) => panic!("warning was not reported as an error"),
+        }
+    }
+
+    #[test]
+    fn repl() {
+        let mut repl = Repl::new(&[]);
+        assert_eq!(repl.eval("1 + 2").unwrap().as_deref(), Some("3"));
+        assert_eq!(repl.eval("fun double(a: i64) -> i64 a * 2").unwrap(), None);
+        assert_eq!(repl.eval("double(21)").unwrap().as_deref(), Some("42"));
+        assert_eq!(repl.eval("val name = \"yacari\"").unwrap(), None);
+        assert_eq!(repl.eval("name").unwrap().as_deref(), Some("\"yacari\""));
+        assert_eq!(repl.eval("1.5 + 0.5").unwrap().as_deref(), Some("2.0"));
+        assert_eq!(
+            repl.eval("double(1) == 2").unwrap().as_deref(),
+            Some("true")
+        );
+        assert_eq!(repl.eval("class Point {\n val x: i64 \n}").unwrap(), None);
+        assert_eq!(repl.eval("Point(1)").unwrap().as_deref(), Some("<Point>"));
+        assert_eq!(repl.eval("while (false) {}").unwrap(), None);
+        assert_eq!(repl.eval("  ").unwrap(), None);
+
+        assert_eq!(repl.type_of("double").unwrap(), "fun(i64) -> i64");
+        assert_eq!(repl.type_of("Point(1).x").unwrap(), "i64");
+        assert!(repl.dump_clif("double(1)").unwrap().contains("__repl"));
+
+        // Errors are located in the input, and do not change the session
+        match repl.eval("double(true)") {
+            Err(ProgramError::Compile(errors)) => assert_eq!(errors[0][0].start(), 0),
+            _ => panic!("expected a compile error"),
+        }
+        match repl.eval("class Point {\n val y: i64 \n}") {
+            Err(ProgramError::Compile(errors)) => assert_eq!(errors[0][0].kind().code(), "E201"),
+            _ => panic!("expected a compile error"),
+        }
+        assert_eq!(repl.eval("double(2)").unwrap().as_deref(), Some("4"));
+
+        assert!(Repl::is_complete("double(1)"));
+        assert!(!Repl::is_complete("fun triple(a: i64) -> i64 {"));
+        assert!(!Repl::is_complete("double(1) +"));
+        assert!(!Repl::is_complete("\"unterminated"));
+    }
+
+    #[test]
+    fn cfg() {
+        let engine = Engine::new(OsFs).path("tests/cfg.yacari");
+        assert_eq!(engine.run::<i64>().unwrap(), 42);
+        let engine = engine.flag("kernel");
+        assert_eq!(engine.run::<i64>().unwrap(), 12);
+        // Neither `OFFSET` matches
+        assert!(matches!(
+            engine.flag("host").run::<i64>(),
+            Err(ProgramError::Compile(errors)) if errors[0][0].kind().code() == "E503"
+        ));
+
+        let codes = |source: &str| {
+            let errors = Parser::new(source)
+                .parse(vec![SmolStr::new_inline("test")])
+                .unwrap_err();
+            errors
+                .iter()
+                .map(|err| err.kind().code())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(codes("@config(kernel) fun main() {}"), ["E106"]);
+        assert_eq!(
+            codes("@cfg fun a() {}\n@cfg() fun b() {}"),
+            ["E107", "E107"]
+        );
+        assert_eq!(codes("class A { @cfg(kernel) val a: i64 }"), ["E108"]);
+        assert_eq!(codes("fun main() { @cfg(kernel) fun a() {} }")[0], "E108");
+    }
+
+    #[test]
+    fn import_cycles() {
+        let diagnostics = check_path(&OsFs, &["tests/import_cycle"]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file, "import_cycle/c.yacari");
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (2, 8));
+        assert_eq!(diagnostics[0].code, Some("E208"));
+        assert_eq!(
+            diagnostics[0].message,
+            "Modules import each other through \
+             'import_cycle::a -> import_cycle::b -> import_cycle::c -> import_cycle::a'."
+        );
+    }
+
+    #[test]
+    fn recovering() {
+        let files = [
+            module(
+                &["broken"],
+                "fun broken() -> i64 unknown + 1\nfun bad(a: Nope) -> i64 1\nfun working() -> i64 2\n\
+                 class C { static val V = working() }",
+            ),
+            module(
+                &["main"],
+                "import broken\nfun main() -> i64 working() + 40\n\
+                 fun calls_broken() -> i64 broken()\nfun calls_bad() -> i64 bad(1)",
+            ),
+        ];
+        let options = CompileOptions {
+            eliminate_dead_code: false,
+            ..CompileOptions::default()
+        };
+        assert!(compile_files_with(&files, &options).is_err());
+        let (modules, errors) = compile_files_recovering(&files, &options);
+        // Poison does not cause more errors where it is used
+        let codes = |errors: &Errors| errors.iter().map(|e| e.kind().code()).collect::<Vec<_>>();
+        assert_eq!(codes(&errors[0]), ["E200", "E203", "E503"]);
+        assert!(errors[1].is_empty());
+
+        let panic = |result| match result {
+            Err(ExecError::Panicked(panic)) => panic.message,
+            res => panic!("expected a panic, got {:?}", res),
+        };
+        let mut program = Program::new(&modules, &[]).unwrap();
+        assert_eq!(program.run(Entry::MAIN), Ok(42));
+        assert_eq!(
+            panic(program.call::<i64>(Entry::new("calls_broken"))),
+            "function 'broken' failed to compile"
+        );
+        // Calling a function with a poisoned parameter poisons the caller as well
+        assert_eq!(
+            panic(program.call::<i64>(Entry::new("calls_bad"))),
+            "function 'calls_bad' failed to compile"
+        );
+
+        // Freezing keeps what is missing from modules with errors
+        let mut program = CompiledProgram::freeze(&modules).link(&[]).unwrap();
+        assert_eq!(program.run(Entry::MAIN), Ok(42));
+    }
+
+    #[test]
+    fn entry_points() {
+        let files = [
+            module(&["a"], "fun value() -> i64 1\nfun init() -> i64 value()"),
+            module(&["b"], "fun value() -> i64 2"),
+        ];
+        let options = CompileOptions {
+            entry_points: vec![SmolStr::new_inline("init"), SmolStr::new_inline("value")],
+            ..CompileOptions::default()
+        };
+        let modules = compile_files_with(&files, &options).unwrap();
+
+        let mut program = Program::new(&modules, &[]).unwrap();
+        assert_eq!(program.call::<i64>(Entry::new("init")), Ok(1));
+        assert_eq!(
+            program.call::<i64>(Entry::in_module(&["b"], "value")),
+            Ok(2)
+        );
+        assert_eq!(
+            program.call::<i64>(Entry::in_module(&["a"], "value")),
+            Ok(1)
+        );
+        assert_eq!(program.run(Entry::new("init")), Ok(1));
+        assert_eq!(
+            program.call::<i64>(Entry::in_module(&["b"], "init")),
+            Err(ExecError::NoSuchFunction(SmolStr::new_inline("init")))
+        );
+    }
+
+    #[test]
+    fn strings() {
+        extern "C" fn length(string: StrRef) -> i64 {
+            unsafe { string.as_str() }.len() as i64
+        }
+        let run = |src: &str| {
+            let symbols: SymbolTable = &[("length", length as *const u8)];
+            let mut program =
+                Program::new(&compile(src, &CompileOptions::default()), symbols).unwrap();
+            let string = program.call::<StrRef>(Entry::MAIN).unwrap();
+            String::from(unsafe { string.as_str() })
+        };
+        let expr = |input: &str| run(&format!("fun main() -> str {{ {} \n }}", input));
+
+        assert_eq!(expr("\"Hello, \" + \"world!\""), "Hello, world!");
+        assert_eq!(expr("\"\""), "");
+        assert_eq!(expr(r#""say \"hi\"\t\\""#), "say \"hi\"\t\\");
+        assert_eq!(expr("str(42) + str(0 - 1)"), "42-1");
+        assert_eq!(expr("str(1.5) + str(2.5f32) + str(true)"), "1.52.5true");
+        assert_eq!(expr("val a = \"a\" \n str(a + a) + a"), "aaa");
+        // Temporaries of concatenations are freed, but not literals or variables
+        assert_eq!(
+            expr("val a = \"a\" + str(1) \n val b = (a + \"b\") + (str(2) + a) \n b + a"),
+            "a1b2a1a1"
+        );
+        assert_eq!(
+            expr("var s = \"\" \n var i = 0 \n while (i < 3) { s = s + str(i) + \",\" \n i = i + 1 } \n s + s"),
+            "0,1,2,0,1,2,"
+        );
+        assert_eq!(
+            run(include_str!("../tests/strings.yacari")),
+            "origin is (0, 0), 7"
+        );
+
+        expr_err("\"a\" + 1", "E500");
+        expr_err("\"a\" - \"b\"", "E501");
+        file_err(
+            "class A { val a: i64 }\nfun main() -> str str(A(1))",
+            "E518",
+        );
+        file_err("fun main() -> str str(main)", "E518");
+
+        // Strings are capped like closure environments, since most are only freed with the program
+        match execute_module::<i64>(
+            "fun main() -> i64 { var i = 0 \n while (true) { val _s = str(i) \n i = i + 1 } \n i }",
+            &[],
+        ) {
+            Err(ProgramError::Exec(ExecError::Panicked(panic))) => {
+                assert_eq!(panic.message, "too many strings")
+            }
+            res => panic!("expected a panic, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn globals() {
+        // Folding gives the same results as computing at runtime
+        for value in &[
+            "0 - 7 >> 1 / 2",
+            "0 - 1 / 2",
+            "(0 - 9) % 4",
+            "(0 - 9) / 4",
+            "1 << 65",
+        ] {
+            let runtime = execute_module::<i64>(&format!("fun main() -> i64 {}", value), &[]);
+            file(
+                &format!("val A = {}\nfun main() -> i64 A", value),
+                runtime.unwrap(),
+            );
+        }
+
+        file("val A = (0 - 7) / 2\nfun main() -> i64 A", -3);
+        file_err("val A = main()\nfun main() {}", "E203");
+        file_err("val A = B\nval B = A + 1\nfun main() {}", "E204");
+        file_err("val A = 1 / 0\nfun main() {}", "E205");
+        file_err(
+            "val A = ((0 - 9223372036854775807) - 1) / (0 - 1)\nfun main() {}",
+            "E211",
+        );
+        file_err("val A = 1 + 1.0\nfun main() {}", "E500");
+        file_err("val A = 1\nfun A() {}", "E201");
+        file_err("val A = 1\nfun main() { A = 2 }", "E505");
+        expr_err("B", "E503");
+    }
+
+    #[test]
+    fn statics() {
+        let modules =
+            compile_files(&read_files(&OsFs, &["tests/statics.yacari"]).unwrap()).unwrap();
+        let loaded = load_snapshot(&save_snapshot(&modules)).unwrap();
+        assert_eq!(execute_modules(&loaded, &[]).unwrap(), 42);
+
+        let class = "class A { static val B = 2 \n val c: i64 } \n";
+        file(
+            &format!("{}fun main() -> i64 {{ val A = A(5) \n A.c }}", class),
+            5,
+        );
+        file_err(&format!("{}fun main() -> i64 A.C", class), "E521");
+        file_err(&format!("{}fun main() {{ A.B = 3 }}", class), "E505");
+        file_err("class A { static val c = 1 \n val c: i64 }", "E201");
+        file_err(
+            "class A { static val B = C.D } \n class C { static val D = A.B }",
+            "E204",
+        );
+        file_err("class A { static val B = C.D }", "E203");
+    }
+
+    #[test]
+    fn panics() {
+        let panic = |src: &str| match execute_module::<i64>(src, &[]) {
+            Err(ProgramError::Exec(ExecError::Panicked(panic))) => panic,
+            res => panic!("expected a panic, got {:?}", res),
+        };
+
+        expr_i64("assert(1 < 2) \n 42", 42);
+        let src = include_str!("../tests/panics.yacari");
+        let reached = panic(src);
+        assert_eq!(reached.message, "reached 0");
+        assert_eq!(reached.module, ["script"]);
+        assert_eq!(reached.start, src.find("panic(").unwrap());
+
+        let src = "fun main() -> i64 {\n assert(1 > 2) \n 42 }";
+        let failed = panic(src);
+        assert_eq!(failed.message, "assertion failed");
+        assert_eq!(failed.start, src.find("assert").unwrap());
+
+        // Division panics where the CPU would fault
+        let min = "(0 - 9223372036854775807) - 1";
+        let divide = |a: &str, op: &str, b: &str| {
+            execute_module::<i64>(
+                &format!(
+                    "fun divide(a: i64, b: i64) -> i64 a {} b\nfun main() -> i64 divide({}, {})",
+                    op, a, b
+                ),
+                &[],
+            )
+        };
+        let message = |result| match result {
+            Err(ProgramError::Exec(ExecError::Panicked(panic))) => panic.message,
+            res => panic!("expected a panic, got {:?}", res),
+        };
+        assert_eq!(message(divide("7", "/", "0")), "division by zero");
+        assert_eq!(message(divide("7", "%", "0")), "division by zero");
+        assert_eq!(message(divide(min, "/", "0 - 1")), "division overflow");
+        assert_eq!(divide(min, "%", "0 - 1").unwrap(), 0);
+        assert_eq!(divide(min, "/", "1").unwrap(), i64::MIN);
+
+        // Builtins can be shadowed like types
+        file(
+            "fun assert(a: i64) -> i64 a\nfun main() -> i64 assert(42)",
+            42,
+        );
+        expr_err("assert(1)", "E502");
+        expr_err("assert(true, false)", "E507");
+        expr_err("panic(1)", "E508");
+    }
+
+    #[test]
+    fn callback_panics() {
+        let src = "fun on_key(code: i64) -> i64 { assert(code > 0) \n code }";
+        let options = CompileOptions {
+            eliminate_dead_code: false,
+            ..CompileOptions::default()
+        };
+        let program = Program::new(&compile(src, &options), &[]).unwrap();
+        let on_key = program
+            .callback::<extern "C" fn(i64) -> i64>(Entry::new("on_key"))
+            .unwrap();
+
+        assert_eq!(on_key(5), 5);
+        assert_eq!(program.take_panic(), None);
+        on_key(0);
+        assert_eq!(program.take_panic().unwrap().message, "assertion failed");
+        assert_eq!(program.take_panic(), None);
+    }
+
+    #[test]
+    fn interrupt() {
+        let src = "fun main() -> i64 { while (true) {} \n 1 }\nfun spin(n: i64) -> i64 spin(n + 1)";
+        let options = CompileOptions {
+            eliminate_dead_code: false,
+            ..CompileOptions::default()
+        };
+        let mut program = Program::new(&compile(src, &options), &[]).unwrap();
+        let interrupt_soon = |program: &Program| {
+            let handle = program.interrupt_handle();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                handle.interrupt();
+            })
+        };
+
+        let interrupter = interrupt_soon(&program);
+        assert_eq!(program.run(Entry::MAIN), Err(ExecError::Interrupted));
+        interrupter.join().unwrap();
+
+        // Tail calls become loops, which need to be interruptible as well
+        let spin = program
+            .callback::<extern "C" fn(i64) -> i64>(Entry::new("spin"))
+            .unwrap();
+        let interrupter = interrupt_soon(&program);
+        assert_eq!(spin(0), 0);
+        assert_eq!(program.take_panic(), None);
+        interrupter.join().unwrap();
+
+        // Interrupting a program that is not running stops its next call
+        program.interrupt_handle().interrupt();
+        assert_eq!(program.run(Entry::MAIN), Err(ExecError::Interrupted));
+    }
+
+    #[test]
+    fn callbacks() {
+        let src = "fun on_tick(dt: f64) -> f64 dt * 2.0\nfun on_key(code: i64) {}";
+        let options = CompileOptions {
+            eliminate_dead_code: false,
+            ..CompileOptions::default()
+        };
+        let program = Program::new(&compile(src, &options), &[]).unwrap();
+
+        let on_tick: Hook<extern "C" fn(f64) -> f64> =
+            program.callback(Entry::new("on_tick")).unwrap();
+        assert_eq!(on_tick(1.5), 3.0);
+        let on_key: Hook<extern "C" fn(i64)> = program.callback(Entry::new("on_key")).unwrap();
+        on_key(5);
+
+        assert_eq!(
+            program
+                .callback::<extern "C" fn() -> i64>(Entry::new("on_tick"))
+                .err(),
+            Some(ExecError::WrongSignature(SmolStr::new_inline("on_tick")))
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        extern "C" fn host_double(value: i64) -> i64 {
+            value * 2
+        }
+        let src = "@extern(\"host_double\")\nextern fun double(value: i64) -> i64\n\
+                   @export(\"on_key\")\nfun handle_key(code: i64) -> i64 double(code) + 1\n\
+                   fun main() -> i64 double(21)";
+        let symbols: SymbolTable = &[("host_double", host_double as *const u8)];
+        file_(src, 42, symbols);
+
+        let program = Program::new(&compile(src, &CompileOptions::default()), symbols).unwrap();
+        let on_key: Hook<extern "C" fn(i64) -> i64> = program.exported("on_key").unwrap();
+        assert_eq!(on_key(4), 9);
+        assert_eq!(
+            program.exported::<extern "C" fn() -> i64>("on_key").err(),
+            Some(ExecError::WrongSignature(SmolStr::new_inline("on_key")))
+        );
+        assert_eq!(
+            program
+                .exported::<extern "C" fn() -> i64>("handle_key")
+                .err(),
+            Some(ExecError::NoSuchFunction(SmolStr::new_inline("handle_key")))
+        );
+
+        let formatted = crate::tooling::format(src).unwrap();
+        assert!(formatted.starts_with("@extern(\"host_double\")\nextern fun double"));
+        assert_eq!(crate::tooling::format(&formatted).unwrap(), formatted);
+
+        let codes = |source: &str| match compile_files(&[module(&["test"], source)]) {
+            Ok(_) => Vec::new(),
+            Err(errors) => errors[0].iter().map(|err| err.kind().code()).collect(),
+        };
+        let duplicate = "@export(\"a\") fun a() {}\n@export(\"a\") fun b() {}";
+        assert_eq!(codes(duplicate), ["E209"]);
+        assert_eq!(codes("@export(\"\") fun a() {}"), ["E107"]);
+        assert_eq!(codes("@inline(a) fun a() {}"), ["E107"]);
+        assert_eq!(codes("@extern(\"a\") fun a() {}"), ["E108"]);
+        assert_eq!(codes("@export(\"a\") extern fun a()"), ["E108"]);
+        assert_eq!(codes("@inline class A {}"), ["E108"]);
+    }
+
+    #[test]
+    fn source_maps() {
+        assert_eq!(line_column("fun\n  main", 6), (2, 3));
+
+        let src = "fun main() -> i64 {\n    val a = five()\n    a * 2\n}\nfun five() -> i64 5";
+        let options = CompileOptions {
+            inline_threshold: 0,
+            ..CompileOptions::default()
+        };
+        let program = Program::new(&compile(src, &options), &[]).unwrap();
+        let map = program
+            .source_maps()
+            .find(|map| map.symbol == "test::main")
+            .unwrap();
+        let (found, _) = program.locate(map.code.start).unwrap();
+        assert_eq!(found.symbol, map.symbol);
+
+        let lines = map
+            .locations
+            .iter()
+            .map(|(_, start)| line_column(src, *start).0)
+            .collect::<Vec<_>>();
+        assert!(lines.contains(&2) && lines.contains(&3));
+    }
+
+    #[test]
+    fn compile_report() {
+        let src = "fun main() -> i64 five() * 2\nfun five() -> i64 5\nfun unused() -> i64 1";
+        let options = CompileOptions {
+            inline_threshold: 0,
+            ..CompileOptions::default()
+        };
+        let program = Program::new(&compile(src, &options), &[]).unwrap();
+        let report = program.compile_report();
+
+        let mut symbols = report
+            .functions
+            .iter()
+            .map(|func| func.symbol.as_str())
+            .collect::<Vec<_>>();
+        symbols.sort_unstable();
+        assert_eq!(symbols, ["test::five", "test::main"]);
+        assert!(report.functions.windows(2).all(|w| w[0].size >= w[1].size));
+        for map in program.source_maps() {
+            assert_eq!(report.size_of(&map.symbol), Some(map.code.len()));
+        }
+        assert_eq!(report.functions[0].module, ["test"]);
+        assert_eq!(
+            report.total(),
+            report.functions.iter().map(|func| func.size).sum::<usize>()
+        );
+        assert!(report.functions.iter().all(|func| func.size > 0));
+        let header = format!("{} bytes of machine code in 2 functions", report.total());
+        assert!(format!("{}", report).starts_with(&header));
+    }
+
+    #[test]
+    fn jit_states() {
+        let modules = compile("fun main() -> i64 42", &CompileOptions::default());
+        let module = modules[0].0.borrow();
+        let mut jit = JIT::new(&[], CodeMemory::default());
+        assert_eq!(jit.exec::<i64>("test::main"), Err(ExecError::NotFinalized));
+
+        jit.define(&module).unwrap();
+        let path = vec![SmolStr::new_inline("test")];
+        assert_eq!(jit.define(&module), Err(StateError::DefinedTwice(path)));
+        assert_eq!(jit.exec::<i64>("test::main"), Err(ExecError::NotFinalized));
+        assert_eq!(jit.function_ptr("test::main"), None);
+
+        jit.finalize().unwrap();
+        assert_eq!(jit.exec::<i64>("test::main"), Ok(42));
+        assert_eq!(jit.finalize(), Err(StateError::Finalized));
+        assert_eq!(jit.define(&module), Err(StateError::Finalized));
+
+        // Functions have different IDs in every JIT they are defined in
+        let mut other = JIT::new(&[], CodeMemory::default());
+        other.define(&module).unwrap();
+        other.finalize().unwrap();
+        assert_eq!(other.exec::<i64>("test::main"), Ok(42));
+    }
+
+    #[test]
+    fn targets() {
+        let modules = compile("fun main() -> i64 40 + 2", &CompileOptions::default());
+        let link = |target: &Target| match Program::for_target(
+            &modules,
+            &[],
+            CodeMemory::default(),
+            target,
+        ) {
+            Err(ProgramError::Target(err)) => Err(err),
+            result => result.map(|_| ()).map_err(|err| panic!("{:?}", err)),
+        };
+        let host = Triple::host().to_string();
+        let target = Target::new(&host).setting("has_sse41", "false");
+        let mut program =
+            Program::for_target(&modules, &[], CodeMemory::default(), &target).unwrap();
+        assert_eq!(program.run(Entry::MAIN), Ok(42));
+
+        let invalid = Target::new("not a triple");
+        let error = TargetError::InvalidTriple(SmolStr::new("not a triple"));
+        assert_eq!(link(&invalid), Err(error));
+        let other = if cfg!(target_arch = "aarch64") {
+            "x86_64-unknown-none"
+        } else {
+            "aarch64-unknown-none"
+        };
+        let error = TargetError::NotHost(SmolStr::new(other));
+        assert_eq!(link(&Target::new(other)), Err(error));
+        for (name, value) in [("is_pic", "maybe"), ("has_everything", "true")] {
+            let error = TargetError::InvalidSetting {
+                name: SmolStr::new(name),
+                value: SmolStr::new(value),
+            };
+            assert_eq!(link(&Target::default().setting(name, value)), Err(error));
+        }
+
+        let result = Engine::new(OsFs)
+            .path("tests/exit_code.yacari")
+            .target(invalid)
+            .execute();
+        assert!(matches!(result, Err(ProgramError::Target(_))));
+    }
+
+    #[test]
+    fn snapshot() {
+        let modules =
+            compile_files(&read_files(&OsFs, &["tests/overloads.yacari"]).unwrap()).unwrap();
+        let snapshot = save_snapshot(&modules);
+        let loaded = load_snapshot(&snapshot).unwrap();
+        assert_eq!(save_snapshot(&loaded), snapshot);
+        assert_eq!(execute_modules(&loaded, &[]).unwrap(), 123);
+
+        let options = CompileOptions {
+            flags: vec![SmolStr::new_inline("kernel")],
+            ..CompileOptions::default()
+        };
+        let modules = compile("fun main() -> i64 5", &options);
+        let loaded = load_snapshot(&save_snapshot(&modules)).unwrap();
+        assert_eq!(loaded[0].flags(), ["kernel"]);
+        assert!(load_snapshot(&snapshot).unwrap()[0].flags().is_empty());
+
+        let mut file = module(&["test"], "fun main() -> i64 5");
+        assert!(loaded[0].compiled_from(&file));
+        file.contents = "fun main() -> i64 6".into();
+        assert!(!loaded[0].compiled_from(&file));
+    }
+
+    #[test]
+    fn bindings() {
+        let options = CompileOptions {
+            eliminate_dead_code: false,
+            ..CompileOptions::default()
+        };
+        let modules = compile(
+            "fun main() -> i64 {\n\
+                 val scale = if (get_env_bool(\"double\")) 2 else 1\n\
+                 val width = get_env_int(\"width\") + get_env_int(\"missing\")\n\
+                 width * scale\n\
+             }\n\
+             fun ratio() -> f64 get_env_float(\"ratio\")\n\
+             fun describe() -> str get_env(\"user\") + \"@\" + get_env(\"width\")",
+            &options,
+        );
+
+        let mut program = Program::new(&modules, &[]).unwrap();
+        program.bind("width", 400i64);
+        program.bind("ratio", 1.5);
+        program.bind("user", "root");
+        assert_eq!(program.run(Entry::MAIN), Ok(400));
+        assert_eq!(program.call::<f64>(Entry::new("ratio")), Ok(1.5));
+        let describe = program.call::<StrRef>(Entry::new("describe")).unwrap();
+        assert_eq!(unsafe { describe.as_str() }, "root@400");
+
+        // Bindings can change between calls, and strings are parsed
+        program.bind("double", true);
+        assert_eq!(program.run(Entry::MAIN), Ok(800));
+        program.bind("width", "30");
+        assert_eq!(program.run(Entry::MAIN), Ok(60));
+        program.bind("width", "wide");
+        assert_eq!(program.run(Entry::MAIN), Ok(0));
+
+        let program = Engine::new(OsFs).bind("width", 21i64).bind("double", true);
+        assert_eq!(program.link(&modules).unwrap().run(Entry::MAIN), Ok(42));
+
+        // Arguments are bound by their index
+        let modules = compile(
+            "fun main() -> i64 get_env_int(\"argc\") * 100 + get_env_int(\"arg0\")\n\
+             fun second() -> str get_env(\"arg1\")",
+            &options,
+        );
+        let mut program = Engine::new(OsFs)
+            .args(&["21", "two"])
+            .link(&modules)
+            .unwrap();
+        assert_eq!(program.run(Entry::MAIN), Ok(221));
+        let second = program.call::<StrRef>(Entry::new("second")).unwrap();
+        assert_eq!(unsafe { second.as_str() }, "two");
+    }
+
+    #[test]
+    fn compiled_program() {
+        fn send_sync<T: Send + Sync>(_: &T) {}
+        let program = Engine::new(OsFs)
+            .path("tests/overloads.yacari")
+            .compile()
+            .map(|modules| CompiledProgram::freeze(&modules))
+            .unwrap();
+        send_sync(&program);
+
+        let other = program.clone();
+        let code = std::thread::spawn(move || other.link(&[]).unwrap().run(Entry::MAIN))
+            .join()
+            .unwrap();
+        assert_eq!(code, Ok(123));
+        assert_eq!(program.link(&[]).unwrap().run(Entry::MAIN), Ok(123));
+    }
+
+    #[test]
+    fn snapshot_invalid() {
+        let modules =
+            compile_files(&read_files(&OsFs, &["tests/exit_code.yacari"]).unwrap()).unwrap();
+        let snapshot = save_snapshot(&modules);
+        let load = |data: &[u8]| load_snapshot(data).err();
+
+        assert_eq!(load(b"yacari"), Some(SnapshotError::InvalidHeader));
+        assert_eq!(
+            load(&snapshot[..snapshot.len() - 1]),
+            Some(SnapshotError::UnexpectedEnd)
+        );
+        let mut version = snapshot.clone();
+        version[4] = 0;
+        assert_eq!(load(&version), Some(SnapshotError::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    #[cfg(feature = "timings")]
+    fn timings() {
+        use crate::{set_clock, take_timings};
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        // Every call advances the clock, so each measured stage takes time
+        static NOW: AtomicU64 = AtomicU64::new(0);
+        set_clock(|| NOW.fetch_add(1000, Ordering::Relaxed));
+
+        compile("fun main() -> i64 5", &CompileOptions::default());
+        let timings = take_timings();
+        assert!(timings.lex.as_nanos() > 0);
+        assert!(timings.parse.as_nanos() > 0);
+        assert!(timings.ir.as_nanos() > 0);
+    }
+
+    /// Walks the same files as `OsFs`, but in reverse order,
+    /// like a filesystem with a different directory layout would.
+    struct ReverseFs;
+
+    impl Filesystem for ReverseFs {
+        fn walk_directory<'fs, T: FnMut(Result<File<'fs>, FileError>)>(
+            &'fs self,
+            path: &str,
+            cls: T,
+        ) {
+            let mut files = Vec::new();
+            OsFs.walk_directory(path, |file| files.push(file));
+            files.into_iter().rev().for_each(cls);
+        }
+    }
+
+    #[test]
+    fn deterministic_order() {
+        let module_paths = |modules: Vec<CompiledModule>| {
+            modules
+                .iter()
+                .map(|module| module.0.borrow().ast.path.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let os = compile_files(&read_files(&OsFs, &["tests/basic_modules"]).unwrap()).unwrap();
+        let reverse =
+            compile_files(&read_files(&ReverseFs, &["tests/basic_modules"]).unwrap()).unwrap();
+        assert_eq!(module_paths(os), module_paths(reverse));
+
+        let reverse =
+            compile_files(&read_files(&ReverseFs, &["tests/basic_modules"]).unwrap()).unwrap();
+        assert_eq!(
+            module_paths(reverse),
+            [["basic_modules", "hello"], ["basic_modules", "world"]]
+        );
+    }
+
+    #[test]
+    fn memory_fs() {
+        const FILES: &[(&str, &str)] = &[
+            (
+                "tests/imports/main.yacari",
+                include_str!("../tests/imports/main.yacari"),
+            ),
+            (
+                "tests/imports/shapes.yacari",
+                include_str!("../tests/imports/shapes.yacari"),
+            ),
+            ("tests/imports/README.md", "Not a source file"),
+            (
+                "tests/exit_code.yacari",
+                include_str!("../tests/exit_code.yacari"),
+            ),
+        ];
+        fn read<FS: Filesystem>(fs: FS, paths: &[&str]) -> Vec<(String, String)> {
+            let files = read_files(&fs, paths).unwrap().into_iter();
+            files
+                .map(|file| (file.path.join("/"), String::from(file.contents)))
+                .collect()
+        }
+        let memory = MemoryFs::new(FILES);
+
+        // Module paths match the ones of the host
+        for paths in [&["tests/imports"][..], &["tests/exit_code.yacari"]] {
+            assert_eq!(read(&memory, paths), read(OsFs, paths));
+        }
+        assert_eq!(
+            read(&memory, &["/tests/imports/"]),
+            read(&memory, &["tests/imports"])
+        );
+        let all = read(&memory, &[""]).into_iter().map(|(path, _)| path);
+        assert_eq!(
+            all.collect::<Vec<_>>(),
+            [
+                "tests/exit_code",
+                "tests/imports/main",
+                "tests/imports/shapes"
+            ]
+        );
+        assert!(read(&memory, &["tests/import"]).is_empty());
+
+        let engine = Engine::new(MemoryFs::new(FILES)).path("tests/imports");
+        assert_eq!(engine.run::<i64>().unwrap(), 42);
+    }
+
+    #[test]
+    fn unreadable_files() {
+        let errors = read_files(&OsFs, &["tests/basic_modules", "tests/invalid_utf8"]).unwrap_err();
+        assert_eq!(
+            errors,
+            [FileError {
+                path: "tests/invalid_utf8/latin1.yacari".into(),
+                kind: FileErrorKind::InvalidUtf8,
+            }]
+        );
+
+        let result = execute_program(OsFs, &["tests/invalid_utf8"], &[]);
+        assert!(matches!(result, Err(ProgramError::Read(errors)) if errors.len() == 1));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let diagnostics = check_path(&OsFs, &["tests/diagnostics"]);
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                file: "diagnostics/second.yacari".into(),
+                start: 36,
+                end: 41,
+                line: 2,
+                column: 5,
+                code: Some("E503"),
+                message: "Unknown variable 'valeu'.".into(),
+            }]
+        );
+        assert_eq!(
+            diagnostics_to_json(&diagnostics),
+            r#"[{"file":"diagnostics/second.yacari","start":36,"end":41,"line":2,"column":5,"code":"E503","message":"Unknown variable 'valeu'."}]"#
+        );
+        let files = read_files(&OsFs, &["tests/diagnostics"]).unwrap();
+        assert_eq!(
+            diagnostics[0].render(&files),
+            "error[E503]: Unknown variable 'valeu'.
+ --> diagnostics/second.yacari:2:5
+  |
+2 |     valeu
+  |     ^^^^^
+"
+        );
+        assert!(check_path(&OsFs, &["tests/basic_modules"]).is_empty());
+
+        let unreadable = check_path(&OsFs, &["tests/invalid_utf8"]);
+        assert_eq!(
+            unreadable[0].to_json(),
+            r#"{"file":"tests/invalid_utf8/latin1.yacari","start":0,"end":0,"line":1,"column":1,"code":null,"message":"tests/invalid_utf8/latin1.yacari: file is not valid UTF-8"}"#
+        );
+    }
+
+    #[test]
+    fn clif() {
+        let modules = compile(
+            "fun main() -> i64 double(21)\nfun double(x: i64) -> i64 x * 2",
+            &CompileOptions {
+                inline_threshold: 0,
+                ..CompileOptions::default()
+            },
+        );
+        let clif = dump_clif(&modules);
+        assert!(clif.starts_with("; test::main\nfunction "));
+        assert!(clif.contains("; test::double\nfunction "));
+        assert!(clif.contains("imul"));
+    }
+
+    #[test]
+    #[cfg(feature = "wasm")]
+    fn wasm() {
+        let modules = compile(
+            include_str!("../tests/wasm.yacari"),
+            &CompileOptions {
+                eliminate_dead_code: false,
+                ..CompileOptions::default()
+            },
+        );
+        let wasm = crate::compile_wasm(&modules).unwrap();
+        assert_eq!(&wasm[..8], b"\0asm\x01\0\0\0");
+        let contains = |name: &[u8]| wasm.windows(name.len()).any(|w| w == name);
+        assert!(contains(b"\x03env\x06report"));
+        assert!(contains(b"test::is_even"));
+        assert!(contains(b"test::clamp"));
+
+        let modules = compile(
+            "fun main() -> str \"no strings yet\"",
+            &CompileOptions::default(),
+        );
+        assert_eq!(
+            crate::compile_wasm(&modules),
+            Err(vec![crate::WasmError::Unsupported {
+                function: "test::main".into(),
+                feature: "strings",
+            }])
+        );
+    }
+
+    #[test]
+    fn tooling() {
+        let src = "val LIMIT = 10
+fun clamp(value: i64) -> i64 {
+    val max = LIMIT
+    if (value > max) max else value
+}
+class Box {
+    static val SIZE = 4
+    val width: i64
+    fun area() -> i64 this.width * Box.SIZE
+}
+fun broken( {
+fun main() -> i64 clamp(value = Box(2).area())";
+        let file = parse_file(src);
+        assert_eq!(file.errors().len(), 1);
+
+        let symbols = file.symbols();
+        fn names(symbols: &[Symbol]) -> Vec<(&str, SymbolKind)> {
+            symbols.iter().map(|s| (s.name.as_str(), s.kind)).collect()
+        }
+        assert_eq!(
+            names(&symbols),
+            [
+                ("LIMIT", SymbolKind::Global),
+                ("clamp", SymbolKind::Function),
+                ("Box", SymbolKind::Class),
+                ("main", SymbolKind::Function),
+            ]
+        );
+        assert_eq!(
+            names(&symbols[2].children),
+            [
+                ("SIZE", SymbolKind::Static),
+                ("width", SymbolKind::Member),
+                ("area", SymbolKind::Method),
+            ]
+        );
+
+        let definition = |usage: usize| {
+            let symbol = file.definition(usage).unwrap();
+            (symbol.kind, symbol.start)
+        };
+        let max = src.find("max").unwrap();
+        assert_eq!(
+            definition(src.rfind("max").unwrap()),
+            (SymbolKind::Variable, max)
+        );
+        assert_eq!(
+            definition(src.find("LIMIT\n").unwrap()),
+            (SymbolKind::Global, 4)
+        );
+        let size = src.find("SIZE").unwrap();
+        assert_eq!(
+            definition(src.rfind("SIZE").unwrap()),
+            (SymbolKind::Static, size)
+        );
+        let value = src.find("value").unwrap();
+        assert_eq!(
+            definition(src.rfind("value =").unwrap()),
+            (SymbolKind::Parameter, value)
+        );
+        let class = src.find("Box").unwrap();
+        assert_eq!(
+            definition(src.rfind("Box").unwrap()),
+            (SymbolKind::Class, class)
+        );
+        assert_eq!(definition(class + 1), (SymbolKind::Class, class));
+        assert_eq!(file.definition(src.find("10").unwrap()), None);
+    }
+
+    #[test]
+    fn format() {
+        let messy = "import  std :: collections
+import io
+// Limits
+val   LIMIT=10 // inclusive
+val STEP = 2.50
+
+
+
+/* Clamps */
+fun clamp( value :i64,max:i64=LIMIT )->i64{if(value>max)max else value}
+class Box{
+    static val SIZE=4
+  var width:i64=1
+
+  @cfg(kernel) fun area()->i64 (this.width+1)*Box.SIZE
+    init { this.width }
+    // nothing after
+}
+fun main()->i64 {
+    val f=fun(x:i64)->i64 x-(1-2)
+    var s=\"a\\n\\\"b\\\"\"
+
+    while (s==\"\") {}
+    clamp(value=f(Box(2).area()), 3)
+}
+@cfg( !kernel ,host)
+extern fun puts(s:str)";
+        let expected = "import std::collections
+import io
+
+// Limits
+val LIMIT = 10 // inclusive
+val STEP = 2.5
+
+/* Clamps */
+fun clamp(value: i64, max: i64 = LIMIT) -> i64 {
+    if (value > max) max else value
+}
+
+class Box {
+    static val SIZE = 4
+    var width: i64 = 1
+
+    @cfg(kernel)
+    fun area() -> i64 (this.width + 1) * Box.SIZE
+
+    init {
+        this.width
+    }
+    // nothing after
+}
+
+fun main() -> i64 {
+    val f = fun(x: i64) -> i64 x - 1 - 2
+    var s = \"a\\n\\\"b\\\"\"
+
+    while (s == \"\") {}
+    clamp(value = f(Box(2).area()), 3)
+}
+
+@cfg(!kernel, host)
+extern fun puts(s: str)
+";
+        assert_eq!(crate::tooling::format(messy).unwrap(), expected);
+        assert_eq!(crate::tooling::format(expected).unwrap(), expected);
+        assert!(crate::tooling::format("fun main( {").is_err());
+
+        for (source, result) in [
+            (include_str!("../tests/closures.yacari"), 42),
+            (include_str!("../tests/operators.yacari"), 42),
+            (include_str!("../tests/constructors.yacari"), 42),
+            (include_str!("../tests/statics.yacari"), 42),
+            (include_str!("../tests/default_args.yacari"), 1164),
+            (include_str!("../tests/loops.yacari"), 50),
+        ] {
+            let formatted = crate::tooling::format(source).unwrap();
+            assert_eq!(crate::tooling::format(&formatted).unwrap(), formatted);
+            file(&formatted, result);
+        }
+    }
+
+    #[test]
+    fn highlighting() {
+        let src = "fun f(a: i64) -> bool /* c */ a >= 10 or \"s\" is str // end\n$ /* open";
+        let classes = highlight(src)
+            .into_iter()
+            .map(|(span, class)| (&src[span], class))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            classes,
+            [
+                ("fun", TokenClass::Keyword),
+                ("f", TokenClass::Identifier),
+                ("(", TokenClass::Operator),
+                ("a", TokenClass::Identifier),
+                (":", TokenClass::Operator),
+                ("i64", TokenClass::Identifier),
+                (")", TokenClass::Operator),
+                ("->", TokenClass::Operator),
+                ("bool", TokenClass::Identifier),
+                ("/* c */", TokenClass::Comment),
+                ("a", TokenClass::Identifier),
+                (">=", TokenClass::Operator),
+                ("10", TokenClass::Literal),
+                ("or", TokenClass::Keyword),
+                ("\"s\"", TokenClass::Literal),
+                ("is", TokenClass::Keyword),
+                ("str", TokenClass::Identifier),
+                ("// end", TokenClass::Comment),
+                ("$", TokenClass::Error),
+                ("/* open", TokenClass::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn basic_ffi() {
+        #[repr(C)]
+        #[derive(Debug, PartialEq)]
+        struct TestStruct {
+            a: i64,
+            b: i64,
+        }
+
+        extern "C" fn make_struct() -> TestStruct {
+            TestStruct { a: 24, b: 457 }
+        }
+
+        let modules = compile(
+            include_str!("../tests/basic_ffi.yacari"),
+            &CompileOptions::default(),
+        );
+        let mut program =
+            Program::new(&modules, &[("make_struct", make_struct as *const u8)]).unwrap();
+        let result = unsafe { program.call_unchecked::<TestStruct>(Entry::MAIN) };
+        assert_eq!(result, Ok(make_struct()));
+    }
+
+    #[test]
+    fn ffi_bools() {
+        extern "C" fn negate(b: bool) -> bool {
+            !b
+        }
+
+        let src = "fun main() -> bool negate(is_less(5, 7))
+            fun is_less(a: i64, b: i64) -> bool a < b
+            extern fun negate(b: bool) -> bool";
+        file_(src, false, &[("negate", negate as *const u8)]);
+
+        let options = CompileOptions {
+            eliminate_dead_code: false,
+            ..CompileOptions::default()
+        };
+        let program =
+            Program::new(&compile(src, &options), &[("negate", negate as *const u8)]).unwrap();
+        let is_less: Hook<extern "C" fn(i64, i64) -> bool> =
+            program.callback(Entry::new("is_less")).unwrap();
+        assert!(is_less(2, 3));
+        assert!(!is_less(3, 2));
     }
 }
@@ -4,8 +4,25 @@ use alloc::{boxed::Box, vec::Vec};
 #[derive(Debug)]
 pub struct Module {
     pub path: Vec<SmolStr>,
+    pub imports: Vec<Import>,
     pub functions: Vec<Function>,
     pub classes: Vec<Class>,
+    pub globals: Vec<Global>,
+    /// Functions declared inside of blocks, referenced by `EExpr::LocalFunction`.
+    pub local_functions: Vec<LocalFunction>,
+    /// The flags this module was parsed with, which decided the `@cfg` declarations it contains.
+    pub flags: Vec<SmolStr>,
+    /// A hash of the source this module was parsed from, see `parser::hash_source`.
+    pub source_hash: u64,
+}
+
+/// An `import`, which makes the classes and functions
+/// of another module of the program usable by name.
+#[derive(Debug)]
+pub struct Import {
+    /// The path of the module, like `std::collections`.
+    pub path: Vec<Token>,
+    pub attributes: Vec<Attribute>,
 }
 
 #[derive(Debug)]
@@ -14,6 +31,20 @@ pub struct Class {
     pub members: Vec<Member>,
     pub methods: Vec<Function>,
     pub functions: Vec<Function>,
+    /// The `static val`s of the class, which are constants like globals.
+    pub statics: Vec<Global>,
+    /// The `init` block, run by the constructor after all members are set.
+    pub init: Option<Expr>,
+    pub attributes: Vec<Attribute>,
+}
+
+/// A `val` at module scope or `static val` in a class,
+/// whose value is evaluated at compile time.
+#[derive(Debug)]
+pub struct Global {
+    pub name: Token,
+    pub value: Expr,
+    pub attributes: Vec<Attribute>,
 }
 
 #[derive(Debug)]
@@ -21,6 +52,8 @@ pub struct Member {
     pub name: Token,
     pub ty: Type,
     pub mutable: bool,
+    /// The value of the member if the constructor is called without it.
+    pub default: Option<Expr>,
 }
 
 #[derive(Debug)]
@@ -29,17 +62,50 @@ pub struct Function {
     pub params: Vec<Parameter>,
     pub ret_type: Option<Type>,
     pub body: Option<Expr>,
+    pub attributes: Vec<Attribute>,
+}
+
+/// An attribute in front of a declaration, like `@cfg(kernel)`.
+#[derive(Debug)]
+pub struct Attribute {
+    /// The start of the `@`.
+    pub start: usize,
+    pub name: Token,
+    /// The arguments in parentheses after the name, if any.
+    pub args: Vec<AttributeArg>,
+}
+
+/// An argument of an attribute, which is either a name that `!` in front of it
+/// negates, or a string. The token of a string holds its content without escapes.
+#[derive(Debug)]
+pub struct AttributeArg {
+    pub negated: bool,
+    pub value: Token,
+}
+
+/// A function declared inside of a block, or a closure.
+#[derive(Debug)]
+pub struct LocalFunction {
+    /// The names of the functions it is nested in, separated by `::`.
+    pub parent: SmolStr,
+    pub func: Function,
 }
 
 #[derive(Debug)]
 pub struct Parameter {
-    pub name: SmolStr,
+    pub name: Token,
     pub ty: Type,
+    pub default: Option<Expr>,
 }
 
 #[derive(Debug)]
-pub struct Type {
-    pub name: Token,
+pub enum Type {
+    Ident(Token),
+
+    Function {
+        params: Vec<Type>,
+        ret_type: Option<Box<Type>>,
+    },
 }
 
 #[derive(Debug)]
@@ -62,15 +128,34 @@ pub enum EExpr {
 
     Block(Vec<Expr>),
 
+    /// A function only visible inside the block it is declared in.
+    /// `index` is its position in `Module::local_functions`.
+    LocalFunction {
+        name: Token,
+        index: usize,
+    },
+
+    /// An anonymous function capturing variables of the function it is in.
+    /// Like local functions, it is stored in `Module::local_functions`.
+    Closure(usize),
+
     If {
         cond: Expr,
         then: Expr,
         els: Option<Expr>,
     },
 
+    /// A loop, which has the value of the `else` branch once the condition
+    /// is false, or of the `break` leaving it early.
     While {
         cond: Expr,
         body: Expr,
+        els: Option<Expr>,
+    },
+
+    /// Leave the innermost loop, with the given value as the value of the loop.
+    Break {
+        value: Option<Expr>,
     },
 
     Binary {
@@ -86,14 +171,27 @@ pub enum EExpr {
 
     Call {
         callee: Expr,
-        args: Vec<Expr>,
+        args: Vec<Argument>,
+    },
+
+    /// Access to a member of a class value, like `point.x`.
+    Get {
+        object: Expr,
+        name: Token,
     },
 }
 
+#[derive(Debug)]
+pub struct Argument {
+    pub name: Option<Token>,
+    pub value: Expr,
+}
+
 #[derive(Debug, Clone)]
 pub enum Literal {
     Bool(bool),
     Int(i64),
     Float(f64),
+    F32(f32),
     String(SmolStr),
 }
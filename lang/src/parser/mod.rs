@@ -3,53 +3,131 @@ pub mod ast;
 use crate::{
     error::{
         Error,
-        ErrorKind::{E100, E101, E102},
+        ErrorKind::{E100, E101, E102, E103, E104, E105, E106, E107, E108, E201},
         Errors, Res,
     },
     lexer::{Lexer, TKind, TKind::*, Token},
-    parser::ast::{EExpr, Expr, Function, Literal, Member, Parameter, Type},
-    smol_str::SmolStr,
+    parser::ast::{
+        Argument, Attribute, AttributeArg, EExpr, Expr, Function, Global, Import, Literal,
+        LocalFunction, Member, Parameter, Type,
+    },
+    smol_str::{SmolStr, SmolStrBuilder},
 };
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, vec, vec::Vec};
 pub use ast::Module;
 use core::{mem, str::FromStr};
 
+/// The maximum nesting depth of expressions and types.
+/// Deeper nesting is rejected, since it would overflow the stack
+/// of the parser and the recursive passes of the compiler.
+const MAX_DEPTH: usize = 256;
+
+/// The attributes allowed on functions with a body.
+const FUNCTION_ATTRIBUTES: &[&str] = &["cfg", "inline", "export"];
+
 pub struct Parser<'src> {
     lexer: Lexer<'src>,
+    source_hash: u64,
     current: Token,
     errors: Errors,
+    depth: usize,
+    /// Names of the functions whose bodies are being parsed, outermost first.
+    enclosing: Vec<SmolStr>,
+    local_functions: Vec<LocalFunction>,
+    /// The flags `@cfg` attributes are checked against, or `None` to keep
+    /// all declarations regardless of their `@cfg`, like tooling needs.
+    flags: Option<Vec<SmolStr>>,
 }
 
 impl<'src> Parser<'src> {
-    pub fn parse(mut self, path: Vec<SmolStr>) -> Result<Module, Errors> {
+    /// Only keep the declarations whose `@cfg` attributes match the given flags.
+    pub fn with_flags(mut self, flags: &[SmolStr]) -> Self {
+        self.flags = Some(flags.to_vec());
+        self
+    }
+
+    pub fn parse(self, path: Vec<SmolStr>) -> Result<Module, Errors> {
+        let (module, errors) = self.parse_recovering(path);
+        if errors.is_empty() {
+            Ok(module)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Parse like `parse`, but return all declarations that could be parsed
+    /// along with the errors, instead of failing on the first one.
+    pub fn parse_recovering(mut self, path: Vec<SmolStr>) -> (Module, Errors) {
+        let mut imports = Vec::new();
         let mut functions = Vec::new();
         let mut classes = Vec::new();
+        let mut globals = Vec::new();
 
         while !self.is_at_end() {
+            let attributes = match self.attributes() {
+                Ok(attributes) => attributes,
+                Err(e) => {
+                    self.errors.push(e);
+                    self.synchronize();
+                    continue;
+                }
+            };
             match self.advance().kind {
-                TKind::Class => self.make_cls(&mut classes),
-                TKind::Fun => self.make_fn(&mut functions, false),
-                TKind::Extern if self.matches(Fun) => self.make_fn(&mut functions, true),
+                TKind::Import => self.make_import(&mut imports, attributes),
+                TKind::Class => self.make_cls(&mut classes, attributes),
+                TKind::Val => self.make_global(&mut globals, attributes),
+                TKind::Fun => self.make_fn(&mut functions, false, attributes),
+                TKind::Extern if self.matches(Fun) => {
+                    self.make_fn(&mut functions, true, attributes)
+                }
                 _ => {
                     self.errors.push(Error::new(self.current.start, E102));
                     self.synchronize()
                 }
             }
         }
-        if self.errors.is_empty() {
-            Ok(Module {
-                functions,
-                classes,
-                path,
-            })
-        } else {
-            Err(self.errors)
+        let module = Module {
+            imports,
+            functions,
+            classes,
+            globals,
+            local_functions: self.local_functions,
+            flags: self.flags.unwrap_or_default(),
+            source_hash: self.source_hash,
+            path,
+        };
+        (module, self.errors)
+    }
+
+    fn make_import(&mut self, imports: &mut Vec<Import>, attributes: Vec<Attribute>) {
+        self.allow(&attributes, &["cfg"]);
+        match self.import() {
+            Ok(i) if self.included(&attributes) => imports.push(Import { attributes, ..i }),
+            Ok(_) => (),
+            Err(e) => {
+                self.errors.push(e);
+                self.synchronize()
+            }
         }
     }
 
-    fn make_cls(&mut self, cls: &mut Vec<ast::Class>) {
+    fn import(&mut self) -> Res<Import> {
+        let mut path = vec![self.consume(Identifier)?];
+        while self.matches(ColonColon) {
+            path.push(self.consume(Identifier)?);
+        }
+        Ok(Import {
+            path,
+            attributes: Vec::new(),
+        })
+    }
+
+    fn make_cls(&mut self, cls: &mut Vec<ast::Class>, attributes: Vec<Attribute>) {
+        self.allow(&attributes, &["cfg"]);
+        let locals = self.local_functions.len();
         match self.class() {
-            Ok(f) => cls.push(f),
+            Ok(c) if self.included(&attributes) => cls.push(ast::Class { attributes, ..c }),
+            Ok(_) => self.local_functions.truncate(locals),
             Err(e) => {
                 self.errors.push(e);
                 self.synchronize()
@@ -57,9 +135,29 @@ impl<'src> Parser<'src> {
         }
     }
 
-    fn make_fn(&mut self, functions: &mut Vec<Function>, is_ext: bool) {
+    fn make_fn(&mut self, functions: &mut Vec<Function>, is_ext: bool, attributes: Vec<Attribute>) {
+        if is_ext {
+            self.allow(&attributes, &["cfg", "extern"]);
+        } else {
+            self.allow(&attributes, FUNCTION_ATTRIBUTES);
+        }
+        let locals = self.local_functions.len();
         match self.function(is_ext) {
-            Ok(f) => functions.push(f),
+            Ok(f) if self.included(&attributes) => functions.push(Function { attributes, ..f }),
+            Ok(_) => self.local_functions.truncate(locals),
+            Err(e) => {
+                self.errors.push(e);
+                self.synchronize()
+            }
+        }
+    }
+
+    fn make_global(&mut self, globals: &mut Vec<Global>, attributes: Vec<Attribute>) {
+        self.allow(&attributes, &["cfg"]);
+        let locals = self.local_functions.len();
+        match self.global() {
+            Ok(g) if self.included(&attributes) => globals.push(Global { attributes, ..g }),
+            Ok(_) => self.local_functions.truncate(locals),
             Err(e) => {
                 self.errors.push(e);
                 self.synchronize()
@@ -67,6 +165,17 @@ impl<'src> Parser<'src> {
         }
     }
 
+    fn global(&mut self) -> Res<Global> {
+        let name = self.consume(Identifier)?;
+        self.consume(Equal)?;
+        let value = self.expression()?;
+        Ok(Global {
+            name,
+            value,
+            attributes: Vec::new(),
+        })
+    }
+
     fn class(&mut self) -> Res<ast::Class> {
         let name = self.consume(Identifier)?;
         self.consume(LeftBrace)?;
@@ -74,12 +183,31 @@ impl<'src> Parser<'src> {
         let mut members = Vec::new();
         let mut methods = Vec::new();
         let mut functions = Vec::new();
+        let mut statics = Vec::new();
+        let mut init = None;
         while !self.check(RightBrace) {
-            match self.advance().kind {
+            let attributes = self.attributes()?;
+            let token = self.advance();
+            match token.kind {
+                Fun => self.make_method(&mut methods, attributes)?,
+                Static if self.matches(Fun) => self.make_method(&mut functions, attributes)?,
+                _ if !attributes.is_empty() => {
+                    let name = attributes[0].name.lex.clone();
+                    return Err(Error::new(attributes[0].start, E108(name)));
+                }
                 Val => members.push(self.member(false)?),
                 Var => members.push(self.member(true)?),
-                Fun => methods.push(self.function(false)?),
-                Static if self.matches(Fun) => functions.push(self.function(false)?),
+                Static if self.matches(Val) => statics.push(self.global()?),
+                // `init` is only special here, which keeps it usable as a name elsewhere
+                Identifier if token.lex == "init" && self.check(LeftBrace) => {
+                    if init.is_some() {
+                        return Err(Error::new(token.start, E201(token.lex)));
+                    }
+                    self.enclosing.push(name.lex.clone());
+                    let block = self.block();
+                    self.enclosing.pop();
+                    init = Some(block?);
+                }
                 _ => return Err(Error::new(self.current.start, E102)),
             }
         }
@@ -90,27 +218,156 @@ impl<'src> Parser<'src> {
             members,
             methods,
             functions,
+            statics,
+            init,
+            attributes: Vec::new(),
         })
     }
 
+    /// Parse a method or static function of a class, which
+    /// is left out if its `@cfg` attributes do not match.
+    fn make_method(
+        &mut self,
+        functions: &mut Vec<Function>,
+        attributes: Vec<Attribute>,
+    ) -> Res<()> {
+        self.allow(&attributes, FUNCTION_ATTRIBUTES);
+        let locals = self.local_functions.len();
+        let function = self.function(false)?;
+        if self.included(&attributes) {
+            functions.push(Function {
+                attributes,
+                ..function
+            });
+        } else {
+            self.local_functions.truncate(locals);
+        }
+        Ok(())
+    }
+
+    /// Parse the attributes in front of a declaration, if it has any.
+    fn attributes(&mut self) -> Res<Vec<Attribute>> {
+        let mut attributes = Vec::new();
+        while self.check(At) {
+            let start = self.advance().start;
+            // `extern` is a keyword, but also the name of an attribute
+            let name = if self.check(Extern) {
+                self.advance()
+            } else {
+                self.consume(Identifier)?
+            };
+            let mut args = Vec::new();
+            if self.matches(LeftParen) {
+                while !self.check(RightParen) {
+                    args.push(self.attribute_arg()?);
+                    if !self.matches(Comma) {
+                        break;
+                    }
+                }
+                self.consume(RightParen)?;
+            }
+
+            let is_name = |arg: &AttributeArg| arg.value.kind == Identifier;
+            let is_string = |arg: &AttributeArg| arg.value.kind == TKind::String;
+            let valid = match name.lex.as_str() {
+                "cfg" => !args.is_empty() && args.iter().all(is_name),
+                "inline" => args.is_empty(),
+                "export" | "extern" => {
+                    args.len() == 1 && is_string(&args[0]) && !args[0].value.lex.is_empty()
+                }
+                _ => return Err(Error::new(name.start, E106(name.lex))),
+            };
+            if !valid {
+                return Err(Error::new(name.start, E107(name.lex)));
+            }
+            attributes.push(Attribute { start, name, args });
+        }
+        Ok(attributes)
+    }
+
+    /// Parse an argument of an attribute, which is a name or a string.
+    /// Strings are resolved, with the token keeping only their content.
+    fn attribute_arg(&mut self) -> Res<AttributeArg> {
+        if self.check(TKind::String) {
+            let start = self.current.start;
+            let lex = self.string()?;
+            return Ok(AttributeArg {
+                negated: false,
+                value: Token {
+                    kind: TKind::String,
+                    lex,
+                    start,
+                },
+            });
+        }
+        let negated = self.matches(Bang);
+        let value = self.consume(Identifier)?;
+        Ok(AttributeArg { negated, value })
+    }
+
+    /// Report all attributes that are not one of the `allowed` ones.
+    fn allow(&mut self, attributes: &[Attribute], allowed: &[&str]) {
+        for attribute in attributes {
+            if !allowed.contains(&attribute.name.lex.as_str()) {
+                self.errors.push(Error::new(
+                    attribute.start,
+                    E108(attribute.name.lex.clone()),
+                ));
+            }
+        }
+    }
+
+    /// Whether a declaration with the given attributes is kept, which it is
+    /// unless one of its `@cfg` attributes does not match the flags.
+    /// All arguments of a `@cfg` need to match, negated ones by not being set.
+    fn included(&self, attributes: &[Attribute]) -> bool {
+        let flags = match &self.flags {
+            Some(flags) => flags,
+            None => return true,
+        };
+        attributes
+            .iter()
+            .filter(|attribute| attribute.name.lex == "cfg")
+            .flat_map(|attribute| &attribute.args)
+            .all(|arg| flags.contains(&arg.value.lex) != arg.negated)
+    }
+
     fn member(&mut self, mutable: bool) -> Res<Member> {
         let name = self.consume(Identifier)?;
         self.consume(Colon)?;
         let ty = self.typ()?;
-        Ok(Member { name, ty, mutable })
+        let default = if self.matches(Equal) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        Ok(Member {
+            name,
+            ty,
+            mutable,
+            default,
+        })
     }
 
     fn function(&mut self, is_ext: bool) -> Res<Function> {
         let name = self.consume(Identifier)?;
+        self.function_named(name, is_ext)
+    }
 
+    fn function_named(&mut self, name: Token, is_ext: bool) -> Res<Function> {
         self.consume(LeftParen)?;
         let mut params = Vec::new();
         if !self.check(RightParen) {
             loop {
-                let name = self.consume(Identifier)?.lex;
+                let name = self.consume(Identifier)?;
                 self.consume(Colon)?;
                 let ty = self.typ()?;
-                params.push(Parameter { name, ty });
+                let default = if self.matches(Equal) {
+                    Some(self.expression()?)
+                } else {
+                    None
+                };
+                params.push(Parameter { name, ty, default });
                 if !self.matches(Comma) {
                     break;
                 }
@@ -125,7 +382,10 @@ impl<'src> Parser<'src> {
         };
 
         let body = if !is_ext {
-            Some(self.expression()?)
+            self.enclosing.push(name.lex.clone());
+            let body = self.expression();
+            self.enclosing.pop();
+            Some(body?)
         } else {
             None
         };
@@ -134,17 +394,41 @@ impl<'src> Parser<'src> {
             params,
             ret_type,
             body,
+            attributes: Vec::new(),
         })
     }
 
     fn higher_expr(&mut self) -> Res<Expr> {
-        if self.check_(&[Var, Val]) {
+        if self.check(At) {
+            let name = self
+                .lexer
+                .peek()
+                .map_or_else(SmolStr::default, |t| t.lex.clone());
+            Err(Error::new(self.current.start, E108(name)))
+        } else if self.check_(&[Var, Val]) {
             self.var_decl()
+        } else if self.check(Fun) && self.peek() == Identifier {
+            self.local_function()
         } else {
             self.expression()
         }
     }
 
+    fn local_function(&mut self) -> Res<Expr> {
+        self.advance();
+        let parent = SmolStr::join(self.enclosing.iter().map(SmolStr::as_str), "::");
+        let func = self.function(false)?;
+        let name = func.name.clone();
+        self.local_functions.push(LocalFunction { parent, func });
+        Ok(Expr {
+            start: name.start,
+            ty: Box::new(EExpr::LocalFunction {
+                name,
+                index: self.local_functions.len() - 1,
+            }),
+        })
+    }
+
     fn var_decl(&mut self) -> Res<Expr> {
         let final_ = self.advance().kind == Val;
         let name = self.consume(Identifier)?;
@@ -161,12 +445,13 @@ impl<'src> Parser<'src> {
     }
 
     fn expression(&mut self) -> Res<Expr> {
-        match self.current.kind {
-            LeftBrace => self.block(),
-            If => self.if_expr(),
-            While => self.while_stmt(),
-            _ => self.binary(0),
-        }
+        self.nested(|this| match this.current.kind {
+            LeftBrace => this.block(),
+            If => this.if_expr(),
+            While => this.while_stmt(),
+            Break => this.break_expr(),
+            _ => this.binary(0),
+        })
     }
 
     fn block(&mut self) -> Res<Expr> {
@@ -205,8 +490,30 @@ impl<'src> Parser<'src> {
         let cond = self.expression()?;
         self.consume(RightParen)?;
         let body = self.expression()?;
+        let els = if self.matches(Else) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        Ok(Expr {
+            ty: Box::new(EExpr::While { cond, body, els }),
+            start,
+        })
+    }
+
+    fn break_expr(&mut self) -> Res<Expr> {
+        let start = self.advance().start;
+        // The value needs to be on the same line, otherwise the next
+        // expression after a `break` without one would be taken as its value
+        let value = if self.check_(&[RightBrace, RightParen, Else, Error])
+            || self.lexer.line_break_between(start, self.current.start)
+        {
+            None
+        } else {
+            Some(self.expression()?)
+        };
         Ok(Expr {
-            ty: Box::new(EExpr::While { cond, body }),
+            ty: Box::new(EExpr::Break { value }),
             start,
         })
     }
@@ -220,7 +527,7 @@ impl<'src> Parser<'src> {
             }
 
             let op = self.advance();
-            let right = self.binary(rbp)?;
+            let right = self.nested(|this| this.binary(rbp))?;
             expr = Expr {
                 start: expr.start,
                 ty: Box::new(EExpr::Binary {
@@ -237,7 +544,7 @@ impl<'src> Parser<'src> {
     fn unary(&mut self) -> Res<Expr> {
         if let Some(rbp) = self.current.kind.prefix_binding_power() {
             let op = self.advance();
-            let right = self.binary(rbp)?;
+            let right = self.nested(|this| this.binary(rbp))?;
             Ok(Expr {
                 start: op.start,
                 ty: Box::new(EExpr::Unary { op, right }),
@@ -256,7 +563,7 @@ impl<'src> Parser<'src> {
                     let mut args = Vec::new();
                     if !self.check(RightParen) {
                         loop {
-                            args.push(self.expression()?);
+                            args.push(self.argument()?);
                             if !self.matches(Comma) {
                                 break;
                             }
@@ -269,12 +576,49 @@ impl<'src> Parser<'src> {
                     }
                 }
 
+                Dot => {
+                    self.advance();
+                    let name = self.consume(Identifier)?;
+                    expr = Expr {
+                        start: expr.start,
+                        ty: Box::new(EExpr::Get { object: expr, name }),
+                    }
+                }
+
                 _ => break,
             }
         }
         Ok(expr)
     }
 
+    fn argument(&mut self) -> Res<Argument> {
+        let value = self.expression()?;
+        // An assignment to a plain identifier makes no sense as an argument,
+        // so `f(a = 1)` is a named argument instead.
+        match *value.ty {
+            EExpr::Binary {
+                left:
+                    Expr {
+                        ty: box EExpr::Identifier(name),
+                        ..
+                    },
+                op,
+                right,
+            } if op.kind == Equal => Ok(Argument {
+                name: Some(name),
+                value: right,
+            }),
+
+            ty => Ok(Argument {
+                name: None,
+                value: Expr {
+                    ty: Box::new(ty),
+                    start: value.start,
+                },
+            }),
+        }
+    }
+
     fn primary(&mut self) -> Res<Expr> {
         match self.current.kind {
             False => Ok(Expr {
@@ -287,25 +631,22 @@ impl<'src> Parser<'src> {
             }),
             String => Ok(Expr {
                 start: self.current.start,
-                ty: Box::new(EExpr::Literal(Literal::String(self.advance().lex))),
+                ty: Box::new(EExpr::Literal(Literal::String(self.string()?))),
             }),
             Int => Ok(Expr {
-                ty: Box::new(EExpr::Literal(Literal::Int(
-                    i64::from_str(&self.current.lex).unwrap(),
-                ))),
-                start: self.advance().start,
+                start: self.current.start,
+                ty: Box::new(EExpr::Literal(Literal::Int(self.number()?))),
             }),
             Float => Ok(Expr {
-                ty: Box::new(EExpr::Literal(Literal::Float(
-                    f64::from_str(&self.current.lex).unwrap(),
-                ))),
-                start: self.advance().start,
+                start: self.current.start,
+                ty: Box::new(EExpr::Literal(self.float()?)),
             }),
 
             Identifier => Ok(Expr {
                 start: self.current.start,
                 ty: Box::new(EExpr::Identifier(self.advance())),
             }),
+            Fun => self.closure(),
             LeftParen => {
                 self.advance();
                 let expr = self.expression()?;
@@ -317,9 +658,104 @@ impl<'src> Parser<'src> {
         }
     }
 
+    fn closure(&mut self) -> Res<Expr> {
+        let start = self.advance().start;
+        let parent = SmolStr::join(self.enclosing.iter().map(SmolStr::as_str), "::");
+        let name = Token {
+            kind: Identifier,
+            lex: SmolStr::new_inline("{closure}"),
+            start,
+        };
+        let func = self.function_named(name, false)?;
+        self.local_functions.push(LocalFunction { parent, func });
+        Ok(Expr {
+            start,
+            ty: Box::new(EExpr::Closure(self.local_functions.len() - 1)),
+        })
+    }
+
+    /// Parse the current number literal, which fails if it
+    /// is out of range or has a type suffix.
+    fn number<T: FromStr>(&mut self) -> Res<T> {
+        let token = self.advance();
+        T::from_str(&token.lex).map_err(|_| Error::new(token.start, E103(token.lex)))
+    }
+
+    /// Parse the current string literal, resolving its escape sequences.
+    fn string(&mut self) -> Res<SmolStr> {
+        let token = self.advance();
+        let content = &token.lex[1..token.lex.len() - 1];
+        let mut string = SmolStrBuilder::new();
+        let mut chars = content.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                string.push(c);
+                continue;
+            }
+            // The lexer ensures a backslash is always followed by another character
+            string.push(match chars.next().unwrap() {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                '\\' => '\\',
+                '"' => '"',
+                c => return Err(Error::new(token.start, E105(c))),
+            });
+        }
+        Ok(string.finish())
+    }
+
+    /// Parse the current float literal, which is an `f64`
+    /// unless it has an `f32` suffix.
+    fn float(&mut self) -> Res<Literal> {
+        let token = self.advance();
+        let parsed = match token.lex.strip_suffix("f32") {
+            Some(lex) => f32::from_str(lex).map(Literal::F32),
+            None => {
+                let lex = token.lex.strip_suffix("f64").unwrap_or(&token.lex);
+                f64::from_str(lex).map(Literal::Float)
+            }
+        };
+        parsed.map_err(|_| Error::new(token.start, E103(token.lex)))
+    }
+
     fn typ(&mut self) -> Res<Type> {
-        let name = self.consume(Identifier)?;
-        Ok(Type { name })
+        if !self.check(Fun) {
+            return Ok(Type::Ident(self.consume(Identifier)?));
+        }
+
+        self.advance();
+        self.consume(LeftParen)?;
+        let mut params = Vec::new();
+        if !self.check(RightParen) {
+            loop {
+                params.push(self.nested(Self::typ)?);
+                if !self.matches(Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(RightParen)?;
+
+        let ret_type = if self.matches(Arrow) {
+            Some(Box::new(self.nested(Self::typ)?))
+        } else {
+            None
+        };
+        Ok(Type::Function { params, ret_type })
+    }
+
+    /// Parse something nested inside the current expression or type,
+    /// failing once nesting gets too deep.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Res<T>) -> Res<T> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::new(self.current.start, E104));
+        }
+        self.depth += 1;
+        let res = parse(self);
+        self.depth -= 1;
+        res
     }
 
     fn matches(&mut self, kind: TKind) -> bool {
@@ -354,6 +790,11 @@ impl<'src> Parser<'src> {
         mem::replace(&mut self.current, next)
     }
 
+    /// Returns the kind of the token after the current one.
+    fn peek(&mut self) -> TKind {
+        self.lexer.peek().map_or(TKind::Error, |token| token.kind)
+    }
+
     fn check(&mut self, kind: TKind) -> bool {
         self.current.kind == kind
     }
@@ -371,22 +812,38 @@ impl<'src> Parser<'src> {
         self.current.kind == TKind::Error
     }
 
+    /// Skip tokens until the next function or class, which
+    /// allows reporting errors in them after a previous one.
     fn synchronize(&mut self) {
-        while !self.is_at_end() {
-            match self.advance().kind {
-                Fun => return,
-                _ => (),
-            }
+        while !self.is_at_end() && !matches!(self.current.kind, Import | Fun | Class | At) {
+            self.advance();
         }
     }
 
     pub fn new(src: &'src str) -> Self {
         let mut lexer = Lexer::new(src);
-        let current = lexer.next().unwrap();
+        let current = lexer.next().unwrap_or_else(|| Token {
+            kind: TKind::Error,
+            lex: SmolStr::new_inline("\0"),
+            start: 0,
+        });
         Self {
             lexer,
+            source_hash: hash_source(src),
             current,
             errors: Vec::new(),
+            depth: 0,
+            enclosing: Vec::new(),
+            local_functions: Vec::new(),
+            flags: None,
         }
     }
 }
+
+/// FNV-1a hash of a source file, which modules keep to find out
+/// if they are outdated, like when loading them from a snapshot.
+pub fn hash_source(src: &str) -> u64 {
+    src.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
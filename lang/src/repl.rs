@@ -0,0 +1,180 @@
+//! An interactive session evaluating one input at a time,
+//! which read-eval-print loops are built on.
+
+use crate::{
+    compile_files_with,
+    compiler::ir::Type,
+    dump_clif,
+    error::Errors,
+    filesystem::File,
+    lexer::{Lexer, TKind},
+    parser::Parser,
+    CompileOptions, CompiledModule, Entry, Program, ProgramError, SmolStr, StrRef, SymbolTable,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+/// The function that expressions are evaluated in.
+const EVAL: &str = "__repl";
+
+/// A session of inputs that build on each other. Declarations like
+/// functions, classes and `val`s are kept for all later inputs,
+/// while anything else is evaluated as an expression on its own.
+/// Errors are always located in the input that caused them.
+pub struct Repl<'s> {
+    symbols: SymbolTable<'s>,
+    options: CompileOptions,
+    /// The source of all declarations entered so far.
+    declarations: String,
+}
+
+impl<'s> Repl<'s> {
+    /// An empty session, with the given host functions available to `extern fun`s.
+    pub fn new(symbols: SymbolTable<'s>) -> Self {
+        Self {
+            symbols,
+            options: CompileOptions {
+                entry_points: vec![SmolStr::new_inline(EVAL)],
+                ..CompileOptions::default()
+            },
+            declarations: String::new(),
+        }
+    }
+
+    /// Evaluate the given input. Returns the value of expressions formatted
+    /// for printing, or `None` for declarations and expressions without a value.
+    pub fn eval(&mut self, input: &str) -> Result<Option<String>, ProgramError> {
+        if Self::is_declaration(input) {
+            let source = format!("{}{}\n", self.declarations, input);
+            self.compile(&source, self.declarations.len())?;
+            self.declarations = source;
+            return Ok(None);
+        }
+        if Lexer::new(input).next().is_none() {
+            return Ok(None);
+        }
+
+        let ty = self.typ(input)?;
+        let modules = self.compile_expr(input, shown(&ty).as_deref())?;
+        let mut program = Program::new(&modules, self.symbols)?;
+        let entry = Entry::new(EVAL);
+        Ok(Some(match ty {
+            Type::Void => {
+                program.call::<()>(entry)?;
+                return Ok(None);
+            }
+            Type::Bool => program.call::<bool>(entry)?.to_string(),
+            Type::I64 => program.call::<i64>(entry)?.to_string(),
+            Type::F32 => format!("{:?}", program.call::<f32>(entry)?),
+            Type::F64 => format!("{:?}", program.call::<f64>(entry)?),
+            // The string is valid as long as the program is alive
+            Type::String => format!("{:?}", unsafe { program.call::<StrRef>(entry)?.as_str() }),
+            // Other values cannot be shown, only that they exist
+            _ => {
+                program.call::<()>(entry)?;
+                format!("<{}>", ty)
+            }
+        }))
+    }
+
+    /// Returns the type of the given expression, without evaluating it.
+    pub fn type_of(&self, input: &str) -> Result<String, ProgramError> {
+        Ok(self.typ(input)?.to_string())
+    }
+
+    /// Returns the Cranelift IR of the given expression
+    /// and all functions it calls, like `dump_clif`.
+    pub fn dump_clif(&self, input: &str) -> Result<String, ProgramError> {
+        let ty = self.typ(input)?;
+        Ok(dump_clif(&self.compile_expr(input, shown(&ty).as_deref())?))
+    }
+
+    /// Whether the given input is complete, or if it continues on the
+    /// next line since it has unclosed brackets or strings,
+    /// or ends in an operator.
+    pub fn is_complete(input: &str) -> bool {
+        let mut depth = 0isize;
+        let mut last = None;
+        for token in Lexer::new(input) {
+            match token.kind {
+                TKind::LeftParen | TKind::LeftBracket | TKind::LeftBrace => depth += 1,
+                TKind::RightParen | TKind::RightBracket | TKind::RightBrace => depth -= 1,
+                TKind::Error if token.lex.starts_with('"') => return false,
+                _ => (),
+            }
+            last = Some(token.kind);
+        }
+        let continues = last.map_or(false, |kind| {
+            kind.infix_binding_power().is_some()
+                || matches!(
+                    kind,
+                    TKind::Arrow | TKind::Comma | TKind::Dot | TKind::ColonColon | TKind::At
+                )
+        });
+        depth <= 0 && !continues
+    }
+
+    /// Declarations are anything that parses as a module, while
+    /// expressions never do since modules only consist of declarations.
+    fn is_declaration(input: &str) -> bool {
+        Lexer::new(input).next().is_some()
+            && Parser::new(input)
+                .parse(vec![SmolStr::new_inline("repl")])
+                .is_ok()
+    }
+
+    /// Returns the type of the given expression, found by compiling it
+    /// in a function without return type and looking at its body.
+    fn typ(&self, input: &str) -> Result<Type, ProgramError> {
+        let modules = self.compile_expr(input, None)?;
+        let module = modules[0].0.borrow();
+        let func = module.funcs.iter().find(|func| func.name == EVAL).unwrap();
+        let ty = func.body.borrow().typ();
+        Ok(ty)
+    }
+
+    /// Compile the given expression into the function `EVAL`,
+    /// returning a value of the given type or nothing.
+    fn compile_expr(
+        &self,
+        input: &str,
+        ret_type: Option<&str>,
+    ) -> Result<Vec<CompiledModule>, ProgramError> {
+        let signature = match ret_type {
+            Some(ty) => format!("fun {}() -> {} {{\n", EVAL, ty),
+            None => format!("fun {}() {{\n", EVAL),
+        };
+        let prefix = self.declarations.len() + signature.len();
+        let source = format!("{}{}{}\n}}\n", self.declarations, signature, input);
+        self.compile(&source, prefix)
+    }
+
+    /// Compile the given source, with the input starting at `offset`.
+    fn compile(&self, source: &str, offset: usize) -> Result<Vec<CompiledModule>, ProgramError> {
+        let file = File {
+            path: vec![SmolStr::new_inline("repl")],
+            contents: source.into(),
+        };
+        compile_files_with(&[file], &self.options).map_err(|errors| {
+            let errors: Errors = errors
+                .into_iter()
+                .flatten()
+                .map(|err| err.moved_back(offset))
+                .collect();
+            ProgramError::Compile(vec![errors])
+        })
+    }
+}
+
+/// Returns the name of the given type if its values can be shown,
+/// which expressions of it return instead of discarding them.
+fn shown(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Bool | Type::I64 | Type::F32 | Type::F64 | Type::String => Some(ty.to_string()),
+        _ => None,
+    }
+}
@@ -1,5 +1,5 @@
 use alloc::{fmt, string::String, sync::Arc};
-use core::{borrow::Borrow, cmp, cmp::Ordering, hash, ops::Deref};
+use core::{borrow::Borrow, cmp, cmp::Ordering, fmt::Write, hash, iter::FromIterator, ops::Deref};
 
 /// This module is almost 1:1 from rust_analyzer: https://github.com/rust-analyzer/smol_str
 /// Thank you to the rust_analyzer team!
@@ -42,6 +42,33 @@ impl SmolStr {
         })
     }
 
+    /// Constructs a `SmolStr` pointing to a static string, which is never copied.
+    #[inline]
+    pub const fn new_static(text: &'static str) -> SmolStr {
+        SmolStr(Repr::Static(text))
+    }
+
+    /// Constructs a `SmolStr` from formatting arguments, like
+    /// `SmolStr::from_fmt(format_args!("{}::{}", a, b))`.
+    /// Unlike `format!`, this does not allocate if the result fits inline.
+    pub fn from_fmt(args: fmt::Arguments<'_>) -> SmolStr {
+        let mut builder = SmolStrBuilder::new();
+        builder.write_fmt(args).unwrap();
+        builder.finish()
+    }
+
+    /// Constructs a `SmolStr` by joining the given parts with `separator` between them.
+    pub fn join<'a>(parts: impl IntoIterator<Item = &'a str>, separator: &str) -> SmolStr {
+        let mut builder = SmolStrBuilder::new();
+        for (i, part) in parts.into_iter().enumerate() {
+            if i != 0 {
+                builder.push_str(separator);
+            }
+            builder.push_str(part);
+        }
+        builder.finish()
+    }
+
     pub fn new<T>(text: T) -> SmolStr
     where
         T: AsRef<str>,
@@ -174,6 +201,22 @@ where
     }
 }
 
+impl FromIterator<char> for SmolStr {
+    fn from_iter<I: IntoIterator<Item = char>>(iter: I) -> SmolStr {
+        let mut builder = SmolStrBuilder::new();
+        iter.into_iter().for_each(|c| builder.push(c));
+        builder.finish()
+    }
+}
+
+impl<'a> FromIterator<&'a str> for SmolStr {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> SmolStr {
+        let mut builder = SmolStrBuilder::new();
+        iter.into_iter().for_each(|s| builder.push_str(s));
+        builder.finish()
+    }
+}
+
 impl From<SmolStr> for String {
     fn from(text: SmolStr) -> Self {
         text.as_str().into()
@@ -195,6 +238,7 @@ const WS: &str =
 #[derive(Clone, Debug)]
 enum Repr {
     Heap(Arc<str>),
+    Static(&'static str),
     Inline { len: u8, buf: [u8; INLINE_CAP] },
     Substring { newlines: usize, spaces: usize },
 }
@@ -240,6 +284,7 @@ impl Repr {
     fn len(&self) -> usize {
         match self {
             Repr::Heap(data) => data.len(),
+            Repr::Static(data) => data.len(),
             Repr::Inline { len, .. } => *len as usize,
             Repr::Substring { newlines, spaces } => *newlines + *spaces,
         }
@@ -249,6 +294,7 @@ impl Repr {
     fn is_empty(&self) -> bool {
         match self {
             Repr::Heap(data) => data.is_empty(),
+            Repr::Static(data) => data.is_empty(),
             Repr::Inline { len, .. } => *len == 0,
             // A substring isn't created for an empty string.
             Repr::Substring { .. } => false,
@@ -259,6 +305,7 @@ impl Repr {
     fn as_str(&self) -> &str {
         match self {
             Repr::Heap(data) => &*data,
+            Repr::Static(data) => data,
             Repr::Inline { len, buf } => {
                 let len = *len as usize;
                 let buf = &buf[..len];
@@ -273,3 +320,100 @@ impl Repr {
         }
     }
 }
+
+/// Builds a `SmolStr` piece by piece. Strings that fit inline are built
+/// on the stack, only longer ones are moved to a heap buffer.
+#[derive(Clone, Debug)]
+pub struct SmolStrBuilder(BuilderRepr);
+
+#[derive(Clone, Debug)]
+enum BuilderRepr {
+    Inline { len: usize, buf: [u8; INLINE_CAP] },
+    Heap(String),
+}
+
+impl SmolStrBuilder {
+    pub const fn new() -> SmolStrBuilder {
+        SmolStrBuilder(BuilderRepr::Inline {
+            len: 0,
+            buf: [0; INLINE_CAP],
+        })
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        match &mut self.0 {
+            BuilderRepr::Inline { len, buf } if *len + s.len() <= INLINE_CAP => {
+                buf[*len..*len + s.len()].copy_from_slice(s.as_bytes());
+                *len += s.len();
+            }
+            BuilderRepr::Inline { len, buf } => {
+                let mut heap = String::with_capacity(*len + s.len());
+                // Only ever filled from whole `str`s, so this is valid UTF-8
+                heap.push_str(unsafe { core::str::from_utf8_unchecked(&buf[..*len]) });
+                heap.push_str(s);
+                self.0 = BuilderRepr::Heap(heap);
+            }
+            BuilderRepr::Heap(heap) => heap.push_str(s),
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.push_str(c.encode_utf8(&mut [0; 4]))
+    }
+
+    /// Returns the string built so far.
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            BuilderRepr::Inline { len, buf } => unsafe {
+                core::str::from_utf8_unchecked(&buf[..*len])
+            },
+            BuilderRepr::Heap(heap) => heap,
+        }
+    }
+
+    pub fn finish(&self) -> SmolStr {
+        SmolStr::new(self.as_str())
+    }
+}
+
+impl Default for SmolStrBuilder {
+    fn default() -> SmolStrBuilder {
+        SmolStrBuilder::new()
+    }
+}
+
+impl fmt::Write for SmolStrBuilder {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::smol_str::{SmolStr, SmolStrBuilder};
+
+    #[test]
+    fn builder() {
+        let mut builder = SmolStrBuilder::new();
+        builder.push_str("fun");
+        builder.push(':');
+        assert_eq!(builder.as_str(), "fun:");
+        builder.push_str(" a rather long string which does not fit inline");
+        assert_eq!(
+            builder.finish(),
+            "fun: a rather long string which does not fit inline"
+        );
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(SmolStr::from_fmt(format_args!("{}#{}", "f", 2)), "f#2");
+        assert_eq!(
+            SmolStr::join(["a", "b", "c"].iter().copied(), "::"),
+            "a::b::c"
+        );
+        assert_eq!("ab€".chars().rev().collect::<SmolStr>(), "€ba");
+        assert_eq!(["x", "yz"].iter().copied().collect::<SmolStr>(), "xyz");
+    }
+}
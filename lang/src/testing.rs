@@ -0,0 +1,163 @@
+//! Language tests written as programs, which state what running them
+//! should result in with directive comments:
+//!
+//! ```text
+//! // expect: 42
+//! fun main() -> i64 40 + 2
+//! ```
+//!
+//! `// expect: <value>` expects `main` to return the given exit code,
+//! `// expect-error: <code>` expects compiling to fail with the given error.
+//! Expected errors can be repeated, and need to match all errors in order.
+
+use crate::{
+    compile_files, diagnostic::file_path, filesystem::File, CodeMemory, Entry, ExecError, Program,
+    ProgramError, SmolStr, StateError, SymbolTable,
+};
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt::{Display, Formatter, Result as FmtResult};
+
+/// What running a test program should result in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expectation {
+    /// The exit code `main` should return.
+    Value(i64),
+    /// The codes of the errors compiling should fail with, like `E503`.
+    Errors(Vec<SmolStr>),
+}
+
+/// Why a test program did not behave as expected.
+#[derive(Debug, PartialEq)]
+pub enum TestFailure {
+    /// The directive at the given line of a file could not be parsed,
+    /// or contradicts an earlier one.
+    InvalidDirective { file: String, line: usize },
+    /// The program failed to compile with other errors than expected.
+    WrongErrors {
+        expected: Vec<SmolStr>,
+        found: Vec<&'static str>,
+    },
+    /// The program compiled, but errors were expected.
+    Compiled,
+    /// `main` returned a different exit code than expected.
+    WrongValue { expected: i64, found: i64 },
+    /// The modules of the program could not be linked together.
+    Link(StateError),
+    /// The program could not be executed, or panicked.
+    Exec(ExecError),
+}
+
+impl Display for TestFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            TestFailure::InvalidDirective { file, line } => {
+                write!(f, "{}:{}: invalid directive", file, line)
+            }
+            TestFailure::WrongErrors { expected, found } => {
+                write!(f, "expected errors {:?}, but found {:?}", expected, found)
+            }
+            TestFailure::Compiled => write!(f, "expected errors, but the program compiled"),
+            TestFailure::WrongValue { expected, found } => {
+                write!(f, "expected {}, but main returned {}", expected, found)
+            }
+            TestFailure::Link(err) => write!(f, "failed to link: {:?}", err),
+            TestFailure::Exec(err) => write!(f, "failed to execute: {:?}", err),
+        }
+    }
+}
+
+/// Returns what the program made out of the given files should result in,
+/// or `None` if none of them contain any directives.
+pub fn expectation(files: &[File]) -> Result<Option<Expectation>, TestFailure> {
+    let mut expectation = None;
+    for file in files {
+        for (line, text) in file.contents.lines().enumerate() {
+            let invalid = || TestFailure::InvalidDirective {
+                file: file_path(file),
+                line: line + 1,
+            };
+            let text = text.trim();
+            if let Some(value) = text.strip_prefix("// expect:") {
+                let value = value.trim().parse().map_err(|_| invalid())?;
+                match expectation {
+                    None => expectation = Some(Expectation::Value(value)),
+                    Some(_) => return Err(invalid()),
+                }
+            } else if let Some(code) = text.strip_prefix("// expect-error:") {
+                let code = SmolStr::new(code.trim());
+                match &mut expectation {
+                    _ if code.is_empty() => return Err(invalid()),
+                    None => expectation = Some(Expectation::Errors(vec![code])),
+                    Some(Expectation::Errors(codes)) => codes.push(code),
+                    Some(Expectation::Value(_)) => return Err(invalid()),
+                }
+            }
+        }
+    }
+    Ok(expectation)
+}
+
+/// Compile and run the program made out of the given files, checking that
+/// it behaves like its directives state. Returns `None` if it has none.
+#[cfg(feature = "std")]
+pub fn run_test(files: &[File], symbols: SymbolTable) -> Option<Result<(), TestFailure>> {
+    run_test_with_memory(files, symbols, CodeMemory::default())
+}
+
+/// Run a test like `run_test`, placing the code of the program in the given memory.
+pub fn run_test_with_memory(
+    files: &[File],
+    symbols: SymbolTable,
+    memory: CodeMemory,
+) -> Option<Result<(), TestFailure>> {
+    let expectation = match expectation(files) {
+        Ok(expectation) => expectation?,
+        Err(failure) => return Some(Err(failure)),
+    };
+    Some(check(files, symbols, memory, expectation))
+}
+
+fn check(
+    files: &[File],
+    symbols: SymbolTable,
+    memory: CodeMemory,
+    expectation: Expectation,
+) -> Result<(), TestFailure> {
+    match (expectation, compile_files(files)) {
+        (Expectation::Value(expected), Ok(modules)) => {
+            let mut program = match Program::with_memory(&modules, symbols, memory) {
+                Ok(program) => program,
+                Err(ProgramError::Link(err)) => return Err(TestFailure::Link(err)),
+                // Code for the host only fails to link
+                Err(err) => unreachable!("{:?}", err),
+            };
+            let found = program.run(Entry::MAIN).map_err(TestFailure::Exec)?;
+            if found == expected {
+                Ok(())
+            } else {
+                Err(TestFailure::WrongValue { expected, found })
+            }
+        }
+        (Expectation::Errors(_), Ok(_)) => Err(TestFailure::Compiled),
+        (expectation, Err(errors)) => {
+            let found = errors
+                .iter()
+                .flatten()
+                .map(|err| err.kind().code())
+                .collect::<Vec<_>>();
+            let expected = match expectation {
+                Expectation::Errors(codes) => codes,
+                Expectation::Value(_) => Vec::new(),
+            };
+            if found
+                .iter()
+                .copied()
+                .eq(expected.iter().map(SmolStr::as_str))
+            {
+                Ok(())
+            } else {
+                Err(TestFailure::WrongErrors { expected, found })
+            }
+        }
+    }
+}
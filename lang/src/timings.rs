@@ -0,0 +1,118 @@
+//! Instrumentation measuring how long each stage of the compiler takes.
+//! Measurements are only taken with the `timings` feature enabled and
+//! once a clock has been set with `set_clock`, since there is no clock
+//! that works everywhere in `no_std`.
+
+#[cfg(feature = "timings")]
+pub use enabled::*;
+
+/// A stage of the compiler pipeline.
+#[derive(Debug, Copy, Clone)]
+pub enum Stage {
+    /// Turning source into tokens.
+    Lex,
+    /// Turning tokens into an AST, not including lexing.
+    Parse,
+    /// Turning the AST into IR, including all passes on the IR.
+    Ir,
+    /// Turning IR into machine code with cranelift.
+    Cranelift,
+    /// Linking the machine code of a module into executable memory.
+    Finalize,
+}
+
+#[cfg(not(feature = "timings"))]
+#[inline(always)]
+pub fn measure<T>(_stage: Stage, cls: impl FnOnce() -> T) -> T {
+    cls()
+}
+
+#[cfg(feature = "timings")]
+mod enabled {
+    use super::Stage;
+    use core::{
+        fmt, mem,
+        sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    /// A monotonic clock returning nanoseconds since an arbitrary point in time.
+    pub type Clock = fn() -> u64;
+
+    static CLOCK: AtomicUsize = AtomicUsize::new(0);
+    /// Time spent in stages nested inside the innermost running stage.
+    static NESTED: AtomicU64 = AtomicU64::new(0);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    static STAGES: [AtomicU64; 5] = [ZERO; 5];
+
+    /// Run the given closure, adding the time it took to the given stage.
+    /// Stages can be nested, in which case the time of the inner stage
+    /// is not counted towards the outer one.
+    pub fn measure<T>(stage: Stage, cls: impl FnOnce() -> T) -> T {
+        let clock = CLOCK.load(Ordering::Relaxed);
+        if clock == 0 {
+            return cls();
+        }
+        let clock = unsafe { mem::transmute::<usize, Clock>(clock) };
+
+        let outer_nested = NESTED.swap(0, Ordering::Relaxed);
+        let start = clock();
+        let result = cls();
+        let elapsed = clock().saturating_sub(start);
+        let nested = NESTED.swap(outer_nested + elapsed, Ordering::Relaxed);
+
+        STAGES[stage as usize].fetch_add(elapsed.saturating_sub(nested), Ordering::Relaxed);
+        result
+    }
+
+    /// Set the clock used for measuring the compiler.
+    /// No measurements are taken before this is called.
+    pub fn set_clock(clock: Clock) {
+        CLOCK.store(clock as usize, Ordering::Relaxed)
+    }
+
+    /// Returns the time spent in each stage since the last call.
+    pub fn take_timings() -> Timings {
+        let take =
+            |stage: Stage| Duration::from_nanos(STAGES[stage as usize].swap(0, Ordering::Relaxed));
+        Timings {
+            lex: take(Stage::Lex),
+            parse: take(Stage::Parse),
+            ir: take(Stage::Ir),
+            cranelift: take(Stage::Cranelift),
+            finalize: take(Stage::Finalize),
+        }
+    }
+
+    /// The time spent in each stage of the compiler.
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub struct Timings {
+        pub lex: Duration,
+        pub parse: Duration,
+        pub ir: Duration,
+        pub cranelift: Duration,
+        pub finalize: Duration,
+    }
+
+    impl Timings {
+        pub fn total(&self) -> Duration {
+            self.lex + self.parse + self.ir + self.cranelift + self.finalize
+        }
+    }
+
+    impl fmt::Display for Timings {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "lex {:?}, parse {:?}, ir {:?}, cranelift {:?}, finalize {:?} (total {:?})",
+                self.lex,
+                self.parse,
+                self.ir,
+                self.cranelift,
+                self.finalize,
+                self.total()
+            )
+        }
+    }
+}
@@ -0,0 +1,606 @@
+//! Formatting source code, by printing its AST with canonical
+//! indentation and spacing. Comments are kept in front of the
+//! declaration or statement following them, or at the end of the
+//! line they were on; comments inside of expressions move in front of it.
+
+use super::{find_comments, Comment};
+use crate::{
+    error::Errors,
+    lexer::{Lexer, TKind},
+    parser::{
+        ast::{self, Argument, Attribute, EExpr, Expr, Literal, Type},
+        Module, Parser,
+    },
+    smol_str::SmolStr,
+};
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+use hashbrown::HashMap;
+
+const INDENT: &str = "    ";
+
+/// Format the given source code, failing if it does not parse.
+pub fn format(source: &str) -> Result<String, Errors> {
+    let module = Parser::new(source).parse(vec![SmolStr::new_inline("file")])?;
+    let mut printer = Printer::new(source, &module);
+    printer.module();
+    let mut out = printer.out;
+    out.push('\n');
+    Ok(out)
+}
+
+struct Printer<'s> {
+    source: &'s str,
+    module: &'s Module,
+    out: String,
+    indent: usize,
+    /// Set to print an empty line before the next line.
+    blank: bool,
+    /// The start and end of every token, in order.
+    tokens: Vec<(TKind, usize, usize)>,
+    /// The start of the closing brace of every opening brace.
+    closing: HashMap<usize, usize>,
+    comments: Vec<Comment<'s>>,
+    next_comment: usize,
+}
+
+impl<'s> Printer<'s> {
+    fn new(source: &'s str, module: &'s Module) -> Self {
+        let tokens = Lexer::new(source)
+            .map(|token| (token.kind, token.start, token.start + token.lex.len()))
+            .collect::<Vec<_>>();
+
+        let mut closing = HashMap::new();
+        let mut open = Vec::new();
+        for (kind, start, _) in &tokens {
+            match kind {
+                TKind::LeftBrace => open.push(*start),
+                TKind::RightBrace => {
+                    if let Some(brace) = open.pop() {
+                        closing.insert(brace, *start);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let mut comments = Vec::new();
+        let mut gap_start = 0;
+        for (_, start, end) in
+            tokens
+                .iter()
+                .copied()
+                .chain(Some((TKind::Error, source.len(), source.len())))
+        {
+            find_comments(source, gap_start, start, &mut comments);
+            gap_start = end;
+        }
+
+        Self {
+            source,
+            module,
+            out: String::with_capacity(source.len()),
+            indent: 0,
+            blank: false,
+            tokens,
+            closing,
+            comments,
+            next_comment: 0,
+        }
+    }
+
+    fn module(&mut self) {
+        enum Decl<'a> {
+            Import(&'a ast::Import),
+            Function(&'a ast::Function),
+            Class(&'a ast::Class),
+            Global(&'a ast::Global),
+        }
+        let module = self.module;
+        let mut decls = module
+            .imports
+            .iter()
+            .map(|import| {
+                let start = start(&import.attributes, import.path[0].start);
+                (start, Decl::Import(import))
+            })
+            .chain(module.functions.iter().map(|func| {
+                let start = start(&func.attributes, func.name.start);
+                (start, Decl::Function(func))
+            }))
+            .chain(module.classes.iter().map(|class| {
+                let start = start(&class.attributes, class.name.start);
+                (start, Decl::Class(class))
+            }))
+            .chain(module.globals.iter().map(|global| {
+                let start = start(&global.attributes, global.name.start);
+                (start, Decl::Global(global))
+            }))
+            .collect::<Vec<_>>();
+        decls.sort_by_key(|(start, _)| *start);
+
+        let mut previous = None;
+        for (start, decl) in decls {
+            let start = self.leading(start);
+            // Imports and globals can be grouped, everything else is kept apart
+            let group = match decl {
+                Decl::Import(_) => Some(TKind::Import),
+                Decl::Global(_) => Some(TKind::Val),
+                _ => None,
+            };
+            self.blank = group.is_none() || group != previous || self.blank_before(start);
+            previous = group;
+            self.comments_before(start);
+            self.line();
+            match decl {
+                Decl::Import(import) => self.import(import),
+                Decl::Function(func) => self.function(func, "fun "),
+                Decl::Class(class) => self.class(class),
+                Decl::Global(global) => self.global(global),
+            }
+        }
+        self.comments_before(self.source.len());
+    }
+
+    fn class(&mut self, class: &ast::Class) {
+        enum Item<'a> {
+            Member(&'a ast::Member),
+            Method(&'a ast::Function),
+            Function(&'a ast::Function),
+            Static(&'a ast::Global),
+            Init(&'a Expr),
+        }
+        let mut items = class
+            .members
+            .iter()
+            .map(|member| (member.name.start, Item::Member(member)))
+            .chain(class.methods.iter().map(|method| {
+                let start = start(&method.attributes, method.name.start);
+                (start, Item::Method(method))
+            }))
+            .chain(class.functions.iter().map(|func| {
+                let start = start(&func.attributes, func.name.start);
+                (start, Item::Function(func))
+            }))
+            .chain(
+                class
+                    .statics
+                    .iter()
+                    .map(|global| (global.name.start, Item::Static(global))),
+            )
+            .chain(class.init.iter().map(|init| (init.start, Item::Init(init))))
+            .collect::<Vec<_>>();
+        items.sort_by_key(|(start, _)| *start);
+
+        self.attributes(&class.attributes);
+        write!(self.out, "class {} {{", class.name.lex).unwrap();
+        let brace = self
+            .tokens
+            .iter()
+            .find(|(kind, start, _)| *kind == TKind::LeftBrace && *start > class.name.start)
+            .map(|(_, start, _)| *start);
+        let close = brace.and_then(|brace| self.closing.get(&brace).copied());
+        if items.is_empty() && close.map_or(true, |close| !self.has_comments(close)) {
+            self.out.push('}');
+            return;
+        }
+
+        self.indent += 1;
+        let mut previous_function = false;
+        for (start, item) in &items {
+            // Members and statics can be grouped, functions are kept apart
+            let function = matches!(item, Item::Method(_) | Item::Function(_) | Item::Init(_));
+            let start = if let Item::Init(_) = item {
+                self.previous_token(*start)
+            } else {
+                self.leading(*start)
+            };
+            self.blank = function || previous_function || self.blank_before(start);
+            previous_function = function;
+            self.comments_before(start);
+            self.line();
+            match item {
+                Item::Member(member) => {
+                    let keyword = if member.mutable { "var" } else { "val" };
+                    write!(self.out, "{} {}: ", keyword, member.name.lex).unwrap();
+                    self.typ(&member.ty);
+                    if let Some(default) = &member.default {
+                        self.out.push_str(" = ");
+                        self.expr(default);
+                    }
+                }
+                Item::Method(func) => self.function(func, "fun "),
+                Item::Function(func) => self.function(func, "static fun "),
+                Item::Static(global) => {
+                    self.out.push_str("static ");
+                    self.global(global);
+                }
+                Item::Init(init) => {
+                    self.out.push_str("init ");
+                    self.expr(init);
+                }
+            }
+        }
+        if let Some(close) = close {
+            self.comments_before(close);
+        }
+        self.indent -= 1;
+        self.blank = false;
+        self.line();
+        self.out.push('}');
+    }
+
+    fn import(&mut self, import: &ast::Import) {
+        self.attributes(&import.attributes);
+        self.out.push_str("import ");
+        for (i, segment) in import.path.iter().enumerate() {
+            if i != 0 {
+                self.out.push_str("::");
+            }
+            self.out.push_str(&segment.lex);
+        }
+    }
+
+    fn global(&mut self, global: &ast::Global) {
+        self.attributes(&global.attributes);
+        write!(self.out, "val {} = ", global.name.lex).unwrap();
+        self.expr(&global.value);
+    }
+
+    /// Print a function, starting with the given keywords.
+    fn function(&mut self, func: &ast::Function, keywords: &str) {
+        self.attributes(&func.attributes);
+        if func.body.is_none() {
+            self.out.push_str("extern ");
+        }
+        self.out.push_str(keywords);
+        self.out.push_str(&func.name.lex);
+        self.signature(func);
+    }
+
+    /// Print the parameters, return type and body of a function.
+    fn signature(&mut self, func: &ast::Function) {
+        self.out.push('(');
+        for (i, param) in func.params.iter().enumerate() {
+            if i != 0 {
+                self.out.push_str(", ");
+            }
+            write!(self.out, "{}: ", param.name.lex).unwrap();
+            self.typ(&param.ty);
+            if let Some(default) = &param.default {
+                self.out.push_str(" = ");
+                self.expr(default);
+            }
+        }
+        self.out.push(')');
+        if let Some(ret_type) = &func.ret_type {
+            self.out.push_str(" -> ");
+            self.typ(ret_type);
+        }
+        if let Some(body) = &func.body {
+            self.out.push(' ');
+            self.expr(body);
+        }
+    }
+
+    /// Print attributes, each on its own line.
+    fn attributes(&mut self, attributes: &[Attribute]) {
+        for attribute in attributes {
+            write!(self.out, "@{}", attribute.name.lex).unwrap();
+            if !attribute.args.is_empty() {
+                self.out.push('(');
+                for (i, arg) in attribute.args.iter().enumerate() {
+                    if i != 0 {
+                        self.out.push_str(", ");
+                    }
+                    if arg.negated {
+                        self.out.push('!');
+                    }
+                    if arg.value.kind == TKind::String {
+                        self.literal(&Literal::String(arg.value.lex.clone()));
+                    } else {
+                        self.out.push_str(&arg.value.lex);
+                    }
+                }
+                self.out.push(')');
+            }
+            self.line();
+        }
+    }
+
+    fn typ(&mut self, ty: &Type) {
+        match ty {
+            Type::Ident(name) => self.out.push_str(&name.lex),
+            Type::Function { params, ret_type } => {
+                self.out.push_str("fun(");
+                for (i, param) in params.iter().enumerate() {
+                    if i != 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.typ(param);
+                }
+                self.out.push(')');
+                if let Some(ret_type) = ret_type {
+                    self.out.push_str(" -> ");
+                    self.typ(ret_type);
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &*expr.ty {
+            EExpr::Literal(literal) => self.literal(literal),
+
+            EExpr::Identifier(name) => self.out.push_str(&name.lex),
+
+            EExpr::Variable {
+                final_,
+                name,
+                value,
+            } => {
+                let keyword = if *final_ { "val" } else { "var" };
+                write!(self.out, "{} {} = ", keyword, name.lex).unwrap();
+                self.expr(value);
+            }
+
+            EExpr::Block(exprs) => self.block(expr.start, exprs),
+
+            EExpr::LocalFunction { index, .. } => {
+                let module = self.module;
+                self.function(&module.local_functions[*index].func, "fun ");
+            }
+
+            EExpr::Closure(index) => {
+                let module = self.module;
+                self.out.push_str("fun");
+                self.signature(&module.local_functions[*index].func);
+            }
+
+            EExpr::If { cond, then, els } => {
+                self.out.push_str("if (");
+                self.expr(cond);
+                self.out.push_str(") ");
+                // Without parentheses, the `else` would belong to the inner `if`
+                let dangling = matches!(
+                    &*then.ty,
+                    EExpr::If { els: None, .. } | EExpr::While { els: None, .. }
+                );
+                self.operand(then, dangling && els.is_some());
+                if let Some(els) = els {
+                    self.out.push_str(" else ");
+                    self.expr(els);
+                }
+            }
+
+            EExpr::While { cond, body, els } => {
+                self.out.push_str("while (");
+                self.expr(cond);
+                self.out.push_str(") ");
+                let dangling = matches!(
+                    &*body.ty,
+                    EExpr::If { els: None, .. } | EExpr::While { els: None, .. }
+                );
+                self.operand(body, dangling && els.is_some());
+                if let Some(els) = els {
+                    self.out.push_str(" else ");
+                    self.expr(els);
+                }
+            }
+
+            EExpr::Break { value } => {
+                self.out.push_str("break");
+                if let Some(value) = value {
+                    self.out.push(' ');
+                    self.expr(value);
+                }
+            }
+
+            EExpr::Binary { left, op, right } => {
+                let (power, _) = op.kind.infix_binding_power().unwrap();
+                // All binary operators are right-associative, so operators
+                // of the same power only need parentheses on the left
+                let left_parens = match &*left.ty {
+                    EExpr::Binary { op, .. } => op.kind.infix_binding_power().unwrap().0 <= power,
+                    ty => !simple(ty) && !matches!(ty, EExpr::Unary { .. }),
+                };
+                let right_parens = match &*right.ty {
+                    EExpr::Binary { op, .. } => op.kind.infix_binding_power().unwrap().0 < power,
+                    // A closure takes everything after it as its body
+                    EExpr::Closure(_) => op.kind != TKind::Equal,
+                    ty => !simple(ty) && !matches!(ty, EExpr::Unary { .. }),
+                };
+                self.operand(left, left_parens);
+                write!(self.out, " {} ", op.lex).unwrap();
+                self.operand(right, right_parens);
+            }
+
+            EExpr::Unary { op, right } => {
+                self.out.push_str(&op.lex);
+                self.operand(right, !simple(&right.ty));
+            }
+
+            EExpr::Call { callee, args } => {
+                self.operand(callee, !simple(&callee.ty));
+                self.out.push('(');
+                for (i, Argument { name, value }) in args.iter().enumerate() {
+                    if i != 0 {
+                        self.out.push_str(", ");
+                    }
+                    if let Some(name) = name {
+                        write!(self.out, "{} = ", name.lex).unwrap();
+                    }
+                    self.expr(value);
+                }
+                self.out.push(')');
+            }
+
+            EExpr::Get { object, name } => {
+                self.operand(object, !simple(&object.ty));
+                write!(self.out, ".{}", name.lex).unwrap();
+            }
+        }
+    }
+
+    fn operand(&mut self, expr: &Expr, parens: bool) {
+        if parens {
+            self.out.push('(');
+            self.expr(expr);
+            self.out.push(')');
+        } else {
+            self.expr(expr);
+        }
+    }
+
+    fn block(&mut self, start: usize, exprs: &[Expr]) {
+        let close = self.closing.get(&start).copied();
+        if exprs.is_empty() && close.map_or(true, |close| !self.has_comments(close)) {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push('{');
+        self.indent += 1;
+        for (i, expr) in exprs.iter().enumerate() {
+            let start = self.leading(expr.start);
+            self.blank = i != 0 && self.blank_before(start);
+            self.comments_before(start);
+            self.line();
+            self.expr(expr);
+        }
+        if let Some(close) = close {
+            self.comments_before(close);
+        }
+        self.indent -= 1;
+        self.blank = false;
+        self.line();
+        self.out.push('}');
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        match literal {
+            Literal::Bool(value) => write!(self.out, "{}", value).unwrap(),
+            Literal::Int(value) => write!(self.out, "{}", value).unwrap(),
+            Literal::Float(value) => self.float(&format!("{}", value), ""),
+            Literal::F32(value) => self.float(&format!("{}", value), "f32"),
+            Literal::String(string) => {
+                self.out.push('"');
+                for c in string.chars() {
+                    match c {
+                        '\n' => self.out.push_str("\\n"),
+                        '\r' => self.out.push_str("\\r"),
+                        '\t' => self.out.push_str("\\t"),
+                        '\0' => self.out.push_str("\\0"),
+                        '\\' => self.out.push_str("\\\\"),
+                        '"' => self.out.push_str("\\\""),
+                        c => self.out.push(c),
+                    }
+                }
+                self.out.push('"');
+            }
+        }
+    }
+
+    /// Print a float literal, which always needs a fractional part.
+    fn float(&mut self, value: &str, suffix: &str) {
+        self.out.push_str(value);
+        if !value.contains('.') {
+            self.out.push_str(".0");
+        }
+        self.out.push_str(suffix);
+    }
+
+    /// Start a new line at the current indentation.
+    fn line(&mut self) {
+        if self.out.is_empty() {
+            self.blank = false;
+            return;
+        }
+        if self.blank && !self.out.ends_with('{') {
+            self.out.push('\n');
+        }
+        self.blank = false;
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Print all comments before the given source offset, which is
+    /// the start of the code the comments in front of it belong to.
+    fn comments_before(&mut self, offset: usize) {
+        let mut own_line = false;
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.start >= offset {
+                break;
+            }
+            self.next_comment += 1;
+            let text = comment.text;
+            if comment.trailing && !self.out.is_empty() {
+                self.out.push(' ');
+            } else {
+                // An empty line before the code moves in front of its comments
+                self.blank |= comment.blank_before;
+                self.line();
+                own_line = true;
+            }
+            self.out.push_str(text);
+        }
+        if own_line {
+            self.blank = self.blank_before(offset);
+        }
+    }
+
+    fn has_comments(&self, offset: usize) -> bool {
+        self.comments
+            .get(self.next_comment)
+            .map_or(false, |comment| comment.start < offset)
+    }
+
+    /// If there is an empty line between the given offset and the code
+    /// or comment before it.
+    fn blank_before(&self, offset: usize) -> bool {
+        let before = &self.source[..offset];
+        let code = before.trim_end_matches(|c: char| c.is_whitespace());
+        before[code.len()..].matches('\n').count() > 1
+    }
+
+    /// The start of the keywords and parentheses in front of the
+    /// given name or expression, like the `val` in `val a = 1`.
+    fn leading(&self, start: usize) -> usize {
+        let mut index = self.tokens.partition_point(|(_, s, _)| *s < start);
+        while index > 0 {
+            match self.tokens[index - 1].0 {
+                TKind::LeftParen
+                | TKind::Import
+                | TKind::Val
+                | TKind::Var
+                | TKind::Fun
+                | TKind::Static
+                | TKind::Extern
+                | TKind::Class => index -= 1,
+                _ => break,
+            }
+        }
+        self.tokens.get(index).map_or(start, |(_, start, _)| *start)
+    }
+
+    /// The start of the token before the one at the given offset.
+    fn previous_token(&self, start: usize) -> usize {
+        let index = self.tokens.partition_point(|(_, s, _)| *s < start);
+        self.tokens[index.saturating_sub(1)].1
+    }
+}
+
+/// The start of a declaration, which is its first attribute if it has any.
+fn start(attributes: &[Attribute], name: usize) -> usize {
+    attributes.first().map_or(name, |attribute| attribute.start)
+}
+
+/// If the expression can be used as an operand of any operator or call without parentheses.
+fn simple(expr: &EExpr) -> bool {
+    matches!(
+        expr,
+        EExpr::Literal(_) | EExpr::Identifier(_) | EExpr::Call { .. } | EExpr::Get { .. }
+    )
+}
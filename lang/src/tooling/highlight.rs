@@ -0,0 +1,91 @@
+//! Classifying the tokens of source code for syntax highlighting,
+//! so editors can color it without reimplementing the lexer.
+
+use super::{find_comments, Comment};
+use crate::lexer::{Lexer, Span, TKind};
+use alloc::vec::Vec;
+
+/// The class of a token, which decides how it is colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    /// Keywords like `fun` and `while`, including the operators `and`, `or` and `is`.
+    Keyword,
+    Identifier,
+    /// Numbers, strings as well as `true`, `false` and `null`.
+    Literal,
+    Comment,
+    /// Operators and punctuation, like `+`, `->` and parentheses.
+    Operator,
+    /// Input the lexer does not understand, like a stray `$`.
+    Error,
+}
+
+/// Classify the tokens and comments of the given source code,
+/// in order of their position. Whitespace is left out.
+/// This works on any input, including source that does not parse.
+pub fn highlight(source: &str) -> Vec<(Span, TokenClass)> {
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    let mut gap_start = 0;
+    for token in Lexer::new(source) {
+        comments.clear();
+        find_comments(source, gap_start, token.start, &mut comments);
+        tokens.extend(comments.iter().map(comment));
+
+        let end = token.start + token.lex.len();
+        let class = match token.kind {
+            // Comments that are never closed are not recognized by the lexer
+            TKind::Error if token.lex.starts_with("/*") => TokenClass::Comment,
+            kind => class(kind),
+        };
+        tokens.push((token.start..end, class));
+        gap_start = end;
+    }
+    comments.clear();
+    find_comments(source, gap_start, source.len(), &mut comments);
+    tokens.extend(comments.iter().map(comment));
+    tokens
+}
+
+fn comment(comment: &Comment) -> (Span, TokenClass) {
+    let end = comment.start + comment.text.len();
+    (comment.start..end, TokenClass::Comment)
+}
+
+fn class(kind: TKind) -> TokenClass {
+    match kind {
+        TKind::Identifier => TokenClass::Identifier,
+
+        TKind::String | TKind::Int | TKind::Float | TKind::True | TKind::False | TKind::Null => {
+            TokenClass::Literal
+        }
+
+        TKind::And
+        | TKind::Break
+        | TKind::Class
+        | TKind::Else
+        | TKind::Enum
+        | TKind::Extern
+        | TKind::For
+        | TKind::Fun
+        | TKind::If
+        | TKind::Import
+        | TKind::In
+        | TKind::Interface
+        | TKind::Is
+        | TKind::Or
+        | TKind::Return
+        | TKind::Static
+        | TKind::Var
+        | TKind::Val
+        | TKind::When
+        | TKind::While => TokenClass::Keyword,
+
+        TKind::Error => TokenClass::Error,
+
+        // Skipped by the lexer, so they never show up
+        TKind::Comment | TKind::Whitespace | TKind::Newline => TokenClass::Comment,
+
+        _ => TokenClass::Operator,
+    }
+}
@@ -0,0 +1,463 @@
+//! APIs for tools working on source code, like editors and language servers.
+//! They work on a single file, which does not need to compile.
+
+use crate::{
+    error::Error,
+    lexer::{Lexer, TKind, Token},
+    parser::{
+        ast::{self, EExpr, Expr, Type},
+        Module, Parser,
+    },
+    smol_str::SmolStr,
+};
+use alloc::{vec, vec::Vec};
+
+mod format;
+mod highlight;
+
+pub use format::format;
+pub use highlight::{highlight, TokenClass};
+
+/// A source file parsed with error recovery, containing
+/// all declarations that could be parsed.
+pub struct ParsedFile<'src> {
+    source: &'src str,
+    module: Module,
+    errors: Vec<Error>,
+}
+
+/// Parse a single source file, even if it contains errors.
+pub fn parse_file(source: &str) -> ParsedFile<'_> {
+    let (module, errors) = Parser::new(source).parse_recovering(vec![SmolStr::new_inline("file")]);
+    ParsedFile {
+        source,
+        module,
+        errors,
+    }
+}
+
+/// A declaration in a source file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: SmolStr,
+    pub kind: SymbolKind,
+    /// The source offsets of the name of the symbol where it is declared.
+    pub start: usize,
+    pub end: usize,
+    /// The symbols declared inside of this one, like the members of a class.
+    pub children: Vec<Symbol>,
+}
+
+impl Symbol {
+    fn new(name: &Token, kind: SymbolKind) -> Self {
+        Self {
+            name: name.lex.clone(),
+            kind,
+            start: name.start,
+            end: name.start + name.lex.len(),
+            children: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Class,
+    Global,
+    Member,
+    Method,
+    /// A `static val` of a class.
+    Static,
+    Parameter,
+    /// A local variable or function.
+    Variable,
+}
+
+impl<'src> ParsedFile<'src> {
+    /// The errors found while parsing. Declarations containing
+    /// an error are missing from the file.
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    /// All declarations at the top level of the file,
+    /// in the order they are declared in.
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let module = &self.module;
+        let mut symbols = Vec::with_capacity(
+            module.functions.len() + module.classes.len() + module.globals.len(),
+        );
+        for func in &module.functions {
+            symbols.push(Symbol::new(&func.name, SymbolKind::Function));
+        }
+        for global in &module.globals {
+            symbols.push(Symbol::new(&global.name, SymbolKind::Global));
+        }
+        for class in &module.classes {
+            let mut symbol = Symbol::new(&class.name, SymbolKind::Class);
+            let children = &mut symbol.children;
+            for member in &class.members {
+                children.push(Symbol::new(&member.name, SymbolKind::Member));
+            }
+            for method in &class.methods {
+                children.push(Symbol::new(&method.name, SymbolKind::Method));
+            }
+            for func in &class.functions {
+                children.push(Symbol::new(&func.name, SymbolKind::Function));
+            }
+            for global in &class.statics {
+                children.push(Symbol::new(&global.name, SymbolKind::Static));
+            }
+            children.sort_by_key(|symbol| symbol.start);
+            symbols.push(symbol);
+        }
+        symbols.sort_by_key(|symbol| symbol.start);
+        symbols
+    }
+
+    /// Find the declaration of the identifier at the given source offset.
+    /// Returns `None` if there is no identifier there, or it does not refer
+    /// to anything declared in this file.
+    pub fn definition(&self, offset: usize) -> Option<Symbol> {
+        let target = Lexer::new(self.source).find(|token| {
+            token.kind == TKind::Identifier
+                && token.start <= offset
+                && offset <= token.start + token.lex.len()
+        })?;
+
+        let mut resolver = Resolver {
+            module: &self.module,
+            symbols: self.symbols(),
+            target: target.start,
+            scopes: Vec::new(),
+            found: None,
+        };
+        resolver.module();
+        resolver.found
+    }
+}
+
+/// Walks all declarations and expressions of a module while keeping
+/// track of the variables in scope, until it finds the target identifier.
+struct Resolver<'m> {
+    module: &'m Module,
+    /// The top level symbols of the module.
+    symbols: Vec<Symbol>,
+    /// The source offset of the identifier to resolve.
+    target: usize,
+    /// The variables in scope, innermost last.
+    scopes: Vec<Vec<Symbol>>,
+    found: Option<Symbol>,
+}
+
+impl<'m> Resolver<'m> {
+    fn module(&mut self) {
+        // Declarations themselves resolve to the symbol they declare
+        let target = self.target;
+        let declared = self
+            .symbols
+            .iter()
+            .flat_map(|symbol| Some(symbol).into_iter().chain(&symbol.children))
+            .find(|symbol| symbol.start == target);
+        if let Some(symbol) = declared {
+            self.found = Some(Symbol {
+                children: Vec::new(),
+                ..symbol.clone()
+            });
+            return;
+        }
+
+        let module = self.module;
+        for func in &module.functions {
+            self.function(func);
+        }
+        for global in &module.globals {
+            self.expr(&global.value);
+        }
+        for class in &module.classes {
+            let members = class
+                .members
+                .iter()
+                .map(|member| Symbol::new(&member.name, SymbolKind::Member))
+                .collect();
+            for member in &class.members {
+                self.typ(&member.ty);
+                if let Some(default) = &member.default {
+                    self.expr(default);
+                }
+            }
+            for func in class.methods.iter().chain(&class.functions) {
+                self.function(func);
+            }
+            for global in &class.statics {
+                self.expr(&global.value);
+            }
+            // The `init` block sees all members as variables
+            if let Some(init) = &class.init {
+                self.scopes.push(members);
+                self.expr(init);
+                self.scopes.pop();
+            }
+        }
+    }
+
+    fn function(&mut self, func: &ast::Function) {
+        let mut params = Vec::with_capacity(func.params.len());
+        for param in &func.params {
+            self.typ(&param.ty);
+            if let Some(default) = &param.default {
+                self.expr(default);
+            }
+            let symbol = Symbol::new(&param.name, SymbolKind::Parameter);
+            self.declare(&param.name, &symbol);
+            params.push(symbol);
+        }
+        if let Some(ret_type) = &func.ret_type {
+            self.typ(ret_type);
+        }
+
+        if let Some(body) = &func.body {
+            self.scopes.push(params);
+            self.expr(body);
+            self.scopes.pop();
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &*expr.ty {
+            EExpr::Literal(_) => (),
+
+            EExpr::Identifier(name) => {
+                if name.start == self.target {
+                    self.found = self.lookup(&name.lex);
+                }
+            }
+
+            EExpr::Variable { name, value, .. } => {
+                self.expr(value);
+                let symbol = Symbol::new(name, SymbolKind::Variable);
+                self.declare(name, &symbol);
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.push(symbol);
+                }
+            }
+
+            EExpr::Block(exprs) => {
+                // Local functions can be called before their declaration
+                let functions = exprs
+                    .iter()
+                    .filter_map(|expr| match &*expr.ty {
+                        EExpr::LocalFunction { name, .. } => {
+                            Some(Symbol::new(name, SymbolKind::Variable))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                self.scopes.push(functions);
+                for expr in exprs {
+                    self.expr(expr);
+                }
+                self.scopes.pop();
+            }
+
+            EExpr::LocalFunction { name, index } => {
+                self.declare(name, &Symbol::new(name, SymbolKind::Variable));
+                let module = self.module;
+                self.function(&module.local_functions[*index].func);
+            }
+
+            EExpr::Closure(index) => {
+                let module = self.module;
+                self.function(&module.local_functions[*index].func)
+            }
+
+            EExpr::If { cond, then, els } => {
+                self.expr(cond);
+                self.expr(then);
+                if let Some(els) = els {
+                    self.expr(els);
+                }
+            }
+
+            EExpr::While { cond, body, els } => {
+                self.expr(cond);
+                self.expr(body);
+                if let Some(els) = els {
+                    self.expr(els);
+                }
+            }
+
+            EExpr::Break { value } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+
+            EExpr::Binary { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+
+            EExpr::Unary { right, .. } => self.expr(right),
+
+            EExpr::Call { callee, args } => {
+                self.expr(callee);
+                for arg in args {
+                    if let Some(name) = arg.name.as_ref().filter(|n| n.start == self.target) {
+                        self.found = self.named_argument(callee, &name.lex);
+                    }
+                    self.expr(&arg.value);
+                }
+            }
+
+            EExpr::Get { object, name } => {
+                self.expr(object);
+                if name.start == self.target {
+                    self.found = self.static_member(object, &name.lex);
+                }
+            }
+        }
+    }
+
+    fn typ(&mut self, ty: &Type) {
+        match ty {
+            Type::Ident(name) if name.start == self.target => {
+                self.found = self
+                    .top_level(&name.lex)
+                    .filter(|symbol| symbol.kind == SymbolKind::Class);
+            }
+            Type::Ident(_) => (),
+            Type::Function { params, ret_type } => {
+                for param in params {
+                    self.typ(param);
+                }
+                if let Some(ret_type) = ret_type {
+                    self.typ(ret_type);
+                }
+            }
+        }
+    }
+
+    /// Set the declaration of the target if the target is the given name.
+    fn declare(&mut self, name: &Token, symbol: &Symbol) {
+        if name.start == self.target {
+            self.found = Some(symbol.clone());
+        }
+    }
+
+    /// Find what the given name refers to at the current position.
+    fn lookup(&self, name: &str) -> Option<Symbol> {
+        let local = self
+            .scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|symbol| symbol.name == name);
+        local.cloned().or_else(|| self.top_level(name))
+    }
+
+    fn top_level(&self, name: &str) -> Option<Symbol> {
+        let symbol = self.symbols.iter().find(|symbol| symbol.name == name)?;
+        Some(Symbol {
+            children: Vec::new(),
+            ..symbol.clone()
+        })
+    }
+
+    /// Resolve the `static val` or function in `Class.name`,
+    /// which is the only kind of member access not needing types.
+    fn static_member(&self, object: &Expr, name: &str) -> Option<Symbol> {
+        let class = match &*object.ty {
+            EExpr::Identifier(class) => self.lookup(&class.lex)?,
+            _ => return None,
+        };
+        if class.kind != SymbolKind::Class {
+            return None;
+        }
+        let class = self.symbols.iter().find(|s| s.start == class.start)?;
+        class
+            .children
+            .iter()
+            .find(|s| s.name == name && matches!(s.kind, SymbolKind::Static | SymbolKind::Function))
+            .cloned()
+    }
+
+    /// Resolve a named argument to the parameter of the function called,
+    /// if that is a function declared at the top level.
+    fn named_argument(&self, callee: &Expr, name: &str) -> Option<Symbol> {
+        let callee = match &*callee.ty {
+            EExpr::Identifier(callee) => self.lookup(&callee.lex)?,
+            _ => return None,
+        };
+        let params = match callee.kind {
+            SymbolKind::Function => {
+                let func = self
+                    .module
+                    .functions
+                    .iter()
+                    .find(|f| f.name.start == callee.start)?;
+                func.params
+                    .iter()
+                    .map(|p| Symbol::new(&p.name, SymbolKind::Parameter))
+                    .collect::<Vec<_>>()
+            }
+            // Constructors take the members of their class
+            SymbolKind::Class => {
+                let class = self
+                    .module
+                    .classes
+                    .iter()
+                    .find(|c| c.name.start == callee.start)?;
+                class
+                    .members
+                    .iter()
+                    .map(|m| Symbol::new(&m.name, SymbolKind::Member))
+                    .collect()
+            }
+            _ => return None,
+        };
+        params.into_iter().find(|param| param.name == name)
+    }
+}
+
+/// A comment in the source, which the lexer skips.
+struct Comment<'s> {
+    start: usize,
+    text: &'s str,
+    /// If the comment is on the same line as the code before it.
+    trailing: bool,
+    /// If there is an empty line before the comment.
+    blank_before: bool,
+}
+
+/// Find the comments in a gap between two tokens,
+/// which only contains comments and whitespace.
+fn find_comments<'s>(source: &'s str, start: usize, end: usize, comments: &mut Vec<Comment<'s>>) {
+    let mut pos = start;
+    let mut newlines = 0;
+    while pos < end {
+        let rest = &source[pos..end];
+        if rest.starts_with("//") || rest.starts_with("/*") {
+            let len = if rest.starts_with("//") {
+                rest.find('\n').unwrap_or(rest.len())
+            } else {
+                rest.find("*/").map_or(rest.len(), |i| i + 2)
+            };
+            comments.push(Comment {
+                start: pos,
+                text: rest[..len].trim_end(),
+                trailing: newlines == 0 && start != 0,
+                blank_before: newlines > 1,
+            });
+            pos += len;
+            newlines = 0;
+        } else {
+            let c = rest.chars().next().unwrap();
+            if c == '\n' {
+                newlines += 1;
+            }
+            pos += c.len_utf8();
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Values the host binds to names before running a program, like the size
+//! of the screen, which programs read with `get_env` and its typed variants.
+
+use crate::{
+    smol_str::SmolStr,
+    vm::string::{StrRef, Strings},
+};
+use core::cell::RefCell;
+use hashbrown::HashMap;
+
+/// A value bound to a name in the environment of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(SmolStr),
+}
+
+impl From<i64> for Binding {
+    fn from(value: i64) -> Self {
+        Binding::Int(value)
+    }
+}
+
+impl From<f64> for Binding {
+    fn from(value: f64) -> Self {
+        Binding::Float(value)
+    }
+}
+
+impl From<bool> for Binding {
+    fn from(value: bool) -> Self {
+        Binding::Bool(value)
+    }
+}
+
+impl From<&str> for Binding {
+    fn from(value: &str) -> Self {
+        Binding::String(SmolStr::new(value))
+    }
+}
+
+/// The environment of a program. Bindings can change between calls
+/// into the program, so compiled code looks them up whenever it reads them.
+#[derive(Default)]
+pub struct Bindings(RefCell<HashMap<SmolStr, Binding>>);
+
+impl Bindings {
+    pub fn set(&self, name: SmolStr, value: Binding) {
+        self.0.borrow_mut().insert(name, value);
+    }
+
+    fn get(&self, name: StrRef) -> Option<Binding> {
+        let name = unsafe { name.as_str() };
+        self.0.borrow().get(name).cloned()
+    }
+}
+
+/// Read a binding of the given type, for `get_env_int` and the other typed variants.
+/// Strings are parsed, like the arguments of a program. Bindings that do not exist
+/// or are of another type read as zero or `false`, as do strings that do not parse.
+pub extern "C" fn get_int(bindings: &Bindings, name: StrRef) -> i64 {
+    match bindings.get(name) {
+        Some(Binding::Int(value)) => value,
+        Some(Binding::String(value)) => value.parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+pub extern "C" fn get_float(bindings: &Bindings, name: StrRef) -> f64 {
+    match bindings.get(name) {
+        Some(Binding::Float(value)) => value,
+        Some(Binding::String(value)) => value.parse().unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
+
+pub extern "C" fn get_bool(bindings: &Bindings, name: StrRef) -> bool {
+    match bindings.get(name) {
+        Some(Binding::Bool(value)) => value,
+        Some(Binding::String(value)) => value == "true",
+        _ => false,
+    }
+}
+
+/// Read a binding as a string, for `get_env`. Bindings of other types
+/// are converted like `str` would, ones that do not exist read as an empty string.
+pub extern "C" fn get_str(bindings: &Bindings, strings: &Strings, name: StrRef) -> StrRef {
+    match bindings.get(name) {
+        Some(Binding::String(value)) => strings.alloc_limited(&value),
+        Some(Binding::Int(value)) => super::string::from_int(strings, value),
+        Some(Binding::Float(value)) => super::string::from_float(strings, value),
+        Some(Binding::Bool(value)) => super::string::from_bool(strings, value),
+        None => strings.alloc_limited(""),
+    }
+}
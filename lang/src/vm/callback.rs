@@ -0,0 +1,99 @@
+//! Typed function pointers to compiled functions, which allow the host
+//! to call into a program after it was started.
+
+use crate::compiler::ir::{FnSig, Type};
+use core::{marker::PhantomData, mem, ops::Deref};
+
+/// A Rust type that values of a yacari type can be passed as.
+/// Compiled code is called with the ABI of these types,
+/// which is why the trait is sealed.
+pub trait AbiValue: Sealed {
+    /// The yacari type of values passed as `Self`.
+    fn ty() -> Type;
+
+    fn matches(ty: &Type) -> bool {
+        *ty == Self::ty()
+    }
+}
+
+/// Keeps `AbiValue` from being implemented outside of this crate.
+/// Public, but in a private module, which makes it impossible to name.
+pub trait Sealed {}
+
+macro_rules! impl_abi_value {
+    ($($rust:ty => $ty:expr),*) => {
+        $(
+            impl Sealed for $rust {}
+
+            impl AbiValue for $rust {
+                fn ty() -> Type {
+                    $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_abi_value!(() => Type::Void, bool => Type::Bool, i64 => Type::I64, f32 => Type::F32, f64 => Type::F64);
+
+/// A function pointer type that compiled functions can be retrieved as.
+/// Implemented for `extern "C"` functions with up to 4 parameters,
+/// where parameters and return value are `AbiValue`s.
+///
+/// # Safety
+/// `matches` may only accept signatures that share the ABI of `Self`.
+pub unsafe trait Callback: Copy {
+    fn matches(sig: &FnSig) -> bool;
+    unsafe fn from_ptr(ptr: *const u8) -> Self;
+}
+
+macro_rules! impl_callback {
+    ($($param:ident),*) => {
+        unsafe impl<R: AbiValue, $($param: AbiValue),*> Callback for extern "C" fn($($param),*) -> R {
+            fn matches(sig: &FnSig) -> bool {
+                let params: &[fn(&Type) -> bool] = &[$($param::matches),*];
+                R::matches(&sig.ret_type)
+                    && sig.params.len() == params.len()
+                    && sig.params.iter().zip(params).all(|(ty, matches)| matches(ty))
+            }
+
+            unsafe fn from_ptr(ptr: *const u8) -> Self {
+                mem::transmute(ptr)
+            }
+        }
+    };
+}
+
+impl_callback!();
+impl_callback!(A);
+impl_callback!(A, B);
+impl_callback!(A, B, C);
+impl_callback!(A, B, C, D);
+
+/// A callback retrieved from a program, which borrows the program: its code
+/// is freed together with the program, which would leave the function dangling.
+/// It dereferences to the function, which allows calling it directly.
+#[derive(Debug, Clone, Copy)]
+pub struct Hook<'p, F: Callback> {
+    func: F,
+    program: PhantomData<&'p ()>,
+}
+
+impl<F: Callback> Hook<'_, F> {
+    /// # Safety
+    /// The function needs to stay valid as long as the hook.
+    pub(crate) unsafe fn new(func: F) -> Self {
+        Self {
+            func,
+            program: PhantomData,
+        }
+    }
+}
+
+impl<F: Callback> Deref for Hook<'_, F> {
+    type Target = F;
+
+    fn deref(&self) -> &F {
+        &self.func
+    }
+}
@@ -0,0 +1,43 @@
+//! Environments of closures, which hold the values they captured.
+//! A function value is a pair of the function's address and its environment,
+//! which is null for functions that are not closures.
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    ptr,
+};
+
+/// The size of each slot in an environment, which holds a single cranelift value.
+pub const SLOT_SIZE: i32 = 8;
+
+/// The maximum amount of slots in all environments of a program, 8 MiB.
+pub const MAX_SLOTS: usize = 1 << 20;
+
+/// The environments of all closures created by a program.
+/// They are freed together with the program, since a program
+/// cannot tell when a closure is no longer used: closures can be returned,
+/// stored in objects and passed to the host. To keep a program that creates
+/// closures in a loop from using up all memory, their total size is capped
+/// at [`MAX_SLOTS`]; creating a closure beyond it panics.
+#[derive(Default)]
+pub struct Environments {
+    envs: RefCell<Vec<Box<[u64]>>>,
+    slots: Cell<usize>,
+}
+
+/// Allocate a new environment with the given amount of slots.
+/// Called by compiled code whenever a closure is created.
+/// Returns null if the environments would exceed [`MAX_SLOTS`].
+pub extern "C" fn allocate(envs: &Environments, slots: u64) -> *mut u64 {
+    let total = envs.slots.get() + slots as usize;
+    if total > MAX_SLOTS {
+        return ptr::null_mut();
+    }
+    envs.slots.set(total);
+
+    let mut env = vec![0; slots as usize].into_boxed_slice();
+    let ptr = env.as_mut_ptr();
+    envs.envs.borrow_mut().push(env);
+    ptr
+}
@@ -1,26 +1,43 @@
 use crate::{
     compiler::{
         ir,
-        ir::{Constant, Expr, IExpr},
+        ir::{Constant, Expr, FuncRef, IExpr, Intrinsic},
     },
     lexer::TKind,
+    smol_str::SmolStr,
     vm::{
+        bindings, closure,
+        closure::SLOT_SIZE,
         function::FnTranslator,
-        get_or_declare_ir_fn, typesys,
-        typesys::{value, values, CValue},
+        get_or_declare_ir_fn, make_sig, panic, string, typesys,
+        typesys::{value, values, CValue, CLIF_PTR},
     },
 };
 use alloc::vec::Vec;
-use cranelift::prelude::*;
+use core::ptr;
+use cranelift::{codegen::ir as clif, prelude::*};
 use cranelift_module::Module;
 use smallvec::SmallVec;
 
 impl<'b> FnTranslator<'b> {
     pub fn trans_expr(&mut self, expr: &ir::Expr) -> CValue {
+        // Instructions belong to the innermost expression with a position
+        let outer = self.srcloc;
+        if let Some(start) = expr.start {
+            self.srcloc = clif::SourceLoc::new(start as u32);
+            self.cl.set_srcloc(self.srcloc);
+        }
+        let value = self.trans_expr_inner(expr);
+        self.srcloc = outer;
+        self.cl.set_srcloc(outer);
+        value
+    }
+
+    fn trans_expr_inner(&mut self, expr: &ir::Expr) -> CValue {
         match &*expr.inner {
             IExpr::Binary { left, op, right } => value(self.binary(left, op.kind, right)),
 
-            IExpr::Constant(constant) => value(self.constant(constant)),
+            IExpr::Constant(constant) => self.constant(constant),
 
             IExpr::Block(insts) => {
                 let mut value = None;
@@ -37,7 +54,14 @@ impl<'b> FnTranslator<'b> {
                 phi,
             } => self.if_(cond, *phi, then, els),
 
-            IExpr::While { cond, body } => self.while_expr(cond, body),
+            IExpr::While {
+                cond,
+                body,
+                els,
+                phi,
+            } => self.while_expr(cond, body, els, *phi),
+
+            IExpr::Break { value } => self.break_(value),
 
             IExpr::Variable { index, typ } => self.variable_expr(*index, typ),
 
@@ -48,10 +72,91 @@ impl<'b> FnTranslator<'b> {
 
             IExpr::Call { callee, args } => self.call(callee, args),
 
+            IExpr::Convert { value: expr, to } => value(self.convert(expr, to)),
+
+            IExpr::Closure { func, captures } => self.closure(func, captures),
+
+            IExpr::Construct { members, .. } => members
+                .iter()
+                .flat_map(|member| self.trans_expr(member))
+                .collect(),
+
+            IExpr::Member { object, index, typ } => self.member(object, *index, typ),
+
+            IExpr::Panic { message } => self.panic(message),
+
+            IExpr::Env { name, typ } => value(self.env(name, typ)),
+
+            IExpr::Intrinsic { intrinsic, args } => value(self.intrinsic(*intrinsic, args)),
+
             IExpr::Poison => panic!("Cannot translate poison values!"),
         }
     }
 
+    /// Translate an expression whose value is returned from the function.
+    /// Calls to the function itself in this position are turned into a
+    /// jump back to its start, which allows recursing without growing the stack.
+    pub(super) fn tail_expr(&mut self, expr: &ir::Expr) {
+        match &*expr.inner {
+            IExpr::Block(insts) if !insts.is_empty() => {
+                let (last, insts) = insts.split_last().unwrap();
+                for inst in insts {
+                    self.trans_expr(inst);
+                }
+                self.tail_expr(last)
+            }
+
+            IExpr::If {
+                cond, then, els, ..
+            } => {
+                let condition = self.trans_expr(cond);
+                let then_b = self.new_block();
+                let else_b = self.new_block();
+                self.br(condition[0], then_b, else_b);
+
+                self.switch_block(then_b);
+                self.cl.seal_block(then_b);
+                self.tail_expr(then);
+
+                self.switch_block(else_b);
+                self.cl.seal_block(else_b);
+                self.tail_expr(els);
+            }
+
+            IExpr::Call { callee, args } if self.is_self_call(callee) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.trans_expr(arg))
+                    .collect::<Vec<_>>();
+                for (param, arg) in self.func.params.iter().zip(args) {
+                    let offset = self.local_offsets[param.index];
+                    for (i, value) in arg.into_iter().enumerate() {
+                        self.cl.def_var(Self::variable(offset + i), value);
+                    }
+                }
+                self.check_panic();
+                self.cl.ins().jump(self.body_block, &[]);
+            }
+
+            _ => {
+                let mut ret = self.trans_expr(expr);
+                if self.func.ret_type == ir::Type::Void {
+                    // Branches of ifs in void functions might still have a value
+                    ret.clear();
+                }
+                self.to_abi(&self.func.ret_type, &mut ret);
+                self.cl.ins().return_(&ret);
+            }
+        }
+    }
+
+    fn is_self_call(&self, callee: &Expr) -> bool {
+        match &*callee.inner {
+            IExpr::Constant(Constant::Function(func)) => ptr::eq(&*func.resolve(), self.func),
+            _ => false,
+        }
+    }
+
     fn variable(index: usize) -> Variable {
         Variable::with_u32(index as u32)
     }
@@ -81,12 +186,28 @@ impl<'b> FnTranslator<'b> {
         let l = self.trans_expr(left)[0];
         let r = self.trans_expr(right)[0];
 
-        if left.typ().is_int() {
+        if left.typ() == ir::Type::String {
+            // `+` is the only operator on strings
+            let strings = self.strings_ptr();
+            let string = self.call_string_runtime(string::concat as *const u8, &[strings, l, r]);
+            // Operands created just for this concatenation are unreachable now
+            for (expr, value) in [(left, l), (right, r)] {
+                if is_temporary_string(expr) {
+                    self.runtime_call(string::free as *const u8, &[strings, value], &[]);
+                }
+            }
+            string
+        } else if left.typ().is_int() {
             match op {
                 TKind::Plus => self.cl.ins().iadd(l, r),
                 TKind::Minus => self.cl.ins().isub(l, r),
                 TKind::Star => self.cl.ins().imul(l, r),
-                TKind::Slash => self.cl.ins().udiv(l, r),
+                TKind::Slash | TKind::Percent => self.divide(op, l, r),
+                TKind::Ampersand => self.cl.ins().band(l, r),
+                TKind::Pipe => self.cl.ins().bor(l, r),
+                TKind::Caret => self.cl.ins().bxor(l, r),
+                TKind::LessLess => self.cl.ins().ishl(l, r),
+                TKind::GreaterGreater => self.cl.ins().sshr(l, r),
                 _ => self.cl.ins().icmp(intcmp(op), l, r),
             }
         } else {
@@ -100,18 +221,292 @@ impl<'b> FnTranslator<'b> {
         }
     }
 
-    fn constant(&mut self, constant: &Constant) -> Value {
-        match constant {
+    /// Divide integers or take the remainder. Dividing by zero and `i64::MIN / -1`
+    /// panic instead, since the CPU would fault on them.
+    fn divide(&mut self, op: TKind, l: Value, r: Value) -> Value {
+        let zero = self.cl.ins().icmp_imm(IntCC::Equal, r, 0);
+        self.panic_if(zero, "division by zero");
+        if op == TKind::Percent {
+            // Cranelift defines `i64::MIN % -1` as 0 without faulting
+            return self.cl.ins().srem(l, r);
+        }
+        let min = self.cl.ins().icmp_imm(IntCC::Equal, l, i64::MIN);
+        let minus_one = self.cl.ins().icmp_imm(IntCC::Equal, r, -1);
+        let overflow = self.cl.ins().band(min, minus_one);
+        self.panic_if(overflow, "division overflow");
+        self.cl.ins().sdiv(l, r)
+    }
+
+    fn constant(&mut self, constant: &Constant) -> CValue {
+        value(match constant {
             Constant::Bool(val) => self.cl.ins().bconst(types::B1, *val),
             Constant::Int(int) => self.cl.ins().iconst(types::I64, *int),
             Constant::Float(float) => self.cl.ins().f64const(*float),
-            Constant::String(_) => unimplemented!(),
+            Constant::F32(float) => self.cl.ins().f32const(*float),
+            Constant::String(string) => {
+                let string = self.strings.alloc(string).as_ptr();
+                self.cl.ins().iconst(CLIF_PTR, string as i64)
+            }
+
+            // Functions are closures without an environment
+            Constant::Function(func) => return self.closure(func, &[]),
 
-            // Functions/Classes are always their own types, so their values are essentially zero-sized.
+            // Classes are always their own types, so their values are essentially zero-sized.
             // However, cranelift of course does not have zero-sized values,
             // so we just return whatever.
-            // TODO is this fine? might be reasonable to return the pointer to the function instead for FFI or something in the future?
-            Constant::Function(_) | Constant::Class(_) => self.cl.ins().iconst(types::I64, 0),
+            Constant::Class(_) => self.cl.ins().iconst(types::I64, 0),
+        })
+    }
+
+    /// Read a member of a class value, which is the
+    /// values it occupies in the flattened class.
+    fn member(&mut self, object: &Expr, index: usize, typ: &ir::Type) -> CValue {
+        let values = self.trans_expr(object);
+        let class = match object.typ() {
+            ir::Type::Class(class) => class,
+            _ => panic!("Member access on non-class value!"),
+        };
+        let offset: usize = class
+            .resolve()
+            .members()
+            .iter()
+            .take(index)
+            .map(|member| typesys::translate_type(&member.ty, |_, _| ()))
+            .sum();
+        let len = typesys::translate_type(typ, |_, _| ());
+        typesys::values(&values[offset..offset + len])
+    }
+
+    /// Create a function value, with an environment holding
+    /// the captured values if there are any.
+    fn closure(&mut self, func: &FuncRef, captures: &[Expr]) -> CValue {
+        let func_ref = self.declare_func_ref(func);
+        let address = self.cl.ins().func_addr(CLIF_PTR, func_ref);
+        if captures.is_empty() {
+            let null = self.cl.ins().iconst(CLIF_PTR, 0);
+            return values(&[address, null]);
+        }
+
+        let mut captured = Vec::new();
+        for capture in captures {
+            let mut values = self.trans_expr(capture);
+            self.to_abi(&capture.typ(), &mut values);
+            captured.extend(values);
+        }
+        let env = self.allocate_env(captured.len());
+        for (slot, value) in captured.into_iter().enumerate() {
+            let offset = slot as i32 * SLOT_SIZE;
+            self.cl.ins().store(MemFlags::trusted(), value, env, offset);
+        }
+        values(&[address, env])
+    }
+
+    fn allocate_env(&mut self, slots: usize) -> Value {
+        let envs = self.environments as *const _ as i64;
+        let envs = self.cl.ins().iconst(CLIF_PTR, envs);
+        let slots = self.cl.ins().iconst(types::I64, slots as i64);
+        let env = self.call_runtime(closure::allocate as *const u8, &[envs, slots]);
+        let exhausted = self.cl.ins().icmp_imm(IntCC::Equal, env, 0);
+        self.panic_if(exhausted, "too many closures");
+        env
+    }
+
+    fn strings_ptr(&mut self) -> Value {
+        let strings = self.strings as *const _ as i64;
+        self.cl.ins().iconst(CLIF_PTR, strings)
+    }
+
+    /// Call a function of the runtime creating a string, which panics
+    /// if the program already has too many strings.
+    fn call_string_runtime(&mut self, func: *const u8, args: &[Value]) -> Value {
+        let string = self.call_runtime(func, args);
+        let exhausted = self.cl.ins().icmp_imm(IntCC::Equal, string, 0);
+        self.panic_if(exhausted, "too many strings");
+        string
+    }
+
+    /// Call a function of the runtime returning a pointer, like `closure::allocate`.
+    fn call_runtime(&mut self, func: *const u8, args: &[Value]) -> Value {
+        let call = self.runtime_call(func, args, &[CLIF_PTR]);
+        self.cl.inst_results(call)[0]
+    }
+
+    fn runtime_call(&mut self, func: *const u8, args: &[Value], returns: &[Type]) -> clif::Inst {
+        let mut sig = self.ir_module.make_signature();
+        for arg in args {
+            let ty = self.cl.func.dfg.value_type(*arg);
+            sig.params.push(AbiParam::new(ty));
+        }
+        for ty in returns {
+            sig.returns.push(AbiParam::new(*ty));
+        }
+        let sig = self.cl.import_signature(sig);
+
+        let func = self.cl.ins().iconst(CLIF_PTR, func as i64);
+        self.cl.ins().call_indirect(sig, func, args)
+    }
+
+    /// Start a panic at the location of the current expression and return from the function.
+    fn panic(&mut self, message: &Expr) -> CValue {
+        let message = self.trans_expr(message)[0];
+        let start = if self.srcloc.is_default() {
+            0
+        } else {
+            self.srcloc.bits() as usize
+        };
+        let location = self.panics.location(&self.ya_module.ast.path, start);
+
+        let panics = self
+            .cl
+            .ins()
+            .iconst(CLIF_PTR, self.panics as *const _ as i64);
+        let location = self.cl.ins().iconst(CLIF_PTR, location as i64);
+        self.runtime_call(panic::abort as *const u8, &[panics, location, message], &[]);
+        let unwind_b = self.unwind_block();
+        self.cl.ins().jump(unwind_b, &[]);
+
+        // Anything after the panic is unreachable, but still needs a block
+        let cont_b = self.switch_new_block();
+        self.cl.seal_block(cont_b);
+        values(&[])
+    }
+
+    /// Panic with the given message if `condition` is true.
+    fn panic_if(&mut self, condition: Value, message: &str) {
+        let panic_b = self.new_block();
+        let cont_b = self.new_block();
+        self.cl.ins().brnz(condition, panic_b, &[]);
+        self.cl.ins().jump(cont_b, &[]);
+        self.switch_block(panic_b);
+        self.cl.seal_block(panic_b);
+        self.panic(&Expr::constant(Constant::String(SmolStr::new(message))));
+        self.cl.ins().jump(cont_b, &[]);
+        self.switch_block(cont_b);
+        self.cl.seal_block(cont_b);
+    }
+
+    /// Panic instead of running the body of the function, which contains errors.
+    pub(super) fn poisoned(&mut self) {
+        let message = SmolStr::from_fmt(format_args!(
+            "function '{}' failed to compile",
+            self.func.name
+        ));
+        self.panic(&Expr::constant(Constant::String(message)));
+        let unwind_b = self.unwind_block();
+        self.cl.ins().jump(unwind_b, &[]);
+    }
+
+    /// Return from the function if the call just made started a panic,
+    /// or if the program was interrupted.
+    /// The check has no source location, since cranelift would otherwise
+    /// drop the location of the call when laying out the branch.
+    fn check_panic(&mut self) {
+        self.cl.set_srcloc(clif::SourceLoc::default());
+        let panics = self.cl.ins().iconst(CLIF_PTR, self.panics.flag() as i64);
+        let panicking = self
+            .cl
+            .ins()
+            .load(types::I8, MemFlags::trusted(), panics, 0);
+        let unwind_b = self.unwind_block();
+        let cont_b = self.new_block();
+        self.cl.ins().brnz(panicking, unwind_b, &[]);
+        self.cl.ins().jump(cont_b, &[]);
+        self.switch_block(cont_b);
+        self.cl.seal_block(cont_b);
+        self.cl.set_srcloc(self.srcloc);
+    }
+
+    fn unwind_block(&mut self) -> Block {
+        match self.unwind_block {
+            Some(block) => block,
+            None => {
+                let block = self.new_block();
+                self.unwind_block = Some(block);
+                block
+            }
+        }
+    }
+
+    fn env(&mut self, name: &Expr, typ: &ir::Type) -> Value {
+        let name = self.trans_expr(name)[0];
+        let bindings = self.bindings as *const _ as i64;
+        let bindings = self.cl.ins().iconst(CLIF_PTR, bindings);
+        let (func, ret) = match typ {
+            ir::Type::String => {
+                let strings = self.strings_ptr();
+                return self.call_string_runtime(
+                    bindings::get_str as *const u8,
+                    &[bindings, strings, name],
+                );
+            }
+            ir::Type::I64 => (bindings::get_int as *const u8, types::I64),
+            ir::Type::F64 => (bindings::get_float as *const u8, types::F64),
+            _ => (bindings::get_bool as *const u8, types::I8),
+        };
+        let call = self.runtime_call(func, &[bindings, name], &[ret]);
+        let mut values = [self.cl.inst_results(call)[0]];
+        self.from_abi(typ, &mut values);
+        values[0]
+    }
+
+    fn intrinsic(&mut self, intrinsic: Intrinsic, args: &[Expr]) -> Value {
+        let float = args[0].typ().is_float();
+        let args = args
+            .iter()
+            .map(|a| self.trans_expr(a)[0])
+            .collect::<SmallVec<[Value; 2]>>();
+        let ins = self.cl.ins();
+        match intrinsic {
+            Intrinsic::Min if float => ins.fmin(args[0], args[1]),
+            Intrinsic::Max if float => ins.fmax(args[0], args[1]),
+            Intrinsic::Abs if float => ins.fabs(args[0]),
+            // The integer versions of these are only implemented for vectors
+            Intrinsic::Min | Intrinsic::Max => {
+                let cc = if intrinsic == Intrinsic::Min {
+                    IntCC::SignedLessThan
+                } else {
+                    IntCC::SignedGreaterThan
+                };
+                let cond = self.cl.ins().icmp(cc, args[0], args[1]);
+                self.cl.ins().select(cond, args[0], args[1])
+            }
+            Intrinsic::Abs => {
+                let negated = self.cl.ins().ineg(args[0]);
+                let cond = self.cl.ins().icmp_imm(IntCC::SignedLessThan, args[0], 0);
+                self.cl.ins().select(cond, negated, args[0])
+            }
+            Intrinsic::Clz => ins.clz(args[0]),
+            Intrinsic::Ctz => ins.ctz(args[0]),
+            Intrinsic::Popcount => ins.popcnt(args[0]),
+            Intrinsic::Rotl => ins.rotl(args[0], args[1]),
+            Intrinsic::Rotr => ins.rotr(args[0], args[1]),
+        }
+    }
+
+    fn convert(&mut self, expr: &Expr, to: &ir::Type) -> Value {
+        let mut value = self.trans_expr(expr);
+        match (expr.typ(), to) {
+            (ir::Type::F32, ir::Type::F64) => self.cl.ins().fpromote(types::F64, value[0]),
+            (ir::Type::F64, ir::Type::F32) => self.cl.ins().fdemote(types::F32, value[0]),
+
+            (from, ir::Type::String) => {
+                let strings = self.strings_ptr();
+                let func = match from {
+                    ir::Type::I64 => string::from_int as *const u8,
+                    ir::Type::Bool => {
+                        self.to_abi(&from, &mut value);
+                        string::from_bool as *const u8
+                    }
+                    ir::Type::F32 => {
+                        value[0] = self.cl.ins().fpromote(types::F64, value[0]);
+                        string::from_float as *const u8
+                    }
+                    _ => string::from_float as *const u8,
+                };
+                self.call_string_runtime(func, &[strings, value[0]])
+            }
+
+            _ => value[0],
         }
     }
 
@@ -139,23 +534,46 @@ impl<'b> FnTranslator<'b> {
         values(self.cl.block_params(cont_b))
     }
 
-    fn while_expr(&mut self, cond: &Expr, body: &Expr) -> CValue {
+    fn while_expr(&mut self, cond: &Expr, body: &Expr, els: &Expr, phi: bool) -> CValue {
         let head_b = self.new_block();
         let body_b = self.new_block();
+        let else_b = self.new_block();
         let cont_b = self.new_block();
+        self.set_cont_params(phi, cont_b, &els.typ());
         self.cl.ins().jump(head_b, &[]);
         self.switch_block(head_b);
         let condition_value = self.trans_expr(cond)[0];
-        self.cl.ins().brz(condition_value, cont_b, &[]);
+        self.cl.ins().brz(condition_value, else_b, &[]);
         self.cl.ins().jump(body_b, &[]);
         self.switch_block(body_b);
         self.cl.seal_block(body_b);
+        self.loops.push((cont_b, phi));
         self.trans_expr(body);
+        self.loops.pop();
+        // Loops without calls would otherwise never notice being interrupted
+        self.check_panic();
         self.cl.ins().jump(head_b, &[]);
-        self.cl.switch_to_block(cont_b);
+
+        self.switch_block(else_b);
         self.cl.seal_block(head_b);
+        self.cl.seal_block(else_b);
+        let els_val = self.trans_expr(els);
+        self.jump_cont(cont_b, phi, els_val);
+
+        self.switch_block(cont_b);
         self.cl.seal_block(cont_b);
-        value(self.cl.ins().iconst(types::I64, 0))
+        values(self.cl.block_params(cont_b))
+    }
+
+    fn break_(&mut self, value: &Expr) -> CValue {
+        let value = self.trans_expr(value);
+        let (cont_b, phi) = *self.loops.last().unwrap();
+        self.jump_cont(cont_b, phi, value);
+
+        // Anything after the break is unreachable, but still needs a block
+        let block = self.switch_new_block();
+        self.cl.seal_block(block);
+        values(&[])
     }
 
     fn variable_expr(&mut self, index: usize, typ: &ir::Type) -> CValue {
@@ -177,25 +595,87 @@ impl<'b> FnTranslator<'b> {
     }
 
     fn call(&mut self, callee: &Expr, args: &SmallVec<[Expr; 4]>) -> CValue {
-        let func_id = {
-            let func = callee.typ().into_fn();
-            let func = func.resolve();
-            get_or_declare_ir_fn(&mut self.ir_module, &*func)
+        let sig = callee.typ().into_fn();
+        // Calls to a known function can be direct, everything else
+        // needs to go through the function value.
+        let mut results = if let IExpr::Constant(Constant::Function(func)) = &*callee.inner {
+            let local_callee = self.declare_func_ref(func);
+            let call_args = self.call_args(args);
+            let call = self.cl.ins().call(local_callee, &call_args);
+            values(self.cl.inst_results(call))
+        } else {
+            let callee = self.trans_expr(callee);
+            let call_args = self.call_args(args);
+            self.indirect_call(&sig, callee[0], callee[1], call_args)
         };
+        self.check_panic();
+        self.from_abi(&sig.ret_type, &mut results);
+        results
+    }
+
+    /// Call a function value, which is a closure if its environment is not null.
+    fn indirect_call(
+        &mut self,
+        sig: &ir::FnSig,
+        address: Value,
+        env: Value,
+        mut args: Vec<Value>,
+    ) -> CValue {
+        let mut clif_sig = self.ir_module.make_signature();
+        make_sig(&mut clif_sig, sig);
+        let function_sig = self.cl.import_signature(clif_sig.clone());
+        clif_sig.params.insert(0, AbiParam::new(CLIF_PTR));
+        let closure_sig = self.cl.import_signature(clif_sig);
+
+        let function_b = self.new_block();
+        let closure_b = self.new_block();
+        let cont_b = self.new_block();
+        typesys::translate_abi_type(&sig.ret_type, |_, ty| {
+            self.cl.append_block_param(cont_b, ty);
+        });
+        self.br(env, closure_b, function_b);
+
+        self.switch_block(function_b);
+        self.cl.seal_block(function_b);
+        let call = self.cl.ins().call_indirect(function_sig, address, &args);
+        let results = values(self.cl.inst_results(call));
+        self.cl.ins().jump(cont_b, &results);
 
-        let local_callee = self
-            .ir_module
-            .declare_func_in_func(func_id, &mut self.cl.func);
+        self.switch_block(closure_b);
+        self.cl.seal_block(closure_b);
+        args.insert(0, env);
+        let call = self.cl.ins().call_indirect(closure_sig, address, &args);
+        let results = values(self.cl.inst_results(call));
+        self.cl.ins().jump(cont_b, &results);
+
+        self.switch_block(cont_b);
+        self.cl.seal_block(cont_b);
+        values(self.cl.block_params(cont_b))
+    }
 
+    /// Translate the arguments of a call from left to right, see `IExpr::Call`.
+    fn call_args(&mut self, args: &SmallVec<[Expr; 4]>) -> Vec<Value> {
         let mut call_args = Vec::new();
         for arg in args {
-            let res = self.trans_expr(arg);
+            let mut res = self.trans_expr(arg);
+            self.to_abi(&arg.typ(), &mut res);
             for val in res {
                 call_args.push(val);
             }
         }
-        let call = self.cl.ins().call(local_callee, &call_args);
-        values(self.cl.inst_results(call))
+        call_args
+    }
+
+    fn declare_func_ref(&mut self, func: &FuncRef) -> clif::FuncRef {
+        let module = func.module.borrow();
+        let func_id = get_or_declare_ir_fn(
+            &mut self.ir_module,
+            &mut self.func_ids,
+            &*func.resolve(),
+            &module.ast.path,
+        );
+        self.ir_module
+            .declare_func_in_func(func_id, &mut self.cl.func)
     }
 }
 
@@ -222,3 +702,13 @@ fn floatcmp(tok: TKind) -> FloatCC {
         _ => panic!("unknown comparison operator"),
     }
 }
+
+/// If this expression creates a new string that nothing else can refer to,
+/// which is the case for concatenations and conversions to strings.
+fn is_temporary_string(expr: &Expr) -> bool {
+    match &*expr.inner {
+        IExpr::Binary { .. } => expr.typ() == ir::Type::String,
+        IExpr::Convert { to, .. } => *to == ir::Type::String,
+        _ => false,
+    }
+}
@@ -1,9 +1,16 @@
 use super::clif;
 use crate::{
     compiler::{ir, ir::Module},
-    vm::typesys,
+    vm::{
+        bindings::Bindings,
+        closure::{Environments, SLOT_SIZE},
+        panic::Panics,
+        string::Strings,
+        typesys,
+        typesys::CValue,
+        FuncIds,
+    },
 };
-use alloc::vec::Vec;
 use cranelift::{
     frontend::{FunctionBuilder, FunctionBuilderContext},
     prelude::*,
@@ -20,15 +27,39 @@ pub struct FnTranslator<'b> {
     local_offsets: SmallVec<[usize; 6]>,
     blocks: SmallVec<[Block; 5]>,
     current_block: Block,
+    /// The block after the function's entry, which self tail calls jump back to.
+    body_block: Block,
+    /// The source location of the expression currently being translated.
+    srcloc: clif::SourceLoc,
     ir_module: &'b mut JITModule,
+    func_ids: &'b mut FuncIds,
     ya_module: &'b Module,
+    /// Where environments of closures created by this function are allocated.
+    environments: &'b Environments,
+    /// Where strings of literals and created by this function are allocated.
+    strings: &'b Strings,
+    panics: &'b Panics,
+    bindings: &'b Bindings,
+    /// The block returning from the function while the program panics,
+    /// created once the function contains a call, loop or panic.
+    unwind_block: Option<Block>,
+    /// The block after each loop the current expression is in, innermost last,
+    /// and if `break` passes its value to it.
+    loops: SmallVec<[(Block, bool); 2]>,
 }
 
 impl<'b> FnTranslator<'b> {
     pub fn build(&mut self) {
         self.init();
-        let ret = self.trans_expr(&self.func.body.borrow());
-        self.cl.ins().return_(&ret);
+        let func = self.func;
+        if func.is_poisoned() {
+            self.poisoned();
+        } else {
+            self.tail_expr(&func.body.borrow());
+        }
+        self.build_unwind_block();
+        // The body block is only complete once all tail calls are known
+        self.cl.seal_all_blocks();
         self.cl.finalize();
     }
 
@@ -37,23 +68,70 @@ impl<'b> FnTranslator<'b> {
         self.cl.append_block_params_for_function_params(entry);
         self.cl.seal_block(entry);
         self.declare_variables();
+
+        self.body_block = self.new_block();
+        self.cl.ins().jump(self.body_block, &[]);
+        self.switch_block(self.body_block);
+    }
+
+    /// Fill the unwind block, returning zeroed values since the caller ignores them.
+    fn build_unwind_block(&mut self) {
+        let block = match self.unwind_block {
+            Some(block) => block,
+            None => return,
+        };
+        self.switch_block(block);
+        let mut values = CValue::new();
+        typesys::translate_abi_type(&self.func.ret_type, |_, ty| {
+            values.push(match ty {
+                types::F32 => self.cl.ins().f32const(0.0),
+                types::F64 => self.cl.ins().f64const(0.0),
+                _ => self.cl.ins().iconst(ty, 0),
+            })
+        });
+        self.cl.ins().return_(&values);
     }
 
     fn declare_variables(&mut self) {
         let entry_block = self.blocks[0];
-        let params = self
-            .cl
-            .block_params(entry_block)
-            .iter()
-            .copied()
-            .collect::<Vec<_>>();
+        let mut params = self.cl.block_params(entry_block).to_vec();
+        let env = if self.func.is_closure() {
+            Some(params.remove(0))
+        } else {
+            None
+        };
         for var in self.func.params.iter() {
             self.declare_local(var);
-            self.define_local(var, &params[self.local_offsets[var.index]..]);
+            let values = self.local_offsets[var.index]..self.local_offsets[var.index + 1];
+            self.from_abi(&var.ty, &mut params[values]);
+            self.define_local(var, &params);
         }
         for var in self.func.locals.iter() {
             self.declare_local(var);
         }
+        if let Some(env) = env {
+            self.load_captures(env);
+        }
+    }
+
+    /// Define the locals holding captured values with the values in the environment.
+    fn load_captures(&mut self, env: Value) {
+        let func = self.func;
+        let mut slot = 0;
+        for var in func.captures.iter() {
+            let mut values = CValue::new();
+            typesys::translate_abi_type(&var.ty, |_, ty| {
+                let offset = slot * SLOT_SIZE;
+                values.push(self.cl.ins().load(ty, MemFlags::trusted(), env, offset));
+                slot += 1;
+            });
+            self.from_abi(&var.ty, &mut values);
+
+            let offset = self.local_offsets[var.index];
+            for (i, value) in values.into_iter().enumerate() {
+                self.cl.def_var(Variable::new(offset + i), value);
+            }
+        }
     }
 
     fn declare_local(&mut self, var: &ir::VarStore) {
@@ -74,6 +152,25 @@ impl<'b> FnTranslator<'b> {
         });
     }
 
+    /// Convert values of the given type to how they are passed
+    /// between functions; see `typesys::translate_abi_type`.
+    fn to_abi(&mut self, typ: &ir::Type, values: &mut [Value]) {
+        typesys::translate_type(typ, |i, ty| {
+            if ty == types::B1 {
+                values[i] = self.cl.ins().bint(types::I8, values[i]);
+            }
+        });
+    }
+
+    /// Convert values of the given type passed between functions back.
+    fn from_abi(&mut self, typ: &ir::Type, values: &mut [Value]) {
+        typesys::translate_type(typ, |i, ty| {
+            if ty == types::B1 {
+                values[i] = self.cl.ins().icmp_imm(IntCC::NotEqual, values[i], 0);
+            }
+        });
+    }
+
     fn new_block(&mut self) -> Block {
         let block = self.cl.create_block();
         self.blocks.push(block);
@@ -96,7 +193,12 @@ impl<'b> FnTranslator<'b> {
         clif: &'b mut clif::Function,
         ctx: &'b mut FunctionBuilderContext,
         ir_module: &'b mut JITModule,
+        func_ids: &'b mut FuncIds,
         ya_module: &'b Module,
+        environments: &'b Environments,
+        strings: &'b Strings,
+        panics: &'b Panics,
+        bindings: &'b Bindings,
     ) -> Self {
         Self {
             func,
@@ -104,8 +206,17 @@ impl<'b> FnTranslator<'b> {
             local_offsets: SmallVec::from_slice(&[0]),
             blocks: SmallVec::new(),
             current_block: Block::with_number(0).unwrap(),
+            body_block: Block::with_number(0).unwrap(),
+            srcloc: clif::SourceLoc::default(),
             ir_module,
+            func_ids,
             ya_module,
+            environments,
+            strings,
+            panics,
+            bindings,
+            unwind_block: None,
+            loops: SmallVec::new(),
         }
     }
 }
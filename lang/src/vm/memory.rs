@@ -0,0 +1,128 @@
+//! The memory compiled code is placed in. With `std`, Cranelift maps memory
+//! of the operating system itself; `core` hosts give every JIT a `MemoryManager`
+//! of its own, so that programs do not share a single code heap.
+//! Cranelift only knows a single, global manager, which forwards
+//! to the manager of the JIT currently placing code.
+
+use core::marker::PhantomData;
+#[cfg(feature = "core")]
+use {
+    alloc::boxed::Box,
+    core::{
+        ptr,
+        sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+    },
+    cranelift_jit::MemoryManager,
+};
+
+/// Where a program places the machine code it is compiled to.
+/// The default is only usable with `std`: `core` builds need
+/// to give every program a manager of its own with `CodeMemory::new`.
+#[derive(Default)]
+pub struct CodeMemory {
+    #[cfg(feature = "core")]
+    manager: Option<Box<dyn MemoryManager>>,
+}
+
+impl CodeMemory {
+    /// Place code with the given manager, which is dropped together
+    /// with the program: it can free all its memory at that point.
+    #[cfg(feature = "core")]
+    pub fn new(manager: impl MemoryManager + 'static) -> Self {
+        Self {
+            manager: Some(Box::new(manager)),
+        }
+    }
+
+    /// Forward all allocations of Cranelift to this memory until the returned
+    /// guard is dropped. Blocks while another JIT is placing code.
+    pub(crate) fn enter(&mut self) -> Entered<'_> {
+        #[cfg(feature = "core")]
+        {
+            if !REGISTERED.swap(true, Ordering::AcqRel) {
+                cranelift_jit::set_manager(Box::new(Forward));
+            }
+            while PLACING
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                core::hint::spin_loop();
+            }
+            let manager = match &mut self.manager {
+                Some(manager) => manager as *mut _,
+                None => ptr::null_mut(),
+            };
+            CURRENT.store(manager, Ordering::Release);
+        }
+        Entered(PhantomData)
+    }
+}
+
+/// Returned by `CodeMemory::enter`, giving up the memory again when dropped.
+pub(crate) struct Entered<'m>(PhantomData<&'m mut CodeMemory>);
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        #[cfg(feature = "core")]
+        {
+            CURRENT.store(ptr::null_mut(), Ordering::Release);
+            PLACING.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Whether `Forward` was given to Cranelift.
+#[cfg(feature = "core")]
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// Set while a JIT places code, since there is only a single `CURRENT` manager.
+#[cfg(feature = "core")]
+static PLACING: AtomicBool = AtomicBool::new(false);
+
+/// The manager of the JIT placing code, null while none is
+/// or if the JIT has none.
+#[cfg(feature = "core")]
+static CURRENT: AtomicPtr<Box<dyn MemoryManager>> = AtomicPtr::new(ptr::null_mut());
+
+/// The manager given to Cranelift, forwarding to the `CURRENT` one.
+#[cfg(feature = "core")]
+struct Forward;
+
+/// Returns the `CURRENT` manager, which Cranelift only uses while a JIT places code.
+#[cfg(feature = "core")]
+fn current() -> &'static mut dyn MemoryManager {
+    let current = CURRENT.load(Ordering::Acquire);
+    assert!(
+        !current.is_null(),
+        "no memory manager to place code with, see `CodeMemory::new`"
+    );
+    // Valid until the JIT placing code drops its `Entered` guard
+    unsafe { &mut **current }
+}
+
+#[cfg(feature = "core")]
+impl MemoryManager for Forward {
+    fn page_size(&self) -> usize {
+        current().page_size()
+    }
+
+    fn set_r(&mut self, ptr: *mut u8, size: usize) {
+        current().set_r(ptr, size)
+    }
+
+    fn set_rx(&mut self, ptr: *mut u8, size: usize) {
+        current().set_rx(ptr, size)
+    }
+
+    fn set_rw(&mut self, ptr: *mut u8, size: usize) {
+        current().set_rw(ptr, size)
+    }
+
+    fn alloc_page_aligned(&mut self, size: usize) -> *mut u8 {
+        current().alloc_page_aligned(size)
+    }
+
+    fn dealloc(&mut self, ptr: *mut u8, size: usize) {
+        current().dealloc(ptr, size)
+    }
+}
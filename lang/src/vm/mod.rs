@@ -1,8 +1,42 @@
+mod bindings;
+mod callback;
+mod closure;
 mod function;
+mod memory;
+mod panic;
+mod report;
+mod source_map;
+mod string;
+mod target;
 mod typesys;
 
-use crate::{compiler::ir, vm::function::FnTranslator};
-use core::mem;
+pub use bindings::Binding;
+pub use callback::{AbiValue, Callback, Hook};
+pub use memory::CodeMemory;
+pub use panic::InterruptHandle;
+pub use report::{CompileReport, FunctionSize};
+pub use source_map::{line_column, SourceMap};
+pub use string::StrRef;
+pub use target::{Target, TargetError};
+
+use crate::{
+    backend::Backend,
+    compiler::ir,
+    error::{ExecError, Panic},
+    smol_str::SmolStr,
+    timings,
+    timings::Stage,
+    vm::{
+        bindings::Bindings, closure::Environments, function::FnTranslator, panic::Panics,
+        string::Strings,
+    },
+};
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt::Write, mem, ops::Range};
 use cranelift::{
     codegen::{
         binemit::{NullStackMapSink, NullTrapSink},
@@ -12,7 +46,16 @@ use cranelift::{
 };
 use cranelift_jit::{JITBuilder, JITModule};
 use cranelift_module::{DataContext, FuncId, FuncOrDataId, Linkage, Module};
+use hashbrown::{HashMap, HashSet};
+use target_lexicon::Triple;
 
+/// Host functions available to programs as `extern fun`, by name.
+/// They need to use the C calling convention (`extern "C"`).
+/// Booleans are passed as a byte of 0 or 1, which is compatible with `bool`.
+/// Function values are passed as two pointers: the address of the function
+/// and the environment of the closure, which is null for other functions.
+/// Closures take their environment before all other arguments.
+/// Strings are passed as `StrRef`, which stays valid as long as the program.
 pub type SymbolTable<'t> = &'t [(&'t str, *const u8)];
 
 #[allow(unused)]
@@ -21,51 +64,312 @@ pub struct JIT {
     ctx: codegen::Context,
     data_ctx: DataContext,
     module: JITModule,
+    /// Source maps of all functions, with the code address
+    /// of functions not finalized yet still missing.
+    source_maps: Vec<(FuncId, SourceMap)>,
+    /// Boxed, since compiled code refers to it by address.
+    environments: Box<Environments>,
+    /// Boxed for the same reason as `environments`.
+    strings: Box<Strings>,
+    /// Boxed for the same reason as `environments`.
+    panics: Box<Panics>,
+    /// Boxed for the same reason as `environments`.
+    bindings: Box<Bindings>,
+    /// Dropped after `module`, which might still use it.
+    memory: CodeMemory,
+    func_ids: FuncIds,
+    /// The paths of all modules defined so far.
+    defined: HashSet<Vec<SmolStr>>,
+    state: State,
+}
+
+/// The settings `JITBuilder::new` uses on the host, which all targets start with:
+/// calls to other functions use absolute addresses, since they might be too far apart.
+const JIT_SETTINGS: &[(&str, &str)] = &[("use_colocated_libcalls", "false"), ("is_pic", "false")];
+
+/// The Cranelift IDs of the functions declared in a JIT, by their symbol.
+/// Unlike addresses of the IR, symbols stay unique once modules are dropped,
+/// and they are what the JIT links functions by.
+pub(crate) type FuncIds = HashMap<SmolStr, FuncId>;
+
+/// How far a JIT is in turning modules into code that can be called.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    /// No module was defined yet.
+    Declared,
+    /// Some modules were defined, but their functions cannot be called yet.
+    Defined,
+    /// All functions can be called, but no more modules can be defined.
+    Finalized,
+}
+
+/// Why a JIT could not take the next step from its current `State`.
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    /// The JIT was already finalized.
+    Finalized,
+    /// The module with the given path was already defined in the JIT.
+    DefinedTwice(Vec<SmolStr>),
+}
+
+impl Backend for JIT {
+    type Error = StateError;
+
+    fn module(&mut self, module: &ir::Module) -> Result<(), StateError> {
+        self.define(module)
+    }
+
+    fn link(&mut self) -> Result<(), StateError> {
+        self.finalize()
+    }
 }
 
 impl JIT {
-    pub(crate) fn jit_module(&mut self, module: &ir::Module) {
-        for func in module.funcs.iter().filter(|f| f.ast.body.is_some()) {
-            make_fn_sig(&mut self.ctx.func.signature, func);
-            let id = declare_ir_function(&mut self.module, func, &self.ctx.func.signature);
-            let mut translator = FnTranslator::new(
-                func,
-                &mut self.ctx.func,
-                &mut self.builder_context,
-                &mut self.module,
-                &module,
-            );
-            translator.build();
-
-            self.module
-                .define_function(
-                    id,
-                    &mut self.ctx,
-                    &mut NullTrapSink {},
-                    &mut NullStackMapSink {},
-                )
-                .unwrap();
+    /// Compile all live functions of the module into machine code.
+    /// Calls to functions of other modules are resolved when finalizing.
+    pub fn define(&mut self, module: &ir::Module) -> Result<(), StateError> {
+        if self.state == State::Finalized {
+            return Err(StateError::Finalized);
+        }
+        if !self.defined.insert(module.ast.path.clone()) {
+            return Err(StateError::DefinedTwice(module.ast.path.clone()));
+        }
+        for func in module.funcs.iter().filter(|f| !f.external && f.live.get()) {
+            timings::measure(Stage::Cranelift, || self.jit_function(func, module));
+        }
+        self.state = State::Defined;
+        Ok(())
+    }
+
+    /// Resolve the calls between all defined modules,
+    /// after which their functions can be called.
+    pub fn finalize(&mut self) -> Result<(), StateError> {
+        if self.state == State::Finalized {
+            return Err(StateError::Finalized);
+        }
+        let memory = self.memory.enter();
+        let module = &mut self.module;
+        timings::measure(Stage::Finalize, || module.finalize_definitions());
+        drop(memory);
+
+        for (id, map) in self
+            .source_maps
+            .iter_mut()
+            .filter(|(_, map)| map.code.start == 0)
+        {
+            let start = self.module.get_finalized_function(*id) as usize;
+            map.code = start..(start + map.code.end);
+        }
+        self.state = State::Finalized;
+        Ok(())
+    }
+
+    /// Returns the source maps of all compiled functions.
+    pub fn source_maps(&self) -> impl Iterator<Item = &SourceMap> {
+        self.source_maps.iter().map(|(_, map)| map)
+    }
+
+    /// Returns the machine code size of all compiled functions,
+    /// as reported by Cranelift when they were defined.
+    pub fn compile_report(&self) -> CompileReport {
+        let mut functions = self
+            .source_maps()
+            .map(|map| FunctionSize {
+                symbol: map.symbol.clone(),
+                module: map.module.clone(),
+                size: map.code.len(),
+            })
+            .collect::<Vec<_>>();
+        functions.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.symbol.cmp(&b.symbol)));
+        CompileReport { functions }
+    }
+
+    /// Write the Cranelift IR of all functions in the module to `out`,
+    /// without compiling them to machine code.
+    pub(crate) fn write_clif(&mut self, module: &ir::Module, out: &mut String) {
+        for func in module.funcs.iter().filter(|f| !f.external && f.live.get()) {
+            self.translate(func, module);
+            writeln!(out, "; {}", func.symbol(&module.ast.path)).unwrap();
+            writeln!(out, "{}", self.ctx.func.display(None)).unwrap();
             self.module.clear_context(&mut self.ctx);
         }
+    }
 
-        self.module.finalize_definitions();
+    fn jit_function(&mut self, func: &ir::Function, module: &ir::Module) {
+        let id = self.translate(func, module);
+        let memory = self.memory.enter();
+        let compiled = self
+            .module
+            .define_function(
+                id,
+                &mut self.ctx,
+                &mut NullTrapSink {},
+                &mut NullStackMapSink {},
+            )
+            .unwrap();
+        drop(memory);
+        self.source_maps.push((
+            id,
+            SourceMap {
+                symbol: func.symbol(&module.ast.path),
+                module: module.ast.path.clone(),
+                // Only the size is known until the function is finalized
+                code: 0..compiled.size as usize,
+                locations: self.source_locations(),
+            },
+        ));
+        self.module.clear_context(&mut self.ctx);
     }
 
-    pub fn exec<T>(&mut self, name: &str) -> T {
-        let id = self.module.get_name(name).unwrap();
-        let id = if let FuncOrDataId::Func(id) = id {
-            id
-        } else {
-            panic!()
+    /// Translate the function into Cranelift IR in the current context.
+    fn translate(&mut self, func: &ir::Function, module: &ir::Module) -> FuncId {
+        make_fn_sig(&mut self.ctx.func.signature, func);
+        let id = declare_ir_function(
+            &mut self.module,
+            &mut self.func_ids,
+            func,
+            func.symbol(&module.ast.path),
+            &self.ctx.func.signature,
+        );
+        let mut translator = FnTranslator::new(
+            func,
+            &mut self.ctx.func,
+            &mut self.builder_context,
+            &mut self.module,
+            &mut self.func_ids,
+            &module,
+            &self.environments,
+            &self.strings,
+            &self.panics,
+            &self.bindings,
+        );
+        translator.build();
+        id
+    }
+
+    /// Returns the source offsets of the function just compiled.
+    fn source_locations(&self) -> Vec<(Range<u32>, usize)> {
+        let result = match &self.ctx.mach_compile_result {
+            Some(result) => result,
+            None => return Vec::new(),
         };
+        result
+            .buffer
+            .get_srclocs_sorted()
+            .iter()
+            .filter(|loc| !loc.loc.is_default())
+            .map(|loc| (loc.start..loc.end, loc.loc.bits() as usize))
+            .collect()
+    }
 
-        let ptr = self.module.get_finalized_function(id);
-        let func = unsafe { mem::transmute::<_, fn() -> T>(ptr) };
-        func()
+    /// Call the function with the given symbol, which needs to take
+    /// no parameters and return a value of type `T`.
+    /// Fails with `ExecError::Panicked` if the program panicked
+    /// and `ExecError::Interrupted` if it was interrupted.
+    pub fn exec<T: AbiValue>(&mut self, name: &str) -> Result<T, ExecError> {
+        let id = self.callable(name)?;
+        if !returns_fit::<T>(&self.module.declarations().get_function_decl(id).signature) {
+            return Err(ExecError::WrongSignature(name.into()));
+        }
+        // The signature was checked to share the ABI of `T`
+        unsafe { self.exec_unchecked(name) }
     }
 
-    pub fn new(symbols: SymbolTable) -> Self {
-        let mut builder = JITBuilder::new(cranelift_module::default_libcall_names());
+    /// Call the function with the given symbol like `exec`,
+    /// without checking that it returns a value of type `T`.
+    ///
+    /// # Safety
+    /// The function needs to return a value with the C ABI of `T`,
+    /// like a `#[repr(C)]` struct with the same fields as a returned class.
+    pub unsafe fn exec_unchecked<T>(&mut self, name: &str) -> Result<T, ExecError> {
+        let ptr = self.module.get_finalized_function(self.callable(name)?);
+        let func = mem::transmute::<_, extern "C" fn() -> T>(ptr);
+        let result = func();
+        match self.panics.take() {
+            Some(err) => Err(err),
+            None => Ok(result),
+        }
+    }
+
+    /// Returns the ID of the function with the given symbol,
+    /// if it can be called without arguments.
+    fn callable(&self, name: &str) -> Result<FuncId, ExecError> {
+        if self.state != State::Finalized {
+            return Err(ExecError::NotFinalized);
+        }
+        let id = match self.module.get_name(name) {
+            Some(FuncOrDataId::Func(id)) => id,
+            Some(FuncOrDataId::Data(_)) => return Err(ExecError::NotAFunction(name.into())),
+            None => return Err(ExecError::NoSuchFunction(name.into())),
+        };
+        let decl = self.module.declarations().get_function_decl(id);
+        if decl.linkage == Linkage::Import || !decl.signature.params.is_empty() {
+            return Err(ExecError::WrongSignature(name.into()));
+        }
+        Ok(id)
+    }
+
+    /// Bind `value` to `name` in the environment of the program.
+    pub fn bind(&self, name: SmolStr, value: Binding) {
+        self.bindings.set(name, value)
+    }
+
+    /// Returns the panic of a function called outside of `exec`, like
+    /// through a callback, which returns zeroed values after panicking.
+    /// An interrupted callback also returns zeroed values, but without a panic.
+    pub fn take_panic(&self) -> Option<Panic> {
+        match self.panics.take()? {
+            ExecError::Panicked(panic) => Some(panic),
+            _ => None,
+        }
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.panics.interrupt_handle()
+    }
+
+    /// Returns a pointer to the function with the given symbol,
+    /// if it was compiled by this JIT and the JIT is finalized.
+    pub fn function_ptr(&self, symbol: &str) -> Option<*const u8> {
+        match self.module.get_name(symbol) {
+            Some(FuncOrDataId::Func(id))
+                if self.state == State::Finalized
+                    && self.module.declarations().get_function_decl(id).linkage
+                        != Linkage::Import =>
+            {
+                Some(self.module.get_finalized_function(id))
+            }
+            _ => None,
+        }
+    }
+
+    /// A JIT placing code in the given memory, which is only
+    /// used while it compiles and is dropped together with it.
+    pub fn new(symbols: SymbolTable, memory: CodeMemory) -> Self {
+        let builder = JITBuilder::new(cranelift_module::default_libcall_names());
+        Self::with_builder(builder, symbols, memory)
+    }
+
+    /// A JIT generating code for the given target, which needs
+    /// to have the architecture of the host to run it.
+    pub fn for_target(
+        symbols: SymbolTable,
+        memory: CodeMemory,
+        target: &Target,
+    ) -> Result<Self, TargetError> {
+        if target.is_host() {
+            return Ok(Self::new(symbols, memory));
+        }
+        let triple = target.triple()?;
+        if triple.architecture != Triple::host().architecture {
+            return Err(TargetError::NotHost(SmolStr::new(triple.to_string())));
+        }
+        let isa = target.isa(triple, JIT_SETTINGS)?;
+        let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
+        Ok(Self::with_builder(builder, symbols, memory))
+    }
+
+    fn with_builder(mut builder: JITBuilder, symbols: SymbolTable, memory: CodeMemory) -> Self {
         for (name, ptr) in symbols {
             builder.symbol(*name, *ptr);
         }
@@ -76,53 +380,79 @@ impl JIT {
             ctx: module.make_context(),
             data_ctx: DataContext::new(),
             module,
+            source_maps: Vec::new(),
+            environments: Box::new(Environments::default()),
+            strings: Box::new(Strings::default()),
+            panics: Box::new(Panics::default()),
+            bindings: Box::new(Bindings::default()),
+            memory,
+            func_ids: FuncIds::new(),
+            defined: HashSet::new(),
+            state: State::Declared,
         }
     }
 }
 
-fn get_or_declare_ir_fn(module: &mut JITModule, func: &ir::Function) -> FuncId {
-    let mut ir = func.ir.borrow_mut();
-    if let Some(ir) = *ir {
-        ir
-    } else {
-        let mut sig = module.make_signature();
-        make_fn_sig(&mut sig, func);
-        let id = module
-            .declare_function(&func.name, get_linkage(func), &sig)
-            .unwrap();
-        *ir = Some(id);
-        id
+fn get_or_declare_ir_fn(
+    module: &mut JITModule,
+    ids: &mut FuncIds,
+    func: &ir::Function,
+    path: &[SmolStr],
+) -> FuncId {
+    let symbol = func.symbol(path);
+    if let Some(id) = ids.get(&symbol) {
+        return *id;
     }
+    let mut sig = module.make_signature();
+    make_fn_sig(&mut sig, func);
+    declare_ir_function(module, ids, func, symbol, &sig)
 }
 
 fn declare_ir_function(
     module: &mut JITModule,
+    ids: &mut FuncIds,
     func: &ir::Function,
+    symbol: SmolStr,
     sig: &clif::Signature,
 ) -> FuncId {
-    let mut ir = func.ir.borrow_mut();
-    if let Some(ir) = *ir {
-        ir
-    } else {
-        let id = module
-            .declare_function(&func.name, get_linkage(func), &sig)
-            .unwrap();
-        *ir = Some(id);
-        id
+    if let Some(id) = ids.get(&symbol) {
+        return *id;
     }
+    let id = module
+        .declare_function(&symbol, get_linkage(func), sig)
+        .unwrap();
+    ids.insert(symbol, id);
+    id
 }
 
 fn get_linkage(func: &ir::Function) -> Linkage {
-    if func.ast.body.is_none() {
+    if func.external {
         Linkage::Import
     } else {
         Linkage::Export
     }
 }
 
+/// If a function with the given signature returns `T`,
+/// with the same ABI as if it was compiled from yacari code returning `T`.
+fn returns_fit<T: AbiValue>(sig: &clif::Signature) -> bool {
+    let mut returns = Vec::new();
+    typesys::translate_abi_type(&T::ty(), |_, ty| returns.push(AbiParam::new(ty)));
+    sig.returns == returns
+}
+
 fn make_fn_sig(sig: &mut clif::Signature, func: &ir::Function) {
-    for p in &func.params {
-        typesys::translate_type(&p.ty, |_, ty| sig.params.push(AbiParam::new(ty)));
+    if func.is_closure() {
+        sig.params.push(AbiParam::new(typesys::CLIF_PTR));
+    }
+    make_sig(sig, &func.signature())
+}
+
+fn make_sig(sig: &mut clif::Signature, fn_sig: &ir::FnSig) {
+    for p in &fn_sig.params {
+        typesys::translate_abi_type(p, |_, ty| sig.params.push(AbiParam::new(ty)));
     }
-    typesys::translate_type(&func.ret_type, |_, ty| sig.returns.push(AbiParam::new(ty)));
+    typesys::translate_abi_type(&fn_sig.ret_type, |_, ty| {
+        sig.returns.push(AbiParam::new(ty))
+    });
 }
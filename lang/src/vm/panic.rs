@@ -0,0 +1,91 @@
+//! Panics, which stop a program once it cannot continue, like after a failed `assert`.
+//! Compiled code cannot unwind the stack, so a panic instead sets a flag
+//! that is checked after every call and loop iteration. While it is set, functions
+//! return immediately with zeroed values until control is back at the host.
+//! The host can set the flag as well to interrupt a program, see `InterruptHandle`.
+
+use crate::{
+    error::{ExecError, Panic},
+    smol_str::SmolStr,
+    vm::string::StrRef,
+};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The panic state of a program.
+#[derive(Default)]
+pub struct Panics {
+    /// Read by compiled code after every call and at the end of every loop iteration.
+    /// Set without a panic when the program was interrupted.
+    panicking: Arc<AtomicBool>,
+    panic: RefCell<Option<Panic>>,
+    /// The locations of all `panic` and `assert` calls, which compiled code refers to by address.
+    locations: RefCell<Vec<Box<Location>>>,
+}
+
+impl Panics {
+    /// Returns the address of the given location, for passing it to `abort`.
+    pub fn location(&self, module: &[SmolStr], start: usize) -> *const Location {
+        let location = Box::new(Location {
+            module: module.to_vec(),
+            start,
+        });
+        let ptr = &*location as *const _;
+        self.locations.borrow_mut().push(location);
+        ptr
+    }
+
+    /// Returns the address of the flag read by compiled code.
+    pub fn flag(&self) -> *const AtomicBool {
+        &*self.panicking
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(Arc::clone(&self.panicking))
+    }
+
+    /// Returns why the program stopped since the last call,
+    /// if it panicked or was interrupted.
+    pub fn take(&self) -> Option<ExecError> {
+        let stopped = self.panicking.swap(false, Ordering::SeqCst);
+        match self.panic.borrow_mut().take() {
+            Some(panic) => Some(ExecError::Panicked(panic)),
+            None if stopped => Some(ExecError::Interrupted),
+            None => None,
+        }
+    }
+}
+
+/// Stops a program the next time it calls a function or finishes a loop iteration,
+/// unwinding it like a panic. It can be used from other threads and interrupt
+/// handlers while the program runs, and stays valid after the program is dropped.
+#[derive(Clone)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Interrupt the program. If it is not running, it stops as soon as it runs again.
+    /// Host functions it is currently in still run to completion.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+pub struct Location {
+    module: Vec<SmolStr>,
+    start: usize,
+}
+
+/// Start a panic at the given location.
+/// Called by compiled code, which returns right after.
+pub extern "C" fn abort(panics: &Panics, location: &Location, message: StrRef) {
+    let message = unsafe { message.as_str() };
+    panics.panicking.store(true, Ordering::SeqCst);
+    *panics.panic.borrow_mut() = Some(Panic {
+        message: message.into(),
+        module: location.module.clone(),
+        start: location.start,
+    });
+}
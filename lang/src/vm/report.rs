@@ -0,0 +1,52 @@
+//! How much machine code a program was compiled to, which shows
+//! what the code heap of a host is used by.
+
+use crate::smol_str::SmolStr;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The machine code size of every function of a program, largest first.
+#[derive(Debug, Default, Clone)]
+pub struct CompileReport {
+    pub functions: Vec<FunctionSize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSize {
+    /// The name of the function inside the JIT.
+    pub symbol: SmolStr,
+    /// The path of the module containing the function.
+    pub module: Vec<SmolStr>,
+    /// The size of the function's machine code, in bytes.
+    pub size: usize,
+}
+
+impl CompileReport {
+    /// The size of all machine code, in bytes.
+    pub fn total(&self) -> usize {
+        self.functions.iter().map(|func| func.size).sum()
+    }
+
+    /// Returns the size of the function with the given symbol, if it was compiled.
+    pub fn size_of(&self, symbol: &str) -> Option<usize> {
+        self.functions
+            .iter()
+            .find(|func| func.symbol == symbol)
+            .map(|func| func.size)
+    }
+}
+
+impl fmt::Display for CompileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes of machine code in {} functions",
+            self.total(),
+            self.functions.len()
+        )?;
+        for func in &self.functions {
+            write!(f, "\n{:>8}  {}", func.size, func.symbol)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! Maps from machine code back to the source it was compiled from,
+//! which allows reporting where in a program a fault happened.
+
+use crate::smol_str::SmolStr;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// Where the machine code of a compiled function came from.
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    /// The name of the function inside the JIT.
+    pub symbol: SmolStr,
+    /// The path of the module containing the function.
+    pub module: Vec<SmolStr>,
+    /// The addresses of the function's machine code.
+    pub code: Range<usize>,
+    /// Regions of machine code relative to the start of the function,
+    /// with the source offset of the expression they were compiled from.
+    /// Sorted by their start.
+    pub locations: Vec<(Range<u32>, usize)>,
+}
+
+impl SourceMap {
+    /// Returns the source offset of the expression that
+    /// the instruction at the given address was compiled from.
+    pub fn lookup(&self, address: usize) -> Option<usize> {
+        if !self.code.contains(&address) {
+            return None;
+        }
+        let offset = (address - self.code.start) as u32;
+        self.locations
+            .iter()
+            .find(|(code, _)| code.contains(&offset))
+            .map(|(_, start)| *start)
+    }
+}
+
+/// Returns the line and column of the given offset in the source,
+/// both starting at 1.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source.as_bytes()[..offset.min(source.len())];
+    let line = before.iter().filter(|b| **b == b'\n').count() + 1;
+    let line_start = before
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(0, |i| i + 1);
+    (line, offset - line_start + 1)
+}
@@ -0,0 +1,125 @@
+//! Strings, which are immutable and live as long as the program that created them,
+//! unless they are temporaries freed by compiled code.
+//! A string value is a pointer to its length in bytes, followed by its UTF-8 bytes.
+
+use crate::{
+    compiler::ir::Type,
+    vm::callback::{AbiValue, Sealed},
+};
+use alloc::{boxed::Box, format, vec};
+use core::{
+    cell::{Cell, RefCell},
+    mem, ptr, slice, str,
+};
+use hashbrown::HashMap;
+
+/// The maximum amount of bytes in all strings of a program, 8 MiB.
+pub const MAX_BYTES: usize = 8 << 20;
+
+/// The strings created by a program, either from literals while compiling it
+/// or while it runs, by their address. Like closure environments, they are
+/// freed together with the program, since a program cannot tell when a string
+/// is no longer used. The exception are the operands of `+` that were just
+/// created by another `+` or `str`, which compiled code frees with [`free`].
+/// All other strings count towards [`MAX_BYTES`]; creating a string
+/// beyond it panics, which keeps a program from using up all memory.
+#[derive(Default)]
+pub struct Strings {
+    strings: RefCell<HashMap<usize, Box<[u64]>>>,
+    bytes: Cell<usize>,
+}
+
+impl Strings {
+    /// Allocate a string with the given content, like a literal.
+    pub fn alloc(&self, content: &str) -> StrRef {
+        let words = words(content);
+        let mut string = vec![0u64; words].into_boxed_slice();
+        string[0] = content.len() as u64;
+        unsafe {
+            let bytes = string.as_mut_ptr().add(1) as *mut u8;
+            bytes.copy_from_nonoverlapping(content.as_ptr(), content.len());
+        }
+
+        let ptr = string.as_ptr();
+        self.bytes
+            .set(self.bytes.get() + words * mem::size_of::<u64>());
+        self.strings.borrow_mut().insert(ptr as usize, string);
+        StrRef(ptr)
+    }
+
+    /// Allocate a string created while the program runs, or return null
+    /// if it would take the strings of the program beyond [`MAX_BYTES`].
+    pub(super) fn alloc_limited(&self, content: &str) -> StrRef {
+        if self.bytes.get() + words(content) * mem::size_of::<u64>() > MAX_BYTES {
+            return StrRef(ptr::null());
+        }
+        self.alloc(content)
+    }
+}
+
+/// The amount of words a string with the given content takes, including its length.
+fn words(content: &str) -> usize {
+    1 + (content.len() + mem::size_of::<u64>() - 1) / mem::size_of::<u64>()
+}
+
+/// A string as it is passed to and returned from compiled functions.
+/// It is only valid as long as the program that created it.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StrRef(*const u64);
+
+impl StrRef {
+    pub(super) fn as_ptr(self) -> *const u64 {
+        self.0
+    }
+
+    /// Returns the content of this string.
+    ///
+    /// # Safety
+    /// The program that created this string needs to be alive.
+    pub unsafe fn as_str<'s>(self) -> &'s str {
+        let len = *self.0 as usize;
+        let bytes = slice::from_raw_parts(self.0.add(1) as *const u8, len);
+        str::from_utf8_unchecked(bytes)
+    }
+}
+
+impl Sealed for StrRef {}
+
+impl AbiValue for StrRef {
+    fn ty() -> Type {
+        Type::String
+    }
+}
+
+/// Concatenate two strings, for `+`.
+pub extern "C" fn concat(strings: &Strings, left: StrRef, right: StrRef) -> StrRef {
+    let (left, right) = unsafe { (left.as_str(), right.as_str()) };
+    strings.alloc_limited(&format!("{}{}", left, right))
+}
+
+/// Free a string that is no longer used, like a temporary of a concatenation.
+/// Called by compiled code only for strings it created and no one else can reach.
+pub extern "C" fn free(strings: &Strings, string: StrRef) {
+    if let Some(string) = strings
+        .strings
+        .borrow_mut()
+        .remove(&(string.as_ptr() as usize))
+    {
+        let bytes = string.len() * mem::size_of::<u64>();
+        strings.bytes.set(strings.bytes.get() - bytes);
+    }
+}
+
+/// Conversions of primitive values to strings, for `str(value)`.
+pub extern "C" fn from_int(strings: &Strings, value: i64) -> StrRef {
+    strings.alloc_limited(&format!("{}", value))
+}
+
+pub extern "C" fn from_float(strings: &Strings, value: f64) -> StrRef {
+    strings.alloc_limited(&format!("{}", value))
+}
+
+pub extern "C" fn from_bool(strings: &Strings, value: bool) -> StrRef {
+    strings.alloc_limited(if value { "true" } else { "false" })
+}
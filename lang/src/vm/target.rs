@@ -0,0 +1,102 @@
+//! The machine programs are compiled for, which is the host running them by default.
+//! Other targets allow checking the code generated for another machine,
+//! like the kernel without SSE, from the host.
+
+use crate::smol_str::SmolStr;
+use alloc::{boxed::Box, string::ToString, vec::Vec};
+use core::str::FromStr;
+use cranelift::codegen::{
+    isa,
+    isa::TargetIsa,
+    settings,
+    settings::{Configurable, SetError},
+};
+use target_lexicon::{PointerWidth, Triple};
+
+/// The machine to generate code for, and the Cranelift settings to generate it with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Target {
+    /// The target triple, like `x86_64-unknown-none`, or `None` for the host.
+    pub triple: Option<SmolStr>,
+    /// Cranelift settings by name, like `is_pic` or the `has_sse41` of x86.
+    /// Once a target has a triple or settings, the features of
+    /// the host CPU are no longer detected and need to be set here.
+    pub settings: Vec<(SmolStr, SmolStr)>,
+}
+
+impl Target {
+    /// The machine with the given target triple, with default settings.
+    pub fn new(triple: &str) -> Self {
+        Self {
+            triple: Some(SmolStr::new(triple)),
+            settings: Vec::new(),
+        }
+    }
+
+    /// Set a Cranelift setting, overriding the default of the code generator.
+    pub fn setting(mut self, name: &str, value: &str) -> Self {
+        self.settings
+            .push((SmolStr::new(name), SmolStr::new(value)));
+        self
+    }
+
+    /// If this is the host with the features of its CPU.
+    pub fn is_host(&self) -> bool {
+        self.triple.is_none() && self.settings.is_empty()
+    }
+
+    /// Returns the triple of this target.
+    pub(crate) fn triple(&self) -> Result<Triple, TargetError> {
+        let triple = match &self.triple {
+            Some(name) => {
+                Triple::from_str(name).map_err(|_| TargetError::InvalidTriple(name.clone()))?
+            }
+            None => Triple::host(),
+        };
+        // Pointers are always 64 bits wide in the generated code
+        if triple.pointer_width() != Ok(PointerWidth::U64) {
+            return Err(TargetError::Unsupported(SmolStr::new(triple.to_string())));
+        }
+        Ok(triple)
+    }
+
+    /// Returns the Cranelift ISA of this target with the given triple. The
+    /// settings of this target are applied after the `defaults` of the backend.
+    pub(crate) fn isa(
+        &self,
+        triple: Triple,
+        defaults: &[(&str, &str)],
+    ) -> Result<Box<dyn TargetIsa>, TargetError> {
+        let name = SmolStr::new(triple.to_string());
+        let mut isa = isa::lookup(triple).map_err(|_| TargetError::Unsupported(name))?;
+        let mut shared = settings::builder();
+        let settings = self.settings.iter().map(|(n, v)| (n.as_str(), v.as_str()));
+        for (name, value) in defaults.iter().copied().chain(settings) {
+            // Settings of the ISA, like CPU features, are not known to the shared builder
+            let result = match shared.set(name, value) {
+                Err(SetError::BadName(_)) => isa.set(name, value),
+                result => result,
+            };
+            result.map_err(|_| TargetError::InvalidSetting {
+                name: SmolStr::new(name),
+                value: SmolStr::new(value),
+            })?;
+        }
+        Ok(isa.finish(settings::Flags::new(shared)))
+    }
+}
+
+/// Why code cannot be generated for a target.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetError {
+    /// The triple could not be parsed.
+    InvalidTriple(SmolStr),
+    /// Cranelift cannot generate code for the given triple,
+    /// or it does not have 64-bit pointers.
+    Unsupported(SmolStr),
+    /// The JIT only runs code for the architecture of the host,
+    /// which the given triple is not.
+    NotHost(SmolStr),
+    /// The setting does not exist, or the value is not valid for it.
+    InvalidSetting { name: SmolStr, value: SmolStr },
+}
@@ -17,19 +17,45 @@ pub fn translate_type<T: FnMut(usize, clif::Type)>(typ: &ir::Type, mut adder: T)
     translate_type_ref(typ, &mut adder)
 }
 
-fn translate_type_ref<T: FnMut(usize, clif::Type)>(typ: &ir::Type, adder: &mut T) -> usize {
+/// Translate a type as it is passed to and returned from functions.
+/// Booleans are bytes of either 0 or 1 there, since cranelift cannot
+/// pass `B1` values through calls portably. This is also how Rust
+/// represents `bool`, so host functions can receive them directly.
+pub fn translate_abi_type<T: FnMut(usize, clif::Type)>(typ: &ir::Type, mut adder: T) -> usize {
+    translate_type(typ, |i, ty| adder(i, abi_type(ty)))
+}
+
+fn abi_type(ty: clif::Type) -> clif::Type {
+    if ty == types::B1 {
+        types::I8
+    } else {
+        ty
+    }
+}
+
+fn translate_type_ref(typ: &ir::Type, adder: &mut dyn FnMut(usize, clif::Type)) -> usize {
     match typ {
         ir::Type::Void | ir::Type::Poison => return 0,
         ir::Type::Bool => adder(0, types::B1),
+        ir::Type::F32 => adder(0, types::F32),
         ir::Type::F64 => adder(0, types::F64),
         ir::Type::I64 => adder(0, types::I64),
-        ir::Type::Function(_) => adder(0, CLIF_PTR),
+        ir::Type::String => adder(0, CLIF_PTR),
+        ir::Type::Function(_) => {
+            // The function's address and the environment if it is a closure
+            adder(0, CLIF_PTR);
+            adder(1, CLIF_PTR);
+            return 2;
+        }
         ir::Type::Class(cls_ref) => {
             let mut count = 0;
             let cls = cls_ref.resolve();
             for mem in cls.content.borrow().values() {
                 match mem {
-                    ClassContent::Member(mem) => count += translate_type_ref(&mem.ty, adder),
+                    ClassContent::Member(mem) => {
+                        let offset = count;
+                        count += translate_type_ref(&mem.ty, &mut |i, ty| adder(offset + i, ty))
+                    }
                     _ => break,
                 }
             }
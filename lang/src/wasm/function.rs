@@ -0,0 +1,397 @@
+use crate::{
+    compiler::{
+        ir,
+        ir::{Constant, Expr, IExpr, Intrinsic, Type},
+    },
+    lexer::TKind,
+    smol_str::SmolStr,
+    wasm::{value_type, write_i64, write_u32, Wasm, I32, I64},
+};
+use alloc::vec::Vec;
+
+const UNREACHABLE: u8 = 0x00;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0B;
+const BR: u8 = 0x0C;
+const BR_IF: u8 = 0x0D;
+const CALL: u8 = 0x10;
+const DROP: u8 = 0x1A;
+const SELECT: u8 = 0x1B;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const F32_CONST: u8 = 0x43;
+const F64_CONST: u8 = 0x44;
+const I32_EQZ: u8 = 0x45;
+const I64_LT_S: u8 = 0x53;
+const I64_GT_S: u8 = 0x55;
+const I64_SUB: u8 = 0x7D;
+const F32_DEMOTE_F64: u8 = 0xB6;
+const F64_PROMOTE_F32: u8 = 0xBB;
+/// The type of blocks without a value.
+const EMPTY: u8 = 0x40;
+
+type Res<T = ()> = Result<T, &'static str>;
+
+/// Translates the IR of a function into the body of a WebAssembly function.
+pub(super) struct FnTranslator<'b> {
+    func: &'b ir::Function,
+    wasm: &'b mut Wasm,
+    code: Vec<u8>,
+    calls: Vec<(usize, SmolStr)>,
+    /// How many blocks, loops and `if`s the current expression is in.
+    depth: u32,
+    /// The depth of the block after each loop the current expression is in,
+    /// innermost last, and if `break` passes its value to it.
+    loops: Vec<(u32, bool)>,
+}
+
+impl<'b> FnTranslator<'b> {
+    /// Returns the code of the function and the calls in it,
+    /// or the feature it uses that is not supported.
+    pub(super) fn build(mut self) -> Res<(Vec<u8>, Vec<(usize, SmolStr)>)> {
+        if self.func.is_closure() {
+            return Err("closures");
+        }
+        if self.func.is_poisoned() {
+            // The body contains errors, so trap instead of running it
+            self.code.extend_from_slice(&[0, UNREACHABLE, END]);
+            return Ok((self.code, self.calls));
+        }
+        let body = self.func.body.borrow();
+        let mut scratch = false;
+        body.visit(&mut |expr| {
+            if let IExpr::Intrinsic { intrinsic, args } = &*expr.inner {
+                scratch |= needs_scratch(*intrinsic, &args[0].typ());
+            }
+        });
+
+        // Parameters are the first locals, so their indices match
+        let locals = self.func.locals.len() + scratch as usize;
+        write_u32(&mut self.code, locals as u32);
+        for local in &self.func.locals {
+            write_u32(&mut self.code, 1);
+            // Variables are never void, but keep the indices right if they were
+            self.code.push(value_type(&local.ty)?.unwrap_or(I32));
+        }
+        if scratch {
+            write_u32(&mut self.code, 2);
+            self.code.push(I64);
+        }
+
+        self.expr(&body)?;
+        if self.func.ret_type == Type::Void {
+            // Functions without a return type might still end with a value
+            self.drop(&body.typ())?;
+        }
+        self.code.push(END);
+        Ok((self.code, self.calls))
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Res {
+        match &*expr.inner {
+            IExpr::Binary { left, op, right } => self.binary(left, op.kind, right),
+
+            IExpr::Constant(constant) => self.constant(constant),
+
+            IExpr::Block(exprs) => {
+                if let Some((last, exprs)) = exprs.split_last() {
+                    for expr in exprs {
+                        self.expr(expr)?;
+                        self.drop(&expr.typ())?;
+                    }
+                    self.expr(last)?;
+                }
+                Ok(())
+            }
+
+            IExpr::If {
+                cond,
+                then,
+                els,
+                phi,
+            } => {
+                self.expr(cond)?;
+                self.code.push(IF);
+                let ty = if *phi { value_type(&then.typ())? } else { None };
+                self.code.push(ty.unwrap_or(EMPTY));
+                self.depth += 1;
+                self.branch(then, *phi)?;
+                self.code.push(ELSE);
+                self.branch(els, *phi)?;
+                self.code.push(END);
+                self.depth -= 1;
+                Ok(())
+            }
+
+            IExpr::While {
+                cond,
+                body,
+                els,
+                phi,
+            } => {
+                // The outer block is left by `break`, the inner one once the condition is false
+                let ty = if *phi { value_type(&els.typ())? } else { None };
+                self.code.extend_from_slice(&[BLOCK, ty.unwrap_or(EMPTY)]);
+                self.code.extend_from_slice(&[BLOCK, EMPTY, LOOP, EMPTY]);
+                self.depth += 3;
+                self.expr(cond)?;
+                self.code.extend_from_slice(&[I32_EQZ, BR_IF, 1]);
+                self.loops.push((self.depth - 2, *phi));
+                self.expr(body)?;
+                self.drop(&body.typ())?;
+                self.code.extend_from_slice(&[BR, 0, END, END]);
+                self.depth -= 2;
+                self.loops.pop();
+                self.branch(els, *phi)?;
+                self.code.push(END);
+                self.depth -= 1;
+                Ok(())
+            }
+
+            IExpr::Break { value } => {
+                let (depth, phi) = *self.loops.last().unwrap();
+                self.branch(value, phi)?;
+                self.code.push(BR);
+                write_u32(&mut self.code, self.depth - depth);
+                Ok(())
+            }
+
+            IExpr::Variable { index, .. } => {
+                self.code.push(LOCAL_GET);
+                write_u32(&mut self.code, *index as u32);
+                Ok(())
+            }
+
+            IExpr::Assign { store, value } => match &*store.inner {
+                IExpr::Variable { index, .. } => {
+                    self.expr(value)?;
+                    self.code.push(LOCAL_TEE);
+                    write_u32(&mut self.code, *index as u32);
+                    Ok(())
+                }
+                _ => panic!("Unknown assignment target!"),
+            },
+
+            IExpr::Call { callee, args } => self.call(callee, args),
+
+            IExpr::Convert { value, to } => {
+                self.expr(value)?;
+                match (value.typ(), to) {
+                    (Type::F32, Type::F64) => self.code.push(F64_PROMOTE_F32),
+                    (Type::F64, Type::F32) => self.code.push(F32_DEMOTE_F64),
+                    (_, Type::String) => return Err("strings"),
+                    _ => (),
+                }
+                Ok(())
+            }
+
+            IExpr::Closure { .. } => Err("closures"),
+
+            IExpr::Construct { .. } | IExpr::Member { .. } => Err("classes"),
+
+            // The message is a string, which cannot be shown yet
+            IExpr::Panic { .. } => {
+                self.code.push(UNREACHABLE);
+                Ok(())
+            }
+
+            IExpr::Env { .. } => Err("environment bindings"),
+
+            IExpr::Intrinsic { intrinsic, args } => {
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                self.intrinsic(*intrinsic, &args[0].typ());
+                Ok(())
+            }
+
+            IExpr::Poison => panic!("Cannot translate poison values!"),
+        }
+    }
+
+    /// Translate a branch of an `if`, which only keeps its value if the `if` has one.
+    fn branch(&mut self, expr: &Expr, phi: bool) -> Res {
+        self.expr(expr)?;
+        if !phi {
+            self.drop(&expr.typ())?;
+        }
+        Ok(())
+    }
+
+    /// Drop the value of an expression of the given type, if it has one.
+    fn drop(&mut self, ty: &Type) -> Res {
+        if value_type(ty)?.is_some() {
+            self.code.push(DROP);
+        }
+        Ok(())
+    }
+
+    fn binary(&mut self, left: &Expr, op: TKind, right: &Expr) -> Res {
+        self.expr(left)?;
+        self.expr(right)?;
+        let opcode = match left.typ() {
+            Type::String => return Err("strings"),
+            Type::F32 => float_op(op, 0x5B, 0x92),
+            Type::F64 => float_op(op, 0x61, 0xA0),
+            _ => match op {
+                TKind::Plus => 0x7C,
+                TKind::Minus => 0x7D,
+                TKind::Star => 0x7E,
+                TKind::Slash => 0x7F,
+                TKind::Percent => 0x81,
+                TKind::Ampersand => 0x83,
+                TKind::Pipe => 0x84,
+                TKind::Caret => 0x85,
+                TKind::LessLess => 0x86,
+                TKind::GreaterGreater => 0x87,
+                TKind::EqualEqual => 0x51,
+                TKind::BangEqual => 0x52,
+                TKind::Less => 0x53,
+                TKind::Greater => 0x55,
+                TKind::LessEqual => 0x57,
+                TKind::GreaterEqual => 0x59,
+                _ => panic!("unknown binary operator"),
+            },
+        };
+        self.code.push(opcode);
+        Ok(())
+    }
+
+    /// Apply an intrinsic to the arguments on the stack. Integers have no instructions
+    /// for `min`, `max` and `abs`, which select between the arguments instead.
+    /// That needs them twice, so they are kept in the scratch locals after all variables.
+    fn intrinsic(&mut self, intrinsic: Intrinsic, ty: &Type) {
+        let (x, y) = (self.func.locals.len(), self.func.locals.len() + 1);
+        let opcode = match (intrinsic, ty) {
+            (Intrinsic::Min, Type::F32) => 0x96,
+            (Intrinsic::Max, Type::F32) => 0x97,
+            (Intrinsic::Abs, Type::F32) => 0x8B,
+            (Intrinsic::Min, Type::F64) => 0xA4,
+            (Intrinsic::Max, Type::F64) => 0xA5,
+            (Intrinsic::Abs, Type::F64) => 0x99,
+            (Intrinsic::Min, _) | (Intrinsic::Max, _) => {
+                self.local(LOCAL_SET, y);
+                self.local(LOCAL_TEE, x);
+                self.local(LOCAL_GET, y);
+                self.local(LOCAL_GET, x);
+                self.local(LOCAL_GET, y);
+                match intrinsic {
+                    Intrinsic::Min => I64_LT_S,
+                    _ => I64_GT_S,
+                }
+            }
+            (Intrinsic::Abs, _) => {
+                self.local(LOCAL_SET, x);
+                self.code.extend_from_slice(&[I64_CONST, 0]);
+                self.local(LOCAL_GET, x);
+                self.code.push(I64_SUB);
+                self.local(LOCAL_GET, x);
+                self.local(LOCAL_GET, x);
+                self.code.extend_from_slice(&[I64_CONST, 0]);
+                I64_LT_S
+            }
+            (Intrinsic::Clz, _) => 0x79,
+            (Intrinsic::Ctz, _) => 0x7A,
+            (Intrinsic::Popcount, _) => 0x7B,
+            (Intrinsic::Rotl, _) => 0x89,
+            (Intrinsic::Rotr, _) => 0x8A,
+        };
+        self.code.push(opcode);
+        if needs_scratch(intrinsic, ty) {
+            self.code.push(SELECT);
+        }
+    }
+
+    fn local(&mut self, opcode: u8, index: usize) {
+        self.code.push(opcode);
+        write_u32(&mut self.code, index as u32);
+    }
+
+    fn constant(&mut self, constant: &Constant) -> Res {
+        match constant {
+            Constant::Bool(value) => {
+                self.code.push(I32_CONST);
+                write_i64(&mut self.code, *value as i64);
+            }
+            Constant::Int(value) => {
+                self.code.push(I64_CONST);
+                write_i64(&mut self.code, *value);
+            }
+            Constant::Float(value) => {
+                self.code.push(F64_CONST);
+                self.code.extend_from_slice(&value.to_le_bytes());
+            }
+            Constant::F32(value) => {
+                self.code.push(F32_CONST);
+                self.code.extend_from_slice(&value.to_le_bytes());
+            }
+            Constant::String(_) => return Err("strings"),
+            Constant::Function(_) => return Err("function values"),
+            Constant::Class(_) => return Err("classes"),
+        }
+        Ok(())
+    }
+
+    /// Call a known function. Its index is filled in once all functions are compiled.
+    fn call(&mut self, callee: &Expr, args: &[Expr]) -> Res {
+        let func = match &*callee.inner {
+            IExpr::Constant(Constant::Function(func)) => func,
+            _ => return Err("function values"),
+        };
+        for arg in args {
+            self.expr(arg)?;
+        }
+
+        let module = func.module.borrow();
+        let func = func.resolve();
+        if func.external {
+            self.wasm.import(&func)?;
+        }
+        self.code.push(CALL);
+        self.calls
+            .push((self.code.len(), func.symbol(&module.ast.path)));
+        self.code.extend_from_slice(&[0; 5]);
+        Ok(())
+    }
+
+    pub(super) fn new(func: &'b ir::Function, wasm: &'b mut Wasm) -> Self {
+        Self {
+            func,
+            wasm,
+            code: Vec::with_capacity(64),
+            calls: Vec::new(),
+            depth: 0,
+            loops: Vec::new(),
+        }
+    }
+}
+
+/// If the intrinsic needs the scratch locals for arguments of the given type,
+/// see `FnTranslator::intrinsic`.
+fn needs_scratch(intrinsic: Intrinsic, ty: &Type) -> bool {
+    matches!(intrinsic, Intrinsic::Min | Intrinsic::Max | Intrinsic::Abs) && *ty == Type::I64
+}
+
+/// The opcode of a float operator, given the opcodes of `eq` and `add`
+/// for the type, which are followed by the other operators.
+fn float_op(op: TKind, eq: u8, add: u8) -> u8 {
+    match op {
+        TKind::EqualEqual => eq,
+        TKind::BangEqual => eq + 1,
+        TKind::Less => eq + 2,
+        TKind::Greater => eq + 3,
+        TKind::LessEqual => eq + 4,
+        TKind::GreaterEqual => eq + 5,
+        TKind::Plus => add,
+        TKind::Minus => add + 1,
+        TKind::Star => add + 2,
+        TKind::Slash => add + 3,
+        _ => panic!("unknown binary operator"),
+    }
+}
@@ -0,0 +1,257 @@
+//! A backend emitting WebAssembly modules, which allows running programs
+//! outside of the kernel, like in a browser. It encodes the binary format directly.
+//!
+//! Only a subset of the language is supported so far: numbers, booleans,
+//! variables, control flow and direct calls. Strings, classes and function
+//! values fail with `WasmError::Unsupported`. Panics trap without a message,
+//! and `extern fun`s are imported from the `env` module by name.
+//! All other functions are exported by their symbol, like `main::main`.
+
+use crate::{
+    backend::Backend,
+    compiler::{ir, ir::Type},
+    smol_str::SmolStr,
+    wasm::function::FnTranslator,
+};
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use hashbrown::HashMap;
+
+mod function;
+
+/// A problem preventing a program from being compiled to WebAssembly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WasmError {
+    /// The function uses a feature that the backend does not support yet.
+    Unsupported {
+        function: SmolStr,
+        feature: &'static str,
+    },
+    /// The function calls a function that was not compiled,
+    /// since its module was not given to the backend.
+    MissingFunction(SmolStr),
+}
+
+const I32: u8 = 0x7F;
+const I64: u8 = 0x7E;
+const F32: u8 = 0x7D;
+const F64: u8 = 0x7C;
+
+/// The WebAssembly type of values of the given type, `None` for `void`.
+fn value_type(ty: &Type) -> Result<Option<u8>, &'static str> {
+    Ok(Some(match ty {
+        Type::Void | Type::Poison => return Ok(None),
+        Type::Bool => I32,
+        Type::I64 => I64,
+        Type::F32 => F32,
+        Type::F64 => F64,
+        Type::String => return Err("strings"),
+        Type::Function(_) => return Err("function values"),
+        Type::Class(_) => return Err("classes"),
+    }))
+}
+
+/// A function signature as encoded in the type section.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FuncType {
+    params: Vec<u8>,
+    results: Vec<u8>,
+}
+
+impl FuncType {
+    fn of(func: &ir::Function) -> Result<FuncType, &'static str> {
+        let mut params = Vec::with_capacity(func.params.len());
+        for param in &func.params {
+            params.extend(value_type(&param.ty)?);
+        }
+        let results = value_type(&func.ret_type)?.into_iter().collect();
+        Ok(FuncType { params, results })
+    }
+}
+
+/// A function compiled to WebAssembly.
+struct WasmFunction {
+    symbol: SmolStr,
+    ty: u32,
+    /// The body, including local declarations.
+    code: Vec<u8>,
+    /// Calls in the body, as the offset of their padded function index
+    /// and the symbol of the called function. Indices are only known
+    /// once all functions are compiled, so they are filled in by `finish`.
+    calls: Vec<(usize, SmolStr)>,
+}
+
+#[derive(Default)]
+pub(crate) struct Wasm {
+    types: Vec<FuncType>,
+    type_indices: HashMap<FuncType, u32>,
+    /// The name and type of all imported `extern fun`s.
+    imports: Vec<(SmolStr, u32)>,
+    functions: Vec<WasmFunction>,
+    errors: Vec<WasmError>,
+}
+
+impl Backend for Wasm {
+    /// Functions that cannot be compiled are collected and reported by `finish` instead.
+    type Error = Infallible;
+
+    fn module(&mut self, module: &ir::Module) -> Result<(), Infallible> {
+        for func in module.funcs.iter().filter(|f| !f.external && f.live.get()) {
+            let symbol = func.symbol(&module.ast.path);
+            let result =
+                FuncType::of(func).and_then(|ty| Ok((ty, FnTranslator::new(func, self).build()?)));
+            match result {
+                Ok((ty, (code, calls))) => {
+                    let ty = self.type_index(ty);
+                    self.functions.push(WasmFunction {
+                        symbol,
+                        ty,
+                        code,
+                        calls,
+                    });
+                }
+                Err(feature) => self.errors.push(WasmError::Unsupported {
+                    function: symbol,
+                    feature,
+                }),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Wasm {
+    /// Encode the module, failing if any function could not be compiled.
+    pub(crate) fn finish(mut self) -> Result<Vec<u8>, Vec<WasmError>> {
+        let mut indices = HashMap::with_capacity(self.imports.len() + self.functions.len());
+        for (index, (name, _)) in self.imports.iter().enumerate() {
+            indices.insert(name.clone(), index as u32);
+        }
+        for (index, func) in self.functions.iter().enumerate() {
+            indices.insert(func.symbol.clone(), (self.imports.len() + index) as u32);
+        }
+        for func in &mut self.functions {
+            for (offset, callee) in &func.calls {
+                match indices.get(callee) {
+                    Some(index) => write_padded_u32(&mut func.code[*offset..], *index),
+                    None => self.errors.push(WasmError::MissingFunction(callee.clone())),
+                }
+            }
+        }
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        let mut out = Vec::with_capacity(1024);
+        out.extend_from_slice(b"\0asm");
+        out.extend_from_slice(&1u32.to_le_bytes());
+
+        section(&mut out, 1, self.types.len(), |out| {
+            for ty in &self.types {
+                out.push(0x60);
+                bytes(out, &ty.params);
+                bytes(out, &ty.results);
+            }
+        });
+        section(&mut out, 2, self.imports.len(), |out| {
+            for (name, ty) in &self.imports {
+                bytes(out, b"env");
+                bytes(out, name.as_bytes());
+                out.push(0x00);
+                write_u32(out, *ty);
+            }
+        });
+        section(&mut out, 3, self.functions.len(), |out| {
+            for func in &self.functions {
+                write_u32(out, func.ty);
+            }
+        });
+        section(&mut out, 7, self.functions.len(), |out| {
+            for (index, func) in self.functions.iter().enumerate() {
+                bytes(out, func.symbol.as_bytes());
+                out.push(0x00);
+                write_u32(out, (self.imports.len() + index) as u32);
+            }
+        });
+        section(&mut out, 10, self.functions.len(), |out| {
+            for func in &self.functions {
+                bytes(out, &func.code);
+            }
+        });
+        Ok(out)
+    }
+
+    fn type_index(&mut self, ty: FuncType) -> u32 {
+        if let Some(index) = self.type_indices.get(&ty) {
+            return *index;
+        }
+        let index = self.types.len() as u32;
+        self.types.push(ty.clone());
+        self.type_indices.insert(ty, index);
+        index
+    }
+
+    /// Import the given external function, unless it already is.
+    fn import(&mut self, func: &ir::Function) -> Result<(), &'static str> {
+        if self.imports.iter().any(|(name, _)| *name == func.name) {
+            return Ok(());
+        }
+        let ty = self.type_index(FuncType::of(func)?);
+        self.imports.push((func.name.clone(), ty));
+        Ok(())
+    }
+}
+
+/// Write a section with the given number of entries, skipping empty ones.
+fn section(out: &mut Vec<u8>, id: u8, entries: usize, write: impl FnOnce(&mut Vec<u8>)) {
+    if entries == 0 {
+        return;
+    }
+    let mut content = Vec::new();
+    write_u32(&mut content, entries as u32);
+    write(&mut content);
+    out.push(id);
+    bytes(out, &content);
+}
+
+/// Write a vector of bytes, which is prefixed with its length.
+fn bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+/// Write an unsigned LEB128 number.
+fn write_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Overwrite the first 5 bytes with an unsigned LEB128 number padded to
+/// that length, which allows filling in numbers only known later.
+fn write_padded_u32(out: &mut [u8], value: u32) {
+    for (i, byte) in out[..5].iter_mut().enumerate() {
+        let bits = ((value >> (i * 7)) & 0x7F) as u8;
+        *byte = if i == 4 { bits } else { bits | 0x80 };
+    }
+}
+
+/// Write a signed LEB128 number.
+fn write_i64(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}